@@ -0,0 +1,164 @@
+//! Chunking throughput, indexing rate and search latency on projects made of
+//! copies of the golden fixture, and chunking throughput on generated
+//! repositories of more files. Indexing and search use the default embedding
+//! model, downloaded on the first run.
+
+use std::path::Path;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use local_code_search::{
+    config::Config,
+    embeddings::{
+        SharedModel,
+        eval::{EvalIndex, chunk_project},
+    },
+    fixtures::{FixtureSpec, generate_fixture},
+};
+use tempfile::TempDir;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden");
+
+/// Copies of the fixture in the benchmarked projects.
+const SIZES: [usize; 3] = [1, 16, 128];
+
+/// Files of the generated repositories.
+const GENERATED_SIZES: [usize; 3] = [100, 1000, 10000];
+
+const QUERIES: [&str; 3] = [
+    "retry an operation with exponential backoff",
+    "parse the header of a JWT",
+    "database connection pool size",
+];
+
+fn chunking(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("chunking");
+    for copies in SIZES {
+        let project = fixture_project(copies);
+        group.throughput(Throughput::Bytes(project_bytes(project.path())));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(copies),
+            &project,
+            |b, project| b.iter(|| chunk_project(&config, project.path()).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn chunking_generated(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("chunking_generated");
+    group.sample_size(10);
+    for files in GENERATED_SIZES {
+        let project = tempfile::tempdir().unwrap();
+        let spec = FixtureSpec {
+            files,
+            depth: 3,
+            fan_out: 4,
+            ..Default::default()
+        };
+        let stats = generate_fixture(&spec, project.path()).unwrap();
+        group.throughput(Throughput::Bytes(stats.bytes));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(files),
+            &project,
+            |b, project| b.iter(|| chunk_project(&config, project.path()).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn indexing(c: &mut Criterion) {
+    let config = in_memory_config();
+    let model = SharedModel::from_config(&config).unwrap();
+    let mut group = c.benchmark_group("indexing");
+    group.sample_size(10);
+    for copies in SIZES {
+        let project = fixture_project(copies);
+        let files = EvalIndex::build(&config, project.path(), model.clone())
+            .unwrap()
+            .files;
+        group.throughput(Throughput::Elements(files as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(copies),
+            &project,
+            |b, project| {
+                b.iter(|| EvalIndex::build(&config, project.path(), model.clone()).unwrap())
+            },
+        );
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let config = in_memory_config();
+    let model = SharedModel::from_config(&config).unwrap();
+    let top_k = config.search.top_k;
+    let mut group = c.benchmark_group("search");
+    for copies in SIZES {
+        let project = fixture_project(copies);
+        let index = EvalIndex::build(&config, project.path(), model.clone()).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(copies), &index, |b, index| {
+            let mut queries = QUERIES.iter().cycle();
+            b.iter(|| index.search(queries.next().unwrap(), top_k).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Indexes are built in memory, the temporary projects would leave theirs
+/// in the cache directory.
+fn in_memory_config() -> Config {
+    let mut config = Config::default();
+    config.storage.index_dir = None;
+    config
+}
+
+/// A project of `copies` copies of the fixture, each file starting with a
+/// comment naming its copy so the index doesn't share their embeddings.
+fn fixture_project(copies: usize) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for copy in 0..copies {
+        copy_tree(
+            Path::new(FIXTURE),
+            &dir.path().join(format!("copy{copy}")),
+            copy,
+        );
+    }
+    dir
+}
+
+fn copy_tree(from: &Path, to: &Path, copy: usize) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_tree(&entry.path(), &target, copy);
+            continue;
+        }
+        let comment = match entry.path().extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => "//",
+            _ => "#",
+        };
+        let text = std::fs::read_to_string(entry.path()).unwrap();
+        std::fs::write(target, format!("{comment} copy {copy}\n{text}")).unwrap();
+    }
+}
+
+fn project_bytes(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                project_bytes(&entry.path())
+            } else {
+                entry.metadata().unwrap().len()
+            }
+        })
+        .sum()
+}
+
+criterion_group!(benches, chunking, chunking_generated, indexing, search);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// One line of the audit log.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The process that connected to the daemon, e.g. "pid 4242 uid 1000".
+    pub client: String,
+    /// The search's request id, which the daemon's logs are tagged with.
+    pub request_id: String,
+    pub projects: Vec<PathBuf>,
+    pub query: String,
+    /// Files of the results, closest first.
+    pub results: Vec<PathBuf>,
+}
+
+/// Searches made against the daemon, appended to a file as one JSON object
+/// per line. Entries are never rewritten.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {:?}", path))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        // A single write keeps concurrent entries from interleaving.
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_entries_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let entry = |query: &str| AuditEntry {
+            timestamp: 1,
+            client: "pid 1 uid 0".to_string(),
+            request_id: "2a".to_string(),
+            projects: vec!["/src/app".into()],
+            query: query.to_string(),
+            results: vec!["/src/app/main.rs".into()],
+        };
+        AuditLog::open(&path)
+            .unwrap()
+            .record(&entry("first"))
+            .unwrap();
+        AuditLog::open(&path)
+            .unwrap()
+            .record(&entry("second"))
+            .unwrap();
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries, [entry("first"), entry("second")]);
+    }
+}
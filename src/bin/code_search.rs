@@ -1,26 +1,83 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
 use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
 use mcp_attr::{
     ErrorCode,
     server::{McpServer, mcp_server, serve_stdio},
 };
-use tarpc::{client, context, tokio_serde::formats::Json};
+use tarpc::context;
+
+use local_code_search::{
+    config::Config,
+    context::{estimate_tokens, fit_to_budget, gather_context},
+    embeddings::{
+        ChunkKind, Corpus, Coverage, Definition, Explanation, IndexEstimate, ProjectInfo,
+        ResponseChunk, SearchCodeResponse, SearchOptions, SkipReason,
+    },
+    notifications::Notification,
+    result_formats,
+    rpc::*,
+    telemetry,
+};
 
-use local_code_search::{embeddings::ResponseChunk, rpc::*};
+/// How long the notifications tool waits for new ones.
+const NOTIFICATION_WAIT: Duration = Duration::from_secs(2);
 
-struct CodeSearchServer;
+/// How long each request of the notifications command waits for new ones.
+const SUBSCRIPTION_WAIT: Duration = Duration::from_secs(60);
+
+/// Symbols listed by the search_symbols tool unless told otherwise.
+const SYMBOL_LIMIT: usize = 20;
+
+struct CodeSearchServer {
+    socket_path: PathBuf,
+    /// Requests larger than this many bytes are sent deflated.
+    compression_bytes: Option<usize>,
+    auto_start: bool,
+    /// Held while starting the daemon, so concurrent tool calls start one.
+    starting: tokio::sync::Mutex<()>,
+}
 
 impl CodeSearchServer {
+    /// Connects to the daemon, starting it first with `auto_start` if it
+    /// isn't running.
+    async fn connect(&self) -> io::Result<CodeSearchRpcClient> {
+        match connect(&self.socket_path, self.compression_bytes).await {
+            Err(e)
+                if self.auto_start
+                    && matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) =>
+            {
+                let _starting = self.starting.lock().await;
+                match connect(&self.socket_path, self.compression_bytes).await {
+                    Ok(client) => Ok(client),
+                    Err(_) => start_daemon(&self.socket_path, self.compression_bytes).await,
+                }
+            }
+            result => result,
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
     async fn handle_search_code(
         &self,
-        project_root: String,
+        project_roots: Vec<String>,
         query: String,
-    ) -> mcp_attr::Result<Vec<ResponseChunk>> {
-        let mut transport =
-            tarpc::serde_transport::unix::connect("/tmp/code_search.sock", Json::default);
-        transport.config_mut().max_frame_length(usize::MAX);
-        let client = CodeSearchRpcClient::new(client::Config::default(), transport.await?).spawn();
+        options: SearchOptions,
+    ) -> mcp_attr::Result<SearchCodeResponse> {
+        let client = self.connect().await?;
+        let scope = if project_roots.is_empty() {
+            ProjectScope::AllRegistered
+        } else {
+            ProjectScope::Paths(project_roots)
+        };
         let result = match client
-            .search_code(context::current(), project_root, query)
+            .search_projects(context::current(), scope, query, options)
             .await
         {
             Ok(result) => result,
@@ -29,50 +86,1036 @@ impl CodeSearchServer {
                     .with_message(e.to_string(), true));
             }
         };
-        Ok(result.unwrap_or_default())
+        match result {
+            Ok(response) => Ok(response),
+            Err(
+                e @ (RpcError::UnknownProject(_)
+                | RpcError::UnknownSession(_)
+                | RpcError::NotFound(_)
+                | RpcError::NotADirectory(_)
+                | RpcError::PermissionDenied(_)
+                | RpcError::ProjectUnavailable(_)
+                | RpcError::TooManyProjects(_)),
+            ) => {
+                Err(mcp_attr::Error::new(ErrorCode::INVALID_PARAMS)
+                    .with_message(e.to_string(), true))
+            }
+            // Failures of the daemon, the agent can retry rather than take no results.
+            Err(
+                e @ (RpcError::ModelLoading
+                | RpcError::ReadOnly
+                | RpcError::Cancelled
+                | RpcError::Tarpc(_)
+                | RpcError::Internal(_)),
+            ) => Err(internal_error(e)),
+        }
+    }
+
+    async fn handle_resource_usage(&self, project_root: String) -> mcp_attr::Result<String> {
+        let client = self.connect().await?;
+        let usage = client
+            .resource_usage(context::current(), project_root)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(format!(
+            "files: {}\ntext: {} bytes\nsyntax trees (estimated): {} bytes\nindex: {} bytes\nembedding model: {} bytes",
+            usage.files, usage.text_bytes, usage.tree_bytes, usage.index_bytes, usage.model_bytes,
+        ))
+    }
+}
+
+fn format_estimate(estimate: &IndexEstimate) -> String {
+    format!(
+        "files: {}\nsize: {} bytes\nchunks (estimated): {}\nindexing time (estimated): {} seconds\nindex (estimated): {} bytes\nmemory (estimated): {} bytes",
+        estimate.files,
+        estimate.bytes,
+        estimate.chunks,
+        estimate.seconds,
+        estimate.index_bytes,
+        estimate.memory_bytes,
+    )
+}
+
+fn format_coverage(coverage: &Coverage) -> String {
+    let mut text = format!(
+        "{:.1}% of indexed bytes in chunks",
+        coverage.covered_percent()
+    );
+    for language in &coverage.languages {
+        let name = match language.language.as_str() {
+            "" => "other",
+            name => name,
+        };
+        text.push_str(&format!(
+            "\n{}: {} files, {} indexed, {:.1}% of {} bytes in chunks",
+            name,
+            language.files,
+            language.indexed,
+            language.covered_percent(),
+            language.bytes
+        ));
+    }
+    if coverage.skipped_files > 0 {
+        text.push_str(&format!("\nskipped {} files:", coverage.skipped_files));
+    }
+    for skipped in &coverage.skipped {
+        let reason = match skipped.reason {
+            SkipReason::Unsupported => "unsupported language",
+            SkipReason::Unreadable => "unreadable",
+        };
+        text.push_str(&format!(
+            "\n{} ({})",
+            skipped.path.to_string_lossy(),
+            reason
+        ));
+    }
+    if coverage.skipped.len() < coverage.skipped_files {
+        text.push_str(&format!(
+            "\n... and {} more",
+            coverage.skipped_files - coverage.skipped.len()
+        ));
+    }
+    text
+}
+
+fn format_notification(notification: &Notification) -> String {
+    format!("{}: {}", notification.id, notification.event)
+}
+
+fn format_project(project: &ProjectInfo) -> String {
+    let mut text = project.root.to_string_lossy().to_string();
+    if let Some(revision) = &project.revision {
+        text.push_str(&format!("@{revision}"));
+    }
+    text.push_str(&format!(": {} files", project.files));
+    match project.pending {
+        0 => text.push_str(", index up to date"),
+        pending => text.push_str(&format!(", {pending} files waiting to be indexed")),
+    }
+    if project.model_loading {
+        text.push_str(" (embedding model loading)");
+    }
+    if let Some(elapsed) = project
+        .last_indexed
+        .and_then(|at| SystemTime::now().duration_since(at).ok())
+    {
+        text.push_str(&format!(", last indexed {} seconds ago", elapsed.as_secs()));
+    }
+    if project.read_only {
+        text.push_str(", read-only");
+    }
+    if !project.available {
+        text.push_str(", unavailable (directory gone)");
     }
+    text.push_str(&format!(", model {}", project.model));
+    if let Some(previous) = &project.previous_model {
+        text.push_str(&format!(" (searching with {previous} until re-embedded)"));
+    }
+    text
+}
+
+fn format_definition(definition: &Definition) -> String {
+    format!(
+        "file://{}:{} ({}) {}",
+        definition.path.to_string_lossy(),
+        definition.row.start,
+        definition.kind.as_str(),
+        definition.signature
+    )
+}
+
+fn format_chunk(chunk: ResponseChunk) -> String {
+    let key_path = chunk
+        .key_path
+        .map(|key_path| format!(" at {key_path}"))
+        .unwrap_or_default();
+    let blame = chunk
+        .blame
+        .map(|blame| {
+            format!(
+                ", last changed by {} on {} in {} ({})",
+                blame.author, blame.date, blame.commit, blame.summary
+            )
+        })
+        .unwrap_or_default();
+    let permalink = chunk
+        .permalink
+        .map(|permalink| format!(" ({permalink})"))
+        .unwrap_or_default();
+    let stale = if chunk.stale {
+        " (changed since indexed, re-read the file)"
+    } else {
+        ""
+    };
+    let explanation = chunk
+        .explanation
+        .map(|explanation| format_explanation(&explanation))
+        .unwrap_or_default();
+    let duplicates = match chunk.duplicates.len() {
+        0 => String::new(),
+        1 => " (also found in 1 other location)".to_string(),
+        n => format!(" (also found in {n} other locations)"),
+    };
+    // Files of a revision may not be in the working tree.
+    let location = match &chunk.commit {
+        Some(commit) => format!("{}@{}", chunk.path.to_string_lossy(), commit),
+        None => format!("file://{}", chunk.path.to_string_lossy()),
+    };
+    format!(
+        "{}:{}:{}-{}:{} (chunk {}, {} lines, ~{} tokens){}{}{}{}{}{} contains:\n{}",
+        location,
+        chunk.row.start,
+        chunk.column.start,
+        chunk.row.end,
+        chunk.column.end,
+        chunk.id,
+        chunk.size.lines,
+        chunk.size.approx_tokens,
+        key_path,
+        permalink,
+        stale,
+        duplicates,
+        blame,
+        explanation,
+        chunk.content
+    )
+}
+
+fn format_explanation(explanation: &Explanation) -> String {
+    let mut text = format!(" (vector distance {:.3}", explanation.vector_distance);
+    for adjustment in &explanation.adjustments {
+        text.push_str(&format!(", {:+.3} {}", adjustment.delta, adjustment.reason));
+    }
+    if !explanation.filters.is_empty() {
+        text.push_str(&format!(
+            ", passed filters: {}",
+            explanation.filters.join(", ")
+        ));
+    }
+    text.push(')');
+    text
+}
+
+/// Parses a `corpus=limit` pair like `docs=2`.
+fn parse_corpus_limit(pair: &str) -> Result<(Corpus, usize), String> {
+    let (corpus, limit) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("Expected corpus=limit, got {pair:?}"))?;
+    let corpus = Corpus::parse(corpus).ok_or_else(|| format!("Unknown corpus {corpus:?}"))?;
+    let limit = limit.parse().map_err(|e| format!("Invalid limit: {e}"))?;
+    Ok((corpus, limit))
+}
+
+fn internal_error(e: impl std::fmt::Display) -> mcp_attr::Error {
+    mcp_attr::Error::new(ErrorCode::INTERNAL_ERROR).with_message(e.to_string(), true)
 }
 
 #[mcp_server]
 impl McpServer for CodeSearchServer {
-    /// Search for code in the given project.
+    /// Search for code in the given projects.
     #[tool]
+    // Every parameter of the tool is an argument.
+    #[allow(clippy::too_many_arguments)]
     async fn search_code(
         &self,
-        /// The root path of the project to search.
+        /// The root paths of the projects to search. An empty list searches
+        /// every project that has been searched before.
+        project_roots: Vec<String>,
+        /// The query to search for.
+        query: String,
+        /// Only return chunks of these kinds: code, function, type, test,
+        /// config, doc or preamble (a file's imports and module docs). Any
+        /// kind if omitted.
+        kinds: Option<Vec<String>>,
+        /// Only return chunks of files in these languages, e.g. rust, python
+        /// or typescript. Any language if omitted.
+        languages: Option<Vec<String>>,
+        /// Only return chunks of files under this path, relative to the
+        /// project root, e.g. "src/server".
+        path_prefix: Option<String>,
+        /// Rank results containing any of these terms last, e.g. to steer
+        /// away from an unrelated subsystem. `-term` words in the query do
+        /// the same.
+        exclude: Option<Vec<String>>,
+        /// Also tell who last changed each result and when, according to git.
+        blame: Option<bool>,
+        /// A session opened with open_session. Results already returned in it
+        /// are skipped and the files they came from are favored.
+        session: Option<String>,
+        /// Also tell how each result's distance came about: the embedding
+        /// distance, the boosts and penalties applied and the filters it
+        /// passed. For tuning ranking.
+        explain: Option<bool>,
+        /// Make sure results reflect the files as they are on disk now, e.g.
+        /// right after editing them, by reindexing changed ones first.
+        require_fresh: Option<bool>,
+        /// Approximate maximum size of the results in tokens. Long snippets
+        /// are shortened and the least relevant results dropped to fit.
+        max_response_tokens: Option<usize>,
+        /// How many results to draw from each corpus instead of the closest
+        /// overall, e.g. {"code": 4, "docs": 1, "config": 1} so docs and
+        /// config aren't crowded out by code. Corpora left out aren't
+        /// searched.
+        corpus_limits: Option<HashMap<String, usize>>,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let mut options = SearchOptions {
+            session,
+            explain: explain.unwrap_or_default(),
+            languages: languages.unwrap_or_default(),
+            path_prefix: path_prefix.map(PathBuf::from),
+            exclude: exclude.unwrap_or_default(),
+            blame: blame.unwrap_or_default(),
+            require_fresh: require_fresh.unwrap_or_default(),
+            ..Default::default()
+        };
+        for kind in kinds.unwrap_or_default() {
+            options.kinds.push(ChunkKind::parse(&kind).ok_or_else(|| {
+                mcp_attr::Error::new(ErrorCode::INVALID_PARAMS)
+                    .with_message(format!("Unknown chunk kind {kind:?}"), true)
+            })?);
+        }
+        for (corpus, limit) in corpus_limits.unwrap_or_default() {
+            let corpus = Corpus::parse(&corpus).ok_or_else(|| {
+                mcp_attr::Error::new(ErrorCode::INVALID_PARAMS)
+                    .with_message(format!("Unknown corpus {corpus:?}"), true)
+            })?;
+            options.corpus_limits.push((corpus, limit));
+        }
+        let mut result = self
+            .handle_search_code(project_roots, query, options)
+            .await?;
+        if let Some(max_tokens) = max_response_tokens {
+            fit_to_budget(&mut result.chunks, max_tokens, |chunk| {
+                let mut header = chunk.clone();
+                header.content = Default::default();
+                estimate_tokens(&format_chunk(header))
+            });
+        }
+        let mut chunks: Vec<String> = result.chunks.into_iter().map(format_chunk).collect();
+        if result.partial {
+            chunks.push("Results are partial, the search ran out of time.".to_string());
+        }
+        // For reports of bad results, the daemon logs searches with it.
+        chunks.push(format!("Request id: {}", result.request_id));
+        Ok(chunks)
+    }
+
+    /// Gather the code relevant to a question into a single block of context,
+    /// with overlapping results merged and ordered by file and position.
+    #[tool]
+    async fn gather_context(
+        &self,
+        /// The root paths of the projects to search. An empty list searches
+        /// every project that has been searched before.
+        project_roots: Vec<String>,
+        /// The question the context should help answer.
+        question: String,
+        /// Approximate maximum size of the context in tokens.
+        token_budget: usize,
+    ) -> mcp_attr::Result<String> {
+        let result = self
+            .handle_search_code(project_roots, question, SearchOptions::default())
+            .await?;
+        let mut context = gather_context(&result.chunks, token_budget);
+        if result.partial {
+            context.push_str("Context is partial, the search ran out of time.\n");
+        }
+        Ok(context)
+    }
+
+    /// Read the chunks before and after a search result in its file, e.g. to
+    /// scroll through the file from a hit without reading all of it.
+    #[tool]
+    async fn chunk_neighbors(
+        &self,
+        /// The chunk id of a search result.
+        chunk_id: String,
+        /// How many chunks before it to return, 1 if omitted.
+        before: Option<usize>,
+        /// How many chunks after it to return, 1 if omitted.
+        after: Option<usize>,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let chunks = client
+            .chunk_neighbors(
+                context::current(),
+                chunk_id,
+                before.unwrap_or(1),
+                after.unwrap_or(1),
+            )
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(chunks.into_iter().map(format_chunk).collect())
+    }
+
+    /// Find the tests most likely covering a file or symbol, by their names,
+    /// what they mention and how similar their code is.
+    #[tool]
+    async fn find_tests_for(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+        /// A file path relative to the project root, or a function or type name.
+        path_or_symbol: String,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let tests = client
+            .find_tests_for(context::current(), project_root, path_or_symbol)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(tests
+            .into_iter()
+            .map(|test| {
+                let row = test
+                    .rows
+                    .map(|rows| format!(":{}", rows.start))
+                    .unwrap_or_default();
+                format!(
+                    "file://{}{} ({})",
+                    test.path.to_string_lossy(),
+                    row,
+                    test.reasons.join(", ")
+                )
+            })
+            .collect())
+    }
+
+    /// Find where the function, type or other symbol at a position of a file
+    /// is defined in the project. Best effort by name, without a language
+    /// server, so overloads and same-named symbols are all listed.
+    #[tool]
+    async fn goto_definition(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+        /// The file path relative to the project root.
+        file: String,
+        /// Zero-based row of the symbol, like in search results.
+        row: usize,
+        /// Zero-based byte column of the symbol on the row.
+        column: usize,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let definitions = client
+            .goto_definition(context::current(), project_root, file, row, column)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(definitions.iter().map(format_definition).collect())
+    }
+
+    /// Find functions, types and other definitions of a project by name,
+    /// regardless of case. Exact matches come first, then names starting with
+    /// the query.
+    #[tool]
+    async fn search_symbols(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+        /// Part of the name to look for.
+        query: String,
+        /// Maximum number of symbols, 20 by default.
+        limit: Option<usize>,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let definitions = client
+            .search_symbols(
+                context::current(),
+                project_root,
+                query,
+                limit.unwrap_or(SYMBOL_LIMIT),
+            )
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(definitions.iter().map(format_definition).collect())
+    }
+
+    /// List the functions, types and other definitions of a file in order,
+    /// for an overview without reading the file.
+    #[tool]
+    async fn outline(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+        /// The file path relative to the project root.
+        file: String,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let definitions = client
+            .outline(context::current(), project_root, file)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(definitions.iter().map(format_definition).collect())
+    }
+
+    /// Search the commit messages of a project's git repository, e.g. for the
+    /// commit that introduced a feature.
+    #[tool]
+    async fn search_history(
+        &self,
+        /// The root path of the project.
         project_root: String,
         /// The query to search for.
         query: String,
     ) -> mcp_attr::Result<Vec<String>> {
-        match self.handle_search_code(project_root, query).await {
-            Ok(result) => Ok(result
-                .into_iter()
-                .map(|chunk| {
-                    format!(
-                        "file://{}:{}:{}-{}:{} contains:\n{}",
-                        chunk.path.to_string_lossy(),
-                        chunk.row.start,
-                        chunk.column.start,
-                        chunk.row.end,
-                        chunk.column.end,
-                        chunk.content
-                    )
-                })
-                .collect()),
-            Err(e) => {
-                std::fs::write(
-                    "/Users/katis/code/local-code-search/code_search.log",
-                    format!("error: {e:?}"),
+        let client = self.connect().await?;
+        let commits = client
+            .search_history(context::current(), project_root, query)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(commits
+            .into_iter()
+            .map(|found| {
+                let commit = found.commit;
+                let mut text = format!(
+                    "commit {} by {} on {}\n{}",
+                    commit.sha, commit.author, commit.date, commit.message
+                );
+                if !commit.files.is_empty() {
+                    text.push_str("\n\nchanged files:\n");
+                    text.push_str(&commit.files.join("\n"));
+                }
+                text
+            })
+            .collect())
+    }
+
+    /// List the TODO, FIXME, HACK and XXX comments of a project.
+    #[tool]
+    async fn list_todos(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+        /// Only list notes with this tag, e.g. "FIXME", or containing this text.
+        filter: Option<String>,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let todos = client
+            .list_todos(context::current(), project_root, filter.unwrap_or_default())
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(todos
+            .into_iter()
+            .map(|todo| {
+                format!(
+                    "file://{}:{} {}: {}",
+                    todo.path.to_string_lossy(),
+                    todo.row,
+                    todo.tag,
+                    todo.text
                 )
-                .unwrap();
-                Err(e)
-            }
-        }
+            })
+            .collect())
+    }
+
+    /// Show which languages of a project are indexed, which files are
+    /// skipped and why, and how much of the indexed files chunks cover, to
+    /// tell whether a search could have missed something.
+    #[tool]
+    async fn coverage(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+    ) -> mcp_attr::Result<String> {
+        let client = self.connect().await?;
+        let coverage = client
+            .coverage(context::current(), project_root)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(format_coverage(&coverage))
+    }
+
+    /// Estimate how long indexing a project would take and how much memory
+    /// and disk it would use, without indexing it. Worth checking for large
+    /// projects before searching them the first time.
+    #[tool]
+    async fn estimate_index(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+    ) -> mcp_attr::Result<String> {
+        let client = self.connect().await?;
+        let estimate = client
+            .estimate_index(context::current(), project_root)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(format_estimate(&estimate))
+    }
+
+    /// List the projects the daemon has registered, with their file counts
+    /// and how up to date their indexes are. Use their roots as project_root.
+    #[tool]
+    async fn list_projects(&self) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let projects = client
+            .list_projects(context::current())
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(projects.iter().map(format_project).collect())
+    }
+
+    /// List the daemon's notifications after an id, e.g. that a project
+    /// finished indexing or a file failed to parse. Without an id, waits
+    /// briefly for new ones. Pass the last id seen to get the rest.
+    #[tool]
+    async fn notifications(
+        &self,
+        /// The id of the last notification seen.
+        after: Option<u64>,
+    ) -> mcp_attr::Result<Vec<String>> {
+        let client = self.connect().await?;
+        let mut ctx = context::current();
+        ctx.deadline = Instant::now() + NOTIFICATION_WAIT;
+        let notifications = client
+            .subscribe(ctx, after)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(notifications.iter().map(format_notification).collect())
+    }
+
+    /// Register a project so it's indexed before it's first searched. With a
+    /// git revision, e.g. "origin/main" or a commit, the files are indexed
+    /// as of that revision without checking it out, e.g. to review a branch.
+    #[tool]
+    async fn register_project(
+        &self,
+        /// The root path of the project.
+        project_root: String,
+        /// A branch, tag or commit to index instead of the working tree.
+        revision: Option<String>,
+    ) -> mcp_attr::Result<String> {
+        let client = self.connect().await?;
+        let project = client
+            .register_project(context::current(), project_root, revision)
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(format!(
+            "registered {project}, pass it as the project root to search it"
+        ))
+    }
+
+    /// Start a named search session for exploring code over several
+    /// searches, or start an existing one over.
+    #[tool]
+    async fn open_session(
+        &self,
+        /// Name to pass to search_code, e.g. the task at hand.
+        name: String,
+    ) -> mcp_attr::Result<String> {
+        let client = self.connect().await?;
+        client
+            .open_session(context::current(), name.clone())
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(format!("session {name} opened"))
+    }
+
+    /// End a search session opened with open_session.
+    #[tool]
+    async fn close_session(&self, name: String) -> mcp_attr::Result<String> {
+        let client = self.connect().await?;
+        client
+            .close_session(context::current(), name.clone())
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        Ok(format!("session {name} closed"))
+    }
+
+    /// Report the memory and index size the daemon uses for the given project.
+    #[tool]
+    async fn resource_usage(
+        &self,
+        /// The root path of a project that has been searched before.
+        project_root: String,
+    ) -> mcp_attr::Result<String> {
+        self.handle_resource_usage(project_root).await
     }
 }
 
+/// Code search MCP server, or a client for the daemon when given a command.
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Search projects and print the results, e.g. for editors or review
+    /// tools.
+    Search {
+        query: String,
+        /// Projects to search, every registered one if none.
+        #[arg(long = "project")]
+        project_roots: Vec<PathBuf>,
+        /// Number of results, `search.top_k` if unset.
+        #[arg(long)]
+        limit: Option<usize>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        /// Reindex the files of results that changed since they were indexed
+        /// before answering.
+        #[arg(long)]
+        require_fresh: bool,
+        /// Draw this many results from a corpus, e.g. `--corpus code=4
+        /// --corpus docs=1`, instead of the closest overall. Corpora are
+        /// code, docs and config.
+        #[arg(long = "corpus", value_parser = parse_corpus_limit)]
+        corpus_limits: Vec<(Corpus, usize)>,
+    },
+    /// Drop index entries of deleted files and reclaim the freed space.
+    Compact {
+        /// The root path of a project that has been searched before.
+        project_root: PathBuf,
+    },
+    /// Write every chunk of a project with its embedding to a JSON lines
+    /// file, the first line naming the model.
+    ExportEmbeddings {
+        /// The root path of a project that has been searched before.
+        project_root: PathBuf,
+        output: PathBuf,
+    },
+    /// Index a project with the embeddings of an export made for the same
+    /// model, e.g. in CI, registering it first if needed.
+    ImportEmbeddings {
+        project_root: PathBuf,
+        input: PathBuf,
+    },
+    /// Show how many files of a project are waiting to be indexed.
+    QueueStatus {
+        /// The root path of a project that has been searched before.
+        project_root: PathBuf,
+    },
+    /// List the registered projects and how up to date their indexes are.
+    ListProjects,
+    /// Print the daemon's notifications as they come, e.g. when a project
+    /// finishes indexing.
+    Notifications,
+    /// Show how many inotify watches the daemon uses and whether it ran out
+    /// of them.
+    WatchStatus,
+    /// Register a project so it's indexed before it's first searched.
+    Register {
+        project_root: PathBuf,
+        /// Index a branch, tag or commit from the git object store instead
+        /// of the working tree.
+        #[arg(long)]
+        revision: Option<String>,
+    },
+    /// Pick up changes to a project's files, e.g. one that isn't watched.
+    Reindex { project_root: PathBuf },
+    /// Reindex the files that differ between two git revisions, e.g. after
+    /// switching branches.
+    ReindexChanges {
+        project_root: PathBuf,
+        from: String,
+        to: String,
+    },
+    /// Index the given files before the rest of their projects.
+    Prioritize { paths: Vec<PathBuf> },
+    /// Project how long indexing a project would take and what it would use.
+    EstimateIndex { project_root: PathBuf },
+    /// Show per-language file counts of a project and which files are left
+    /// out of its index.
+    Coverage { project_root: PathBuf },
+    /// List chunks of a project no search has returned, candidates for dead
+    /// or poorly documented code.
+    NeverMatched {
+        project_root: PathBuf,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Stop watching and indexing a project, its index is kept for a while
+    /// in case it's restored.
+    RemoveProject { project_root: PathBuf },
+    /// Register a removed project again with the index it had.
+    RestoreProject { project_root: PathBuf },
+    /// Restart the daemon, e.g. after upgrading it, keeping its projects
+    /// and their indexes.
+    Restart,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum OutputFormat {
+    #[default]
+    Text,
+    /// `path:line:column: message` lines for vim's quickfix list or emacs'
+    /// compilation mode.
+    Quickfix,
+    /// A SARIF 2.1.0 log.
+    Sarif,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    serve_stdio(CodeSearchServer).await?;
+    let config = Config::load_default()?;
+    let _telemetry = telemetry::init(&config, "code_search")?;
+    match Args::parse().command {
+        None => {
+            serve_stdio(CodeSearchServer {
+                socket_path: config.socket_path,
+                compression_bytes: config.rpc_compression_bytes,
+                auto_start: config.auto_start,
+                starting: tokio::sync::Mutex::new(()),
+            })
+            .await?
+        }
+        Some(Command::Search {
+            query,
+            project_roots,
+            limit,
+            format,
+            require_fresh,
+            corpus_limits,
+        }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let scope = if project_roots.is_empty() {
+                ProjectScope::AllRegistered
+            } else {
+                ProjectScope::Paths(
+                    project_roots
+                        .iter()
+                        .map(|root| root.to_string_lossy().into())
+                        .collect(),
+                )
+            };
+            let options = SearchOptions {
+                limit,
+                require_fresh,
+                corpus_limits,
+                ..Default::default()
+            };
+            let response = client
+                .search_projects(context::current(), scope, query.clone(), options)
+                .await??;
+            match format {
+                OutputFormat::Text => {
+                    for chunk in response.chunks {
+                        println!("{}\n", format_chunk(chunk));
+                    }
+                    println!("request id {}", response.request_id);
+                }
+                OutputFormat::Quickfix => print!("{}", result_formats::quickfix(&response.chunks)),
+                OutputFormat::Sarif => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&result_formats::sarif(&query, &response.chunks))?
+                ),
+            }
+        }
+        Some(Command::ExportEmbeddings {
+            project_root,
+            output,
+        }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            // The daemon writes the file, relative to its own directory.
+            let output = std::path::absolute(&output)?;
+            let exported = client
+                .export_embeddings(
+                    context::current(),
+                    project_root.to_string_lossy().into(),
+                    output.to_string_lossy().into(),
+                )
+                .await??;
+            println!(
+                "exported {} chunks to {}",
+                exported,
+                output.to_string_lossy()
+            );
+        }
+        Some(Command::ImportEmbeddings {
+            project_root,
+            input,
+        }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let input = std::path::absolute(&input)?;
+            let imported = client
+                .import_embeddings(
+                    context::current(),
+                    project_root.to_string_lossy().into(),
+                    input.to_string_lossy().into(),
+                )
+                .await??;
+            println!("imported {} embeddings", imported);
+        }
+        Some(Command::Compact { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let stats = client
+                .compact(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!(
+                "removed {} files and {} chunks, {} -> {} bytes",
+                stats.removed_files, stats.removed_chunks, stats.bytes_before, stats.bytes_after
+            );
+        }
+        Some(Command::QueueStatus { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let status = client
+                .queue_status(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!("pending: {}", status.pending);
+            if status.model_loading {
+                println!("waiting for the embedding model to load");
+            }
+            for path in status.next {
+                println!("next: {}", path.to_string_lossy());
+            }
+        }
+        Some(Command::ListProjects) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            for project in client.list_projects(context::current()).await?? {
+                println!("{}", format_project(&project));
+            }
+        }
+        Some(Command::Notifications) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let mut after = None;
+            loop {
+                let mut ctx = context::current();
+                ctx.deadline = Instant::now() + SUBSCRIPTION_WAIT;
+                for notification in client.subscribe(ctx, after).await?? {
+                    println!("{}", format_notification(&notification));
+                    after = Some(notification.id);
+                }
+            }
+        }
+        Some(Command::WatchStatus) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let status = client.watch_status(context::current()).await??;
+            let count = |count: Option<usize>| count.map_or("?".to_string(), |c| c.to_string());
+            println!(
+                "inotify watches: {} of {}",
+                count(status.watches),
+                count(status.max_user_watches)
+            );
+            if status.limit_reached {
+                println!("ran out of watches, raise fs.inotify.max_user_watches");
+            }
+            if let Some(fallback) = status.fallback {
+                println!("watcher failed, falling back to {:?}", fallback);
+            }
+            for (root, directories) in status.by_directory {
+                println!(
+                    "{}: watched by directory ({} directories)",
+                    root.to_string_lossy(),
+                    directories
+                );
+            }
+        }
+        Some(Command::Register {
+            project_root,
+            revision,
+        }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let project = client
+                .register_project(
+                    context::current(),
+                    project_root.to_string_lossy().into(),
+                    revision,
+                )
+                .await??;
+            println!("registered {project}");
+        }
+        Some(Command::Reindex { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            client
+                .reindex(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!("reindexing {}", project_root.to_string_lossy());
+        }
+        Some(Command::ReindexChanges {
+            project_root,
+            from,
+            to,
+        }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let changed = client
+                .reindex_changes(
+                    context::current(),
+                    project_root.to_string_lossy().into(),
+                    from,
+                    to,
+                )
+                .await??;
+            println!("reindexing {changed} changed files");
+        }
+        Some(Command::Prioritize { paths }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let paths = paths
+                .iter()
+                .map(|path| path.to_string_lossy().into())
+                .collect();
+            let prioritized = client.prioritize(context::current(), paths).await??;
+            println!("prioritized {prioritized} files");
+        }
+        Some(Command::EstimateIndex { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let estimate = client
+                .estimate_index(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!("{}", format_estimate(&estimate));
+        }
+        Some(Command::Coverage { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let coverage = client
+                .coverage(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!("{}", format_coverage(&coverage));
+        }
+        Some(Command::NeverMatched {
+            project_root,
+            limit,
+        }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            let chunks = client
+                .never_matched(
+                    context::current(),
+                    project_root.to_string_lossy().into(),
+                    limit,
+                )
+                .await??;
+            for chunk in chunks {
+                println!(
+                    "{}:{}-{} {}",
+                    chunk.path.to_string_lossy(),
+                    chunk.row.start + 1,
+                    chunk.row.end + 1,
+                    chunk.kind.as_str()
+                );
+            }
+        }
+        Some(Command::RemoveProject { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            client
+                .remove_project(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!("removed {}", project_root.to_string_lossy());
+        }
+        Some(Command::RestoreProject { project_root }) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            client
+                .restore_project(context::current(), project_root.to_string_lossy().into())
+                .await??;
+            println!("restored {}", project_root.to_string_lossy());
+        }
+        Some(Command::Restart) => {
+            let client = connect(&config.socket_path, config.rpc_compression_bytes).await?;
+            client.restart(context::current()).await??;
+            println!("restarting the daemon");
+        }
+    }
     Ok(())
 }
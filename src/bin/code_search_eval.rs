@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use local_code_search::{
+    config::Config,
+    embeddings::{
+        embedding_model_from_name,
+        eval::{EvalReport, evaluate, load_queries},
+    },
+};
+
+/// Index a project with two embedding models and compare them on an eval query set.
+#[derive(Parser)]
+struct Args {
+    /// Root of the project to index.
+    project: PathBuf,
+    /// JSON lines file of `{"query": ..., "expected": [paths relative to the project]}`.
+    #[arg(long)]
+    queries: PathBuf,
+    /// Model code of the baseline model, e.g. BAAI/bge-small-en-v1.5.
+    #[arg(long)]
+    model_a: String,
+    /// Model code of the candidate model.
+    #[arg(long)]
+    model_b: String,
+    #[arg(long, default_value_t = 5)]
+    top_k: usize,
+    /// Config file for chunking settings, defaults to the daemon's config.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    // Indexed from scratch in memory, leaving the daemon's indexes alone.
+    config.storage.index_dir = None;
+    let project = std::fs::canonicalize(&args.project)?;
+    let queries = load_queries(&args.queries)?;
+    let model_a = embedding_model_from_name(&args.model_a)?;
+    let model_b = embedding_model_from_name(&args.model_b)?;
+
+    let a = evaluate(&config, &project, model_a, &queries, args.top_k)?;
+    let b = evaluate(&config, &project, model_b, &queries, args.top_k)?;
+
+    print_report(&a, &b, args.top_k);
+    println!();
+    println!("{:<50} {:>12} {:>12}", "query", "recall a", "recall b");
+    for ((query, a), b) in queries.iter().zip(&a.results).zip(&b.results) {
+        println!(
+            "{:<50} {:>12.2} {:>12.2}",
+            truncate(&query.query, 50),
+            a.recall,
+            b.recall
+        );
+    }
+    Ok(())
+}
+
+fn print_report(a: &EvalReport, b: &EvalReport, top_k: usize) {
+    println!("{:<20} {:>30} {:>30}", "", a.model, b.model);
+    println!(
+        "{:<20} {:>30.2?} {:>30.2?}",
+        "index time", a.index_time, b.index_time
+    );
+    println!(
+        "{:<20} {:>30.3} {:>30.3}",
+        format!("recall@{top_k}"),
+        a.mean_recall(),
+        b.mean_recall()
+    );
+    println!(
+        "{:<20} {:>30.2?} {:>30.2?}",
+        "mean latency",
+        a.mean_latency(),
+        b.mean_latency()
+    );
+    println!(
+        "{:<20} {:>30.2?} {:>30.2?}",
+        "p95 latency",
+        a.p95_latency(),
+        b.p95_latency()
+    );
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => text[..index].to_string(),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use clap::Parser;
+
+use local_code_search::fixtures::{FixtureLanguage, FixtureSpec, generate_fixture};
+
+/// Generate a synthetic multi-language repository to benchmark or test
+/// indexing at a given scale.
+#[derive(Parser)]
+struct Args {
+    /// Directory to write the repository to, it must not exist yet.
+    output: PathBuf,
+    /// Number of files, copies included.
+    #[arg(long, default_value_t = 1000)]
+    files: usize,
+    /// Directory levels the files are nested in.
+    #[arg(long, default_value_t = 3)]
+    depth: usize,
+    /// Subdirectories of each directory.
+    #[arg(long, default_value_t = 4)]
+    fan_out: usize,
+    /// Share of files copying an earlier one, from 0 to 1.
+    #[arg(long, default_value_t = 0.1)]
+    duplication: f64,
+    /// Functions, or config sections, per file.
+    #[arg(long, default_value_t = 5)]
+    functions: usize,
+    /// File extensions of the languages to write, e.g. `--language rs
+    /// --language py`. All of rs, py, go, ts, java, yaml and json if unset.
+    #[arg(long = "language", value_parser = parse_language)]
+    languages: Vec<FixtureLanguage>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+fn parse_language(extension: &str) -> Result<FixtureLanguage, String> {
+    FixtureLanguage::parse(extension).ok_or_else(|| format!("unknown language {extension:?}"))
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.output.exists() {
+        bail!("{:?} already exists", args.output);
+    }
+    let spec = FixtureSpec {
+        files: args.files,
+        depth: args.depth,
+        fan_out: args.fan_out,
+        duplication: args.duplication,
+        functions: args.functions,
+        languages: if args.languages.is_empty() {
+            FixtureLanguage::ALL.to_vec()
+        } else {
+            args.languages
+        },
+        seed: args.seed,
+    };
+    let stats = generate_fixture(&spec, &args.output)?;
+    println!(
+        "wrote {} files ({} copies), {} bytes to {}",
+        stats.files,
+        stats.duplicates,
+        stats.bytes,
+        args.output.to_string_lossy()
+    );
+    Ok(())
+}
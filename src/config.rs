@@ -0,0 +1,902 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::rpc::{DEFAULT_COMPRESSION_BYTES, DEFAULT_SOCKET_PATH};
+
+const ENV_PREFIX: &str = "CODE_SEARCH_";
+
+/// Model code prefixes, lowercase, with the query and passage prefixes of
+/// the models.
+const KNOWN_PREFIXES: [(&str, &str, &str); 4] = [
+    ("intfloat/multilingual-e5", "query: ", "passage: "),
+    ("qdrant/multilingual-e5", "query: ", "passage: "),
+    (
+        "nomic-ai/nomic-embed-text",
+        "search_query: ",
+        "search_document: ",
+    ),
+    (
+        "lightonai/modernbert-embed",
+        "search_query: ",
+        "search_document: ",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Unix socket the daemon listens on and clients connect to.
+    pub socket_path: PathBuf,
+    /// Log filter in `RUST_LOG` syntax, e.g. "debug" or "info,local_code_search=trace".
+    pub log_level: String,
+    /// Maximum number of projects the daemon indexes at once, unlimited if unset.
+    pub max_projects: Option<usize>,
+    /// Maximum number of client connections served concurrently, further
+    /// clients wait until one disconnects.
+    pub max_channels: usize,
+    /// Responses and requests larger than this many bytes are sent deflated,
+    /// they're never compressed if unset.
+    pub rpc_compression_bytes: Option<usize>,
+    /// Seconds without searches, file changes or indexing after which the
+    /// daemon saves its registered projects and exits, it runs until stopped
+    /// if unset. The next daemon registers the same projects again.
+    pub idle_shutdown_secs: Option<u64>,
+    /// Whether the MCP server starts the daemon when it isn't running, e.g.
+    /// after it shut down idle.
+    pub auto_start: bool,
+    /// Whether project files are watched and reindexed on change. Projects
+    /// set to false in `projects`, e.g. read-only checkouts or CI, are
+    /// indexed once and refreshed only by `reindex`.
+    pub watch: bool,
+    pub watch_strategy: WatchStrategy,
+    /// What the daemon does when files can't be watched natively, e.g. over
+    /// the inotify limits or in a sandbox.
+    pub watch_fallback: WatchFallback,
+    /// How often files are polled for changes with `watch_fallback = "poll"`.
+    pub poll_interval_ms: u64,
+    /// How long a file emptied on disk keeps its chunks before it's indexed
+    /// empty. Build tools often truncate files and then write them again,
+    /// the rewrite replaces them as usual.
+    pub empty_file_settle_ms: u64,
+    /// Threads walking and parsing a project's files when it's registered,
+    /// as many as fit the machine if 0.
+    pub walk_threads: usize,
+    /// Whether symlinks in projects are followed, e.g. to linked vendor
+    /// trees. Files reachable through several links are indexed once either
+    /// way.
+    pub follow_symlinks: bool,
+    /// File names marking a project root. Directories below a project that
+    /// contain one, e.g. nested repositories, are projects of their own and
+    /// left out of the enclosing one.
+    pub stop_markers: Vec<String>,
+    pub submodules: SubmodulePolicy,
+    /// Bytes of file contents a project keeps in memory to show results
+    /// from, the least recently used are read from disk again when needed.
+    pub text_cache_bytes: usize,
+    pub chunking: ChunkingConfig,
+    pub preprocess: PreprocessConfig,
+    pub embedding: EmbeddingConfig,
+    pub search: SearchConfig,
+    pub storage: StorageConfig,
+    pub verify: VerifyConfig,
+    pub audit: AuditConfig,
+    pub history: HistoryConfig,
+    pub permalinks: PermalinkConfig,
+    pub query_expansion: QueryExpansionConfig,
+    pub telemetry: TelemetryConfig,
+    /// Settings for single projects keyed by project root, in the same layout
+    /// as this file, e.g. `[projects."/src/app".preprocess]`.
+    pub projects: HashMap<PathBuf, toml::Table>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            socket_path: DEFAULT_SOCKET_PATH.into(),
+            log_level: "info".to_string(),
+            max_projects: None,
+            max_channels: 10,
+            rpc_compression_bytes: Some(DEFAULT_COMPRESSION_BYTES),
+            idle_shutdown_secs: None,
+            auto_start: false,
+            watch: true,
+            watch_strategy: WatchStrategy::default(),
+            watch_fallback: WatchFallback::default(),
+            poll_interval_ms: 5000,
+            empty_file_settle_ms: 2000,
+            walk_threads: 0,
+            follow_symlinks: false,
+            stop_markers: vec![".code-search-root".to_string()],
+            submodules: SubmodulePolicy::default(),
+            text_cache_bytes: 256 * 1024 * 1024,
+            chunking: ChunkingConfig::default(),
+            preprocess: PreprocessConfig::default(),
+            embedding: EmbeddingConfig::default(),
+            search: SearchConfig::default(),
+            storage: StorageConfig::default(),
+            verify: VerifyConfig::default(),
+            audit: AuditConfig::default(),
+            history: HistoryConfig::default(),
+            permalinks: PermalinkConfig::default(),
+            query_expansion: QueryExpansionConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            projects: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// `~/.config/local-code-search/config.toml` or the platform equivalent.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("local-code-search/config.toml"))
+    }
+
+    /// Loads the config from `path`, falling back to defaults if the file
+    /// doesn't exist, and applies `CODE_SEARCH_*` environment overrides.
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).with_context(|| format!("Invalid config {:?}", path))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config {:?}", path)),
+        };
+        config.with_env_overrides(std::env::vars())?.validated()
+    }
+
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) => Self::load(&path),
+            None => Self::default().with_env_overrides(std::env::vars()),
+        }
+    }
+
+    /// Overrides settings with variables named after their key path in upper
+    /// snake case, e.g. `CODE_SEARCH_SEARCH_TOP_K` for `search.top_k`. Values
+    /// are parsed as TOML and taken as a plain string if that fails.
+    pub fn with_env_overrides(
+        self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let mut table = toml::Table::try_from(&self)?;
+        let mut overridden = Vec::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let (section, key) = key_slot(&mut table, &key.to_lowercase());
+            section.insert(key, parse_env_value(&value));
+            overridden.push(name);
+        }
+        toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Invalid environment overrides {}", overridden.join(", ")))
+    }
+
+    /// The config with the `projects` settings for `root` applied on top.
+    pub fn for_project(&self, root: &Path) -> Result<Config> {
+        let Some(settings) = self
+            .projects
+            .iter()
+            .find(|(path, _)| std::fs::canonicalize(path).as_deref().unwrap_or(path) == root)
+            .map(|(_, settings)| settings)
+        else {
+            return Ok(self.clone());
+        };
+        let mut table = toml::Table::try_from(self)?;
+        merge(&mut table, settings.clone());
+        toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Invalid settings for project {:?}", root))
+    }
+
+    /// Checks that the project settings apply cleanly, so mistakes show up
+    /// when loading rather than when the project is first searched.
+    fn validated(self) -> Result<Self> {
+        for root in self.projects.keys() {
+            self.for_project(root)?;
+        }
+        Ok(self)
+    }
+
+    /// Whether switching from `self` to `other` invalidates the stored
+    /// embeddings, so projects have to be indexed from scratch.
+    pub fn requires_reindex(&self, other: &Config) -> bool {
+        self.embedding.model != other.embedding.model
+            || self.embedding.passage_prefixes() != other.embedding.passage_prefixes()
+            || self.storage.backend != other.storage.backend
+            || self.storage.index_dir != other.storage.index_dir
+            || self.storage.encryption != other.storage.encryption
+            || self.storage.read_only != other.storage.read_only
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// OTLP gRPC endpoint spans are exported to, e.g. "http://localhost:4317".
+    /// Spans aren't exported if unset.
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Where the daemon's config comes from, so it can be reloaded with the same
+/// command-line overrides applied on top.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    /// Config file, defaults are used if unset.
+    pub path: Option<PathBuf>,
+    /// Settings taking precedence over the file and the environment, in the
+    /// same layout as the file.
+    pub overrides: toml::Table,
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config> {
+        let config = match &self.path {
+            Some(path) => Config::load(path)?,
+            None => Config::default().with_env_overrides(std::env::vars())?,
+        };
+        let mut table = toml::Table::try_from(&config)?;
+        merge(&mut table, self.overrides.clone());
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .context("Invalid command-line overrides")?;
+        config.validated()
+    }
+}
+
+fn merge(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(inner)), toml::Value::Table(value)) => merge(inner, value),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Finds the table and key an environment variable refers to. Underscores
+/// separate both sections and words, so the key is matched against the
+/// existing sections. Unknown keys end up in the innermost matching table and
+/// are rejected when deserializing.
+fn key_slot<'a>(table: &'a mut toml::Table, key: &str) -> (&'a mut toml::Table, String) {
+    let section = table
+        .iter()
+        .filter(|(_, value)| value.is_table())
+        .map(|(name, _)| name)
+        .find(|name| {
+            key.strip_prefix(name.as_str())
+                .is_some_and(|rest| rest.starts_with('_'))
+        })
+        .cloned();
+    match section {
+        Some(section) if !table.contains_key(key) => {
+            let rest = &key[section.len() + 1..];
+            let Some(toml::Value::Table(inner)) = table.get_mut(&section) else {
+                unreachable!()
+            };
+            key_slot(inner, rest)
+        }
+        _ => (table, key.to_string()),
+    }
+}
+
+fn parse_env_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkingConfig {
+    /// Maximum chunk size in characters for the AST splitter.
+    pub max_chunk_size: usize,
+    /// Lines per chunk for the line window splitter.
+    pub window_lines: usize,
+    /// Whitespace separated tokens per chunk for the token window splitter.
+    pub window_tokens: usize,
+    pub strategy: SplitterKind,
+    /// Per language overrides of `strategy`, keyed by language name (e.g. "rust").
+    pub languages: HashMap<String, SplitterKind>,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: 1000,
+            window_lines: 40,
+            window_tokens: 200,
+            strategy: SplitterKind::Ast,
+            languages: HashMap::new(),
+        }
+    }
+}
+
+impl ChunkingConfig {
+    pub fn strategy_for(&self, language: &str) -> SplitterKind {
+        self.languages
+            .get(language)
+            .copied()
+            .unwrap_or(self.strategy)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SplitterKind {
+    Ast,
+    LineWindow,
+    TokenWindow,
+}
+
+/// Changes to the text the model embeds. Search results still show the
+/// original source.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreprocessConfig {
+    /// Replace runs of whitespace with a single space.
+    pub collapse_whitespace: bool,
+    /// Leave out comments at the start of a file that mention a license or copyright.
+    pub strip_license_headers: bool,
+    pub comments: CommentMode,
+}
+
+/// How a project's files are watched. Recursive watches take an inotify
+/// watch for every directory, ignored ones like `node_modules` included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchStrategy {
+    /// Recursively, or like `directories` if that would take most of the
+    /// user's inotify watches.
+    #[default]
+    Auto,
+    Recursive,
+    /// Only the directories that aren't ignored, each on its own. New
+    /// directories are watched from the next verification pass.
+    Directories,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchFallback {
+    /// Poll the watched files every `poll_interval_ms`.
+    #[default]
+    Poll,
+    /// Stop watching, projects are refreshed by verification passes and
+    /// `reindex` only.
+    IndexOnly,
+}
+
+/// What happens to the git submodules of a project.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubmodulePolicy {
+    /// Walked like any other directory.
+    #[default]
+    Index,
+    /// Left out of the index.
+    Skip,
+    /// Left out of the project and registered as projects of their own, so
+    /// they're indexed with their own ignore files and a submodule shared
+    /// by several projects is indexed once.
+    Separate,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommentMode {
+    /// Embedded along with the code around them.
+    #[default]
+    Include,
+    /// Left out of the embedded text.
+    Exclude,
+    /// Left out of the code chunks and indexed as chunks of their own with
+    /// the `doc` kind.
+    Separate,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddingConfig {
+    /// fastembed model code, e.g. "BAAI/bge-small-en-v1.5". Defaults to fastembed's default model.
+    pub model: Option<String>,
+    /// Chunks embedded per model call. Larger batches are faster but use
+    /// more memory while they run.
+    pub batch_size: usize,
+    /// Batches of all projects that run at once, further ones wait. Bounds
+    /// peak memory while several projects index.
+    pub max_concurrent_batches: usize,
+    /// Threads ONNX Runtime computes a model call with, shared by all models
+    /// and kept apart from the threads serving requests. Half the cores if 0.
+    /// Read once at startup.
+    pub intra_op_threads: usize,
+    /// Threads running independent parts of the model graph in parallel.
+    pub inter_op_threads: usize,
+    /// Settings of single models keyed by model code, over the ones known
+    /// for them, e.g. `[embedding.models."intfloat/multilingual-e5-small"]`.
+    pub models: HashMap<String, ModelConfig>,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            model: None,
+            batch_size: 32,
+            max_concurrent_batches: 2,
+            intra_op_threads: 0,
+            inter_op_threads: 1,
+            models: HashMap::new(),
+        }
+    }
+}
+
+impl EmbeddingConfig {
+    /// The settings of the model with the code, the known ones for it with
+    /// the configured ones on top.
+    pub fn model_settings(&self, model_code: &str) -> ModelConfig {
+        let known = ModelConfig::known(model_code);
+        let configured = self
+            .models
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(model_code))
+            .map(|(_, settings)| settings.clone())
+            .unwrap_or_default();
+        ModelConfig {
+            query_prefix: configured.query_prefix.or(known.query_prefix),
+            passage_prefix: configured.passage_prefix.or(known.passage_prefix),
+            query_model: configured.query_model.or(known.query_model),
+        }
+    }
+
+    /// The configured passage prefixes, which the embeddings in indexes
+    /// depend on.
+    fn passage_prefixes(&self) -> Vec<(String, Option<&str>)> {
+        let mut prefixes: Vec<_> = self
+            .models
+            .iter()
+            .map(|(code, settings)| (code.to_lowercase(), settings.passage_prefix.as_deref()))
+            .collect();
+        prefixes.sort();
+        prefixes
+    }
+}
+
+/// How an embedding model is used. Asymmetric models are trained to embed
+/// questions and the texts answering them differently, e.g. e5 models with
+/// "query: " and "passage: " prefixes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    /// Prepended to search queries before they're embedded, "" for none.
+    pub query_prefix: Option<String>,
+    /// Prepended to the chunks before they're embedded, "" for none.
+    /// Changing it reindexes.
+    pub passage_prefix: Option<String>,
+    /// Model code of the model embedding search queries, for dual encoders
+    /// whose query and passage models differ. It must have the same
+    /// dimensions.
+    pub query_model: Option<String>,
+}
+
+impl ModelConfig {
+    /// The prefixes the models supported by fastembed were trained with.
+    fn known(model_code: &str) -> Self {
+        let model_code = model_code.to_lowercase();
+        KNOWN_PREFIXES
+            .iter()
+            .find(|(code, _, _)| model_code.starts_with(code))
+            .map(|(_, query_prefix, passage_prefix)| Self {
+                query_prefix: Some(query_prefix.to_string()),
+                passage_prefix: Some(passage_prefix.to_string()),
+                query_model: None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Number of chunks returned per search.
+    pub top_k: usize,
+    /// Time a search may take before the results found so far are returned
+    /// as partial. Requests with a closer deadline get less.
+    pub budget_ms: u64,
+    /// What the columns of results count, to match the editor showing them.
+    pub column_encoding: ColumnEncoding,
+    /// How much lower the distance of a chunk gets with an identifier made
+    /// of the query's words, e.g. `parseJwtHeader` for "parse jwt header".
+    /// Chunks with some of the words in identifiers get part of it. 0 turns
+    /// the identifier index off.
+    pub identifier_weight: f32,
+    /// How much higher the distance of a chunk containing a `-term` of the
+    /// query gets, enough by default to rank it after the rest without
+    /// dropping it.
+    pub exclusion_penalty: f32,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            top_k: 5,
+            budget_ms: 5000,
+            column_encoding: ColumnEncoding::default(),
+            identifier_weight: 0.15,
+            exclusion_penalty: 1.0,
+        }
+    }
+}
+
+/// Units of the columns of results, counted from the start of their line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColumnEncoding {
+    /// UTF-8 bytes, as tree-sitter counts them.
+    #[default]
+    Bytes,
+    /// Unicode scalar values, as most editors show them.
+    Chars,
+    /// UTF-16 code units, the default of the language server protocol.
+    Utf16,
+}
+
+impl ColumnEncoding {
+    /// The column after `line_prefix`, the text from the start of the line.
+    pub fn column(self, line_prefix: &str) -> usize {
+        match self {
+            Self::Bytes => line_prefix.len(),
+            Self::Chars => line_prefix.chars().count(),
+            Self::Utf16 => line_prefix.encode_utf16().count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Directory for on-disk SQLite indexes, one file per project and model,
+    /// so restarts only reindex the files that changed. Defaults to the
+    /// user's cache directory, indexes are kept in memory without one. The
+    /// `memory` backend keeps them in memory regardless.
+    pub index_dir: Option<PathBuf>,
+    /// Only query the existing indexes in `index_dir`, without watching or
+    /// indexing projects. For indexes built elsewhere and mounted read-only.
+    pub read_only: bool,
+    /// Where the key on-disk indexes are encrypted with comes from, indexes
+    /// are stored as plain text if unset. Needs the `encryption` feature.
+    pub encryption: Option<EncryptionKey>,
+    /// How long the on-disk index of a removed project is kept, so it can be
+    /// restored without embedding the project again.
+    pub removed_retention_secs: u64,
+}
+
+impl StorageConfig {
+    /// `~/.cache/local-code-search` or the platform equivalent.
+    pub fn default_index_dir() -> Option<PathBuf> {
+        Some(dirs::cache_dir()?.join("local-code-search"))
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            index_dir: StorageConfig::default_index_dir(),
+            read_only: false,
+            encryption: None,
+            removed_retention_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum EncryptionKey {
+    /// Read from this environment variable, e.g. `{ env = "INDEX_KEY" }`.
+    Env(String),
+    /// Printed by this command, e.g. to read it from the system keychain with
+    /// `{ command = ["secret-tool", "lookup", "service", "code-search"] }`.
+    Command(Vec<String>),
+}
+
+impl EncryptionKey {
+    pub fn resolve(&self) -> Result<String> {
+        let key = match self {
+            EncryptionKey::Env(name) => std::env::var(name)
+                .with_context(|| format!("Index key variable {} is not set", name))?,
+            EncryptionKey::Command(command) => {
+                let (program, args) = command
+                    .split_first()
+                    .context("Index key command is empty")?;
+                let output = std::process::Command::new(program)
+                    .args(args)
+                    .output()
+                    .with_context(|| format!("Failed to run index key command {:?}", program))?;
+                if !output.status.success() {
+                    anyhow::bail!("Index key command {:?} failed: {}", program, output.status);
+                }
+                String::from_utf8(output.stdout).context("Index key is not UTF-8")?
+            }
+        };
+        let key = key.trim_end_matches(['\r', '\n']);
+        if key.is_empty() {
+            anyhow::bail!("Index key is empty");
+        }
+        Ok(key.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    /// SQLite with the sqlite-vec extension for nearest neighbour search.
+    #[default]
+    Sqlite,
+    /// Embeddings kept in process memory and searched exhaustively. Has no
+    /// limits on embedding dimensions.
+    Memory,
+}
+
+/// Background passes that rehash project files and reindex the ones that
+/// changed without the watcher noticing, e.g. while the daemon was down.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerifyConfig {
+    pub enabled: bool,
+    /// Minimum time between passes.
+    pub interval_secs: u64,
+    /// A pass only starts after no searches or file changes for this long.
+    pub idle_secs: u64,
+    /// Files read per second, to keep passes from competing with other work.
+    pub files_per_second: u32,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 24 * 60 * 60,
+            idle_secs: 10 * 60,
+            files_per_second: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// File every search is appended to with its client, projects, query and
+    /// result paths. Searches aren't logged if unset.
+    pub log_path: Option<PathBuf>,
+}
+
+/// Search over the commit messages of projects in git repositories.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Only the most recent commits are indexed.
+    pub max_commits: usize,
+    /// Embed the paths of the changed files along with the message.
+    pub include_files: bool,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_commits: 5000,
+            include_files: false,
+        }
+    }
+}
+
+/// Links from search results to the lines on the git remote's web view,
+/// e.g. GitHub or GitLab, at the checked out commit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermalinkConfig {
+    pub enabled: bool,
+    /// Name of the git remote links point to.
+    pub remote: String,
+}
+
+impl Default for PermalinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            remote: "origin".to_string(),
+        }
+    }
+}
+
+/// Searches also with a code snippet a local LLM drafts for the query, which
+/// helps vague natural-language questions find code that looks nothing like
+/// them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryExpansionConfig {
+    pub enabled: bool,
+    /// Generate endpoint of an Ollama compatible server.
+    pub endpoint: String,
+    pub model: String,
+    /// Searches go ahead with the query alone if no snippet arrives in time.
+    pub timeout_ms: u64,
+}
+
+impl Default for QueryExpansionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:11434/api/generate".to_string(),
+            model: "qwen2.5-coder:1.5b".to_string(),
+            timeout_ms: 2000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn env_overrides_nested_keys() {
+        let config = Config::default()
+            .with_env_overrides(vars(&[
+                ("CODE_SEARCH_SOCKET_PATH", "/run/code_search.sock"),
+                ("CODE_SEARCH_SEARCH_TOP_K", "12"),
+                ("CODE_SEARCH_CHUNKING_STRATEGY", "line-window"),
+                ("CODE_SEARCH_CHUNKING_LANGUAGES_YAML", "token-window"),
+                ("CODE_SEARCH_EMBEDDING_MODEL", "BAAI/bge-base-en-v1.5"),
+                ("HOME", "/root"),
+            ]))
+            .unwrap();
+        assert_eq!(config.socket_path, Path::new("/run/code_search.sock"));
+        assert_eq!(config.search.top_k, 12);
+        assert_eq!(config.chunking.strategy, SplitterKind::LineWindow);
+        assert_eq!(
+            config.chunking.strategy_for("yaml"),
+            SplitterKind::TokenWindow
+        );
+        assert_eq!(
+            config.embedding.model.as_deref(),
+            Some("BAAI/bge-base-en-v1.5")
+        );
+    }
+
+    #[test]
+    fn env_overrides_reject_unknown_keys() {
+        let result =
+            Config::default().with_env_overrides(vars(&[("CODE_SEARCH_SEARCH_TOP_N", "3")]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn source_overrides_merge_into_sections() {
+        let source = ConfigSource {
+            path: None,
+            overrides: toml::from_str("watch = false\n[storage]\nindex_dir = \"/tmp/index\"")
+                .unwrap(),
+        };
+        let config = source.load().unwrap();
+        assert!(!config.watch);
+        assert_eq!(
+            config.storage.index_dir.as_deref(),
+            Some(Path::new("/tmp/index"))
+        );
+        assert_eq!(config.storage.backend, StorageBackend::Sqlite);
+    }
+
+    #[test]
+    fn project_settings_apply_to_their_root() {
+        let config: Config = toml::from_str(
+            "[preprocess]\ncollapse_whitespace = true\n[projects.\"/src/app\".preprocess]\ncomments = \"separate\"",
+        )
+        .unwrap();
+        let project = config.for_project(Path::new("/src/app")).unwrap();
+        assert_eq!(project.preprocess.comments, CommentMode::Separate);
+        assert!(project.preprocess.collapse_whitespace);
+        let other = config.for_project(Path::new("/src/other")).unwrap();
+        assert_eq!(other.preprocess.comments, CommentMode::Include);
+
+        let invalid: Config =
+            toml::from_str("[projects.\"/src/app\".preprocess]\ncomments = \"skip\"").unwrap();
+        assert!(invalid.validated().is_err());
+    }
+
+    #[test]
+    fn encryption_key_from_command() {
+        let config: Config =
+            toml::from_str("[storage.encryption]\ncommand = [\"echo\", \"s3cret\"]").unwrap();
+        let key = config.storage.encryption.unwrap().resolve().unwrap();
+        assert_eq!(key, "s3cret");
+    }
+
+    #[test]
+    fn columns_count_the_configured_units() {
+        let config: Config = toml::from_str("[search]\ncolumn_encoding = \"utf16\"").unwrap();
+        assert_eq!(config.search.column_encoding, ColumnEncoding::Utf16);
+        let prefix = "let crab = \"🦀é\"";
+        assert_eq!(ColumnEncoding::Bytes.column(prefix), 19);
+        assert_eq!(ColumnEncoding::Chars.column(prefix), 15);
+        assert_eq!(ColumnEncoding::Utf16.column(prefix), 16);
+    }
+
+    #[test]
+    fn model_settings_override_the_known_prefixes() {
+        let config: Config = toml::from_str(
+            "[embedding.models.\"intfloat/multilingual-e5-base\"]\nquery_prefix = \"\"",
+        )
+        .unwrap();
+        let e5 = config
+            .embedding
+            .model_settings("intfloat/multilingual-e5-small");
+        assert_eq!(e5.query_prefix.as_deref(), Some("query: "));
+        assert_eq!(e5.passage_prefix.as_deref(), Some("passage: "));
+        let e5 = config
+            .embedding
+            .model_settings("intfloat/multilingual-e5-base");
+        assert_eq!(e5.query_prefix.as_deref(), Some(""));
+        assert_eq!(e5.passage_prefix.as_deref(), Some("passage: "));
+        assert_eq!(
+            config.embedding.model_settings("Xenova/bge-small-en-v1.5"),
+            ModelConfig::default()
+        );
+
+        let mut changed = config.clone();
+        changed
+            .embedding
+            .models
+            .get_mut("intfloat/multilingual-e5-base")
+            .unwrap()
+            .query_prefix = Some("question: ".to_string());
+        assert!(!config.requires_reindex(&changed));
+        changed.embedding.models.insert(
+            "intfloat/multilingual-e5-base".to_string(),
+            ModelConfig {
+                passage_prefix: Some("document: ".to_string()),
+                ..ModelConfig::default()
+            },
+        );
+        assert!(config.requires_reindex(&changed));
+    }
+
+    #[test]
+    fn project_settings_override_search_weights() {
+        let config: Config = toml::from_str(
+            "[search]\nidentifier_weight = 0.2\n[projects.\"/src/config-repo\".search]\nidentifier_weight = 0.05\nexclusion_penalty = 0.5",
+        )
+        .unwrap();
+        let project = config.for_project(Path::new("/src/config-repo")).unwrap();
+        assert_eq!(project.search.identifier_weight, 0.05);
+        assert_eq!(project.search.exclusion_penalty, 0.5);
+        let other = config.for_project(Path::new("/src/app")).unwrap();
+        assert_eq!(other.search.identifier_weight, 0.2);
+        assert_eq!(other.search.exclusion_penalty, 1.0);
+    }
+
+    #[test]
+    fn only_storage_of_the_index_requires_reindex() {
+        let config = Config::default();
+        let mut changed = config.clone();
+        changed.storage.removed_retention_secs = 60;
+        assert!(!config.requires_reindex(&changed));
+        changed.storage.read_only = true;
+        assert!(config.requires_reindex(&changed));
+    }
+}
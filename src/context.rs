@@ -0,0 +1,232 @@
+use std::{fmt::Write, path::PathBuf};
+
+use crate::embeddings::{ChunkSize, ResponseChunk};
+
+/// Rough number of characters per token of source code.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens of contents every result kept by `fit_to_budget` gets at least.
+const MIN_SNIPPET_TOKENS: usize = 16;
+
+/// Marks contents shortened by `fit_to_budget`.
+const TRUNCATED: &str = "\n... (truncated)";
+
+/// A stretch of a file made of one or more merged chunks.
+struct Excerpt {
+    path: PathBuf,
+    start: (usize, usize),
+    end: (usize, usize),
+    content: String,
+}
+
+/// Stitches search results into one block of context. The most relevant
+/// chunks that fit in `token_budget` are kept, overlapping or touching
+/// chunks of a file are merged, and excerpts are ordered by file and position.
+pub fn gather_context(chunks: &[ResponseChunk], token_budget: usize) -> String {
+    let mut excerpts: Vec<Excerpt> = Vec::new();
+    let mut tokens = 0;
+    for chunk in chunks {
+        let chunk_tokens = estimate_tokens(&chunk.content);
+        if tokens + chunk_tokens > token_budget {
+            continue;
+        }
+        tokens += chunk_tokens;
+        excerpts.push(Excerpt {
+            path: chunk.path.clone(),
+            start: (chunk.row.start, chunk.column.start),
+            end: (chunk.row.end, chunk.column.end),
+            content: chunk.content.to_string(),
+        });
+    }
+
+    excerpts.sort_by(|a, b| (&a.path, a.start).cmp(&(&b.path, b.start)));
+    let mut merged: Vec<Excerpt> = Vec::new();
+    for excerpt in excerpts {
+        match merged.last_mut() {
+            Some(last) if last.path == excerpt.path && excerpt.start <= last.end => {
+                if excerpt.end > last.end
+                    && let Some(offset) = offset_of(&excerpt.content, excerpt.start, last.end)
+                {
+                    last.content.push_str(&excerpt.content[offset..]);
+                    last.end = excerpt.end;
+                }
+            }
+            _ => merged.push(excerpt),
+        }
+    }
+
+    let mut context = String::new();
+    for excerpt in merged {
+        writeln!(
+            context,
+            "{}:{}-{}\n```\n{}\n```",
+            excerpt.path.to_string_lossy(),
+            excerpt.start.0,
+            excerpt.end.0,
+            excerpt.content.trim_end_matches('\n')
+        )
+        .unwrap();
+    }
+    context
+}
+
+/// Shortens the contents of the chunks and drops the least relevant ones so
+/// that they take roughly `max_tokens`, with `overhead` giving the tokens
+/// describing a chunk besides its contents. The budget is shared evenly,
+/// chunks shorter than their share leave the rest to the others.
+pub fn fit_to_budget(
+    chunks: &mut Vec<ResponseChunk>,
+    max_tokens: usize,
+    overhead: impl Fn(&ResponseChunk) -> usize,
+) {
+    let overheads: Vec<usize> = chunks.iter().map(&overhead).collect();
+    let total: usize = chunks
+        .iter()
+        .zip(&overheads)
+        .map(|(chunk, overhead)| overhead + estimate_tokens(&chunk.content))
+        .sum();
+    if total <= max_tokens {
+        return;
+    }
+    let mut kept = chunks.len();
+    while kept > 1
+        && overheads[..kept].iter().sum::<usize>() + kept * MIN_SNIPPET_TOKENS > max_tokens
+    {
+        kept -= 1;
+    }
+    chunks.truncate(kept);
+
+    let mut remaining = max_tokens.saturating_sub(overheads[..kept].iter().sum());
+    let mut by_size: Vec<usize> = (0..kept).collect();
+    by_size.sort_by_key(|&i| chunks[i].content.len());
+    for (done, i) in by_size.into_iter().enumerate() {
+        let share = remaining / (kept - done);
+        let tokens = estimate_tokens(&chunks[i].content);
+        if tokens > share {
+            let kept_tokens = share.saturating_sub(estimate_tokens(TRUNCATED));
+            let content = truncate(&chunks[i].content, kept_tokens * CHARS_PER_TOKEN);
+            chunks[i].content = format!("{content}{TRUNCATED}").into();
+            chunks[i].size = ChunkSize::of(&chunks[i].content);
+        }
+        remaining -= tokens.min(share);
+    }
+}
+
+/// The longest prefix of `text` of at most `max_bytes` bytes, cut at the end
+/// of a line if there's one.
+fn truncate(text: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    match text[..end].rfind('\n') {
+        Some(newline) => &text[..newline],
+        None => &text[..end],
+    }
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Byte offset of the (row, column) position `target` in `content`, which
+/// starts at `start`.
+fn offset_of(content: &str, start: (usize, usize), target: (usize, usize)) -> Option<usize> {
+    let (mut row, mut column) = start;
+    for (offset, c) in content.char_indices() {
+        if (row, column) >= target {
+            return Some(offset);
+        }
+        if c == '\n' {
+            row += 1;
+            column = 0;
+        } else {
+            column += c.len_utf8();
+        }
+    }
+    ((row, column) >= target).then_some(content.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embeddings::ChunkKind;
+
+    use super::*;
+
+    fn chunk(
+        path: &str,
+        start: (usize, usize),
+        end: (usize, usize),
+        content: &str,
+    ) -> ResponseChunk {
+        ResponseChunk {
+            path: path.into(),
+            row: start.0..end.0,
+            column: start.1..end.1,
+            content: content.into(),
+            key_path: None,
+            kind: ChunkKind::Code,
+            distance: 0.0,
+            indexed_at: None,
+            stale: false,
+            blame: None,
+            permalink: None,
+            duplicates: Vec::new(),
+            explanation: None,
+            commit: None,
+            id: String::new(),
+            size: Default::default(),
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_chunks_in_file_order() {
+        let chunks = [
+            chunk("b.rs", (0, 0), (1, 0), "fn b() {}\n"),
+            chunk("a.rs", (2, 0), (4, 1), "fn second() {\n    2\n}"),
+            chunk("a.rs", (0, 0), (2, 0), "fn first() {}\n\n"),
+            chunk("a.rs", (3, 0), (3, 9), "    2"),
+        ];
+        assert_eq!(
+            gather_context(&chunks, 1000),
+            "a.rs:0-4\n```\nfn first() {}\n\nfn second() {\n    2\n}\n```\nb.rs:0-1\n```\nfn b() {}\n```\n"
+        );
+    }
+
+    #[test]
+    fn keeps_the_most_relevant_chunks_within_budget() {
+        let chunks = [
+            chunk("a.rs", (0, 0), (1, 0), "relevant\n"),
+            chunk("b.rs", (0, 0), (1, 0), &"x".repeat(100)),
+            chunk("c.rs", (0, 0), (1, 0), "also\n"),
+        ];
+        let context = gather_context(&chunks, 5);
+        assert!(context.contains("relevant") && context.contains("also"));
+        assert!(!context.contains("b.rs"));
+    }
+
+    #[test]
+    fn shortens_long_chunks_and_drops_the_last_to_fit() {
+        let line = "let x = 1;\n";
+        let mut chunks = vec![
+            chunk("a.rs", (0, 0), (1, 0), "short\n"),
+            chunk("b.rs", (0, 0), (100, 0), &line.repeat(100)),
+            chunk("c.rs", (0, 0), (100, 0), &line.repeat(100)),
+        ];
+        fit_to_budget(&mut chunks, 120, |_| 10);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&*chunks[0].content, "short\n");
+        assert!(chunks[1].content.ends_with(TRUNCATED));
+        assert!(chunks[1].content.starts_with(line));
+        assert_eq!(chunks[1].size, ChunkSize::of(&chunks[1].content));
+        assert!(chunks[1].size.lines < 100);
+        let tokens: usize = chunks
+            .iter()
+            .map(|c| 10 + estimate_tokens(&c.content))
+            .sum();
+        assert!(tokens <= 120, "{tokens}");
+
+        fit_to_budget(&mut chunks, 40, |_| 10);
+        assert_eq!(chunks.len(), 1);
+    }
+}
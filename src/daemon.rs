@@ -0,0 +1,1696 @@
+use anyhow::Result;
+use dashmap::{DashMap, Entry};
+use futures::{future, prelude::*};
+use ignore_files::IgnoreFilter;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::ErrorKind,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tarpc::{
+    context,
+    server::{self, Channel},
+};
+use tokio::sync::mpsc;
+use watchexec::{
+    ErrorHook, WatchedPath, Watchexec,
+    error::{CriticalError, FsWatcherError, RuntimeError},
+    filter::Filterer,
+    sources::fs::Watcher,
+};
+use watchexec_events::Event;
+use watchexec_filterer_ignore::IgnoreFilterer;
+use watchexec_signals::Signal;
+
+use crate::{
+    audit::{AuditEntry, AuditLog},
+    config::{Config, ConfigSource, SubmodulePolicy, WatchFallback, WatchStrategy},
+    embeddings::{
+        Boundaries, ColdChunk, CommitMatch, CompactionStats, Coverage, Definition, IndexEstimate,
+        ProjectInfo, ProjectRpcClient, ProjectService, QueueStatus, ResourceUsage, ResponseChunk,
+        SearchCodeResponse, SearchOptions, SharedModel, SymbolTable, TestMatch, Todo,
+        estimate_index, parse_chunk_id, project_directories, project_index_path,
+        purge_removed_indexes, remote_identity, resolve_commit, restore_index, submodules,
+    },
+    file_events::{changed_files, project_root_of},
+    notifications::{self, Notification, notifications_after, notify},
+    rpc::*,
+    session::Sessions,
+    socket_activation::activated_listener,
+    watch_limits::{
+        WatchStatus, count_directories, max_user_watches, over_watch_share, watches_in_use,
+    },
+};
+
+/// Time reserved for sending a search response back before the client's deadline.
+const RESPONSE_MARGIN: Duration = Duration::from_millis(200);
+
+/// Results fetched per project for searches in a session, some of them are
+/// ones the session has seen.
+const SESSION_OVERFETCH: usize = 3;
+
+/// How often the daemon checks whether it's time for a verification pass.
+const VERIFY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the daemon checks whether it's been idle long enough to exit.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the daemon checks that the directories of projects still exist.
+const AVAILABILITY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time for the response to a restart request to be sent before the daemon
+/// replaces itself.
+const RESTART_DELAY: Duration = Duration::from_millis(200);
+
+/// The daemon state and the client of the connection being served.
+#[derive(Clone)]
+struct CodeSearchServer(Arc<CodeSearchServerState>, Arc<str>);
+
+impl CodeSearchRpc for CodeSearchServer {
+    async fn search_code(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        query: String,
+        options: SearchOptions,
+    ) -> Result<SearchCodeResponse, RpcError> {
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path.clone()).await?;
+        self.search(&ctx, vec![(project_path, project_stub)], query, options)
+            .await
+    }
+
+    async fn search_projects(
+        self,
+        ctx: context::Context,
+        scope: ProjectScope,
+        query: String,
+        options: SearchOptions,
+    ) -> Result<SearchCodeResponse, RpcError> {
+        let projects = match scope {
+            ProjectScope::Paths(paths) => {
+                let mut projects = Vec::with_capacity(paths.len());
+                for path in paths {
+                    let path = self.project_key(&path)?;
+                    projects.push((path.clone(), self.project_rpc(path).await?));
+                }
+                projects
+            }
+            ProjectScope::AllRegistered => self
+                .0
+                .projects
+                .iter()
+                .filter(|project| !self.0.unavailable.contains_key(project.key()))
+                .map(|project| (project.key().clone(), project.value().clone()))
+                .collect(),
+        };
+        self.search(&ctx, projects, query, options).await
+    }
+
+    async fn resource_usage(
+        self,
+        ctx: context::Context,
+        project_path: String,
+    ) -> Result<ResourceUsage, RpcError> {
+        let project_stub = self.registered_project(project_path)?;
+        project_stub.resource_usage(child_context(&ctx)).await?
+    }
+
+    async fn compact(
+        self,
+        ctx: context::Context,
+        project_path: String,
+    ) -> Result<CompactionStats, RpcError> {
+        let project_stub = self.registered_project(project_path)?;
+        project_stub.compact(child_context(&ctx)).await?
+    }
+
+    async fn export_embeddings(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        output_path: String,
+    ) -> Result<usize, RpcError> {
+        let project_stub = self.registered_project(project_path)?;
+        project_stub
+            .export_embeddings(child_context(&ctx), output_path.into())
+            .await?
+    }
+
+    async fn import_embeddings(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        input_path: String,
+    ) -> Result<usize, RpcError> {
+        let project_path = self.project_key(&project_path)?;
+        // Not indexed before the import is loaded, its embeddings would be
+        // computed again.
+        let project_stub = self.register(project_path, None, true).await?;
+        project_stub
+            .import_embeddings(child_context(&ctx), input_path.into())
+            .await?
+    }
+
+    async fn queue_status(
+        self,
+        ctx: context::Context,
+        project_path: String,
+    ) -> Result<QueueStatus, RpcError> {
+        let project_stub = self.registered_project(project_path)?;
+        project_stub.queue_status(child_context(&ctx)).await?
+    }
+
+    async fn list_projects(self, ctx: context::Context) -> Result<Vec<ProjectInfo>, RpcError> {
+        let projects: Vec<(PathBuf, ProjectRpcClient)> = self
+            .0
+            .projects
+            .iter()
+            .map(|project| (project.key().clone(), project.value().clone()))
+            .collect();
+        let ctx = child_context(&ctx);
+        let mut infos = Vec::with_capacity(projects.len());
+        let results = future::join_all(
+            projects
+                .iter()
+                .map(|(_, project)| project.project_info(ctx)),
+        )
+        .await;
+        for ((key, _), info) in projects.iter().zip(results) {
+            let mut info = info??;
+            info.available = !self.0.unavailable.contains_key(key);
+            infos.push(info);
+        }
+        infos.sort_by(|a, b| a.root.cmp(&b.root));
+        Ok(infos)
+    }
+
+    async fn reindex_changes(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        from: String,
+        to: String,
+    ) -> Result<usize, RpcError> {
+        self.touch();
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        project_stub
+            .reindex_changes(child_context(&ctx), from, to)
+            .await?
+    }
+
+    async fn register_project(
+        self,
+        _ctx: context::Context,
+        project_path: String,
+        revision: Option<String>,
+    ) -> Result<String, RpcError> {
+        self.touch();
+        let root = canonical_root(&project_path)?;
+        if let Some(revision) = &revision {
+            // Checked up front, the project service can't report it.
+            let (root, revision) = (root.clone(), revision.clone());
+            tokio::task::spawn_blocking(move || resolve_commit(&root, &revision))
+                .await
+                .map_err(anyhow::Error::from)??;
+        }
+        let key = revision_key(&root, revision.as_deref());
+        self.register(root, revision, false).await?;
+        Ok(key.to_string_lossy().into())
+    }
+
+    async fn reindex(self, ctx: context::Context, project_path: String) -> Result<(), RpcError> {
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        project_stub.reindex(child_context(&ctx)).await?
+    }
+
+    async fn chunk_neighbors(
+        self,
+        ctx: context::Context,
+        chunk_id: String,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<ResponseChunk>, RpcError> {
+        let Some((path, byte)) = parse_chunk_id(&chunk_id) else {
+            return Err(anyhow::anyhow!("Invalid chunk id {:?}", chunk_id).into());
+        };
+        let Some((_, path, project)) = self.project_of_file(&path) else {
+            return Err(RpcError::UnknownProject(path.to_string_lossy().into()));
+        };
+        project
+            .chunk_neighbors(child_context(&ctx), path, byte.start, before, after)
+            .await?
+    }
+
+    async fn prioritize(
+        self,
+        ctx: context::Context,
+        paths: Vec<String>,
+    ) -> Result<usize, RpcError> {
+        let mut by_project: Vec<(PathBuf, ProjectRpcClient, Vec<PathBuf>)> = Vec::new();
+        for path in paths {
+            let Some((root, path, project)) = self.project_of_file(Path::new(&path)) else {
+                continue;
+            };
+            match by_project.iter_mut().find(|(other, _, _)| *other == root) {
+                Some((_, _, paths)) => paths.push(path),
+                None => by_project.push((root, project, vec![path])),
+            }
+        }
+        let mut prioritized = 0;
+        for (_, project, paths) in by_project {
+            prioritized += project.prioritize(child_context(&ctx), paths).await??;
+        }
+        Ok(prioritized)
+    }
+
+    async fn find_tests_for(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        path_or_symbol: String,
+    ) -> Result<Vec<TestMatch>, RpcError> {
+        self.touch();
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        let limit = self.0.config.read().unwrap().search.top_k;
+        project_stub
+            .find_tests_for(child_context(&ctx), path_or_symbol, limit)
+            .await?
+    }
+
+    async fn goto_definition(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        file: String,
+        row: usize,
+        column: usize,
+    ) -> Result<Vec<Definition>, RpcError> {
+        self.touch();
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        let limit = self.0.config.read().unwrap().search.top_k;
+        project_stub
+            .goto_definition(child_context(&ctx), file, row, column, limit)
+            .await?
+    }
+
+    async fn search_symbols(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<Definition>, RpcError> {
+        self.touch();
+        let root = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(root.clone()).await?;
+        let persisted = self
+            .persisted_symbols(root, {
+                let query = query.clone();
+                move |symbols, _| symbols.search(&query, limit)
+            })
+            .await?;
+        match persisted {
+            Some(definitions) => Ok(definitions),
+            None => {
+                project_stub
+                    .search_symbols(child_context(&ctx), query, limit)
+                    .await?
+            }
+        }
+    }
+
+    async fn outline(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        file: String,
+    ) -> Result<Vec<Definition>, RpcError> {
+        self.touch();
+        let root = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(root.clone()).await?;
+        let persisted = self
+            .persisted_symbols(root, {
+                let file = file.clone();
+                move |symbols, root| symbols.outline(&root.join(&file))
+            })
+            .await?;
+        match persisted {
+            Some(definitions) => Ok(definitions),
+            None => project_stub.outline(child_context(&ctx), file).await?,
+        }
+    }
+
+    async fn search_history(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        query: String,
+    ) -> Result<Vec<CommitMatch>, RpcError> {
+        self.touch();
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        let limit = self.0.config.read().unwrap().search.top_k;
+        project_stub
+            .search_history(child_context(&ctx), query, limit)
+            .await?
+    }
+
+    async fn list_todos(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        filter: String,
+    ) -> Result<Vec<Todo>, RpcError> {
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        project_stub.list_todos(child_context(&ctx), filter).await?
+    }
+
+    async fn coverage(
+        self,
+        ctx: context::Context,
+        project_path: String,
+    ) -> Result<Coverage, RpcError> {
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        project_stub.coverage(child_context(&ctx)).await?
+    }
+
+    async fn never_matched(
+        self,
+        ctx: context::Context,
+        project_path: String,
+        limit: usize,
+    ) -> Result<Vec<ColdChunk>, RpcError> {
+        let project_path = self.project_key(&project_path)?;
+        let project_stub = self.project_rpc(project_path).await?;
+        project_stub
+            .never_matched(child_context(&ctx), limit)
+            .await?
+    }
+
+    async fn remove_project(
+        self,
+        ctx: context::Context,
+        project_path: String,
+    ) -> Result<(), RpcError> {
+        // The project may be removed because its directory is gone.
+        let root =
+            std::fs::canonicalize(&project_path).unwrap_or_else(|_| project_path.clone().into());
+        let Some((root, project_stub)) = self.0.projects.remove(&root) else {
+            return Err(RpcError::UnknownProject(project_path));
+        };
+        self.0.unavailable.remove(&root);
+        self.0.watched.remove(&root);
+        self.update_watched_paths();
+
+        project_stub.remove(child_context(&ctx)).await??;
+        tracing::info!("removed project {:?}", root);
+        self.purge_removed_indexes();
+        Ok(())
+    }
+
+    async fn restore_project(
+        self,
+        _ctx: context::Context,
+        project_path: String,
+    ) -> Result<(), RpcError> {
+        let root = canonical_root(&project_path)?;
+        if self.0.projects.contains_key(&root) {
+            return Err(anyhow::anyhow!("Project {:?} is registered", root).into());
+        }
+        self.purge_removed_indexes();
+        let config = self.0.config.read().unwrap().for_project(&root)?;
+        let Some(index_path) = project_index_path(&config, &root)? else {
+            return Err(anyhow::anyhow!("Only projects with an index_dir can be restored").into());
+        };
+        if !restore_index(&index_path)? {
+            return Err(anyhow::anyhow!("No removed index of {:?} to restore", root).into());
+        }
+        tracing::info!("restored project {:?}", root);
+        self.project_rpc(root).await?;
+        Ok(())
+    }
+
+    async fn estimate_index(
+        self,
+        _ctx: context::Context,
+        project_path: String,
+    ) -> Result<IndexEstimate, RpcError> {
+        let root = canonical_root(&project_path)?;
+        let config = self.0.config.read().unwrap().for_project(&root)?;
+        let model = SharedModel::from_config(&config)?;
+        let estimate = tokio::task::spawn_blocking(move || {
+            estimate_index(&root, &config, model.dimensions(), model.file_size())
+        })
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(estimate)
+    }
+
+    async fn subscribe(
+        self,
+        ctx: context::Context,
+        after: Option<u64>,
+    ) -> Result<Vec<Notification>, RpcError> {
+        Ok(notifications_after(after, ctx.deadline - RESPONSE_MARGIN).await)
+    }
+
+    async fn watch_status(self, _ctx: context::Context) -> Result<WatchStatus, RpcError> {
+        let fallback = self
+            .0
+            .watcher_failed
+            .load(Ordering::SeqCst)
+            .then(|| self.0.config.read().unwrap().watch_fallback);
+        let by_directory = self
+            .0
+            .watched
+            .iter()
+            .filter_map(|project| match project.value() {
+                ProjectWatch::Recursive => None,
+                ProjectWatch::Directories(directories) => {
+                    Some((project.key().clone(), directories.len()))
+                }
+            })
+            .collect();
+        Ok(WatchStatus {
+            watches: watches_in_use(),
+            max_user_watches: max_user_watches(),
+            limit_reached: self.0.watch_limit_reached.load(Ordering::SeqCst),
+            by_directory,
+            fallback,
+        })
+    }
+
+    async fn open_session(self, _ctx: context::Context, name: String) -> Result<(), RpcError> {
+        self.0.sessions.lock().unwrap().open(name);
+        Ok(())
+    }
+
+    async fn close_session(self, _ctx: context::Context, name: String) -> Result<(), RpcError> {
+        if !self.0.sessions.lock().unwrap().close(&name) {
+            return Err(RpcError::UnknownSession(name));
+        }
+        Ok(())
+    }
+
+    async fn restart(self, ctx: context::Context) -> Result<(), RpcError> {
+        if self.0.stopping.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let saved = self.save_projects(&child_context(&ctx)).await?;
+        tracing::info!("restarting with {} projects", saved);
+        let server = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(RESTART_DELAY).await;
+            // A socket-activated daemon keeps listening on the socket passed
+            // to it across the exec.
+            server.remove_socket();
+            let error = exec_daemon();
+            // The projects are registered again once the daemon is started.
+            tracing::error!("failed to restart: {:?}", error);
+            std::process::exit(1);
+        });
+        Ok(())
+    }
+}
+
+impl CodeSearchServer {
+    /// Searches the projects in parallel and merges their results by distance.
+    /// Projects that fail or don't answer within the budget are left out and
+    /// the response is marked partial.
+    async fn search(
+        &self,
+        ctx: &context::Context,
+        projects: Vec<(PathBuf, ProjectRpcClient)>,
+        query: String,
+        mut options: SearchOptions,
+    ) -> Result<SearchCodeResponse, RpcError> {
+        self.touch();
+        let search = self.0.config.read().unwrap().search.clone();
+        let requested = options.clone();
+        let limit = requested.limit.unwrap_or(search.top_k);
+        if let Some(name) = &options.session {
+            if self.0.sessions.lock().unwrap().get_mut(name).is_none() {
+                return Err(RpcError::UnknownSession(name.clone()));
+            }
+            // Make up for the results the session has already seen.
+            options.limit = Some(limit * SESSION_OVERFETCH);
+            for (_, limit) in &mut options.corpus_limits {
+                *limit *= SESSION_OVERFETCH;
+            }
+        }
+        let remaining = ctx
+            .deadline
+            .saturating_duration_since(Instant::now())
+            .saturating_sub(RESPONSE_MARGIN);
+        let budget = Duration::from_millis(search.budget_ms).min(remaining);
+
+        let ctx = child_context(ctx);
+        let results = future::join_all(projects.iter().map(|(_, project)| {
+            tokio::time::timeout(
+                budget,
+                project.search_code(ctx, query.clone(), options.clone(), budget),
+            )
+        }))
+        .await;
+
+        let mut response = SearchCodeResponse {
+            request_id: ctx.trace_id().to_string(),
+            ..Default::default()
+        };
+        let mut responses = Vec::with_capacity(results.len());
+        let mut failure = None;
+        for ((root, _), result) in projects.iter().zip(results) {
+            let error = match result {
+                Ok(Ok(Ok(project_response))) => {
+                    response.partial |= project_response.partial;
+                    responses.push(project_response);
+                    continue;
+                }
+                Ok(Ok(Err(e))) => e,
+                Ok(Err(e)) => e.into(),
+                Err(_) => {
+                    response.partial = true;
+                    continue;
+                }
+            };
+            tracing::warn!("searching {:?} failed: {}", root, error);
+            response.partial = true;
+            failure.get_or_insert(error);
+        }
+        // Only fail when no project answered, e.g. while the model loads.
+        if responses.is_empty()
+            && let Some(error) = failure
+        {
+            return Err(error);
+        }
+        response.model = responses.first().and_then(|first| first.model.clone());
+        if responses.iter().all(|other| other.model == response.model) {
+            response.chunks = responses.into_iter().flat_map(|r| r.chunks).collect();
+            response
+                .chunks
+                .sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        } else {
+            // Distances of different models don't compare, so the projects'
+            // results take turns, best first.
+            response.model = None;
+            let mut ranked: Vec<(usize, ResponseChunk)> = responses
+                .into_iter()
+                .flat_map(|r| r.chunks.into_iter().enumerate())
+                .collect();
+            ranked.sort_by(|(a_rank, a), (b_rank, b)| {
+                a_rank.cmp(b_rank).then(a.distance.total_cmp(&b.distance))
+            });
+            response.chunks = ranked.into_iter().map(|(_, chunk)| chunk).collect();
+        }
+        if let Some(name) = &options.session
+            && let Some(session) = self.0.sessions.lock().unwrap().get_mut(name)
+        {
+            session.apply(&mut response.chunks);
+            requested.limit_results(&mut response.chunks, limit);
+            session.record(&response.chunks);
+        }
+        requested.limit_results(&mut response.chunks, limit);
+        tracing::info!(
+            request_id = %response.request_id,
+            results = response.chunks.len(),
+            partial = response.partial,
+            "searched {} projects",
+            projects.len()
+        );
+        self.audit(
+            projects.into_iter().map(|(root, _)| root).collect(),
+            query,
+            &response,
+        );
+        Ok(response)
+    }
+
+    fn audit(&self, projects: Vec<PathBuf>, query: String, response: &SearchCodeResponse) {
+        let audit_log = self.0.audit_log.read().unwrap();
+        let Some(audit_log) = audit_log.as_ref() else {
+            return;
+        };
+        let entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            client: self.1.to_string(),
+            request_id: response.request_id.clone(),
+            projects,
+            query,
+            results: response
+                .chunks
+                .iter()
+                .map(|chunk| chunk.path.clone())
+                .collect(),
+        };
+        if let Err(e) = audit_log.record(&entry) {
+            tracing::error!("failed to write the audit log: {:?}", e);
+        }
+    }
+
+    async fn file_created_or_modified(&self, path: PathBuf) {
+        tracing::debug!("file_created_or_modified: {:?}", path);
+        self.touch();
+        // Indexing stopped, the next daemon finds the change.
+        if self.0.stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        let Some((_, path, project_stub)) = self.project_of_file(&path) else {
+            return;
+        };
+        match project_stub
+            .file_updated(context::current(), path.clone())
+            .await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                tracing::error!("error: {:?}", e);
+                notify(notifications::Event::FileFailed {
+                    path,
+                    error: e.to_string(),
+                });
+            }
+            Err(e) => tracing::error!("error: {:?}", e),
+        }
+    }
+
+    /// Reloads the config file and hands the new settings to running projects,
+    /// keeping their indexes warm unless the embeddings are invalidated.
+    async fn reload_config(&self) {
+        let config = match self.0.config_source.load() {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("keeping the current config: {:?}", e);
+                return;
+            }
+        };
+        let previous = self.0.config.read().unwrap().clone();
+        if *previous == config {
+            return;
+        }
+        if previous.audit != config.audit {
+            *self.0.audit_log.write().unwrap() = match open_audit_log(&config) {
+                Ok(audit_log) => audit_log,
+                Err(e) => {
+                    tracing::error!("searches aren't audited: {:?}", e);
+                    None
+                }
+            };
+        }
+        *self.0.config.write().unwrap() = Arc::new(config.clone());
+        let projects: Vec<ProjectRpcClient> = self
+            .0
+            .projects
+            .iter()
+            .map(|project| project.value().clone())
+            .collect();
+        for project in projects {
+            match project
+                .reload_config(context::current(), config.clone())
+                .await
+            {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!("error: {:?}", e),
+                Err(e) => tracing::error!("error: {:?}", e),
+            }
+        }
+        tracing::info!("reloaded config from {:?}", self.0.config_source.path);
+    }
+
+    /// Deletes the indexes of projects removed longer ago than the retention.
+    fn purge_removed_indexes(&self) {
+        let storage = self.0.config.read().unwrap().storage.clone();
+        let Some(index_dir) = &storage.index_dir else {
+            return;
+        };
+        let retention = Duration::from_secs(storage.removed_retention_secs);
+        match purge_removed_indexes(index_dir, retention) {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("deleted {} indexes of removed projects", purged),
+            Err(e) => tracing::warn!("error deleting indexes of removed projects: {:?}", e),
+        }
+    }
+
+    /// Marks the daemon as busy, postponing verification passes.
+    fn touch(&self) {
+        *self.0.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Runs verification passes over all projects at the configured interval,
+    /// once the daemon has been idle long enough.
+    async fn verify_when_idle(self) {
+        let mut last_pass = Instant::now();
+        loop {
+            tokio::time::sleep(VERIFY_CHECK_INTERVAL).await;
+            let verify = self.0.config.read().unwrap().verify.clone();
+            let idle = self.0.last_activity.lock().unwrap().elapsed();
+            if !verify.enabled
+                || last_pass.elapsed() < Duration::from_secs(verify.interval_secs)
+                || idle < Duration::from_secs(verify.idle_secs)
+            {
+                continue;
+            }
+            last_pass = Instant::now();
+            let projects: Vec<ProjectRpcClient> = self
+                .0
+                .projects
+                .iter()
+                .filter(|project| !self.0.unavailable.contains_key(project.key()))
+                .map(|project| project.value().clone())
+                .collect();
+            tracing::info!("verifying {} projects", projects.len());
+            self.rewatch_directories().await;
+            for project in projects {
+                match project
+                    .verify(context::current(), verify.files_per_second)
+                    .await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::error!("error: {:?}", e),
+                    Err(e) => tracing::error!("error: {:?}", e),
+                }
+            }
+        }
+    }
+
+    /// Marks projects whose directory was deleted or unmounted unavailable,
+    /// which stops watching them, and brings them back once it reappears.
+    async fn check_availability(self) {
+        loop {
+            tokio::time::sleep(AVAILABILITY_CHECK_INTERVAL).await;
+            let projects: Vec<(PathBuf, ProjectRpcClient)> = self
+                .0
+                .projects
+                .iter()
+                .map(|project| (project.key().clone(), project.value().clone()))
+                .collect();
+            for (key, project) in projects {
+                // Revisions are read from the repository at their root.
+                let root = match project.project_info(context::current()).await {
+                    Ok(Ok(info)) => info.root,
+                    _ => continue,
+                };
+                // Hung network mounts block.
+                let available = tokio::task::spawn_blocking(move || root.is_dir())
+                    .await
+                    .unwrap_or(true);
+                match (available, self.0.unavailable.contains_key(&key)) {
+                    (false, false) => self.project_gone(key),
+                    (true, true) => self.project_back(key, project).await,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn project_gone(&self, key: PathBuf) {
+        tracing::warn!(
+            "the directory of {:?} is gone, it's unavailable until it's back",
+            key
+        );
+        let watched = self.0.watched.remove(&key).is_some();
+        if watched {
+            self.update_watched_paths();
+        }
+        self.0.unavailable.insert(key.clone(), watched);
+        notify(notifications::Event::ProjectUnavailable { project: key });
+    }
+
+    /// Watches the project again if it was and reindexes it to catch up on
+    /// what changed while it was gone.
+    async fn project_back(&self, key: PathBuf, project: ProjectRpcClient) {
+        let Some((_, watched)) = self.0.unavailable.remove(&key) else {
+            return;
+        };
+        tracing::info!("the directory of {:?} is back", key);
+        if watched {
+            let config = self.0.config.read().unwrap().clone();
+            let project_config = config.for_project(&key).ok();
+            let project_config = project_config.as_ref().unwrap_or(&config);
+            self.watch(key.clone(), project_config).await;
+        }
+        match project.reindex(context::current()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!("error: {:?}", e),
+            Err(e) => tracing::error!("error: {:?}", e),
+        }
+        notify(notifications::Event::ProjectAvailable { project: key });
+    }
+
+    /// Reads the symbols persisted in the project's on-disk index, which the
+    /// daemon has before the project service finished reading the files. None
+    /// for projects indexed in memory, the project service has those.
+    async fn persisted_symbols<T: Send + 'static>(
+        &self,
+        root: PathBuf,
+        read: impl FnOnce(&SymbolTable, &Path) -> Result<T> + Send + 'static,
+    ) -> Result<Option<T>, RpcError> {
+        let config = self.0.config.read().unwrap().for_project(&root)?;
+        let found = tokio::task::spawn_blocking(move || {
+            SymbolTable::open_persisted(&config, &root)?
+                .map(|symbols| read(&symbols, &root))
+                .transpose()
+        })
+        .await
+        .map_err(anyhow::Error::from)??;
+        Ok(found)
+    }
+
+    /// Stops indexing and saves the registered projects for the next daemon
+    /// to register, returns how many.
+    async fn save_projects(&self, ctx: &context::Context) -> Result<usize, RpcError> {
+        let projects: Vec<(PathBuf, ProjectRpcClient)> = self
+            .0
+            .projects
+            .iter()
+            .map(|project| (project.key().clone(), project.value().clone()))
+            .collect();
+        let mut saved = Vec::with_capacity(projects.len());
+        for (key, project) in projects {
+            match project.flush(*ctx).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(pending)) => tracing::info!("{:?} has {} files left to index", key, pending),
+                Ok(Err(e)) => tracing::error!("error: {:?}", e),
+                Err(e) => tracing::error!("error: {:?}", e),
+            }
+            // Projects still reading their files may not answer in time,
+            // their key is the root unless it names a revision.
+            saved.push(match project.project_info(*ctx).await {
+                Ok(Ok(info)) => SavedProject {
+                    root: info.root,
+                    revision: info.revision,
+                },
+                _ if key.is_dir() => SavedProject {
+                    root: key,
+                    revision: None,
+                },
+                _ => {
+                    tracing::warn!("not saving {:?} for the next daemon", key);
+                    continue;
+                }
+            });
+        }
+        let socket_path = self.0.config.read().unwrap().socket_path.clone();
+        std::fs::write(
+            registry_path(&socket_path),
+            serde_json::to_vec(&saved).map_err(anyhow::Error::from)?,
+        )
+        .map_err(anyhow::Error::from)?;
+        Ok(saved.len())
+    }
+
+    /// Removes the socket file, unless systemd or launchd bound the socket
+    /// and own it.
+    fn remove_socket(&self) {
+        if !self.0.socket_activated {
+            let socket_path = self.0.config.read().unwrap().socket_path.clone();
+            std::fs::remove_file(socket_path).ok();
+        }
+    }
+
+    /// Saves the registered projects and exits once the daemon has been idle
+    /// for `idle_shutdown_secs` with nothing left to index.
+    async fn shut_down_when_idle(self) {
+        loop {
+            tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+            let Some(idle_shutdown_secs) = self.0.config.read().unwrap().idle_shutdown_secs else {
+                continue;
+            };
+            let idle = self.0.last_activity.lock().unwrap().elapsed();
+            if idle < Duration::from_secs(idle_shutdown_secs) || self.indexing().await {
+                continue;
+            }
+            if self.0.stopping.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            match self.save_projects(&context::current()).await {
+                Ok(saved) => tracing::info!("idle, exiting with {} projects saved", saved),
+                Err(e) => tracing::error!("idle, exiting without saving the projects: {:?}", e),
+            }
+            self.remove_socket();
+            std::process::exit(0);
+        }
+    }
+
+    /// Whether any project has files left to index or waits for its model.
+    async fn indexing(&self) -> bool {
+        let projects: Vec<ProjectRpcClient> = self
+            .0
+            .projects
+            .iter()
+            .map(|project| project.value().clone())
+            .collect();
+        for project in projects {
+            match project.project_info(context::current()).await {
+                Ok(Ok(info)) if info.pending == 0 && !info.model_loading => {}
+                // Projects still reading their files don't answer yet.
+                _ => return true,
+            }
+        }
+        false
+    }
+
+    /// Registers the projects saved by the daemon that restarted into this
+    /// one or shut down idle before it.
+    async fn register_saved(self, registry: PathBuf) {
+        let saved = match std::fs::read(&registry) {
+            Ok(saved) => saved,
+            Err(e) if e.kind() == ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::error!("failed to read {:?}: {:?}", registry, e);
+                return;
+            }
+        };
+        std::fs::remove_file(&registry).ok();
+        let saved: Vec<SavedProject> = match serde_json::from_slice(&saved) {
+            Ok(saved) => saved,
+            Err(e) => {
+                tracing::error!("failed to read {:?}: {:?}", registry, e);
+                return;
+            }
+        };
+        tracing::info!(
+            "registering {} projects from before the restart",
+            saved.len()
+        );
+        for project in saved {
+            if let Err(e) = self
+                .register(project.root.clone(), project.revision, false)
+                .await
+            {
+                tracing::error!("failed to register {:?}: {:?}", project.root, e);
+            }
+        }
+    }
+
+    /// Unregisters a project whose files or index couldn't be read.
+    fn project_failed(&self, key: PathBuf, error: anyhow::Error) {
+        tracing::error!("failed to open project {:?}: {:?}", key, error);
+        self.0.projects.remove(&key);
+        if self.0.watched.remove(&key).is_some() {
+            self.update_watched_paths();
+        }
+        notify(notifications::Event::ProjectFailed {
+            project: key,
+            error: error.to_string(),
+        });
+    }
+
+    /// Fails with `RpcError::ProjectUnavailable` while the project's
+    /// directory is gone.
+    fn check_available(&self, key: &Path) -> Result<(), RpcError> {
+        if self.0.unavailable.contains_key(key) {
+            return Err(RpcError::ProjectUnavailable(key.to_string_lossy().into()));
+        }
+        Ok(())
+    }
+
+    /// Watches the directories created since projects watched directory by
+    /// directory were last walked.
+    async fn rewatch_directories(&self) {
+        let config = self.0.config.read().unwrap().clone();
+        let roots: Vec<PathBuf> = self
+            .0
+            .watched
+            .iter()
+            .filter(|project| matches!(project.value(), ProjectWatch::Directories(_)))
+            .map(|project| project.key().clone())
+            .collect();
+        for root in roots {
+            let project_config = config.for_project(&root).ok();
+            let project_config = project_config.as_ref().unwrap_or(&config);
+            self.watch_directories(root, project_config).await;
+        }
+    }
+
+    // pub fn file_deleted(&self, path: PathBuf) {
+    //     let project_stub = self.project_rpc(path.parent().unwrap().to_path_buf());
+    //     project_stub.file_deleted(context::current(), path);
+    // }
+
+    fn registered_project(&self, project_path: String) -> Result<ProjectRpcClient, RpcError> {
+        let key = self
+            .project_key(&project_path)
+            .map_err(|_| RpcError::UnknownProject(project_path.clone()))?;
+        let project = self
+            .0
+            .projects
+            .get(&key)
+            .map(|project| project.clone())
+            .ok_or(RpcError::UnknownProject(project_path))?;
+        self.check_available(&key)?;
+        Ok(project)
+    }
+
+    /// The innermost registered project containing the file, its root and
+    /// the file's path as the project knows it, see `project_root_of`.
+    fn project_of_file(&self, file_path: &Path) -> Option<(PathBuf, PathBuf, ProjectRpcClient)> {
+        let roots: Vec<PathBuf> = self
+            .0
+            .projects
+            .iter()
+            .map(|project| project.key().clone())
+            .collect();
+        let (root, path) = project_root_of(&roots, file_path)?;
+        let project = self.0.projects.get(&root)?.value().clone();
+        Some((root, path, project))
+    }
+
+    /// A registered project cloned from the same origin as `root`, e.g.
+    /// another worktree of the repository.
+    fn sibling_checkout(&self, root: &Path) -> Option<PathBuf> {
+        let origin = remote_identity(root, "origin")?;
+        let registered: Vec<PathBuf> = self
+            .0
+            .projects
+            .iter()
+            .map(|project| project.key().clone())
+            .collect();
+        registered.into_iter().find(|project| {
+            project != root && remote_identity(project, "origin").as_ref() == Some(&origin)
+        })
+    }
+
+    /// How a project is known to the daemon: `root@revision` for a registered
+    /// revision, the canonical root otherwise.
+    fn project_key(&self, project_path: &str) -> Result<PathBuf, RpcError> {
+        let key = PathBuf::from(project_path);
+        if self.0.projects.contains_key(&key) {
+            return Ok(key);
+        }
+        let root = canonical_root(project_path)?;
+        // The same project spelled in another case on a case-insensitive
+        // filesystem.
+        match self.project_of_file(&root) {
+            Some((registered, path, _)) if registered == path => Ok(registered),
+            _ => Ok(root),
+        }
+    }
+
+    /// Registers the project at `project_path` unless it is already.
+    async fn project_rpc(&self, project_path: PathBuf) -> Result<ProjectRpcClient, RpcError> {
+        self.register(project_path, None, false).await
+    }
+
+    /// Registers the project at `root`, or at a revision of it, unless it is
+    /// already. Revisions aren't watched, their files don't change. With
+    /// `defer_indexing` a new project only indexes its files once asked to
+    /// import embeddings.
+    async fn register(
+        &self,
+        root: PathBuf,
+        revision: Option<String>,
+        defer_indexing: bool,
+    ) -> Result<ProjectRpcClient, RpcError> {
+        let config = self.0.config.read().unwrap().clone();
+        let project_path = revision_key(&root, revision.as_deref());
+        // Looked up before taking the entry, which locks its shard.
+        let sibling = if self.0.projects.contains_key(&project_path) || revision.is_some() {
+            None
+        } else {
+            self.sibling_checkout(&project_path)
+        };
+        // Counted before taking the entry, `len` locks every shard.
+        let project_count = self.0.projects.len();
+        let full = config.max_projects.filter(|&max| project_count >= max);
+        let project = match (self.0.projects.entry(project_path.clone()), full) {
+            (Entry::Occupied(project), _) => {
+                self.check_available(&project_path)?;
+                return Ok(project.get().clone());
+            }
+            (Entry::Vacant(_), Some(max)) => {
+                return Err(RpcError::TooManyProjects(max));
+            }
+            (Entry::Vacant(entry), None) => {
+                let server = self.clone();
+                let key = project_path.clone();
+                entry
+                    .insert(ProjectService::start(
+                        root,
+                        config.clone(),
+                        sibling,
+                        revision.clone(),
+                        defer_indexing,
+                        move |error| server.project_failed(key, error),
+                    ))
+                    .clone()
+            }
+        };
+        if revision.is_some() {
+            return Ok(project);
+        }
+        let project_config = config.for_project(&project_path).ok();
+        let project_config = project_config.as_ref().unwrap_or(&config);
+        if project_config.submodules == SubmodulePolicy::Separate {
+            self.register_submodules(&project_path).await;
+        }
+        if !project_config.watch
+            || project_config.storage.read_only
+            || self.0.index_only.load(Ordering::SeqCst)
+        {
+            return Ok(project);
+        }
+
+        self.watch(project_path.clone(), project_config).await;
+        self.0.filter_path.send(project_path).await.ok();
+        Ok(project)
+    }
+
+    /// Starts watching the project at `root` per its `watch_strategy`.
+    async fn watch(&self, root: PathBuf, config: &Config) {
+        let by_directory = match config.watch_strategy {
+            WatchStrategy::Recursive => false,
+            WatchStrategy::Directories => true,
+            WatchStrategy::Auto => {
+                let directories = tokio::task::spawn_blocking({
+                    let root = root.clone();
+                    move || count_directories(&root)
+                })
+                .await
+                .unwrap_or_default();
+                let over = over_watch_share(directories);
+                if over {
+                    tracing::info!(
+                        "watching {:?} directory by directory, watching its {} directories recursively would take most inotify watches",
+                        root,
+                        directories
+                    );
+                }
+                over
+            }
+        };
+        if by_directory {
+            self.0
+                .watched
+                .insert(root.clone(), ProjectWatch::Directories(Vec::new()));
+            self.watch_directories(root, config).await;
+        } else {
+            self.0.watched.insert(root, ProjectWatch::Recursive);
+            self.update_watched_paths();
+        }
+    }
+
+    /// Watches the directories of a project watched directory by directory,
+    /// again to pick up new ones.
+    async fn watch_directories(&self, root: PathBuf, config: &Config) {
+        let (follow_symlinks, boundaries) = (config.follow_symlinks, Boundaries::new(config));
+        let directories = tokio::task::spawn_blocking({
+            let root = root.clone();
+            move || project_directories(&root, follow_symlinks, &boundaries)
+        })
+        .await
+        .unwrap_or_default();
+        // The project may have been removed meanwhile.
+        if let Some(mut watch) = self.0.watched.get_mut(&root) {
+            *watch = ProjectWatch::Directories(directories);
+        }
+        self.update_watched_paths();
+    }
+
+    /// Watches the config file's directory and the paths of the watched
+    /// projects, nothing once the watcher fell back to index-only mode.
+    fn update_watched_paths(&self) {
+        if self.0.index_only.load(Ordering::SeqCst) {
+            self.0.watch_config.pathset(Vec::<WatchedPath>::new());
+            return;
+        }
+        let mut paths: Vec<WatchedPath> = self
+            .0
+            .config_dir
+            .iter()
+            .map(WatchedPath::non_recursive)
+            .collect();
+        for project in self.0.watched.iter() {
+            match project.value() {
+                ProjectWatch::Recursive => paths.push(WatchedPath::recursive(project.key())),
+                ProjectWatch::Directories(directories) => {
+                    paths.extend(directories.iter().map(WatchedPath::non_recursive))
+                }
+            }
+        }
+        self.0.watch_config.pathset(paths);
+    }
+
+    /// Records that adding a watch failed for lack of inotify watches and
+    /// falls back per `watch_fallback`.
+    fn watch_limit_reached(&self, error: &FsWatcherError) {
+        if !self.0.watch_limit_reached.swap(true, Ordering::SeqCst) {
+            tracing::warn!(
+                "ran out of inotify watches with {} of {} in use by the daemon, set watch_strategy = \"directories\" to take fewer",
+                watches_in_use().map_or("?".to_string(), |watches| watches.to_string()),
+                max_user_watches().map_or("?".to_string(), |max| max.to_string()),
+            );
+        }
+        self.fall_back_from_native_watcher(error);
+    }
+
+    /// Stops relying on native file events after the watcher failed with
+    /// `error`, polling instead or leaving changes to verification passes
+    /// and `reindex` per `watch_fallback`. Only the first failure counts.
+    fn fall_back_from_native_watcher(&self, error: &FsWatcherError) {
+        if self.0.watcher_failed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let config = self.0.config.read().unwrap().clone();
+        let hint = match error {
+            FsWatcherError::TooManyWatches(_) | FsWatcherError::TooManyHandles(_) => {
+                ", raise fs.inotify.max_user_watches and fs.inotify.max_user_instances to watch natively"
+            }
+            _ => "",
+        };
+        match config.watch_fallback {
+            WatchFallback::Poll => {
+                tracing::warn!(
+                    "can't watch files ({}), polling them every {}ms instead{}",
+                    error,
+                    config.poll_interval_ms,
+                    hint
+                );
+                self.0
+                    .watch_config
+                    .file_watcher(Watcher::Poll(Duration::from_millis(
+                        config.poll_interval_ms,
+                    )));
+            }
+            WatchFallback::IndexOnly => {
+                tracing::warn!(
+                    "can't watch files ({}), projects are only refreshed by verification passes and reindex{}",
+                    error,
+                    hint
+                );
+                self.0.index_only.store(true, Ordering::SeqCst);
+                self.update_watched_paths();
+            }
+        }
+    }
+
+    /// Registers the submodules of a project as projects of their own, which
+    /// register theirs in turn.
+    async fn register_submodules(&self, root: &Path) {
+        let submodules = match submodules(root) {
+            Ok(submodules) => submodules,
+            Err(e) => {
+                tracing::warn!("not registering the submodules of {:?}: {:?}", root, e);
+                return;
+            }
+        };
+        for submodule in submodules {
+            if let Err(e) = Box::pin(self.register(submodule.clone(), None, false)).await {
+                tracing::warn!("not registering submodule {:?}: {:?}", submodule, e);
+            }
+        }
+    }
+}
+
+/// The canonical path of the project directory at `project_path`, with why
+/// it's unusable otherwise.
+fn canonical_root(project_path: &str) -> Result<PathBuf, RpcError> {
+    let path_error = |error: std::io::Error| match error.kind() {
+        ErrorKind::NotFound => RpcError::NotFound(project_path.to_string()),
+        ErrorKind::NotADirectory => RpcError::NotADirectory(project_path.to_string()),
+        ErrorKind::PermissionDenied => RpcError::PermissionDenied(project_path.to_string()),
+        _ => RpcError::Internal(format!(
+            "Invalid project path {:?}: {}",
+            project_path, error
+        )),
+    };
+    let root = std::fs::canonicalize(project_path).map_err(path_error)?;
+    if !root.is_dir() {
+        return Err(RpcError::NotADirectory(project_path.to_string()));
+    }
+    std::fs::read_dir(&root).map_err(path_error)?;
+    Ok(root)
+}
+
+/// `root@revision`, which doesn't contain the files of the root so file
+/// events aren't routed to it.
+fn revision_key(root: &Path, revision: Option<&str>) -> PathBuf {
+    match revision {
+        Some(revision) => format!("{}@{}", root.to_string_lossy(), revision).into(),
+        None => root.to_path_buf(),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MultiFilterer<F> {
+    filterers: Vec<F>,
+}
+
+impl<F: Send> MultiFilterer<F> {
+    fn new(filterers: Vec<F>) -> Self {
+        Self { filterers }
+    }
+
+    fn add(&mut self, filterer: F) {
+        self.filterers.push(filterer);
+    }
+}
+
+impl MultiFilterer<IgnoreFilterer> {
+    async fn ignore_from_origin(
+        &mut self,
+        path: &std::path::Path,
+    ) -> anyhow::Result<(), watchexec::error::RuntimeError> {
+        let (ignored, errors) = ignore_files::from_origin(path).await;
+        if !errors.is_empty() {
+            tracing::warn!("errors: {:?}", errors);
+        }
+        let filterer = IgnoreFilter::new(path, &ignored).await?;
+        self.add(IgnoreFilterer(filterer));
+        Ok(())
+    }
+}
+
+impl<F: Filterer> Filterer for MultiFilterer<F> {
+    fn check_event(
+        &self,
+        event: &Event,
+        priority: watchexec_events::Priority,
+    ) -> Result<bool, watchexec::error::RuntimeError> {
+        for filterer in self.filterers.iter() {
+            if !filterer.check_event(event, priority)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+struct CodeSearchServerState {
+    config: RwLock<Arc<Config>>,
+    config_source: ConfigSource,
+    watch_config: watchexec::Config,
+    /// The config file's directory, watched for changes to the file.
+    config_dir: Option<PathBuf>,
+    /// How the watched projects are watched, by root.
+    watched: DashMap<PathBuf, ProjectWatch>,
+    /// Projects whose directory is gone, by key, and whether they were
+    /// watched before.
+    unavailable: DashMap<PathBuf, bool>,
+    /// Whether adding a watch failed for lack of inotify watches.
+    watch_limit_reached: AtomicBool,
+    /// Whether the native file watcher failed and `watch_fallback` applies.
+    watcher_failed: AtomicBool,
+    /// Whether new projects are left unwatched after the watcher failed.
+    index_only: AtomicBool,
+    projects: DashMap<PathBuf, ProjectRpcClient>,
+    filter_path: mpsc::Sender<PathBuf>,
+    /// Last request or file change.
+    last_activity: Mutex<Instant>,
+    audit_log: RwLock<Option<AuditLog>>,
+    sessions: Mutex<Sessions>,
+    /// The daemon is restarting or shutting down, indexing stopped.
+    stopping: AtomicBool,
+    /// The socket was bound by systemd or launchd, which keep its file.
+    socket_activated: bool,
+}
+
+/// A project registered when the daemon restarted or shut down.
+#[derive(Serialize, Deserialize)]
+struct SavedProject {
+    root: PathBuf,
+    revision: Option<String>,
+}
+
+/// Where a stopping daemon saves its projects for the next one, next to
+/// the socket as there's one daemon per socket.
+fn registry_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("projects.json")
+}
+
+/// Replaces the process with the daemon binary, run with the same arguments.
+/// Run by the path it was started with rather than `current_exe`, which
+/// names the old binary once it's replaced by an upgrade.
+fn exec_daemon() -> std::io::Error {
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| "code_search_server".into());
+    std::process::Command::new(program).args(args).exec()
+}
+
+enum ProjectWatch {
+    Recursive,
+    /// Each of the project's directories on its own.
+    Directories(Vec<PathBuf>),
+}
+
+fn open_audit_log(config: &Config) -> Result<Option<AuditLog>> {
+    config
+        .audit
+        .log_path
+        .as_deref()
+        .map(AuditLog::open)
+        .transpose()
+}
+
+/// Runs the daemon until it receives a termination signal. Changes to the
+/// config file are applied without a restart.
+///
+/// Must be called inside a local task set (e.g. `#[actix::main]`), the ignore
+/// filterers are built on the current thread.
+pub async fn run(config_source: ConfigSource) -> Result<()> {
+    let config = config_source.load()?;
+    let config_path = config_source.path.clone();
+    let socket_path = config.socket_path.clone();
+    let max_channels = config.max_channels.max(1);
+    let compression_bytes = config.rpc_compression_bytes;
+    let activated = activated_listener()?;
+    if activated.is_none() {
+        std::fs::remove_file(&socket_path).ok();
+    }
+
+    // Start loading the model now, searches report it as loading until it's ready.
+    if let Err(e) = SharedModel::from_config(&config) {
+        tracing::error!("invalid embedding model: {:?}", e);
+    }
+    // Projects with a model of their own share it with the others using it.
+    for root in config.projects.keys() {
+        let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.clone());
+        if let Ok(project_config) = config.for_project(&root)
+            && project_config.embedding.model != config.embedding.model
+            && let Err(e) = SharedModel::from_config(&project_config)
+        {
+            tracing::error!("invalid embedding model for {:?}: {:?}", root, e);
+        }
+    }
+
+    let audit_log = open_audit_log(&config)?;
+
+    let watch_config = watchexec::Config::default();
+    // Watch the directory, editors often replace the file when saving.
+    let config_dir = config_path
+        .as_deref()
+        .and_then(Path::parent)
+        .map(Path::to_path_buf);
+    let (add_project_path_tx, mut add_project_path_rx) = tokio::sync::mpsc::channel::<PathBuf>(10);
+    let server = CodeSearchServer(
+        Arc::new(CodeSearchServerState {
+            config: RwLock::new(Arc::new(config)),
+            config_source,
+            watch_config: watch_config.clone(),
+            config_dir,
+            watched: DashMap::new(),
+            unavailable: DashMap::new(),
+            watch_limit_reached: AtomicBool::new(false),
+            watcher_failed: AtomicBool::new(false),
+            index_only: AtomicBool::new(false),
+            projects: DashMap::new(),
+            filter_path: add_project_path_tx,
+            last_activity: Mutex::new(Instant::now()),
+            audit_log: RwLock::new(audit_log),
+            sessions: Mutex::new(Sessions::default()),
+            stopping: AtomicBool::new(false),
+            socket_activated: activated.is_some(),
+        }),
+        "daemon".into(),
+    );
+    server.update_watched_paths();
+    tokio::spawn(server.clone().verify_when_idle());
+    tokio::spawn(server.clone().check_availability());
+    tokio::spawn(server.clone().shut_down_when_idle());
+    server.purge_removed_indexes();
+    tokio::spawn(server.clone().register_saved(registry_path(&socket_path)));
+
+    watch_config.on_action({
+        let server = server.clone();
+        move |mut action| {
+            for event in action.events.iter() {
+                tracing::debug!("event: {:?}", event);
+            }
+            for path in changed_files(&action.events) {
+                let server = server.clone();
+                if config_path.as_ref() == Some(&path) {
+                    tokio::spawn(async move { server.reload_config().await });
+                } else {
+                    tokio::spawn(async move { server.file_created_or_modified(path).await });
+                }
+            }
+            if action.signals().any(|sig| {
+                matches!(
+                    sig,
+                    Signal::Interrupt | Signal::Terminate | Signal::ForceStop
+                )
+            }) {
+                action.quit();
+            }
+            action
+        }
+    });
+    watch_config.on_error({
+        let server = server.clone();
+        move |hook: ErrorHook| match &hook.error {
+            RuntimeError::FsWatcher {
+                kind: Watcher::Native,
+                err: error @ (FsWatcherError::TooManyWatches(_) | FsWatcherError::TooManyHandles(_)),
+            } => server.watch_limit_reached(error),
+            RuntimeError::FsWatcher {
+                kind: Watcher::Native,
+                err: error @ FsWatcherError::PathAdd { err, .. },
+            } if is_watch_limit(err) => server.watch_limit_reached(error),
+            error => tracing::error!("watcher error: {}", error),
+        }
+    });
+
+    let codec = move || CompressedJson::new(compression_bytes);
+    let mut listener = match activated {
+        Some(listener) => {
+            let listener = tokio::net::UnixListener::from_std(listener)?;
+            if listener.local_addr()?.as_pathname() != Some(&socket_path) {
+                tracing::warn!(
+                    "listening on {:?} from the service manager, clients connect to {:?}",
+                    listener.local_addr()?,
+                    socket_path
+                );
+            }
+            tarpc::serde_transport::unix::listen_on(listener, codec).await?
+        }
+        None => tarpc::serde_transport::unix::listen(&socket_path, codec).await?,
+    };
+    listener.config_mut().max_frame_length(usize::MAX);
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            listener
+                .filter_map(|r| future::ready(r.ok()))
+                .map(move |transport| {
+                    let client = match transport.get_ref().peer_cred() {
+                        Ok(cred) => match cred.pid() {
+                            Some(pid) => format!("pid {} uid {}", pid, cred.uid()),
+                            None => format!("uid {}", cred.uid()),
+                        },
+                        Err(_) => "unknown".to_string(),
+                    };
+                    tracing::debug!("new channel from {}", client);
+                    let server = CodeSearchServer(server.0.clone(), client.into());
+                    server::BaseChannel::with_defaults(transport)
+                        .execute(server.clone().serve())
+                        .for_each(move |response| {
+                            // Any request keeps the daemon from shutting down idle.
+                            server.touch();
+                            spawn(response)
+                        })
+                })
+                .buffer_unordered(max_channels)
+                .for_each(|_| async {})
+                .await;
+        }
+    });
+
+    tokio::task::spawn_local({
+        let watch_config = watch_config.clone();
+        async move {
+            // the internals of ignore_files are not Send, so we spawn a task in
+            // the current thread and communicate with the Server task via a channel
+            let mut filterers = MultiFilterer::new(vec![]);
+            while let Some(path) = add_project_path_rx.recv().await {
+                if let Err(e) = filterers.ignore_from_origin(&path).await {
+                    tracing::error!("error: {:?}", e);
+                }
+                watch_config.filterer(filterers.clone());
+            }
+        }
+    });
+
+    // The watcher is created again after falling back if the native one
+    // can't be created at all.
+    loop {
+        let wx = Watchexec::with_config(watch_config.clone())?;
+        match wx.main().await? {
+            Err(CriticalError::FsWatcherInit {
+                kind: Watcher::Native,
+                err,
+            }) if !server.0.watcher_failed.load(Ordering::SeqCst) => match err {
+                FsWatcherError::TooManyWatches(_) | FsWatcherError::TooManyHandles(_) => {
+                    server.watch_limit_reached(&err)
+                }
+                _ => server.fall_back_from_native_watcher(&err),
+            },
+            result => break result?,
+        }
+    }
+    tracing::info!("watchexec exited");
+
+    Ok(())
+}
+
+/// Whether adding a watch failed because of the inotify limits.
+fn is_watch_limit(error: &notify::Error) -> bool {
+    match &error.kind {
+        notify::ErrorKind::MaxFilesWatch => true,
+        notify::ErrorKind::Io(error) => matches!(error.raw_os_error(), Some(24 | 28)),
+        _ => false,
+    }
+}
+
+/// Context for requests made while serving `ctx`. Keeps its deadline and
+/// trace id but takes the span of the current one, so project spans nest
+/// under the daemon's request span and are logged with the same id.
+fn child_context(ctx: &context::Context) -> context::Context {
+    let mut child = context::current();
+    child.deadline = ctx.deadline;
+    child.trace_context.trace_id = ctx.trace_context.trace_id;
+    child
+}
+
+async fn spawn(fut: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(fut);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unusable_project_paths_have_typed_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let file = dir.path().join("file.rs");
+        std::fs::write(&file, "").unwrap();
+
+        let root = |path: &Path| canonical_root(&path.to_string_lossy());
+        assert_eq!(
+            root(dir.path()).unwrap(),
+            dir.path().canonicalize().unwrap()
+        );
+        assert!(matches!(root(&missing), Err(RpcError::NotFound(_))));
+        assert!(matches!(root(&file), Err(RpcError::NotADirectory(_))));
+        assert!(matches!(
+            root(&file.join("lib.rs")),
+            Err(RpcError::NotADirectory(_))
+        ));
+    }
+}
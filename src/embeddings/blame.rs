@@ -0,0 +1,70 @@
+use std::{ops::RangeInclusive, path::Path, process::Command};
+
+use serde::{Deserialize, Serialize};
+
+/// The last commit that changed a range of lines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Blame {
+    pub commit: String,
+    pub author: String,
+    /// Author date in ISO 8601, e.g. "2025-03-18T10:04:11+01:00".
+    pub date: String,
+    /// First line of the commit message.
+    pub summary: String,
+}
+
+/// Asks git for the last commit that changed the zero-based `rows` of the
+/// file as of `commit`, or `HEAD`. None if the file isn't tracked by git.
+pub fn last_change(
+    path: &Path,
+    rows: RangeInclusive<usize>,
+    commit: Option<&str>,
+) -> Option<Blame> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path.parent()?)
+        .args(["log", "-1", "--no-patch", "--format=%H%x00%an%x00%aI%x00%s"])
+        .args(commit)
+        .arg(format!(
+            "-L{},{}:{}",
+            rows.start() + 1,
+            rows.end() + 1,
+            path.to_string_lossy()
+        ))
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_log(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_log(output: &str) -> Option<Blame> {
+    let mut fields = output.lines().next()?.splitn(4, '\0');
+    Some(Blame {
+        commit: fields.next()?.to_string(),
+        author: fields.next()?.to_string(),
+        date: fields.next()?.to_string(),
+        summary: fields.next()?.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_log_format() {
+        let output = "3f2a\0Ada Lovelace\x002025-03-18T10:04:11+01:00\0Fix the\0parser\n";
+        assert_eq!(
+            parse_log(output),
+            Some(Blame {
+                commit: "3f2a".to_string(),
+                author: "Ada Lovelace".to_string(),
+                date: "2025-03-18T10:04:11+01:00".to_string(),
+                summary: "Fix the\0parser".to_string(),
+            })
+        );
+        assert_eq!(parse_log(""), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::HashSet,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+
+use super::{project_files::ProjectFiles, project_repository::hash_chunk, vector_store::ChunkKind};
+
+/// When chunks were last returned by a search, by file and the hash of
+/// their embedded text, so hits outlive reindexing chunks that didn't
+/// change. Lives in the index database for on-disk indexes.
+pub struct ChunkHits {
+    conn: Connection,
+}
+
+/// A chunk no search has returned since hits were first recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColdChunk {
+    pub path: PathBuf,
+    pub row: Range<usize>,
+    pub kind: ChunkKind,
+}
+
+impl ChunkHits {
+    /// Takes its own connection to the index database, or an in-memory one.
+    pub fn new(conn: Connection) -> Result<Self> {
+        conn.execute(
+            "
+            CREATE TABLE IF NOT EXISTS chunk_hits (
+                path TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                hit_at INTEGER NOT NULL,
+                PRIMARY KEY (path, content_hash)
+            )",
+            [],
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Records that the chunks, given by path and content hash, were just
+    /// returned by a search.
+    pub fn record<'c>(&self, chunks: impl IntoIterator<Item = (&'c Path, &'c str)>) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO chunk_hits (path, content_hash, hit_at) VALUES (?, ?, ?)
+                ON CONFLICT (path, content_hash) DO UPDATE SET hit_at = excluded.hit_at",
+            )?;
+            for (path, content_hash) in chunks {
+                stmt.execute(params![path.to_string_lossy(), content_hash, now])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM chunk_hits WHERE path = ?",
+            [path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// The chunks of the files that no search has returned, by path and
+    /// position, up to `limit`.
+    pub fn never_matched(&self, files: &ProjectFiles, limit: usize) -> Result<Vec<ColdChunk>> {
+        let hit = self.hits()?;
+        let mut paths: Vec<&Path> = files.paths().collect();
+        paths.sort();
+        let mut cold = Vec::new();
+        for path in paths {
+            let Some(text) = files.peek_text(path) else {
+                continue;
+            };
+            for chunk in files.file_chunks(path, &text) {
+                if cold.len() == limit {
+                    return Ok(cold);
+                }
+                if !hit.contains(&(path.to_path_buf(), hash_chunk(&chunk.text))) {
+                    cold.push(ColdChunk {
+                        path: path.to_path_buf(),
+                        row: chunk.chunk.start.row..chunk.chunk.end.row,
+                        kind: chunk.kind,
+                    });
+                }
+            }
+        }
+        Ok(cold)
+    }
+
+    /// The files with chunks searches returned.
+    pub fn hit_files(&self) -> Result<HashSet<PathBuf>> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT path FROM chunk_hits")?;
+        let paths = stmt
+            .query_map([], |row| Ok(PathBuf::from(row.get::<_, String>(0)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(paths)
+    }
+
+    /// The chunks searches returned, by path and content hash.
+    fn hits(&self) -> Result<HashSet<(PathBuf, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, content_hash FROM chunk_hits")?;
+        let hits = stmt
+            .query_map([], |row| {
+                Ok((PathBuf::from(row.get::<_, String>(0)?), row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_hits_until_files_are_removed() {
+        let hits = ChunkHits::new(Connection::open_in_memory().unwrap()).unwrap();
+        let (a, b) = (Path::new("/p/a.rs"), Path::new("/p/b.rs"));
+        hits.record([(a, "1"), (b, "2")]).unwrap();
+        hits.record([(a, "1")]).unwrap();
+        hits.remove(b).unwrap();
+
+        assert_eq!(
+            hits.hits().unwrap(),
+            HashSet::from([(a.to_path_buf(), "1".to_string())])
+        );
+        assert_eq!(hits.hit_files().unwrap(), HashSet::from([a.to_path_buf()]));
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+
+use tree_sitter::{Node, Tree};
+
+use super::{code_splitter::Chunk, vector_store::ChunkKind};
+
+/// Markers of test code inside otherwise regular source files.
+const TEST_MARKERS: &[&str] = &["#[test]", "#[cfg(test)]", "@Test", "def test_", "func Test"];
+
+/// Guesses what a code chunk holds from the syntax nodes it contains and the
+/// file it's in.
+pub fn classify(language: &str, path: &Path, tree: &Tree, chunk: &Chunk) -> ChunkKind {
+    if matches!(language, "json" | "yaml") {
+        return ChunkKind::Config;
+    }
+    if is_test_file(path, chunk.text) {
+        return ChunkKind::Test;
+    }
+    largest_node_within(tree.root_node(), chunk)
+        .and_then(node_kind)
+        .unwrap_or(ChunkKind::Code)
+}
+
+/// Whether the path looks like a test file or the text contains tests.
+pub fn is_test_file(path: &Path, text: &str) -> bool {
+    is_test_path(path) || TEST_MARKERS.iter().any(|marker| text.contains(marker))
+}
+
+fn is_test_path(path: &Path) -> bool {
+    let in_test_dir = path.parent().is_some_and(|dir| {
+        dir.components().any(|component| {
+            matches!(
+                component.as_os_str().to_str(),
+                Some("test" | "tests" | "__tests__" | "spec")
+            )
+        })
+    });
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let lowercase = stem.to_lowercase();
+    in_test_dir
+        || lowercase.starts_with("test_")
+        || [".test", ".spec"]
+            .iter()
+            .any(|suffix| lowercase.ends_with(suffix))
+        || ["_test", "_spec", "Test", "Tests", "Spec"]
+            .iter()
+            .any(|suffix| stem.ends_with(suffix))
+}
+
+/// The largest named node lying entirely inside the chunk.
+fn largest_node_within<'a>(node: Node<'a>, chunk: &Chunk) -> Option<Node<'a>> {
+    let mut cursor = node.walk();
+    let mut largest: Option<Node> = None;
+    for child in node.named_children(&mut cursor) {
+        let candidate =
+            if chunk.range.start <= child.start_byte() && child.end_byte() <= chunk.range.end {
+                Some(child)
+            } else if child.start_byte() < chunk.range.end && chunk.range.start < child.end_byte() {
+                largest_node_within(child, chunk)
+            } else {
+                None
+            };
+        if let Some(candidate) = candidate
+            && largest
+                .is_none_or(|largest| candidate.byte_range().len() > largest.byte_range().len())
+        {
+            largest = Some(candidate);
+        }
+    }
+    largest
+}
+
+pub(super) fn node_kind(node: Node) -> Option<ChunkKind> {
+    let kind = node.kind();
+    if kind == "decorated_definition" {
+        return node_kind(node.child_by_field_name("definition")?);
+    }
+    if ["function", "method", "constructor"]
+        .iter()
+        .any(|name| kind.contains(name))
+    {
+        return Some(ChunkKind::Function);
+    }
+    if [
+        "struct",
+        "enum",
+        "class",
+        "interface",
+        "trait",
+        "type_alias",
+        "object_declaration",
+    ]
+    .iter()
+    .any(|name| kind.contains(name))
+        || matches!(kind, "type_item" | "type_declaration" | "impl_item")
+    {
+        return Some(ChunkKind::Type);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_rust_items() {
+        let source = "struct Session {\n    id: u64,\n}\n\nfn open_session() -> Session {\n    Session { id: 1 }\n}\n\nconst LIMIT: u64 = 10;\n\n#[test]\nfn opens() {\n    open_session();\n}\n";
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        // One chunk per item.
+        let mut start = 0;
+        let kinds: Vec<(String, ChunkKind)> = source
+            .split_inclusive("\n\n")
+            .map(|text| {
+                let chunk = Chunk {
+                    text,
+                    range: start..start + text.len(),
+                    ..Default::default()
+                };
+                start += text.len();
+                let kind = classify("rust", Path::new("src/session.rs"), &tree, &chunk);
+                (text.lines().next().unwrap().to_string(), kind)
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("struct Session {".to_string(), ChunkKind::Type),
+                (
+                    "fn open_session() -> Session {".to_string(),
+                    ChunkKind::Function
+                ),
+                ("const LIMIT: u64 = 10;".to_string(), ChunkKind::Code),
+                ("#[test]".to_string(), ChunkKind::Test),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_test_paths() {
+        assert!(is_test_path(Path::new("/app/tests/login.rs")));
+        assert!(is_test_path(Path::new("/app/src/login_test.go")));
+        assert!(is_test_path(Path::new("/app/src/login.test.ts")));
+        assert!(is_test_path(Path::new("/app/src/test_login.py")));
+        assert!(!is_test_path(Path::new("/app/src/attestation.rs")));
+    }
+}
@@ -22,37 +22,103 @@ impl<'a> CodeSplitter<'a> {
         let mut chunks = Vec::new();
         let node = self.tree.root_node();
         self.process_chunks(&mut chunks, Chunk::default(), node);
+        self.cover_gaps(&mut chunks);
         chunks
     }
 
+    /// Extends the chunks so that every byte of the source lands in exactly one
+    /// of them, including whitespace the syntax tree leaves outside its nodes.
+    fn cover_gaps(&self, chunks: &mut Vec<Chunk<'a>>) {
+        let mut previous = Chunk::default();
+        for chunk in chunks.iter_mut() {
+            if chunk.range.start != previous.range.end {
+                *chunk = previous.merge_end(chunk.clone(), self.source);
+            }
+            previous = chunk.clone();
+        }
+
+        let Some(last) = chunks.last_mut() else {
+            return;
+        };
+        let trailing_range = last.range.end..self.source.len();
+        if trailing_range.is_empty() {
+            return;
+        }
+        let trailing_text = &self.source[trailing_range.clone()];
+        let trailing = Chunk {
+            text: trailing_text,
+            range: trailing_range,
+            start: last.end,
+            end: last.end.advance(trailing_text),
+        };
+        if self.chunk_size(last.range.start..trailing.range.end) <= self.max_chunk_size {
+            *last = last.merge(trailing, self.source);
+        } else {
+            self.push_windows(chunks, trailing);
+        }
+    }
+
+    /// Splits a chunk that can't be divided along the syntax tree, like a huge
+    /// string literal, into consecutive windows of at most `max_chunk_size`
+    /// characters. Windows preferably end after a newline in their second half.
+    fn push_windows(&self, chunks: &mut Vec<Chunk<'a>>, chunk: Chunk<'a>) {
+        let mut start = chunk.range.start;
+        let mut position = chunk.start;
+        while start < chunk.range.end {
+            let rest = &self.source[start..chunk.range.end];
+            let mut end = match rest.char_indices().nth(self.max_chunk_size.max(1)) {
+                Some((offset, _)) => start + offset,
+                None => chunk.range.end,
+            };
+            if end < chunk.range.end {
+                let window = &self.source[start..end];
+                if let Some(newline) = window.rfind('\n').filter(|&i| i >= window.len() / 2) {
+                    end = start + newline + 1;
+                }
+            }
+            let text = &self.source[start..end];
+            let end_position = position.advance(text);
+            chunks.push(Chunk {
+                text,
+                range: start..end,
+                start: position,
+                end: end_position,
+            });
+            start = end;
+            position = end_position;
+        }
+    }
+
     #[recursive]
     fn process_chunks<'c>(&self, chunks: &'c mut Vec<Chunk<'a>>, mut last: Chunk<'a>, node: Node) {
         let mut current_chunk: Option<Chunk> = None;
         let mut cursor = node.walk();
         for child in node.children(&mut cursor) {
-            if child.byte_range().len() > self.max_chunk_size {
+            // The chunk of a child also covers the whitespace separating it from
+            // the previous sibling, so that has to count against the budget too.
+            let new_chunk = last.merge_end(Chunk::from_node(self.source, &child), self.source);
+            let new_chunk_size = self.chunk_size(new_chunk.range.clone());
+            if new_chunk_size > self.max_chunk_size {
                 if let Some(chunk) = current_chunk.take() {
-                    if !chunk.is_empty() {
-                        chunks.push(chunk);
-                    }
+                    push_chunk(chunks, chunk);
+                }
+                if child.child_count() > 0 {
+                    self.process_chunks(chunks, last.clone(), child);
+                } else {
+                    self.push_windows(chunks, new_chunk);
                 }
-                self.process_chunks(chunks, last.clone(), child);
             } else if current_chunk
                 .as_ref()
                 .map(|chunk| self.chunk_size(chunk.range.clone()))
                 .unwrap_or(0)
-                + self.chunk_size(child.byte_range())
+                + new_chunk_size
                 > self.max_chunk_size
             {
                 if let Some(chunk) = current_chunk.take() {
-                    if !chunk.is_empty() {
-                        chunks.push(chunk);
-                    }
+                    push_chunk(chunks, chunk);
                 }
-                current_chunk =
-                    Some(last.merge_end(Chunk::from_node(self.source, &child), self.source));
+                current_chunk = Some(new_chunk);
             } else {
-                let new_chunk = last.merge_end(Chunk::from_node(self.source, &child), self.source);
                 match current_chunk {
                     Some(chunk) if !chunk.is_empty() => {
                         current_chunk = Some(chunk.merge(new_chunk, self.source))
@@ -63,9 +129,7 @@ impl<'a> CodeSplitter<'a> {
             last = Chunk::from_node(self.source, &child);
         }
         if let Some(chunk) = current_chunk {
-            if !chunk.is_empty() {
-                chunks.push(chunk);
-            }
+            push_chunk(chunks, chunk);
         }
     }
 
@@ -74,6 +138,12 @@ impl<'a> CodeSplitter<'a> {
     }
 }
 
+fn push_chunk<'a>(chunks: &mut Vec<Chunk<'a>>, chunk: Chunk<'a>) {
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Chunk<'a> {
     pub text: &'a str,
@@ -88,6 +158,22 @@ pub struct TextPosition {
     pub column: usize,
 }
 
+impl TextPosition {
+    /// The position after `text`, if it starts at this position.
+    pub fn advance(self, text: &str) -> Self {
+        match text.rfind('\n') {
+            Some(newline) => Self {
+                row: self.row + text.matches('\n').count(),
+                column: text.len() - newline - 1,
+            },
+            None => Self {
+                row: self.row,
+                column: self.column + text.len(),
+            },
+        }
+    }
+}
+
 impl From<tree_sitter::Point> for TextPosition {
     fn from(point: tree_sitter::Point) -> Self {
         Self {
@@ -127,7 +213,7 @@ impl<'a> Chunk<'a> {
         Self {
             text: &source[range.clone()],
             range,
-            start: self.start.min(other.start),
+            start: self.end,
             end: self.end.max(other.end),
         }
     }
@@ -135,11 +221,13 @@ impl<'a> Chunk<'a> {
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
     fn test_code_splitter() {
-        let code = std::fs::read_to_string("src/embedings/project_repository.rs").unwrap();
+        let code = std::fs::read_to_string("src/embeddings/project_repository.rs").unwrap();
         let mut parser = Parser::new();
         parser
             .set_language(&tree_sitter_rust::LANGUAGE.into())
@@ -147,13 +235,117 @@ mod tests {
         let tree = parser.parse(&code, None).unwrap();
         let splitter = CodeSplitter::new(&tree, &code, 1500);
         let chunks = splitter.chunks();
-        let text = chunks
-            .iter()
-            .enumerate()
-            .fold(String::new(), |acc, (i, c)| {
-                format!("{}\n====CHUNK {}=====\n{}", acc, i, c.text)
-            });
-        println!("{}", text);
-        assert_eq!(text, "\nfn main()\n\n{\nlet x = 1;\nlet y = 2;\n}");
+        let text: String = chunks.iter().map(|chunk| chunk.text).collect();
+        assert_eq!(text, code);
+    }
+
+    fn ident() -> impl Strategy<Value = String> {
+        "[a-z][a-z_]{0,10}"
+    }
+
+    fn statement() -> impl Strategy<Value = String> {
+        prop_oneof![
+            (ident(), 0..1000u32).prop_map(|(name, value)| format!("let {name} = {value};")),
+            (ident(), ident()).prop_map(|(a, b)| format!("{a}({b});")),
+            "[a-z ]{0,30}".prop_map(|text| format!("// {text}")),
+        ]
+    }
+
+    fn item() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-z ]{0,30}".prop_map(|text| format!("//! {text}\n")),
+            "[a-z ]{0,30}".prop_map(|text| format!("// {text}\n")),
+            "[a-z ]{0,30}".prop_map(|text| format!("/// {text}\n")),
+            (ident(), ident()).prop_map(|(a, b)| format!("use {a}::{b};\n")),
+            (ident(), "[a-z \n]{0,600}")
+                .prop_map(|(name, text)| format!("const {name}: &str = \"{text}\";\n")),
+            (ident(), prop::collection::vec(ident(), 0..6)).prop_map(|(name, fields)| {
+                let fields: Vec<String> =
+                    fields.iter().map(|f| format!("    {f}: u32,\n")).collect();
+                format!("struct {name} {{\n{}}}\n", fields.concat())
+            }),
+            (ident(), prop::collection::vec(statement(), 0..20)).prop_map(|(name, body)| {
+                let body: Vec<String> = body.iter().map(|s| format!("    {s}\n")).collect();
+                format!("fn {name}() {{\n{}}}\n", body.concat())
+            }),
+        ]
+    }
+
+    fn source() -> impl Strategy<Value = String> {
+        (
+            "[ \n]{0,3}",
+            prop::collection::vec((item(), "\n{0,2}"), 0..15),
+        )
+            .prop_map(|(leading, items)| {
+                items
+                    .into_iter()
+                    .fold(leading, |source, (item, separator)| {
+                        source + &item + &separator
+                    })
+            })
+    }
+
+    fn parse(source: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    fn position_of(source: &str, byte: usize) -> TextPosition {
+        let before = &source[..byte];
+        let row = before.matches('\n').count();
+        let column = byte - before.rfind('\n').map(|newline| newline + 1).unwrap_or(0);
+        TextPosition { row, column }
+    }
+
+    proptest! {
+        #[test]
+        fn chunks_are_ordered_and_disjoint(source in source(), max_chunk_size in 40..400usize) {
+            let tree = parse(&source);
+            let chunks = CodeSplitter::new(&tree, &source, max_chunk_size).chunks();
+            for pair in chunks.windows(2) {
+                prop_assert!(
+                    pair[0].range.end <= pair[1].range.start,
+                    "{:?} overlaps or precedes {:?}", pair[0].range, pair[1].range
+                );
+            }
+        }
+
+        #[test]
+        fn chunks_match_their_ranges(source in source(), max_chunk_size in 40..400usize) {
+            let tree = parse(&source);
+            for chunk in CodeSplitter::new(&tree, &source, max_chunk_size).chunks() {
+                prop_assert_eq!(chunk.text, &source[chunk.range.clone()]);
+                prop_assert_eq!(chunk.start, position_of(&source, chunk.range.start));
+                prop_assert_eq!(chunk.end, position_of(&source, chunk.range.end));
+            }
+        }
+
+        #[test]
+        fn chunks_fit_the_budget(source in source(), max_chunk_size in 40..400usize) {
+            let tree = parse(&source);
+            for chunk in CodeSplitter::new(&tree, &source, max_chunk_size).chunks() {
+                prop_assert!(
+                    chunk.text.chars().count() <= max_chunk_size,
+                    "chunk of {} chars exceeds {}: {:?}", chunk.text.chars().count(), max_chunk_size, chunk.text
+                );
+            }
+        }
+
+        #[test]
+        fn chunks_cover_every_byte(source in source(), max_chunk_size in 40..400usize) {
+            let tree = parse(&source);
+            let chunks = CodeSplitter::new(&tree, &source, max_chunk_size).chunks();
+            if source.trim().is_empty() {
+                return Ok(());
+            }
+            prop_assert_eq!(chunks.first().map(|chunk| chunk.range.start), Some(0));
+            prop_assert_eq!(chunks.last().map(|chunk| chunk.range.end), Some(source.len()));
+            for pair in chunks.windows(2) {
+                prop_assert_eq!(pair[0].range.end, pair[1].range.start);
+            }
+        }
     }
 }
@@ -0,0 +1,239 @@
+use std::{collections::HashSet, path::Path, process::Command};
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use zerocopy::IntoBytes;
+
+use crate::config::HistoryConfig;
+
+use super::{model::SharedModel, sqlite_store::check_dimensions, vector_store::cosine_distance};
+
+/// Separates commits in the `git log` output, followed by the fields of the
+/// commit separated by NUL.
+const RECORD_SEPARATOR: char = '\x1e';
+
+/// Commits embedded at a time, the lock is released in between.
+pub const COMMIT_BATCH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Commit {
+    pub sha: String,
+    pub author: String,
+    /// Author date in ISO 8601.
+    pub date: String,
+    pub message: String,
+    /// Files the commit changed, only read with `history.include_files`.
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitMatch {
+    pub commit: Commit,
+    /// Distance to the query, only comparable between commits of the same search.
+    pub distance: f32,
+}
+
+/// Commit messages of the project's git repository, embedded for search in a
+/// table of their own next to the chunks.
+pub struct CommitHistory {
+    conn: Connection,
+    model: SharedModel,
+    config: HistoryConfig,
+    vector_search: bool,
+}
+
+impl CommitHistory {
+    pub fn new(conn: Connection, model: SharedModel, config: HistoryConfig) -> Result<Self> {
+        let vector_search = conn
+            .query_row("SELECT vec_version()", [], |row| row.get::<_, String>(0))
+            .is_ok();
+        conn.execute(
+            "
+            CREATE TABLE IF NOT EXISTS commits (
+                id INTEGER PRIMARY KEY,
+                sha TEXT NOT NULL UNIQUE,
+                author TEXT NOT NULL,
+                date TEXT NOT NULL,
+                message TEXT NOT NULL,
+                files TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let embeddings = if vector_search {
+            format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS commit_embeddings using vec0(embedding float[{}])",
+                model.dimensions()
+            )
+        } else {
+            "CREATE TABLE IF NOT EXISTS commit_embeddings (rowid INTEGER PRIMARY KEY, embedding BLOB NOT NULL)".to_string()
+        };
+        conn.execute(&embeddings, [])?;
+        check_dimensions(&conn, "commit_embeddings", "embedding", model.dimensions())?;
+        Ok(Self {
+            conn,
+            model,
+            config,
+            vector_search,
+        })
+    }
+
+    /// Commits of `root` within `history.max_commits` that aren't stored
+    /// yet, newest first.
+    pub fn unindexed(&self, root: &Path) -> Result<Vec<Commit>> {
+        let mut command = Command::new("git");
+        command
+            .arg("-C")
+            .arg(root)
+            .arg("log")
+            .arg(format!("--max-count={}", self.config.max_commits))
+            .arg(format!(
+                "--format={RECORD_SEPARATOR}%H%x00%an%x00%aI%x00%B%x00"
+            ));
+        if self.config.include_files {
+            command.arg("--name-only");
+        }
+        let output = command.output().context("Failed to run git log")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git log failed in {:?}: {}",
+                root,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let mut stmt = self.conn.prepare("SELECT sha FROM commits")?;
+        let stored: HashSet<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(parse_log(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .filter(|commit| !stored.contains(&commit.sha))
+            .collect())
+    }
+
+    pub fn store(&mut self, commits: &[Commit]) -> Result<()> {
+        if commits.is_empty() {
+            return Ok(());
+        }
+        let texts: Vec<String> = commits.iter().map(embedded_text).collect();
+        let embeddings = self.model.embed(&texts)?;
+        let tx = self.conn.unchecked_transaction()?;
+        for (commit, embedding) in commits.iter().zip(embeddings) {
+            let id: i64 = tx.query_row(
+                "INSERT INTO commits (sha, author, date, message, files) VALUES (?, ?, ?, ?, ?) RETURNING id",
+                params![
+                    commit.sha,
+                    commit.author,
+                    commit.date,
+                    commit.message,
+                    commit.files.join("\n")
+                ],
+                |row| row.get(0),
+            )?;
+            tx.execute(
+                "INSERT INTO commit_embeddings (rowid, embedding) VALUES (?, ?)",
+                params![id, embedding.as_bytes()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<CommitMatch>> {
+        let query_embedding = self.model.embed_queries(&[query])?.remove(0);
+        let mut scored: Vec<(i64, f32)> = if self.vector_search {
+            let mut stmt = self.conn.prepare(
+                "SELECT rowid, distance FROM commit_embeddings WHERE embedding MATCH ? ORDER BY distance LIMIT ?",
+            )?;
+            stmt.query_map(params![query_embedding.as_bytes(), limit], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?
+        } else {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT rowid, embedding FROM commit_embeddings")?;
+            stmt.query_map([], |row| {
+                let bytes: Vec<u8> = row.get(1)?;
+                let embedding: Vec<f32> = bytes
+                    .chunks_exact(size_of::<f32>())
+                    .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                Ok((row.get(0)?, cosine_distance(&query_embedding, &embedding)))
+            })?
+            .collect::<rusqlite::Result<_>>()?
+        };
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+        scored.truncate(limit);
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT sha, author, date, message, files FROM commits WHERE id = ?")?;
+        scored
+            .into_iter()
+            .map(|(id, distance)| {
+                let commit = stmt.query_row([id], |row| {
+                    Ok(Commit {
+                        sha: row.get(0)?,
+                        author: row.get(1)?,
+                        date: row.get(2)?,
+                        message: row.get(3)?,
+                        files: row.get::<_, String>(4)?.lines().map(String::from).collect(),
+                    })
+                })?;
+                Ok(CommitMatch { commit, distance })
+            })
+            .collect()
+    }
+}
+
+fn embedded_text(commit: &Commit) -> String {
+    if commit.files.is_empty() {
+        commit.message.clone()
+    } else {
+        format!("{}\n\n{}", commit.message, commit.files.join("\n"))
+    }
+}
+
+fn parse_log(output: &str) -> Vec<Commit> {
+    output
+        .split(RECORD_SEPARATOR)
+        .filter_map(|record| {
+            let mut fields = record.splitn(5, '\0');
+            Some(Commit {
+                sha: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                message: fields.next()?.trim().to_string(),
+                files: fields
+                    .next()?
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commits_with_changed_files() {
+        let output = "\x1eaaa\0Ada\x002025-01-02T03:04:05+00:00\0Add retry logic\n\nBacks off exponentially.\n\0\nsrc/retry.rs\nsrc/lib.rs\n\n\x1ebbb\0Grace\x002025-01-01T00:00:00+00:00\0Initial commit\n\0";
+        let commits = parse_log(output);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].sha, "aaa");
+        assert_eq!(
+            commits[0].message,
+            "Add retry logic\n\nBacks off exponentially."
+        );
+        assert_eq!(commits[0].files, ["src/retry.rs", "src/lib.rs"]);
+        assert_eq!(commits[1].author, "Grace");
+        assert!(commits[1].files.is_empty());
+    }
+}
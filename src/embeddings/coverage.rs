@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::project_files::{ProjectFiles, has_supported_extension, language_of};
+
+/// Skipped files listed in a report, the rest are only counted.
+const MAX_LISTED_SKIPS: usize = 100;
+
+/// How much of a project is searchable.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Coverage {
+    /// Most files first.
+    pub languages: Vec<LanguageCoverage>,
+    /// Files of the project that aren't indexed, up to `MAX_LISTED_SKIPS`.
+    pub skipped: Vec<SkippedFile>,
+    pub skipped_files: usize,
+}
+
+impl Coverage {
+    /// Share of the indexed files' bytes inside some chunk, 0 to 100.
+    pub fn covered_percent(&self) -> f64 {
+        let bytes: u64 = self.languages.iter().map(|language| language.bytes).sum();
+        let covered: u64 = self
+            .languages
+            .iter()
+            .map(|language| language.covered_bytes)
+            .sum();
+        percent(covered, bytes)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LanguageCoverage {
+    pub language: String,
+    pub files: usize,
+    /// Files whose current contents are indexed, the others are queued.
+    pub indexed: usize,
+    pub bytes: u64,
+    /// Bytes inside some chunk, the rest is e.g. blank lines between them or
+    /// comments left out by `preprocess.comments`.
+    pub covered_bytes: u64,
+}
+
+impl LanguageCoverage {
+    pub fn covered_percent(&self) -> f64 {
+        percent(self.covered_bytes, self.bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkipReason {
+    /// There's no parser for files like it.
+    Unsupported,
+    /// It couldn't be read or parsed, e.g. because it isn't UTF-8.
+    Unreadable,
+}
+
+/// The coverage of the project's files, given the paths a walk of the
+/// project found, empty for revisions, which aren't walked.
+pub fn coverage(files: &ProjectFiles, walked: &[PathBuf]) -> Coverage {
+    let mut languages: HashMap<&str, LanguageCoverage> = HashMap::new();
+    for path in files.paths() {
+        let name = language_of(path).unwrap_or_default();
+        let language = languages.entry(name).or_insert_with(|| LanguageCoverage {
+            language: name.to_string(),
+            ..Default::default()
+        });
+        language.files += 1;
+        language.indexed += usize::from(files.is_indexed(path));
+        let Some(text) = files.peek_text(path) else {
+            continue;
+        };
+        let ranges = files
+            .file_chunks(path, &text)
+            .into_iter()
+            .map(|chunk| chunk.chunk.range)
+            .collect();
+        language.bytes += text.len() as u64;
+        language.covered_bytes += covered_bytes(ranges) as u64;
+    }
+    let mut languages: Vec<LanguageCoverage> = languages.into_values().collect();
+    languages.sort_by(|a, b| b.files.cmp(&a.files).then(a.language.cmp(&b.language)));
+
+    let mut coverage = Coverage {
+        languages,
+        ..Default::default()
+    };
+    for path in walked.iter().filter(|path| !files.contains(path)) {
+        coverage.skipped_files += 1;
+        if coverage.skipped.len() < MAX_LISTED_SKIPS {
+            coverage.skipped.push(SkippedFile {
+                path: path.clone(),
+                reason: skip_reason(path),
+            });
+        }
+    }
+    coverage
+}
+
+fn skip_reason(path: &Path) -> SkipReason {
+    if has_supported_extension(path) {
+        SkipReason::Unreadable
+    } else {
+        SkipReason::Unsupported
+    }
+}
+
+/// Bytes inside any of the ranges, which may overlap.
+fn covered_bytes(mut ranges: Vec<Range<usize>>) -> usize {
+    ranges.sort_by_key(|range| range.start);
+    let mut covered = 0;
+    let mut end = 0;
+    for range in ranges {
+        let start = range.start.max(end);
+        if range.end > start {
+            covered += range.end - start;
+            end = range.end;
+        }
+    }
+    covered
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 100.0;
+    }
+    part as f64 * 100.0 / whole as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_overlapping_chunks_once() {
+        assert_eq!(covered_bytes(vec![10..20, 0..5, 15..30, 40..45]), 30);
+        assert_eq!(covered_bytes(vec![0..10, 2..4]), 10);
+        assert_eq!(covered_bytes(Vec::new()), 0);
+    }
+}
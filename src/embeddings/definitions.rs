@@ -0,0 +1,201 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, Point, Tree};
+
+use super::{classify::node_kind, project_files::ProjectFiles, vector_store::ChunkKind};
+
+/// Named nodes that define something but aren't functions or types, e.g.
+/// Rust constants and modules.
+const OTHER_DEFINITIONS: [&str; 6] = [
+    "const_item",
+    "static_item",
+    "mod_item",
+    "macro_definition",
+    "const_spec",
+    "module",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Definition {
+    pub path: PathBuf,
+    pub name: String,
+    /// Function, type, or code for other definitions.
+    pub kind: ChunkKind,
+    pub row: Range<usize>,
+    /// The first line of the definition.
+    pub signature: String,
+}
+
+/// Finds where the identifier at `point` in the file is defined in the
+/// project, best effort without a language server: definitions with the
+/// same name, those of the kind the identifier is used as first, then ones in
+/// the same file and directory.
+pub fn find_definitions(
+    files: &ProjectFiles,
+    path: &Path,
+    point: Point,
+    limit: usize,
+) -> Result<Vec<Definition>> {
+    let (text, tree) = files
+        .syntax(path)
+        .with_context(|| format!("{:?} is not a file of the project", path))?;
+    let node = identifier_at(tree, point).with_context(|| {
+        format!(
+            "No identifier at {}:{} in {:?}",
+            point.row, point.column, path
+        )
+    })?;
+    let name = &text[node.byte_range()];
+    let wanted = reference_kind(node);
+
+    let mut definitions: Vec<Definition> = files
+        .paths()
+        .filter_map(|candidate| Some((candidate, files.syntax(candidate)?)))
+        .filter(|(_, (text, _))| text.contains(name))
+        .flat_map(|(candidate, (text, tree))| definitions_named(candidate, &text, tree, name))
+        .collect();
+    definitions.sort_by_key(|definition| {
+        (
+            wanted.is_some_and(|kind| kind != definition.kind),
+            definition.path != path,
+            definition.path.parent() != path.parent(),
+            definition.path.clone(),
+            definition.row.start,
+        )
+    });
+    definitions.truncate(limit);
+    Ok(definitions)
+}
+
+/// The identifier at `point`, or just before it so a cursor right after a
+/// name finds it.
+fn identifier_at(tree: &Tree, point: Point) -> Option<Node<'_>> {
+    let root = tree.root_node();
+    let before = Point::new(point.row, point.column.saturating_sub(1));
+    [point, before]
+        .into_iter()
+        .filter_map(|point| root.descendant_for_point_range(point, point))
+        .find(|node| is_identifier(node.kind()))
+}
+
+fn is_identifier(kind: &str) -> bool {
+    kind.ends_with("identifier") || matches!(kind, "name" | "constant")
+}
+
+/// What the identifier is used as: a type by its node kind, a function if
+/// it's what a call calls.
+fn reference_kind(node: Node) -> Option<ChunkKind> {
+    if node.kind() == "type_identifier" {
+        return Some(ChunkKind::Type);
+    }
+    let mut ancestor = node.parent();
+    // Method calls name their function in a field expression.
+    for _ in 0..2 {
+        let parent = ancestor?;
+        if parent.kind().contains("call") || parent.kind().contains("invocation") {
+            let callee = parent
+                .child_by_field_name("function")
+                .or_else(|| parent.child_by_field_name("name"))?;
+            let called = callee.byte_range().contains(&node.start_byte());
+            return called.then_some(ChunkKind::Function);
+        }
+        ancestor = parent.parent();
+    }
+    None
+}
+
+/// The definitions in the file whose name is `name`.
+fn definitions_named(path: &Path, text: &str, tree: &Tree, name: &str) -> Vec<Definition> {
+    definitions_matching(path, text, tree, |candidate| candidate == name)
+}
+
+/// Every definition in the file, in file order, for its outline.
+pub fn file_definitions(path: &Path, text: &str, tree: &Tree) -> Vec<Definition> {
+    let mut definitions = definitions_matching(path, text, tree, |_| true);
+    definitions.sort_by_key(|definition| (definition.row.start, definition.row.end));
+    definitions
+}
+
+fn definitions_matching(
+    path: &Path,
+    text: &str,
+    tree: &Tree,
+    matches: impl Fn(&str) -> bool,
+) -> Vec<Definition> {
+    let mut definitions = Vec::new();
+    let mut stack = vec![tree.root_node()];
+    while let Some(node) = stack.pop() {
+        let mut cursor = node.walk();
+        stack.extend(node.named_children(&mut cursor));
+        let Some(name_node) = node.child_by_field_name("name") else {
+            continue;
+        };
+        let name = &text[name_node.byte_range()];
+        if !matches(name) {
+            continue;
+        }
+        let Some(kind) = definition_kind(node) else {
+            continue;
+        };
+        definitions.push(Definition {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+            kind,
+            row: node.start_position().row..node.end_position().row + 1,
+            signature: text[node.byte_range()]
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+        });
+    }
+    definitions
+}
+
+fn definition_kind(node: Node) -> Option<ChunkKind> {
+    let kind = node.kind();
+    // Calls and struct literals have names too.
+    if ["call", "invocation", "expression"]
+        .iter()
+        .any(|usage| kind.contains(usage))
+    {
+        return None;
+    }
+    if kind == "type_spec" {
+        return Some(ChunkKind::Type);
+    }
+    node_kind(node).or_else(|| OTHER_DEFINITIONS.contains(&kind).then_some(ChunkKind::Code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_functions_and_types_by_usage() {
+        let source = "struct Session {}\n\nfn Session() -> Session {\n    Session {}\n}\n\nfn main() {\n    let session: Session = Session();\n}\n";
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let path = Path::new("src/main.rs");
+
+        let found = definitions_named(path, source, &tree, "Session");
+        let kinds: Vec<ChunkKind> = found.iter().map(|definition| definition.kind).collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.contains(&ChunkKind::Type) && kinds.contains(&ChunkKind::Function));
+
+        let annotation = identifier_at(&tree, Point::new(7, 18)).unwrap();
+        assert_eq!(reference_kind(annotation), Some(ChunkKind::Type));
+        let call = identifier_at(&tree, Point::new(7, 28)).unwrap();
+        assert_eq!(&source[call.byte_range()], "Session");
+        assert_eq!(reference_kind(call), Some(ChunkKind::Function));
+    }
+}
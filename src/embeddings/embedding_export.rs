@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::config::PreprocessConfig;
+
+use super::vector_store::{ChunkKind, VectorStore};
+
+/// The first line of an export, what the embeddings were computed with.
+/// Embeddings only apply to projects that embed their chunks alike.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub model: String,
+    pub dimensions: usize,
+    /// What the chunks were prefixed with before they were embedded.
+    pub passage_prefix: String,
+    pub preprocess: PreprocessConfig,
+}
+
+/// A chunk of an export, one per line after the header. Paths are relative
+/// to the project root, so exports of one checkout apply to others.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedChunk {
+    pub path: PathBuf,
+    pub row: Range<usize>,
+    pub column: Range<usize>,
+    pub byte: Range<usize>,
+    pub kind: ChunkKind,
+    /// Hash of the embedded text, which includes the chunk's preamble.
+    pub content_hash: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Writes the header and then every chunk of the store as JSON lines, for
+/// analysis elsewhere or loading into other vector databases. Returns how
+/// many chunks were written.
+pub fn export_embeddings(
+    store: &dyn VectorStore,
+    header: &ExportHeader,
+    root: &Path,
+    mut out: impl Write,
+) -> Result<usize> {
+    serde_json::to_writer(&mut out, header)?;
+    out.write_all(b"\n")?;
+    let mut exported = 0;
+    store.for_each_embedding(&mut |chunk, embedding| {
+        let chunk = ExportedChunk {
+            path: chunk
+                .path
+                .strip_prefix(root)
+                .unwrap_or(&chunk.path)
+                .to_path_buf(),
+            row: chunk.row,
+            column: chunk.column,
+            byte: chunk.byte,
+            kind: chunk.kind,
+            content_hash: chunk.content_hash,
+            embedding,
+        };
+        serde_json::to_writer(&mut out, &chunk)?;
+        out.write_all(b"\n")?;
+        exported += 1;
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(exported)
+}
+
+/// Reads an export made elsewhere, e.g. in CI with a GPU, checking that its
+/// embeddings were computed like `expected`. Returns them by content hash.
+pub fn import_embeddings(
+    input: impl BufRead,
+    expected: &ExportHeader,
+) -> Result<HashMap<String, Vec<f32>>> {
+    let mut lines = input.lines();
+    let header: ExportHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).context("Not an embedding export")?,
+        None => bail!("The embedding export is empty"),
+    };
+    if header.model != expected.model || header.dimensions != expected.dimensions {
+        bail!(
+            "The export has {}-dimensional embeddings of {}, the project uses {}-dimensional ones of {}",
+            header.dimensions,
+            header.model,
+            expected.dimensions,
+            expected.model
+        );
+    }
+    if header.passage_prefix != expected.passage_prefix {
+        bail!(
+            "The export's chunks were embedded with the prefix {:?}, the project's are with {:?}",
+            header.passage_prefix,
+            expected.passage_prefix
+        );
+    }
+    if header.preprocess != expected.preprocess {
+        bail!("The export's chunks were preprocessed differently than the project's");
+    }
+    let dimensions = expected.dimensions;
+    let mut embeddings = HashMap::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let chunk: ExportedChunk = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid chunk on line {} of the export", i + 2))?;
+        if chunk.embedding.len() != dimensions {
+            bail!(
+                "The chunk on line {} of the export has a {}-dimensional embedding",
+                i + 2,
+                chunk.embedding.len()
+            );
+        }
+        embeddings.insert(chunk.content_hash, chunk.embedding);
+    }
+    Ok(embeddings)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embeddings::{
+        code_splitter::TextPosition, memory_store::MemoryStore, vector_store::ChunkRecord,
+    };
+
+    use super::*;
+
+    #[test]
+    fn exports_a_header_and_a_line_per_chunk() {
+        let mut store = MemoryStore::default();
+        let record = ChunkRecord {
+            start: TextPosition { row: 2, column: 0 },
+            end: TextPosition { row: 4, column: 1 },
+            byte: 10..40,
+            content_hash: "a".to_string(),
+            kind: ChunkKind::Function,
+        };
+        let embeddings = HashMap::from([("a".to_string(), vec![0.5, 0.25])]);
+        store
+            .upsert_file("/p/src/lib.rs", &[record], &embeddings)
+            .unwrap();
+        let header = ExportHeader {
+            model: "test".to_string(),
+            dimensions: 2,
+            passage_prefix: "passage: ".to_string(),
+            preprocess: PreprocessConfig::default(),
+        };
+
+        let mut out = Vec::new();
+        let exported = export_embeddings(&store, &header, Path::new("/p"), &mut out).unwrap();
+
+        assert_eq!(exported, 1);
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(
+            serde_json::from_str::<ExportHeader>(lines[0]).unwrap(),
+            header
+        );
+        assert_eq!(
+            serde_json::from_str::<ExportedChunk>(lines[1]).unwrap(),
+            ExportedChunk {
+                path: "src/lib.rs".into(),
+                row: 2..4,
+                column: 0..1,
+                byte: 10..40,
+                kind: ChunkKind::Function,
+                content_hash: "a".to_string(),
+                embedding: vec![0.5, 0.25],
+            }
+        );
+
+        let imported = import_embeddings(&out[..], &header).unwrap();
+        assert_eq!(
+            imported,
+            HashMap::from([("a".to_string(), vec![0.5, 0.25])])
+        );
+        let other_model = ExportHeader {
+            model: "other".to_string(),
+            ..header.clone()
+        };
+        let error = import_embeddings(&out[..], &other_model).unwrap_err();
+        assert!(error.to_string().contains("embeddings of test"), "{error}");
+        let other_prefix = ExportHeader {
+            passage_prefix: String::new(),
+            ..header.clone()
+        };
+        let error = import_embeddings(&out[..], &other_prefix).unwrap_err();
+        assert!(error.to_string().contains("\"passage: \""), "{error}");
+        let other_preprocess = ExportHeader {
+            preprocess: PreprocessConfig {
+                collapse_whitespace: true,
+                ..PreprocessConfig::default()
+            },
+            ..header
+        };
+        let error = import_embeddings(&out[..], &other_preprocess).unwrap_err();
+        assert!(error.to_string().contains("preprocessed"), "{error}");
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::project_files::{Boundaries, TREE_NODE_BYTES, supported_files};
+use crate::config::Config;
+
+/// Chunks come out about half the maximum size on average.
+const AVERAGE_CHUNK_FILL: f64 = 0.5;
+/// Rough syntax tree density of source code.
+const SOURCE_BYTES_PER_TREE_NODE: u64 = 6;
+/// Stored per chunk besides its embedding, e.g. path, positions and hash.
+const CHUNK_ROW_BYTES: u64 = 256;
+/// Embedding throughput of a small model on a laptop CPU.
+const EMBEDDED_CHUNKS_PER_SECOND: f64 = 40.0;
+
+/// What indexing a project would take, projected from the sizes of the files
+/// it would index without reading them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexEstimate {
+    pub files: usize,
+    pub bytes: u64,
+    pub chunks: u64,
+    pub seconds: u64,
+    /// Size of the vector index.
+    pub index_bytes: u64,
+    /// File texts and syntax trees kept in memory plus the embedding model.
+    pub memory_bytes: u64,
+}
+
+/// Walks the project like indexing does, honoring ignore files, but only
+/// looks at file sizes.
+pub fn estimate_index(
+    root: &Path,
+    config: &Config,
+    dimensions: usize,
+    model_bytes: u64,
+) -> IndexEstimate {
+    let mut estimate = IndexEstimate::default();
+    for path in supported_files(root, config.follow_symlinks, &Boundaries::new(config)) {
+        let Ok(metadata) = path.and_then(|path| Ok(std::fs::metadata(path)?)) else {
+            continue;
+        };
+        estimate.files += 1;
+        estimate.bytes += metadata.len();
+    }
+    let chunk_bytes = (config.chunking.max_chunk_size as f64 * AVERAGE_CHUNK_FILL).max(1.0);
+    estimate.chunks = estimate.files as u64 + (estimate.bytes as f64 / chunk_bytes) as u64;
+    estimate.seconds = (estimate.chunks as f64 / EMBEDDED_CHUNKS_PER_SECOND).ceil() as u64;
+    estimate.index_bytes = estimate.chunks * (dimensions as u64 * 4 + CHUNK_ROW_BYTES);
+    estimate.memory_bytes = estimate.bytes.min(config.text_cache_bytes as u64)
+        + estimate.bytes / SOURCE_BYTES_PER_TREE_NODE * TREE_NODE_BYTES as u64
+        + model_bytes;
+    estimate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_indexed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.path().join("lib.py"), "x = 1\n").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not indexed\n").unwrap();
+
+        let estimate = estimate_index(dir.path(), &Config::default(), 384, 1000);
+        assert_eq!(estimate.files, 2);
+        assert_eq!(estimate.bytes, 19);
+        assert_eq!(estimate.chunks, 2);
+        assert_eq!(estimate.index_bytes, 2 * (384 * 4 + CHUNK_ROW_BYTES));
+        assert!(estimate.memory_bytes > 1000);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use fastembed::EmbeddingModel;
+use serde::Deserialize;
+
+use crate::config::Config;
+
+use super::{
+    model::SharedModel,
+    project_files::{Boundaries, ProjectFiles},
+    project_repository::ProjectRepository,
+    vector_store::ChunkFilter,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvalQuery {
+    pub query: String,
+    /// Paths relative to the project root that a good answer should contain.
+    pub expected: Vec<PathBuf>,
+}
+
+pub fn load_queries(path: &Path) -> Result<Vec<EvalQuery>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+pub struct EvalReport {
+    pub model: String,
+    pub index_time: Duration,
+    pub results: Vec<QueryResult>,
+}
+
+pub struct QueryResult {
+    pub recall: f64,
+    pub latency: Duration,
+}
+
+impl EvalReport {
+    pub fn mean_recall(&self) -> f64 {
+        if self.results.is_empty() {
+            return 0.0;
+        }
+        self.results.iter().map(|r| r.recall).sum::<f64>() / self.results.len() as f64
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        if self.results.is_empty() {
+            return Duration::ZERO;
+        }
+        self.results.iter().map(|r| r.latency).sum::<Duration>() / self.results.len() as u32
+    }
+
+    pub fn p95_latency(&self) -> Duration {
+        let mut latencies: Vec<Duration> = self.results.iter().map(|r| r.latency).collect();
+        latencies.sort();
+        let index = (latencies.len() * 95).div_ceil(100).saturating_sub(1);
+        latencies.get(index).copied().unwrap_or_default()
+    }
+}
+
+/// Indexes the project with the given model and runs every query against it.
+pub fn evaluate(
+    config: &Config,
+    project_path: &Path,
+    model: EmbeddingModel,
+    queries: &[EvalQuery],
+    top_k: usize,
+) -> Result<EvalReport> {
+    let model_name = model.to_string();
+    let index = EvalIndex::build(config, project_path, SharedModel::get(model)?)?;
+
+    let mut results = Vec::with_capacity(queries.len());
+    for query in queries {
+        let start = Instant::now();
+        let paths = index.search(&query.query, top_k)?;
+        let latency = start.elapsed();
+
+        let found = query
+            .expected
+            .iter()
+            .filter(|expected| paths.contains(&project_path.join(expected)))
+            .count();
+        let recall = if query.expected.is_empty() {
+            1.0
+        } else {
+            found as f64 / query.expected.len() as f64
+        };
+        results.push(QueryResult { recall, latency });
+    }
+
+    Ok(EvalReport {
+        model: model_name,
+        index_time: index.index_time,
+        results,
+    })
+}
+
+/// Reads and chunks the project's files like indexing does, without
+/// embedding them. Returns the number of chunks.
+pub fn chunk_project(config: &Config, project_path: &Path) -> Result<usize> {
+    let files = read_project(config, project_path)?;
+    Ok(files
+        .paths()
+        .filter_map(|path| Some(files.file_chunks(path, &files.text(path)?).len()))
+        .sum())
+}
+
+/// A project indexed with the storage settings of the config, for
+/// evaluations and benchmarks.
+pub struct EvalIndex {
+    repository: ProjectRepository,
+    pub files: usize,
+    /// Time spent embedding and storing the chunks, without reading the files.
+    pub index_time: Duration,
+}
+
+impl EvalIndex {
+    pub fn build(config: &Config, project_path: &Path, model: SharedModel) -> Result<Self> {
+        let files = read_project(config, project_path)?;
+        let mut repository = ProjectRepository::with_model(model, &config.storage, project_path)?;
+
+        let start = Instant::now();
+        for path in files.paths() {
+            let Some(text) = files.text(path) else {
+                continue;
+            };
+            let chunks = files.file_chunks(path, &text);
+            repository.upsert_file(&path.to_string_lossy(), chunks)?;
+        }
+        Ok(Self {
+            repository,
+            files: files.paths().count(),
+            index_time: start.elapsed(),
+        })
+    }
+
+    /// Paths of the files of the closest chunks, closest first.
+    pub fn search(&self, query: &str, top_k: usize) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .repository
+            .search(query, top_k, &ChunkFilter::default())?
+            .into_iter()
+            .map(|chunk| chunk.path)
+            .collect())
+    }
+}
+
+fn read_project(config: &Config, project_path: &Path) -> Result<ProjectFiles> {
+    ProjectFiles::new(
+        project_path.to_path_buf(),
+        config.chunking.clone(),
+        config.preprocess.clone(),
+        config.walk_threads,
+        config.follow_symlinks,
+        &Boundaries::new(config),
+        // Evaluated projects are indexed at once, dropping texts would
+        // only add reads.
+        usize::MAX,
+    )
+}
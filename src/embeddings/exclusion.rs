@@ -0,0 +1,46 @@
+use super::project_files::ResponseChunk;
+
+/// Splits `-term` words out of the query, returning the rest of the query
+/// and the terms.
+pub fn split_exclusions(query: &str) -> (String, Vec<String>) {
+    let mut kept = Vec::new();
+    let mut excluded = Vec::new();
+    for word in query.split_whitespace() {
+        match word.strip_prefix('-') {
+            Some(term) if !term.is_empty() && !term.starts_with('-') => {
+                excluded.push(term.to_string())
+            }
+            _ => kept.push(word),
+        }
+    }
+    (kept.join(" "), excluded)
+}
+
+/// Adds `penalty` to the distance of chunks containing any of the terms,
+/// ignoring case, and keeps the order by distance.
+pub fn penalize_excluded(chunks: &mut [ResponseChunk], terms: &[String], penalty: f32) {
+    let terms: Vec<String> = terms.iter().map(|term| term.to_lowercase()).collect();
+    for chunk in chunks.iter_mut() {
+        let content = chunk.content.to_lowercase();
+        if let Some(term) = terms.iter().find(|term| content.contains(term.as_str())) {
+            chunk.adjust(format!("contains excluded {term:?}"), penalty);
+        }
+    }
+    chunks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_excluded_terms_from_the_query() {
+        assert_eq!(
+            split_exclusions("retry logic -grpc  -Legacy -- off-by-one"),
+            (
+                "retry logic -- off-by-one".to_string(),
+                vec!["grpc".to_string(), "Legacy".to_string()]
+            )
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::QueryExpansionConfig;
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    response: String,
+}
+
+/// Asks the configured LLM for code that could answer the query, to search
+/// with alongside the query itself.
+pub fn hypothetical_snippet(
+    config: &QueryExpansionConfig,
+    query: &str,
+    timeout: Duration,
+) -> Result<String> {
+    let prompt = format!(
+        "Write a short code snippet that answers the question below. \
+         Reply with the code only.\n\nQuestion: {query}"
+    );
+    let response: GenerateResponse = ureq::post(&config.endpoint)
+        .timeout(timeout)
+        .send_json(json!({
+            "model": config.model,
+            "prompt": prompt,
+            "stream": false,
+        }))
+        .with_context(|| format!("query expansion request to {} failed", config.endpoint))?
+        .into_json()?;
+    Ok(strip_fences(&response.response).to_string())
+}
+
+/// The code inside the first Markdown code block, or the whole reply if it
+/// has none.
+fn strip_fences(reply: &str) -> &str {
+    let Some((_, rest)) = reply.split_once("```") else {
+        return reply.trim();
+    };
+    // Skip the language tag after the opening fence.
+    let code = rest.split_once('\n').map_or("", |(_, code)| code);
+    code.split_once("```").map_or(code, |(code, _)| code).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_markdown_fences() {
+        assert_eq!(strip_fences("fn main() {}\n"), "fn main() {}");
+        assert_eq!(
+            strip_fences("Here you go:\n```rust\nfn main() {}\n```\nEnjoy"),
+            "fn main() {}"
+        );
+        assert_eq!(strip_fences("```\nlet x = 1;"), "let x = 1;");
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    project_files::{ResponseChunk, extension_of, language_of, ranking_penalty},
+    project_service::SearchOptions,
+};
+
+/// How the distance of a result came about, returned with
+/// `SearchOptions::explain` to tune ranking.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Explanation {
+    /// Distance between the embeddings of the chunk and the query, or its
+    /// expansion if that was closer.
+    pub vector_distance: f32,
+    /// Changes to the distance in the order they were made. The distance of
+    /// the result is the vector distance plus their deltas.
+    pub adjustments: Vec<Adjustment>,
+    /// The filters of the search the chunk passed, e.g. "language rust".
+    pub filters: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Adjustment {
+    pub reason: String,
+    /// Negative for boosts.
+    pub delta: f32,
+}
+
+impl Explanation {
+    /// Explains a result as it comes out of the index, where only the
+    /// ranking penalty of its file type has been applied.
+    pub fn of(chunk: &ResponseChunk, options: &SearchOptions) -> Self {
+        let penalty = ranking_penalty(&chunk.path);
+        let mut explanation = Self {
+            vector_distance: chunk.distance - penalty,
+            ..Default::default()
+        };
+        if penalty != 0.0 {
+            let extension = extension_of(&chunk.path).unwrap_or_default();
+            explanation.adjustments.push(Adjustment {
+                reason: format!("ranked lower as a .{extension} file"),
+                delta: penalty,
+            });
+        }
+        if !options.kinds.is_empty() {
+            explanation
+                .filters
+                .push(format!("kind {}", chunk.kind.as_str()));
+        }
+        if !options.languages.is_empty() {
+            let language = language_of(&chunk.path).unwrap_or_default();
+            explanation.filters.push(format!("language {language}"));
+        }
+        if let Some(prefix) = &options.path_prefix {
+            explanation
+                .filters
+                .push(format!("under {}", prefix.to_string_lossy()));
+        }
+        explanation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::ChunkKind;
+
+    #[test]
+    fn records_penalties_and_filters() {
+        let mut chunk = ResponseChunk {
+            path: "types/index.d.ts".into(),
+            row: 0..1,
+            column: 0..0,
+            content: Default::default(),
+            key_path: None,
+            kind: ChunkKind::Type,
+            distance: 0.35,
+            indexed_at: None,
+            stale: false,
+            blame: None,
+            permalink: None,
+            duplicates: Vec::new(),
+            explanation: None,
+            commit: None,
+            id: String::new(),
+            size: Default::default(),
+        };
+        let options = SearchOptions {
+            kinds: vec![ChunkKind::Type],
+            ..Default::default()
+        };
+        chunk.explanation = Some(Explanation::of(&chunk, &options));
+        chunk.adjust("boosted", -0.1);
+
+        let explanation = chunk.explanation.unwrap();
+        assert!((explanation.vector_distance - 0.3).abs() < 1e-6);
+        let deltas: Vec<f32> = explanation.adjustments.iter().map(|a| a.delta).collect();
+        assert_eq!(deltas, [0.05, -0.1]);
+        let total = explanation.vector_distance + deltas.iter().sum::<f32>();
+        assert!((total - chunk.distance).abs() < 1e-6);
+        assert_eq!(explanation.filters, ["kind type"]);
+    }
+}
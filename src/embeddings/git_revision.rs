@@ -0,0 +1,178 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+
+use super::project_files::language_of;
+
+/// Mode of symlinks in git trees, their blob is the link target.
+const SYMLINK_MODE: &str = "120000";
+
+/// The commit `revision` points to in the repository at `root`, e.g. for
+/// "origin/main".
+pub fn resolve_commit(root: &Path, revision: &str) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["rev-parse", "--verify", "--quiet", "--end-of-options"])
+        .arg(format!("{revision}^{{commit}}"))
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("No commit {:?} in the repository at {:?}", revision, root);
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// The files under `root` in the commit that are in a supported language, by
+/// path relative to `root`, with their contents read from the object store.
+/// Files that aren't UTF-8 are left out.
+pub fn read_tree(root: &Path, commit: &str) -> Result<Vec<(PathBuf, String)>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["ls-tree", "-r", "-z", commit])
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to list the files of {}: {}",
+            commit,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let blobs = parse_tree(&output.stdout);
+    let objects: Vec<&str> = blobs.iter().map(|(_, object)| object.as_str()).collect();
+    let contents = read_blobs(root, &objects)?;
+    Ok(blobs
+        .into_iter()
+        .zip(contents)
+        .filter_map(|((path, _), content)| Some((path, String::from_utf8(content).ok()?)))
+        .collect())
+}
+
+/// The contents of a file as of the commit, by its path in the working tree.
+pub fn read_file(path: &Path, commit: &str) -> Result<String> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        bail!("Not a file path: {:?}", path);
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["cat-file", "blob"])
+        .arg(format!("{}:./{}", commit, name.to_string_lossy()))
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to read {:?} at {}: {}",
+            path,
+            commit,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The files under `root` that differ between the revisions, by path
+/// relative to `root`, including ones deleted or renamed away.
+pub fn changed_files(root: &Path, from: &str, to: &str) -> Result<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["diff", "--name-only", "-z", "--relative", "--no-renames"])
+        .args(["--end-of-options", from, to])
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to diff {} and {}: {}",
+            from,
+            to,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output
+        .stdout
+        .split(|byte| *byte == 0)
+        .filter(|path| !path.is_empty())
+        .filter_map(|path| Some(PathBuf::from(std::str::from_utf8(path).ok()?)))
+        .collect())
+}
+
+/// Paths and object ids of the supported files in `git ls-tree -r -z` output.
+fn parse_tree(output: &[u8]) -> Vec<(PathBuf, String)> {
+    output
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let (info, path) = std::str::from_utf8(entry).ok()?.split_once('\t')?;
+            let mut info = info.split(' ');
+            let (mode, kind, object) = (info.next()?, info.next()?, info.next()?);
+            let path = PathBuf::from(path);
+            (kind == "blob" && mode != SYMLINK_MODE && language_of(&path).is_some())
+                .then(|| (path, object.to_string()))
+        })
+        .collect()
+}
+
+/// Reads the objects with a single `git cat-file --batch`.
+fn read_blobs(root: &Path, objects: &[&str]) -> Result<Vec<Vec<u8>>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+    let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        bail!("Failed to connect to git cat-file");
+    };
+    // Written from another thread so git doesn't block on a full pipe while
+    // the contents aren't read yet.
+    let input: String = objects.iter().map(|object| format!("{object}\n")).collect();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let mut stdout = BufReader::new(stdout);
+    let mut contents = Vec::with_capacity(objects.len());
+    for object in objects {
+        let mut header = String::new();
+        stdout.read_line(&mut header)?;
+        let size: usize = header
+            .trim_end()
+            .rsplit(' ')
+            .next()
+            .and_then(|size| size.parse().ok())
+            .with_context(|| format!("Failed to read object {}: {:?}", object, header))?;
+        // The contents are followed by a newline.
+        let mut content = vec![0; size + 1];
+        stdout.read_exact(&mut content)?;
+        content.pop();
+        contents.push(content);
+    }
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("git cat-file writer panicked"))??;
+    child.wait()?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_supported_blobs() {
+        let output = b"100644 blob aaa\tsrc/main.rs\x00100644 blob bbb\tREADME.md\x00120000 blob ccc\tsrc/link.rs\x00160000 commit ddd\tvendor/lib\x00100755 blob eee\tscripts/build.py\x00";
+        assert_eq!(
+            parse_tree(output),
+            [
+                (PathBuf::from("src/main.rs"), "aaa".to_string()),
+                (PathBuf::from("scripts/build.py"), "eee".to_string()),
+            ]
+        );
+    }
+}
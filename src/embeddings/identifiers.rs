@@ -0,0 +1,326 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use rusqlite::{Connection, params, params_from_iter};
+
+use super::{
+    preprocess::EmbeddingChunk,
+    project_files::{ResponseChunk, chunk_id},
+    project_repository::{OutputChunk, hash_chunk},
+    vector_store::{ChunkFilter, ChunkKind},
+};
+
+/// Query words too common to tell identifiers apart.
+const STOP_WORDS: [&str; 18] = [
+    "a", "an", "and", "by", "for", "from", "how", "in", "is", "it", "of", "on", "or", "the", "to",
+    "what", "where", "with",
+];
+
+/// Score of a chunk with an identifier made of all the query's words, e.g.
+/// `parseJwtHeader` for "parse jwt header". Chunks with only some of the
+/// words in their identifiers score the share of them found times half this.
+const WHOLE_IDENTIFIER_SCORE: f32 = 1.0;
+
+const IDENTIFIER_COLUMNS: &str = "path, start_row, start_column, end_row, end_column, start_byte, end_byte, kind, content_hash, identifiers";
+
+/// A lexical index of the identifiers in each chunk, split into words, to
+/// find chunks naming what a query asks for when embeddings miss it. Written
+/// along with the chunks, in the index database for on-disk indexes.
+pub struct IdentifierIndex {
+    conn: Connection,
+    created: bool,
+}
+
+impl IdentifierIndex {
+    /// Takes its own connection to the index database, or an in-memory one.
+    pub fn new(conn: Connection) -> Result<Self> {
+        let created = !has_identifiers(&conn);
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS chunk_identifiers (
+                path TEXT NOT NULL,
+                start_row INTEGER NOT NULL,
+                start_column INTEGER NOT NULL,
+                end_row INTEGER NOT NULL,
+                end_column INTEGER NOT NULL,
+                start_byte INTEGER NOT NULL,
+                end_byte INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                identifiers TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS chunk_identifiers_path ON chunk_identifiers (path);",
+        )?;
+        Ok(Self { conn, created })
+    }
+
+    /// The identifiers already in the database, None if it has none.
+    pub fn persisted(conn: Connection) -> Option<Self> {
+        has_identifiers(&conn).then_some(Self {
+            conn,
+            created: false,
+        })
+    }
+
+    /// Whether `new` created the table, so the files indexed before have no
+    /// identifiers yet.
+    pub fn created(&self) -> bool {
+        self.created
+    }
+
+    /// Replaces the identifiers of the file's chunks.
+    pub fn replace(&self, path: &Path, chunks: &[EmbeddingChunk]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM chunk_identifiers WHERE path = ?",
+            [path.to_string_lossy()],
+        )?;
+        {
+            let mut stmt = tx.prepare(&format!(
+                "INSERT INTO chunk_identifiers ({IDENTIFIER_COLUMNS})
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            ))?;
+            for chunk in chunks {
+                let identifiers = identifiers(&chunk.text);
+                if identifiers.is_empty() {
+                    continue;
+                }
+                stmt.execute(params![
+                    path.to_string_lossy(),
+                    chunk.chunk.start.row,
+                    chunk.chunk.start.column,
+                    chunk.chunk.end.row,
+                    chunk.chunk.end.column,
+                    chunk.chunk.range.start,
+                    chunk.chunk.range.end,
+                    chunk.kind.as_str(),
+                    hash_chunk(&chunk.text),
+                    identifiers.into_iter().collect::<Vec<_>>().join(" "),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM chunk_identifiers WHERE path = ?",
+            [path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Up to `limit` chunks matching the filter whose identifiers contain
+    /// some of the query `words`, see `query_words`, best scoring first.
+    pub fn search(
+        &self,
+        words: &[String],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<(OutputChunk, f32)>> {
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let conditions = vec!["identifiers LIKE ?"; words.len()].join(" OR ");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {IDENTIFIER_COLUMNS} FROM chunk_identifiers WHERE {conditions}"
+        ))?;
+        let patterns = words.iter().map(|word| format!("%{word}%"));
+        let mut rows = stmt.query(params_from_iter(patterns))?;
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next()? {
+            let path = PathBuf::from(row.get::<_, String>(0)?);
+            let kind = ChunkKind::parse(&row.get::<_, String>(7)?).unwrap_or(ChunkKind::Code);
+            if !filter.matches(&path, kind) {
+                continue;
+            }
+            let score = score(&row.get::<_, String>(9)?, words);
+            if score == 0.0 {
+                continue;
+            }
+            let chunk = OutputChunk {
+                path,
+                row: row.get(1)?..row.get(3)?,
+                column: row.get(2)?..row.get(4)?,
+                byte: row.get(5)?..row.get(6)?,
+                kind,
+                content_hash: row.get(8)?,
+                distance: 0.0,
+                duplicates: Vec::new(),
+            };
+            hits.push((chunk, score));
+        }
+        hits.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| (&a.path, a.byte.start).cmp(&(&b.path, b.byte.start)))
+        });
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
+
+fn has_identifiers(conn: &Connection) -> bool {
+    conn.prepare("SELECT path FROM chunk_identifiers LIMIT 0")
+        .is_ok()
+}
+
+/// The lowercase words of an identifier, split at underscores, dashes, case
+/// changes and digits: `parseJWTHeader2` is parse, jwt, header and 2.
+pub fn split_identifier(identifier: &str) -> Vec<String> {
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if !word.is_empty() {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            let boundary = (c.is_uppercase()
+                && (previous.is_lowercase() || previous.is_numeric() || next_is_lower))
+                || (c.is_numeric() != previous.is_numeric());
+            if boundary {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The words of a query to look up in the identifiers, once each, without
+/// stop words. Identifiers in the query are split like those in the code.
+pub fn query_words(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for word in query
+        .split(|c: char| c.is_whitespace() || c == '.' || c == ',')
+        .flat_map(split_identifier)
+    {
+        if !STOP_WORDS.contains(&word.as_str()) && !words.contains(&word) {
+            words.push(word);
+        }
+    }
+    words
+}
+
+/// The identifiers in the text with their words joined by `_`, once each.
+/// Single letters are left out.
+fn identifiers(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|token| token.chars().next().is_some_and(|c| !c.is_numeric()))
+        .map(|token| split_identifier(token).join("_"))
+        .filter(|identifier| identifier.chars().count() > 1)
+        .collect()
+}
+
+/// How well the identifiers, as stored, match the query words.
+fn score(identifiers: &str, words: &[String]) -> f32 {
+    let mut found = vec![false; words.len()];
+    for identifier in identifiers.split(' ') {
+        let parts: Vec<&str> = identifier.split('_').collect();
+        let contains = |word: &String| parts.contains(&word.as_str());
+        if words.len() > 1 && words.iter().all(contains) {
+            return WHOLE_IDENTIFIER_SCORE;
+        }
+        for (found, word) in found.iter_mut().zip(words) {
+            *found |= contains(word);
+        }
+    }
+    let share = found.iter().filter(|found| **found).count() as f32 / words.len() as f32;
+    share * WHOLE_IDENTIFIER_SCORE / 2.0
+}
+
+/// Adds the lexical `hits` missing from the vector search's `chunks` after
+/// them, at the distance of the farthest, as there's no vector distance to
+/// rank them by. Returns the scores of the hits by the id of their chunk.
+/// Hits with the text of a chunk already found, e.g. copies in other files,
+/// score for that chunk.
+pub fn fuse_identifier_hits(
+    chunks: &mut Vec<OutputChunk>,
+    hits: Vec<(OutputChunk, f32)>,
+) -> HashMap<String, f32> {
+    let farthest = chunks
+        .iter()
+        .map(|chunk| chunk.distance)
+        .fold(None, |farthest: Option<f32>, distance| {
+            Some(farthest.map_or(distance, |farthest| farthest.max(distance)))
+        })
+        .unwrap_or(1.0);
+    let mut scores = HashMap::new();
+    for (mut hit, score) in hits {
+        let found = chunks
+            .iter()
+            .find(|chunk| chunk.content_hash == hit.content_hash);
+        let id = match found {
+            Some(chunk) => chunk_id(&chunk.path, &chunk.byte),
+            None => {
+                hit.distance = farthest;
+                let id = chunk_id(&hit.path, &hit.byte);
+                chunks.push(hit);
+                id
+            }
+        };
+        let best: &mut f32 = scores.entry(id).or_default();
+        *best = best.max(score);
+    }
+    scores
+}
+
+/// Lowers the distance of the chunks with identifier `scores` by `weight`
+/// times their score and keeps the order by distance.
+pub fn boost_identifier_matches(
+    chunks: &mut [ResponseChunk],
+    scores: &HashMap<String, f32>,
+    weight: f32,
+) {
+    for chunk in chunks.iter_mut() {
+        if let Some(score) = scores.get(&chunk.id) {
+            chunk.adjust("identifiers match the query", -weight * score);
+        }
+    }
+    chunks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_identifiers_and_scores_chunks_naming_the_query() {
+        assert_eq!(
+            split_identifier("parseJWTHeader2"),
+            ["parse", "jwt", "header", "2"]
+        );
+        assert_eq!(
+            split_identifier("MAX_RETRY-count"),
+            ["max", "retry", "count"]
+        );
+        assert_eq!(
+            query_words("parse the JWT header"),
+            ["parse", "jwt", "header"]
+        );
+
+        let stored: Vec<String> = identifiers("fn parseJwtHeader(token: &str) { decode(token) }")
+            .into_iter()
+            .collect();
+        assert_eq!(stored, ["decode", "fn", "parse_jwt_header", "str", "token"]);
+        let stored = stored.join(" ");
+        let words = query_words("parse jwt header");
+        assert_eq!(score(&stored, &words), WHOLE_IDENTIFIER_SCORE);
+        let words = query_words("decode jwt claims");
+        assert_eq!(score(&stored, &words), WHOLE_IDENTIFIER_SCORE / 3.0);
+        assert_eq!(score(&stored, &query_words("retry")), 0.0);
+    }
+}
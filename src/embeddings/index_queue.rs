@@ -0,0 +1,155 @@
+use std::{
+    collections::{BinaryHeap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// How many directories of recent search hits are kept for prioritization.
+const HOT_DIRECTORY_LIMIT: usize = 32;
+
+/// Files waiting to be embedded. Explicitly prioritized files come first,
+/// then files in directories that recently produced search hits, otherwise
+/// the most recently modified file wins.
+#[derive(Default, Clone)]
+pub struct IndexQueue {
+    pending: HashSet<PathBuf>,
+    prioritized: VecDeque<PathBuf>,
+    by_modified: BinaryHeap<(SystemTime, PathBuf)>,
+    hot: BinaryHeap<(SystemTime, PathBuf)>,
+    hot_directories: VecDeque<PathBuf>,
+}
+
+impl IndexQueue {
+    pub fn push(&mut self, path: PathBuf, modified: SystemTime) {
+        if !self.pending.insert(path.clone()) {
+            return;
+        }
+        if self.is_hot(&path) {
+            self.hot.push((modified, path.clone()));
+        }
+        self.by_modified.push((modified, path));
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        // Heap entries are dropped lazily when popped.
+        self.pending.remove(path);
+    }
+
+    pub fn pop(&mut self) -> Option<PathBuf> {
+        while let Some(path) = self
+            .prioritized
+            .pop_front()
+            .or_else(|| self.hot.pop().map(|(_, path)| path))
+            .or_else(|| self.by_modified.pop().map(|(_, path)| path))
+        {
+            if self.pending.remove(&path) {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The next `count` files in the order they'd be popped.
+    pub fn peek(&self, count: usize) -> Vec<PathBuf> {
+        let mut queue = self.clone();
+        std::iter::from_fn(|| queue.pop()).take(count).collect()
+    }
+
+    /// Moves the given pending files to the front of the queue, the first one
+    /// first. Returns how many of them were pending.
+    pub fn prioritize<'p>(&mut self, paths: impl IntoIterator<Item = &'p Path>) -> usize {
+        let paths: Vec<PathBuf> = paths
+            .into_iter()
+            .filter(|path| self.pending.contains(*path))
+            .map(Path::to_path_buf)
+            .collect();
+        for path in paths.iter().rev() {
+            self.prioritized.push_front(path.clone());
+        }
+        paths.len()
+    }
+
+    /// Moves pending files in the given directories to the front of the queue.
+    pub fn prioritize_directories<'p>(&mut self, directories: impl IntoIterator<Item = &'p Path>) {
+        let mut added = false;
+        for directory in directories {
+            if self.hot_directories.iter().any(|hot| hot == directory) {
+                continue;
+            }
+            if self.hot_directories.len() == HOT_DIRECTORY_LIMIT {
+                self.hot_directories.pop_front();
+            }
+            self.hot_directories.push_back(directory.to_path_buf());
+            added = true;
+        }
+        if !added || self.pending.is_empty() {
+            return;
+        }
+        self.hot = self
+            .by_modified
+            .iter()
+            .filter(|(_, path)| self.pending.contains(path) && self.is_hot(path))
+            .cloned()
+            .collect();
+    }
+
+    fn is_hot(&self, path: &Path) -> bool {
+        path.parent()
+            .is_some_and(|parent| self.hot_directories.iter().any(|hot| hot == parent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn pops_recently_searched_directories_then_recently_modified() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut queue = IndexQueue::default();
+        queue.push("/p/a/old.rs".into(), at(1));
+        queue.push("/p/b/new.rs".into(), at(3));
+        queue.push("/p/a/newer.rs".into(), at(2));
+        queue.push("/p/c/mid.rs".into(), at(2));
+        queue.remove(Path::new("/p/c/mid.rs"));
+
+        queue.prioritize_directories([Path::new("/p/a")]);
+
+        assert_eq!(queue.pop(), Some("/p/a/newer.rs".into()));
+        assert_eq!(queue.pop(), Some("/p/a/old.rs".into()));
+        assert_eq!(queue.pop(), Some("/p/b/new.rs".into()));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn prioritized_files_come_first() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut queue = IndexQueue::default();
+        queue.push("/p/a.rs".into(), at(3));
+        queue.push("/p/b.rs".into(), at(2));
+        queue.push("/p/c.rs".into(), at(1));
+
+        let prioritized = queue.prioritize([
+            Path::new("/p/c.rs"),
+            Path::new("/p/indexed.rs"),
+            Path::new("/p/b.rs"),
+        ]);
+
+        assert_eq!(prioritized, 2);
+        assert_eq!(
+            queue.peek(5),
+            [
+                PathBuf::from("/p/c.rs"),
+                PathBuf::from("/p/b.rs"),
+                PathBuf::from("/p/a.rs")
+            ]
+        );
+        assert_eq!(queue.len(), 3);
+    }
+}
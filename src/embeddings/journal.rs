@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use rusqlite::{Connection, params};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalState {
+    /// Queued but not yet stored in the index.
+    Pending,
+    /// Stored in the index with the recorded file hash.
+    Indexed,
+}
+
+impl JournalState {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalState::Pending => "pending",
+            JournalState::Indexed => "indexed",
+        }
+    }
+}
+
+/// Records which files of an on-disk index are up to date, so a restarted
+/// daemon only queues the files that were pending or changed since. Lives
+/// in the index database, every change is committed right away.
+pub struct IndexJournal {
+    conn: Connection,
+}
+
+impl IndexJournal {
+    /// Takes its own connection to the index database.
+    pub fn new(conn: Connection) -> Result<Self> {
+        conn.execute(
+            "
+            CREATE TABLE IF NOT EXISTS journal (
+                path TEXT PRIMARY KEY,
+                file_hash TEXT NOT NULL,
+                state TEXT NOT NULL,
+                indexed_at INTEGER
+            )",
+            [],
+        )?;
+        if conn
+            .prepare("SELECT indexed_at FROM journal LIMIT 0")
+            .is_err()
+        {
+            conn.execute("ALTER TABLE journal ADD COLUMN indexed_at INTEGER", [])?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Hashes of the files the index holds the current version of, and when
+    /// they were indexed if that was recorded.
+    pub fn indexed(&self) -> Result<HashMap<PathBuf, (String, Option<SystemTime>)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, file_hash, indexed_at FROM journal WHERE state = ?")?;
+        let rows = stmt.query_map([JournalState::Indexed.as_str()], |row| {
+            let indexed_at = row
+                .get::<_, Option<u64>>(2)?
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+            Ok((
+                PathBuf::from(row.get::<_, String>(0)?),
+                (row.get(1)?, indexed_at),
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn record<'p>(
+        &self,
+        state: JournalState,
+        files: impl IntoIterator<Item = (&'p Path, &'p str)>,
+    ) -> Result<()> {
+        let indexed_at = (state == JournalState::Indexed).then(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        });
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO journal (path, file_hash, state, indexed_at) VALUES (?, ?, ?, ?)",
+            )?;
+            for (path, file_hash) in files {
+                stmt.execute(params![
+                    path.to_string_lossy(),
+                    file_hash,
+                    state.as_str(),
+                    indexed_at
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM journal WHERE path = ?",
+            [path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_files_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        {
+            let journal = IndexJournal::new(Connection::open(&path).unwrap()).unwrap();
+            journal
+                .record(
+                    JournalState::Pending,
+                    [(Path::new("/p/a.rs"), "a1"), (Path::new("/p/b.rs"), "b1")],
+                )
+                .unwrap();
+            journal
+                .record(JournalState::Indexed, [(Path::new("/p/a.rs"), "a1")])
+                .unwrap();
+        }
+
+        let journal = IndexJournal::new(Connection::open(&path).unwrap()).unwrap();
+        let indexed = journal.indexed().unwrap();
+        assert_eq!(
+            indexed.keys().collect::<Vec<_>>(),
+            [&PathBuf::from("/p/a.rs")]
+        );
+        let (hash, indexed_at) = &indexed[Path::new("/p/a.rs")];
+        assert_eq!(hash, "a1");
+        assert!(indexed_at.is_some());
+        journal.remove(Path::new("/p/a.rs")).unwrap();
+        assert!(journal.indexed().unwrap().is_empty());
+    }
+}
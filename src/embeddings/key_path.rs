@@ -0,0 +1,106 @@
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree};
+
+/// The keys leading from the document root to the smallest node holding
+/// all of `range`, joined with dots, e.g. `services.api.env`. Only YAML and
+/// JSON files have key paths.
+pub fn key_path(language: &str, tree: &Tree, source: &str, range: Range<usize>) -> Option<String> {
+    let pair_kinds: &[&str] = match language {
+        "yaml" => &["block_mapping_pair", "flow_pair"],
+        "json" => &["pair"],
+        _ => return None,
+    };
+
+    // Surrounding whitespace would widen the node to the enclosing mapping.
+    let text = &source[range.clone()];
+    let start = range.start + (text.len() - text.trim_start().len());
+    let end = (range.end - (text.len() - text.trim_end().len())).max(start);
+    let mut node = tree.root_node().descendant_for_byte_range(start, end);
+
+    let mut keys = Vec::new();
+    while let Some(current) = node {
+        if pair_kinds.contains(&current.kind())
+            && let Some(key) = current.child_by_field_name("key")
+        {
+            keys.push(key_text(key, source));
+        }
+        node = current.parent();
+    }
+    if keys.is_empty() {
+        return None;
+    }
+    keys.reverse();
+    Some(keys.join("."))
+}
+
+fn key_text(key: Node, source: &str) -> String {
+    source[key.byte_range()]
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .to_string()
+}
+
+// Only YAML and JSON have key paths.
+#[cfg(all(test, any(feature = "lang-yaml", feature = "lang-json")))]
+mod tests {
+    use super::*;
+
+    fn key_path_of(
+        language: tree_sitter::Language,
+        name: &str,
+        source: &str,
+        text: &str,
+    ) -> Option<String> {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&language).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let start = source.find(text).unwrap();
+        key_path(name, &tree, source, start..start + text.len())
+    }
+
+    #[cfg(feature = "lang-yaml")]
+    #[test]
+    fn yaml_key_paths() {
+        let source = "services:\n  api:\n    env:\n      DATABASE_URL: postgres://db\n    ports:\n      - 8080\n";
+        let yaml = || tree_sitter_yaml::LANGUAGE.into();
+        assert_eq!(
+            key_path_of(yaml(), "yaml", source, "DATABASE_URL: postgres://db\n").as_deref(),
+            Some("services.api.env.DATABASE_URL")
+        );
+        assert_eq!(
+            key_path_of(
+                yaml(),
+                "yaml",
+                source,
+                "env:\n      DATABASE_URL: postgres://db\n    ports:\n      - 8080\n"
+            )
+            .as_deref(),
+            Some("services.api")
+        );
+        assert_eq!(
+            key_path_of(yaml(), "yaml", source, source).as_deref(),
+            Some("services")
+        );
+        assert_eq!(
+            key_path_of(yaml(), "yaml", "- a\n- b\n", "- a\n- b\n"),
+            None
+        );
+    }
+
+    #[cfg(feature = "lang-json")]
+    #[test]
+    fn json_key_paths() {
+        let source = "{\n  \"compilerOptions\": {\n    \"strict\": true,\n    \"target\": \"es2022\"\n  }\n}\n";
+        assert_eq!(
+            key_path_of(
+                tree_sitter_json::LANGUAGE.into(),
+                "json",
+                source,
+                "\"target\": \"es2022\""
+            )
+            .as_deref(),
+            Some("compilerOptions.target")
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+
+use super::{
+    project_repository::{CompactionStats, OutputChunk},
+    vector_store::{ChunkFilter, ChunkRecord, VectorStore, min_distance, nearest},
+};
+
+/// Keeps embeddings in process memory and compares the query against all of
+/// them, so any embedding dimension works.
+#[derive(Default)]
+pub struct MemoryStore {
+    files: HashMap<String, Vec<(ChunkRecord, Vec<f32>)>>,
+}
+
+impl VectorStore for MemoryStore {
+    fn content_hashes(&self, path: &str) -> Result<HashMap<String, usize>> {
+        let mut hashes = HashMap::new();
+        for (chunk, _) in self.files.get(path).into_iter().flatten() {
+            *hashes.entry(chunk.content_hash.clone()).or_default() += 1;
+        }
+        Ok(hashes)
+    }
+
+    fn upsert_file(
+        &mut self,
+        path: &str,
+        chunks: &[ChunkRecord],
+        embeddings: &HashMap<String, Vec<f32>>,
+    ) -> Result<()> {
+        let mut existing: HashMap<String, Vec<Vec<f32>>> = HashMap::new();
+        for (chunk, embedding) in self.files.remove(path).into_iter().flatten() {
+            existing
+                .entry(chunk.content_hash)
+                .or_default()
+                .push(embedding);
+        }
+
+        let mut stored = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let embedding = match existing.get_mut(&chunk.content_hash).and_then(Vec::pop) {
+                Some(embedding) => embedding,
+                None => embeddings
+                    .get(&chunk.content_hash)
+                    .context("Missing embedding for new chunk")?
+                    .clone(),
+            };
+            stored.push((chunk.clone(), embedding));
+        }
+        self.files.insert(path.to_string(), stored);
+        Ok(())
+    }
+
+    fn compact(&mut self, keep: &dyn Fn(&Path) -> bool) -> Result<CompactionStats> {
+        let bytes_before = self.storage_bytes()?;
+        let mut removed_files = 0;
+        let mut removed_chunks = 0;
+        self.files.retain(|path, chunks| {
+            let kept = keep(Path::new(path));
+            if !kept {
+                removed_files += 1;
+                removed_chunks += chunks.len();
+            }
+            kept
+        });
+        self.files.shrink_to_fit();
+        Ok(CompactionStats {
+            removed_files,
+            removed_chunks,
+            bytes_before,
+            bytes_after: self.storage_bytes()?,
+        })
+    }
+
+    fn storage_bytes(&self) -> Result<u64> {
+        let bytes: usize = self
+            .files
+            .iter()
+            .map(|(path, chunks)| {
+                path.len()
+                    + chunks
+                        .iter()
+                        .map(|(chunk, embedding)| {
+                            size_of::<ChunkRecord>()
+                                + chunk.content_hash.len()
+                                + size_of_val(embedding.as_slice())
+                        })
+                        .sum::<usize>()
+            })
+            .sum();
+        Ok(bytes as u64)
+    }
+
+    fn embedding(&self, content_hash: &str) -> Result<Option<Vec<f32>>> {
+        Ok(self
+            .files
+            .values()
+            .flatten()
+            .find(|(chunk, _)| chunk.content_hash == content_hash)
+            .map(|(_, embedding)| embedding.clone()))
+    }
+
+    fn paths_with(&self, content_hash: &str) -> Result<Vec<String>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|(_, chunks)| {
+                chunks
+                    .iter()
+                    .any(|(chunk, _)| chunk.content_hash == content_hash)
+            })
+            .map(|(path, _)| path.clone())
+            .collect())
+    }
+
+    fn for_each_embedding(
+        &self,
+        visit: &mut dyn FnMut(OutputChunk, Vec<f32>) -> Result<()>,
+    ) -> Result<()> {
+        let mut paths: Vec<&String> = self.files.keys().collect();
+        paths.sort();
+        for path in paths {
+            for (chunk, embedding) in &self.files[path] {
+                visit(chunk.output(path, 0.0), embedding.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        embeddings: &[Vec<f32>],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        let scored = self
+            .files
+            .iter()
+            .flat_map(|(path, chunks)| {
+                chunks
+                    .iter()
+                    .filter(|(chunk, _)| filter.matches(Path::new(path), chunk.kind))
+                    .map(move |(chunk, chunk_embedding)| {
+                        chunk.output(path, min_distance(embeddings, chunk_embedding))
+                    })
+            })
+            .collect();
+        Ok(nearest(scored, limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embeddings::{code_splitter::TextPosition, vector_store::ChunkKind};
+
+    use super::*;
+
+    fn record(row: usize, content_hash: &str) -> ChunkRecord {
+        ChunkRecord {
+            start: TextPosition { row, column: 0 },
+            end: TextPosition {
+                row: row + 1,
+                column: 0,
+            },
+            byte: 0..1,
+            content_hash: content_hash.to_string(),
+            kind: ChunkKind::Code,
+        }
+    }
+
+    #[test]
+    fn upsert_reuses_stored_embeddings() {
+        let mut store = MemoryStore::default();
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0]),
+        ]);
+        store
+            .upsert_file("f", &[record(0, "a"), record(1, "b")], &embeddings)
+            .unwrap();
+        // "a" moved and "b" was removed, no new embeddings are needed.
+        store
+            .upsert_file("f", &[record(5, "a")], &HashMap::new())
+            .unwrap();
+
+        let results = store
+            .search(&[vec![1.0, 0.1]], 10, &ChunkFilter::default())
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].row, 5..6);
+        assert!(
+            store
+                .upsert_file("f", &[record(0, "c")], &HashMap::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn finds_copies_in_other_files() {
+        let mut store = MemoryStore::default();
+        let embeddings = HashMap::from([("a".to_string(), vec![1.0, 0.0])]);
+        store
+            .upsert_file("f", &[record(0, "a")], &embeddings)
+            .unwrap();
+        store
+            .upsert_file("vendor/f", &[record(3, "a")], &embeddings)
+            .unwrap();
+
+        assert_eq!(store.embedding("a").unwrap(), Some(vec![1.0, 0.0]));
+        assert_eq!(store.embedding("b").unwrap(), None);
+        let mut paths = store.paths_with("a").unwrap();
+        paths.sort();
+        assert_eq!(paths, ["f", "vendor/f"]);
+    }
+}
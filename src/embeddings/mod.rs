@@ -1,7 +1,62 @@
+mod blame;
+mod chunk_hits;
+mod classify;
 mod code_splitter;
+mod commit_history;
+mod coverage;
+mod definitions;
+mod embedding_export;
+mod estimate;
+pub mod eval;
+mod exclusion;
+mod expansion;
+mod explain;
+mod git_revision;
+mod identifiers;
+mod index_queue;
+mod journal;
+mod key_path;
+mod memory_store;
+mod model;
+mod permalink;
+mod preamble;
+mod preprocess;
 mod project_files;
 mod project_repository;
 mod project_service;
+mod splitter;
+mod sqlite_store;
+mod submodules;
+mod symbols;
+mod test_discovery;
+mod text_cache;
+mod text_slice;
+mod todos;
+mod vector_store;
 
-pub use project_files::ResponseChunk;
-pub use project_service::{ProjectRpcClient, ProjectService};
+pub use blame::Blame;
+pub use chunk_hits::ColdChunk;
+pub use commit_history::{Commit, CommitMatch};
+pub use coverage::{Coverage, LanguageCoverage, SkipReason, SkippedFile};
+pub use definitions::Definition;
+pub use estimate::{IndexEstimate, estimate_index};
+pub use explain::{Adjustment, Explanation};
+pub use git_revision::resolve_commit;
+pub use model::{SharedModel, embedding_model_from_name};
+pub use permalink::remote_identity;
+pub use project_files::{
+    Boundaries, ChunkSize, ResponseChunk, chunk_id, parse_chunk_id, project_directories,
+};
+pub use project_repository::{
+    CompactionStats, project_index_path, purge_removed_indexes, restore_index,
+};
+pub use project_service::{
+    ProjectInfo, ProjectRpcClient, ProjectService, QueueStatus, ResourceUsage, SearchCodeResponse,
+    SearchOptions,
+};
+pub use submodules::submodules;
+pub use symbols::SymbolTable;
+pub use test_discovery::TestMatch;
+pub use text_slice::TextSlice;
+pub use todos::Todo;
+pub use vector_store::{ChunkKind, Corpus};
@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex, OnceLock, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::{Result, anyhow, bail};
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use ort::environment::GlobalThreadPoolOptions;
+
+use crate::{
+    config::{Config, EmbeddingConfig},
+    notifications::{Event, notify},
+};
+
+/// An embedding model shared by every project using it. Loading the ONNX
+/// model takes seconds, so it happens on a background thread and callers
+/// decide whether to wait for it or report that it is still loading.
+#[derive(Clone)]
+pub struct SharedModel {
+    model_code: String,
+    dimensions: usize,
+    cache_dir: PathBuf,
+    model: Arc<OnceLock<Result<TextEmbedding, String>>>,
+    batches: Arc<Batches>,
+    encoding: Arc<RwLock<Encoding>>,
+}
+
+/// How texts are prepared for the model, see `ModelConfig`.
+#[derive(Default)]
+struct Encoding {
+    query_prefix: String,
+    passage_prefix: String,
+    query_model: Option<SharedModel>,
+}
+
+/// Limits how many texts go through the model at once, as inference memory
+/// grows with the batch.
+#[derive(Default)]
+struct Batches {
+    size: AtomicUsize,
+    /// Batches of all projects using the model that may run at once, the
+    /// rest wait for one of them to finish.
+    max_running: AtomicUsize,
+    running: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl Batches {
+    fn start(&self) -> RunningBatch<'_> {
+        let max_running = self.max_running.load(Ordering::Relaxed).max(1);
+        let mut running = self.running.lock().unwrap();
+        while *running >= max_running {
+            running = self.finished.wait(running).unwrap();
+        }
+        *running += 1;
+        RunningBatch(self)
+    }
+}
+
+struct RunningBatch<'a>(&'a Batches);
+
+impl Drop for RunningBatch<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+/// Intra- and inter-op threads of the ONNX Runtime thread pool, which is
+/// created once before the first model loads.
+static INFERENCE_THREADS: OnceLock<(usize, usize)> = OnceLock::new();
+
+fn inference_threads(config: &EmbeddingConfig) -> (usize, usize) {
+    let intra = match config.intra_op_threads {
+        0 => std::thread::available_parallelism().map_or(1, |cores| cores.get() / 2),
+        threads => threads,
+    };
+    (intra.max(1), config.inter_op_threads.max(1))
+}
+
+/// Runs the inference of every model on one pool of threads instead of
+/// each session taking all cores, so indexing leaves room for the runtime
+/// serving requests. The threads sleep rather than spin between calls.
+fn start_inference_threads((intra, inter): (usize, usize)) -> (usize, usize) {
+    let started = GlobalThreadPoolOptions::default()
+        .with_intra_threads(intra)
+        .and_then(|options| options.with_inter_threads(inter))
+        .and_then(|options| options.with_spin_control(false))
+        .and_then(|options| {
+            ort::init()
+                .with_name("local-code-search")
+                .with_global_thread_pool(options)
+                .commit()
+        });
+    match started {
+        Ok(_) => tracing::info!("inference threads: {} intra-op, {} inter-op", intra, inter),
+        Err(e) => tracing::error!("failed to start the inference thread pool: {}", e),
+    }
+    (intra, inter)
+}
+
+impl SharedModel {
+    /// The model configured in `config`, or fastembed's default.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let threads = inference_threads(&config.embedding);
+        if *INFERENCE_THREADS.get_or_init(|| start_inference_threads(threads)) != threads {
+            tracing::warn!("changed inference threads take effect after a restart");
+        }
+        let model_name = match &config.embedding.model {
+            Some(name) => embedding_model_from_name(name)?,
+            None => InitOptions::default().model_name,
+        };
+        let model = Self::get(model_name)?;
+        model.set_batching(
+            config.embedding.batch_size,
+            config.embedding.max_concurrent_batches,
+        );
+        model.set_encoding(&config.embedding)?;
+        Ok(model)
+    }
+
+    /// Returns the model, starting to load it if nothing has used it before.
+    pub fn get(model_name: EmbeddingModel) -> Result<Self> {
+        static MODELS: OnceLock<Mutex<HashMap<String, SharedModel>>> = OnceLock::new();
+
+        let model_info = TextEmbedding::get_model_info(&model_name)?;
+        let (model_code, dimensions) = (model_info.model_code.clone(), model_info.dim);
+        let mut models = MODELS.get_or_init(Default::default).lock().unwrap();
+        if let Some(model) = models.get(&model_code) {
+            return Ok(model.clone());
+        }
+
+        INFERENCE_THREADS
+            .get_or_init(|| start_inference_threads(inference_threads(&Default::default())));
+        let options = InitOptions::new(model_name);
+        let model = Self {
+            model_code,
+            dimensions,
+            cache_dir: options.cache_dir.clone(),
+            model: Arc::default(),
+            batches: Arc::default(),
+            encoding: Arc::default(),
+        };
+        let defaults = EmbeddingConfig::default();
+        model.set_batching(defaults.batch_size, defaults.max_concurrent_batches);
+        model.set_encoding(&defaults)?;
+        std::thread::spawn({
+            let cell = model.model.clone();
+            let model_code = model.model_code.clone();
+            move || {
+                tracing::info!("loading embedding model {}", model_code);
+                let loaded = TextEmbedding::try_new(options).map_err(|e| e.to_string());
+                match &loaded {
+                    Ok(_) => notify(Event::ModelLoaded { model: model_code }),
+                    Err(e) => {
+                        tracing::error!("failed to load embedding model {}: {}", model_code, e);
+                        notify(Event::ModelFailed {
+                            model: model_code,
+                            error: e.clone(),
+                        });
+                    }
+                }
+                cell.set(loaded).ok();
+            }
+        });
+        models.insert(model.model_code.clone(), model.clone());
+        Ok(model)
+    }
+
+    pub fn model_code(&self) -> &str {
+        &self.model_code
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.model.get().is_none()
+    }
+
+    /// Blocks until the model has loaded.
+    pub fn wait(&self) -> Result<&TextEmbedding> {
+        self.model
+            .wait()
+            .as_ref()
+            .map_err(|e| anyhow!("Failed to load embedding model {}: {}", self.model_code, e))
+    }
+
+    /// Applies to every project using the model.
+    fn set_batching(&self, batch_size: usize, max_concurrent_batches: usize) {
+        self.batches.size.store(batch_size, Ordering::Relaxed);
+        self.batches
+            .max_running
+            .store(max_concurrent_batches, Ordering::Relaxed);
+    }
+
+    /// Applies to every project using the model.
+    fn set_encoding(&self, config: &EmbeddingConfig) -> Result<()> {
+        let settings = config.model_settings(&self.model_code);
+        let query_model = match &settings.query_model {
+            Some(name) => {
+                let query_model = Self::get(embedding_model_from_name(name)?)?;
+                if query_model.dimensions != self.dimensions {
+                    bail!(
+                        "Query model {} has {} dimensions, {} has {}",
+                        query_model.model_code,
+                        query_model.dimensions,
+                        self.model_code,
+                        self.dimensions
+                    );
+                }
+                Some(query_model)
+            }
+            None => None,
+        };
+        *self.encoding.write().unwrap() = Encoding {
+            query_prefix: settings.query_prefix.unwrap_or_default(),
+            passage_prefix: settings.passage_prefix.unwrap_or_default(),
+            query_model,
+        };
+        Ok(())
+    }
+
+    /// What the chunks are prefixed with before they're embedded, which the
+    /// stored embeddings depend on.
+    pub fn passage_prefix(&self) -> String {
+        self.encoding.read().unwrap().passage_prefix.clone()
+    }
+
+    /// Embeds chunks of code or text to be searched, see `embed_queries` for
+    /// what they're searched with.
+    pub fn embed(&self, texts: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>> {
+        let prefix = self.passage_prefix();
+        if prefix.is_empty() {
+            return self.embed_texts(texts);
+        }
+        let texts: Vec<String> = texts
+            .iter()
+            .map(|text| format!("{prefix}{}", text.as_ref()))
+            .collect();
+        self.embed_texts(&texts)
+    }
+
+    /// Embeds search queries, with the query model if the model has one.
+    pub fn embed_queries(&self, queries: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>> {
+        let (prefix, query_model) = {
+            let encoding = self.encoding.read().unwrap();
+            (encoding.query_prefix.clone(), encoding.query_model.clone())
+        };
+        let queries: Vec<String> = queries
+            .iter()
+            .map(|query| format!("{prefix}{}", query.as_ref()))
+            .collect();
+        query_model.as_ref().unwrap_or(self).embed_texts(&queries)
+    }
+
+    /// Blocks until the model has loaded and embeds the texts a batch at a
+    /// time, waiting while too many batches are running.
+    fn embed_texts(&self, texts: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>> {
+        let model = self.wait()?;
+        let batch_size = self.batches.size.load(Ordering::Relaxed).max(1);
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(batch_size) {
+            let _running = self.batches.start();
+            let batch: Vec<&str> = batch.iter().map(AsRef::as_ref).collect();
+            embeddings.extend(model.embed(batch, Some(batch_size))?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Size of the model weights on disk, which is roughly what inference keeps resident.
+    pub fn file_size(&self) -> u64 {
+        model_file_size(&self.cache_dir, &self.model_code)
+    }
+}
+
+/// Sums the ONNX files of the model in the Hugging Face style cache fastembed downloads to.
+fn model_file_size(cache_dir: &Path, model_code: &str) -> u64 {
+    let snapshots = cache_dir
+        .join(format!("models--{}", model_code.replace('/', "--")))
+        .join("snapshots");
+    let Ok(snapshots) = std::fs::read_dir(snapshots) else {
+        return 0;
+    };
+    snapshots
+        .flatten()
+        .flat_map(|snapshot| std::fs::read_dir(snapshot.path()).into_iter().flatten())
+        .flatten()
+        .filter(|file| file.path().extension().is_some_and(|ext| ext == "onnx"))
+        .filter_map(|file| std::fs::metadata(file.path()).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+pub fn embedding_model_from_name(name: &str) -> Result<EmbeddingModel> {
+    TextEmbedding::list_supported_models()
+        .into_iter()
+        .find(|info| info.model_code.eq_ignore_ascii_case(name))
+        .map(|info| info.model)
+        .ok_or_else(|| anyhow!("Unsupported embedding model {:?}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn never_runs_more_batches_than_allowed() {
+        let batches = Batches::default();
+        batches.max_running.store(2, Ordering::Relaxed);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _batch = batches.start();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(*batches.running.lock().unwrap(), 0);
+    }
+}
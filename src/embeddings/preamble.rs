@@ -0,0 +1,149 @@
+use tree_sitter::{Node, Tree};
+
+use super::code_splitter::Chunk;
+
+/// Imports and package declarations, by their node kinds in the supported
+/// grammars.
+const IMPORT_KINDS: [&str; 16] = [
+    "use_declaration",
+    "extern_crate_declaration",
+    "inner_attribute_item",
+    "import_statement",
+    "import_from_statement",
+    "future_import_statement",
+    "import_declaration",
+    "package_declaration",
+    "package_clause",
+    "package_header",
+    "import_list",
+    "import_header",
+    "import",
+    "preproc_include",
+    "using_directive",
+    "extern_alias_directive",
+];
+
+const COMMENT_KINDS: [&str; 3] = ["comment", "line_comment", "block_comment"];
+
+/// End of the imports, package declaration and module docs the file starts
+/// with. Comments only count when something of the preamble follows them,
+/// otherwise they document the code after them.
+pub fn preamble_end(tree: &Tree, source: &str) -> Option<usize> {
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let mut end = None;
+    let mut statements = 0;
+    for node in root.named_children(&mut cursor) {
+        let kind = node.kind();
+        if COMMENT_KINDS.contains(&kind) {
+            let text = &source[node.byte_range()];
+            // Rust module docs.
+            if text.starts_with("//!") || text.starts_with("/*!") {
+                end = Some(node.end_byte());
+            }
+            continue;
+        }
+        let is_preamble = IMPORT_KINDS.contains(&kind)
+            || (statements == 0 && is_docstring(node))
+            || (kind == "mod_item" && node.child_by_field_name("body").is_none());
+        if !is_preamble {
+            break;
+        }
+        statements += 1;
+        end = Some(node.end_byte());
+    }
+    end
+}
+
+/// A string on its own as the first statement, like Python module docs.
+fn is_docstring(node: Node) -> bool {
+    node.kind() == "expression_statement"
+        && node.named_child_count() == 1
+        && node
+            .named_child(0)
+            .is_some_and(|child| child.kind() == "string")
+}
+
+/// Cuts the chunks at the end of the preamble, merging the ones before it
+/// when they fit in `max_chunk_size`. Returns the preamble chunks and the rest.
+pub fn split_off_preamble<'a>(
+    chunks: Vec<Chunk<'a>>,
+    source: &'a str,
+    end: usize,
+    max_chunk_size: usize,
+) -> (Vec<Chunk<'a>>, Vec<Chunk<'a>>) {
+    let (mut preamble, mut rest) = (Vec::new(), Vec::new());
+    for chunk in chunks {
+        if chunk.range.end <= end {
+            preamble.push(chunk);
+        } else if chunk.range.start >= end {
+            rest.push(chunk);
+        } else {
+            let head = &source[chunk.range.start..end];
+            let middle = chunk.start.advance(head);
+            preamble.push(Chunk {
+                text: head,
+                range: chunk.range.start..end,
+                start: chunk.start,
+                end: middle,
+            });
+            rest.push(Chunk {
+                text: &source[end..chunk.range.end],
+                range: end..chunk.range.end,
+                start: middle,
+                end: chunk.end,
+            });
+        }
+    }
+    if let (Some(first), Some(last)) = (preamble.first(), preamble.last())
+        && source[first.range.start..last.range.end].chars().count() <= max_chunk_size
+    {
+        let range = first.range.start..last.range.end;
+        preamble = vec![Chunk {
+            text: &source[range.clone()],
+            range,
+            start: first.start,
+            end: last.end,
+        }];
+    }
+    (preamble, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::code_splitter::CodeSplitter;
+
+    fn parse(source: &str) -> Tree {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    #[test]
+    fn splits_off_imports_and_module_docs() {
+        let source = "//! Reads things.\n\nuse std::fs;\n// Grouped.\nuse std::io;\nmod util;\n\n/// Entry point.\nfn main() {\n    println!(\"hi\");\n}\n";
+        let tree = parse(source);
+        let end = preamble_end(&tree, source).unwrap();
+        assert_eq!(
+            &source[..end],
+            "//! Reads things.\n\nuse std::fs;\n// Grouped.\nuse std::io;\nmod util;"
+        );
+
+        let chunks = CodeSplitter::new(&tree, source, 1000).chunks();
+        let (preamble, rest) = split_off_preamble(chunks, source, end, 1000);
+        assert_eq!(preamble.len(), 1);
+        assert_eq!(preamble[0].text, &source[..end]);
+        assert_eq!(rest[0].range.start, end);
+        assert_eq!(rest[0].start.row, 5);
+        assert_eq!(rest.last().unwrap().range.end, source.len());
+    }
+
+    #[test]
+    fn leaves_doc_comments_of_code_alone() {
+        let source = "/// Entry point.\nfn main() {}\n";
+        assert_eq!(preamble_end(&parse(source), source), None);
+    }
+}
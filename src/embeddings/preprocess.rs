@@ -0,0 +1,255 @@
+use std::{borrow::Cow, ops::Range};
+
+use tree_sitter::{Node, Tree};
+
+use crate::config::{CommentMode, PreprocessConfig};
+
+use super::{code_splitter::Chunk, vector_store::ChunkKind};
+
+/// A chunk along with the text the model embeds for it.
+pub struct EmbeddingChunk<'a> {
+    pub chunk: Chunk<'a>,
+    pub text: Cow<'a, str>,
+    pub kind: ChunkKind,
+}
+
+/// Applies the preprocessing to the chunks of a file. Only the embedded text
+/// changes, chunk ranges keep pointing at the original source. Code chunks
+/// left without any text are dropped.
+pub fn embedding_chunks<'a>(
+    config: &PreprocessConfig,
+    tree: &Tree,
+    source: &'a str,
+    chunks: Vec<Chunk<'a>>,
+) -> Vec<EmbeddingChunk<'a>> {
+    let comments = if config.strip_license_headers || config.comments != CommentMode::Include {
+        comment_blocks(tree, source)
+    } else {
+        Vec::new()
+    };
+    let license_header = config
+        .strip_license_headers
+        .then(|| license_header(&comments, source))
+        .flatten();
+
+    let mut removed: Vec<Range<usize>> = license_header.iter().cloned().collect();
+    if config.comments != CommentMode::Include {
+        removed.extend(comments.iter().map(|comment| comment.range.clone()));
+    }
+    removed.sort_by_key(|range| range.start);
+
+    let docs = match config.comments {
+        CommentMode::Separate => comments
+            .into_iter()
+            .filter(|comment| Some(&comment.range) != license_header.as_ref())
+            .collect(),
+        _ => Vec::new(),
+    };
+    let code = chunks.into_iter().filter_map(|chunk| {
+        let text = without_ranges(&chunk, &removed);
+        if matches!(text, Cow::Owned(_)) && text.trim().is_empty() {
+            return None;
+        }
+        Some(EmbeddingChunk {
+            chunk,
+            text,
+            kind: ChunkKind::Code,
+        })
+    });
+    let docs = docs.into_iter().map(|chunk| EmbeddingChunk {
+        text: Cow::Borrowed(chunk.text),
+        chunk,
+        kind: ChunkKind::Doc,
+    });
+
+    code.chain(docs)
+        .map(|mut chunk| {
+            if config.collapse_whitespace {
+                chunk.text =
+                    Cow::Owned(chunk.text.split_whitespace().collect::<Vec<_>>().join(" "));
+            }
+            chunk
+        })
+        .collect()
+}
+
+/// The chunk text minus the parts of it covered by `removed`, which is sorted by start.
+fn without_ranges<'a>(chunk: &Chunk<'a>, removed: &[Range<usize>]) -> Cow<'a, str> {
+    let offset = chunk.range.start;
+    let mut overlapping = removed
+        .iter()
+        .filter(|range| range.start < chunk.range.end && range.end > chunk.range.start)
+        .map(|range| {
+            range.start.max(chunk.range.start) - offset..range.end.min(chunk.range.end) - offset
+        })
+        .peekable();
+    if overlapping.peek().is_none() {
+        return Cow::Borrowed(chunk.text);
+    }
+
+    let mut text = String::with_capacity(chunk.text.len());
+    let mut kept_from = 0;
+    for range in overlapping {
+        if range.start > kept_from {
+            text.push_str(&chunk.text[kept_from..range.start]);
+        }
+        kept_from = kept_from.max(range.end);
+    }
+    text.push_str(&chunk.text[kept_from..]);
+    Cow::Owned(text)
+}
+
+fn is_comment(node: Node) -> bool {
+    node.kind().ends_with("comment")
+}
+
+pub(super) fn comments<'a>(tree: &Tree, source: &'a str) -> Vec<Chunk<'a>> {
+    let mut comments = Vec::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if is_comment(node) {
+            comments.push(Chunk {
+                text: &source[node.byte_range()],
+                range: node.byte_range(),
+                start: node.start_position().into(),
+                end: node.end_position().into(),
+            });
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return comments;
+            }
+        }
+    }
+}
+
+/// Comments merged with the ones directly below them, a blank line or code
+/// in between starts a new block.
+fn comment_blocks<'a>(tree: &Tree, source: &'a str) -> Vec<Chunk<'a>> {
+    let mut blocks: Vec<Chunk> = Vec::new();
+    for comment in comments(tree, source) {
+        if let Some(block) = blocks.last_mut() {
+            let gap = &source[block.range.end..comment.range.start];
+            // Some grammars include the newline ending a line comment.
+            let newlines = gap.matches('\n').count() + usize::from(block.text.ends_with('\n'));
+            if gap.trim().is_empty() && newlines <= 1 {
+                block.range.end = comment.range.end;
+                block.text = &source[block.range.clone()];
+                block.end = comment.end;
+                continue;
+            }
+        }
+        blocks.push(comment);
+    }
+    blocks
+}
+
+/// The comment block the file starts with, if it mentions a license or copyright.
+fn license_header(comments: &[Chunk], source: &str) -> Option<Range<usize>> {
+    let header = comments.first()?;
+    if !source[..header.range.start].trim().is_empty() {
+        return None;
+    }
+    let text = header.text.to_lowercase();
+    (text.contains("license") || text.contains("copyright")).then(|| header.range.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::code_splitter::CodeSplitter;
+
+    const SOURCE: &str = "// Copyright 2024 Example Corp.\n// Licensed under the MIT license.\n\n/// Adds the numbers.\nfn add(a: i32,   b: i32) -> i32 {\n    a + b // no overflow check\n}\n";
+
+    fn preprocess(config: &PreprocessConfig) -> Vec<(Range<usize>, String, ChunkKind)> {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        let tree = parser.parse(SOURCE, None).unwrap();
+        let chunks = CodeSplitter::new(&tree, SOURCE, 1000).chunks();
+        embedding_chunks(config, &tree, SOURCE, chunks)
+            .into_iter()
+            .map(|chunk| (chunk.chunk.range, chunk.text.into_owned(), chunk.kind))
+            .collect()
+    }
+
+    #[test]
+    fn preprocessing_changes_only_the_embedded_text() {
+        let original = preprocess(&PreprocessConfig::default());
+        let processed = preprocess(&PreprocessConfig {
+            collapse_whitespace: true,
+            strip_license_headers: true,
+            comments: CommentMode::Exclude,
+        });
+
+        assert_eq!(
+            original
+                .iter()
+                .map(|(_, text, _)| text.as_str())
+                .collect::<String>(),
+            SOURCE
+        );
+        assert!(
+            processed
+                .iter()
+                .all(|(range, _, _)| original.iter().any(|(original, _, _)| original == range))
+        );
+        let embedded: Vec<&str> = processed.iter().map(|(_, text, _)| text.as_str()).collect();
+        assert_eq!(
+            embedded.join(" "),
+            "fn add(a: i32, b: i32) -> i32 { a + b }"
+        );
+    }
+
+    #[test]
+    fn license_header_ends_at_blank_line() {
+        let processed = preprocess(&PreprocessConfig {
+            strip_license_headers: true,
+            ..Default::default()
+        });
+        assert!(
+            processed
+                .iter()
+                .all(|(_, text, _)| !text.contains("Copyright"))
+        );
+        assert!(
+            processed
+                .iter()
+                .any(|(_, text, _)| text.contains("/// Adds"))
+        );
+    }
+
+    #[test]
+    fn separate_comments_become_doc_chunks() {
+        let processed = preprocess(&PreprocessConfig {
+            comments: CommentMode::Separate,
+            ..Default::default()
+        });
+        let docs: Vec<&str> = processed
+            .iter()
+            .filter(|(_, _, kind)| *kind == ChunkKind::Doc)
+            .map(|(range, text, _)| {
+                assert_eq!(&SOURCE[range.clone()], text);
+                text.trim_end()
+            })
+            .collect();
+        assert_eq!(
+            docs,
+            [
+                "// Copyright 2024 Example Corp.\n// Licensed under the MIT license.",
+                "/// Adds the numbers.",
+                "// no overflow check",
+            ]
+        );
+        assert!(
+            processed
+                .iter()
+                .filter(|(_, _, kind)| *kind == ChunkKind::Code)
+                .all(|(_, text, _)| !text.contains("//"))
+        );
+    }
+}
@@ -1,159 +1,889 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use blake2::{Blake2b512, Digest};
-use ignore::Walk;
+use ignore::{DirEntry, WalkBuilder, WalkState};
 use std::{
     collections::{HashMap, HashSet},
-    ops::Range,
+    ops::{Range, RangeInclusive},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+    time::SystemTime,
 };
 use tree_sitter::{Language, Tree};
 
+use crate::{
+    config::{ChunkingConfig, ColumnEncoding, Config, PreprocessConfig, SubmodulePolicy},
+    context::estimate_tokens,
+    notifications::{Event, notify},
+};
+
 use super::{
-    code_splitter::{Chunk, CodeSplitter},
+    blame::Blame,
+    classify::classify,
+    explain::{Adjustment, Explanation},
+    git_revision::{read_file, read_tree},
+    key_path::key_path,
+    preamble::{preamble_end, split_off_preamble},
+    preprocess::{EmbeddingChunk, embedding_chunks},
     project_repository::OutputChunk,
+    splitter::splitter_for,
+    submodules::is_submodule,
+    text_cache::TextCache,
+    text_slice::TextSlice,
+    todos::{Todo, extract_todos},
+    vector_store::ChunkKind,
 };
 
+/// Rough heap size of a tree-sitter node, tree-sitter doesn't report its memory use.
+pub(super) const TREE_NODE_BYTES: usize = 64;
+
 pub struct ProjectFiles {
     files: HashMap<PathBuf, ProjectFile>,
+    chunking: ChunkingConfig,
+    preprocess: PreprocessConfig,
+    /// The commit the files were read from, if not the working tree.
+    commit: Option<String>,
+    column_encoding: ColumnEncoding,
+    /// Contents of the files used lately, others are read again when needed.
+    texts: Mutex<TextCache>,
 }
 
 impl ProjectFiles {
-    pub fn new(path: PathBuf) -> Result<Self> {
+    /// Reads and parses the files on `walk_threads` threads, see
+    /// `walk_supported_files`. Only `text_budget` bytes of their contents
+    /// are kept.
+    pub fn new(
+        path: PathBuf,
+        chunking: ChunkingConfig,
+        preprocess: PreprocessConfig,
+        walk_threads: usize,
+        follow_symlinks: bool,
+        boundaries: &Boundaries,
+        text_budget: usize,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let texts = Mutex::new(TextCache::new(text_budget));
+        let walk = walker(&path, follow_symlinks, boundaries);
+        walk_supported_files(walk, walk_threads, |path_buf| {
+            let file = path_buf.and_then(|path_buf| {
+                let (file, text) = ProjectFile::new(path_buf.clone())
+                    .with_context(|| format!("Failed to read {:?}", path_buf))?;
+                texts.lock().unwrap().insert(path_buf.clone(), text);
+                Ok((path_buf, file))
+            });
+            match file {
+                // Deleted or renamed since the walk found it, the watcher
+                // reports where it went.
+                Err(e) if is_not_found(&e) => {
+                    tracing::warn!("skipping a file that disappeared while walking: {}", e);
+                    true
+                }
+                Err(e) => {
+                    tx.send(Err(e)).ok();
+                    false
+                }
+                Ok(file) => tx.send(Ok(file)).is_ok(),
+            }
+        });
+        drop(tx);
+        let files = rx.into_iter().collect::<Result<HashMap<_, _>>>()?;
+        Ok(Self {
+            files,
+            chunking,
+            preprocess,
+            commit: None,
+            column_encoding: ColumnEncoding::default(),
+            texts,
+        })
+    }
+
+    /// The files under `root` as of the commit, read from the git object
+    /// store without checking it out. Files that fail to parse are skipped.
+    pub fn at_commit(
+        root: &Path,
+        commit: String,
+        chunking: ChunkingConfig,
+        preprocess: PreprocessConfig,
+        text_budget: usize,
+    ) -> Result<Self> {
         let mut files = HashMap::new();
-        let supported_extensions =
-            HashSet::from(["rs", "ts", "tsx", "py", "java", "kt", "json", "yaml", "yml"]);
-        for result in Walk::new(path) {
-            let entry = result?;
-            if entry.path().is_file()
-                && supported_extensions.contains(
-                    &entry
-                        .path()
-                        .extension()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or_default(),
-                )
-            {
-                let path_buf: PathBuf = entry.path().into();
-                let file = ProjectFile::new(path_buf.clone())?;
-                files.insert(path_buf, file);
+        let mut texts = TextCache::new(text_budget);
+        for (path, text) in read_tree(root, &commit)? {
+            let path = root.join(path);
+            match ProjectFile::parse(path.clone(), &text) {
+                Ok(file) => {
+                    texts.insert(path.clone(), text.into());
+                    files.insert(path, file);
+                }
+                Err(e) => {
+                    tracing::warn!("skipping {:?} at {}: {:?}", path, commit, e);
+                    notify(Event::FileFailed {
+                        path,
+                        error: format!("{e:#}"),
+                    });
+                }
             }
         }
-        Ok(Self { files })
+        Ok(Self {
+            files,
+            chunking,
+            preprocess,
+            commit: Some(commit),
+            column_encoding: ColumnEncoding::default(),
+            texts: Mutex::new(texts),
+        })
+    }
+
+    pub fn commit(&self) -> Option<&str> {
+        self.commit.as_deref()
+    }
+
+    pub fn set_chunking(&mut self, chunking: ChunkingConfig, preprocess: PreprocessConfig) {
+        self.chunking = chunking;
+        self.preprocess = preprocess;
+    }
+
+    /// What the columns of the chunks returned count, bytes by default.
+    pub fn set_column_encoding(&mut self, column_encoding: ColumnEncoding) {
+        self.column_encoding = column_encoding;
+    }
+
+    /// Keeps the contents of files searches returned in memory over others.
+    pub fn mark_hit(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.texts.lock().unwrap().mark_hit(paths);
+    }
+
+    pub fn set_text_budget(&mut self, text_budget: usize) {
+        self.texts.get_mut().unwrap().set_budget(text_budget);
     }
 
     pub fn create_or_update(&mut self, file_path: &Path) -> Result<()> {
-        match self.files.get_mut(file_path) {
-            Some(file) => file.update(),
+        let text = match self.files.get_mut(file_path) {
+            Some(file) => file.update()?,
             _ => {
-                let file = ProjectFile::new(file_path.to_path_buf())?;
+                let (file, text) = ProjectFile::new(file_path.to_path_buf())?;
                 self.files.insert(file_path.to_path_buf(), file);
-                Ok(())
+                text
             }
+        };
+        self.texts
+            .get_mut()
+            .unwrap()
+            .insert(file_path.to_path_buf(), text);
+        Ok(())
+    }
+
+    /// Returns the bytes of file text held in memory and an estimate of the
+    /// memory used by their syntax trees.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let trees = self.files.values().fold(0, |tree, file| {
+            tree + file.tree.root_node().descendant_count() * TREE_NODE_BYTES
+        });
+        (self.texts.lock().unwrap().bytes(), trees)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+
+    /// Forgets files that no longer exist on disk and returns their paths.
+    /// Files read from a commit don't go missing.
+    pub fn remove_missing(&mut self) -> Vec<PathBuf> {
+        if self.commit.is_some() {
+            return Vec::new();
+        }
+        let missing: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| !path.is_file())
+            .cloned()
+            .collect();
+        let texts = self.texts.get_mut().unwrap();
+        for path in &missing {
+            self.files.remove(path);
+            texts.forget(path);
         }
+        missing
+    }
+
+    /// Hex encoded hash of the file contents as last read.
+    pub fn file_hash(&self, file_path: &Path) -> Option<String> {
+        let file = self.files.get(file_path)?;
+        Some(file.hash.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+
+    /// Whether the file on disk still has the contents last read.
+    pub fn is_current(&self, file_path: &Path) -> Result<bool> {
+        let Some(file) = self.files.get(file_path) else {
+            return Ok(false);
+        };
+        Ok(hash_file(&std::fs::read_to_string(file_path)?) == file.hash)
     }
 
-    pub fn all_chunks(&self) -> Vec<(PathBuf, Vec<Chunk>)> {
+    /// Whether the file is empty on disk although it had contents when last
+    /// read, e.g. truncated by a tool about to write it again.
+    pub fn was_emptied(&self, file_path: &Path) -> bool {
+        self.files.get(file_path).is_some_and(|file| {
+            file.hash != hash_file("")
+                && std::fs::metadata(file_path).is_ok_and(|metadata| metadata.len() == 0)
+        })
+    }
+
+    /// Records that the index holds the file's contents as last read.
+    pub fn mark_indexed(&mut self, file_path: &Path, at: Option<SystemTime>) {
+        if let Some(file) = self.files.get_mut(file_path) {
+            file.indexed = Some(IndexedVersion {
+                at,
+                hash: file.hash.clone(),
+            });
+        }
+    }
+
+    /// When a file of the project was last indexed, if any was this run or
+    /// the journal knows.
+    pub fn last_indexed(&self) -> Option<SystemTime> {
         self.files
-            .iter()
-            .map(|(path, file)| (path.clone(), file.chunks()))
-            .collect()
+            .values()
+            .filter_map(|file| file.indexed.as_ref()?.at)
+            .max()
+    }
+
+    /// The file's contents as last read. Reads them again if they were
+    /// dropped from memory, unless the file changed since, which the watcher
+    /// or the next verification picks up.
+    pub fn text(&self, file_path: &Path) -> Option<Arc<str>> {
+        self.read_text(file_path, true)
+    }
+
+    /// The file's contents like `text`, for passes over many files, e.g.
+    /// indexing. Unlike searches they don't keep texts in memory, read
+    /// evicted ones aren't cached again.
+    pub fn peek_text(&self, file_path: &Path) -> Option<Arc<str>> {
+        self.read_text(file_path, false)
+    }
+
+    fn read_text(&self, file_path: &Path, used: bool) -> Option<Arc<str>> {
+        let file = self.files.get(file_path)?;
+        let cached = if used {
+            self.texts.lock().unwrap().get(file_path)
+        } else {
+            self.texts.lock().unwrap().peek(file_path)
+        };
+        if cached.is_some() {
+            return cached;
+        }
+        let text = match &self.commit {
+            Some(commit) => read_file(file_path, commit),
+            None => std::fs::read_to_string(file_path).map_err(anyhow::Error::from),
+        };
+        let text: Arc<str> = match text {
+            Ok(text) if hash_file(&text) == file.hash => text.into(),
+            Ok(_) => return None,
+            Err(e) => {
+                tracing::warn!("failed to read {:?} again: {:?}", file_path, e);
+                return None;
+            }
+        };
+        if used {
+            self.texts
+                .lock()
+                .unwrap()
+                .insert(file_path.to_path_buf(), text.clone());
+        }
+        Some(text)
+    }
+
+    /// The file's contents with its syntax tree.
+    pub fn syntax(&self, file_path: &Path) -> Option<(Arc<str>, &Tree)> {
+        let text = self.text(file_path)?;
+        Some((text, &self.files.get(file_path)?.tree))
+    }
+
+    /// TODO-style notes of all files matching `filter`, ordered by location.
+    pub fn todos(&self, filter: &str) -> Vec<Todo> {
+        let mut todos: Vec<Todo> = self
+            .files
+            .values()
+            .flat_map(|file| &file.todos)
+            .filter(|todo| todo.matches(filter))
+            .cloned()
+            .collect();
+        todos.sort_by(|a, b| (&a.path, a.row, a.column).cmp(&(&b.path, b.row, b.column)));
+        todos
+    }
+
+    /// Whether the index holds the file's contents as last read.
+    pub fn is_indexed(&self, file_path: &Path) -> bool {
+        self.files.get(file_path).is_some_and(|file| {
+            file.indexed
+                .as_ref()
+                .is_some_and(|indexed| indexed.hash == file.hash)
+        })
+    }
+
+    pub fn contains(&self, file_path: &Path) -> bool {
+        self.files.contains_key(file_path)
     }
 
-    pub fn file_chunks(&self, file_path: &Path) -> Vec<Chunk> {
+    /// The chunks of the file, given its contents from `text`.
+    pub fn file_chunks<'a>(&'a self, file_path: &Path, text: &'a str) -> Vec<EmbeddingChunk<'a>> {
         let Some(file) = self.files.get(file_path) else {
             return vec![];
         };
-        file.chunks()
+        file.chunks(text, &self.chunking, &self.preprocess)
     }
 
-    pub fn chunks_to_response(&self, chunks: Vec<OutputChunk>) -> Vec<ResponseChunk> {
-        chunks
-            .into_iter()
-            .flat_map(|chunk| {
-                let file = self.files.get(&chunk.path)?;
-                Some(ResponseChunk {
-                    content: file.text[chunk.byte.start..chunk.byte.end].into(),
-                    path: chunk.path,
-                    row: chunk.row,
-                    column: chunk.column,
-                })
+    /// Reads the file from disk to tell whether the chunk is stale, unless
+    /// the files were read from a commit. None if the chunk doesn't fit the
+    /// file as last read, which changed since it was indexed.
+    pub fn chunk_to_response(&self, chunk: OutputChunk) -> Option<ResponseChunk> {
+        let file = self.files.get(&chunk.path)?;
+        let text = self.text(&chunk.path)?;
+        let indexed_hash = file
+            .indexed
+            .as_ref()
+            .map_or(&file.hash, |indexed| &indexed.hash);
+        let stale = self.commit.is_none()
+            && std::fs::read_to_string(&chunk.path)
+                .is_ok_and(|text| hash_file(&text) != *indexed_hash);
+        let content = TextSlice::get(text.clone(), chunk.byte.clone())?;
+        Some(ResponseChunk {
+            id: chunk_id(&chunk.path, &chunk.byte),
+            size: ChunkSize::of(&content),
+            content,
+            path: chunk.path,
+            row: chunk.row,
+            column: self.encode_column(&text, chunk.byte.start, chunk.column.start)
+                ..self.encode_column(&text, chunk.byte.end, chunk.column.end),
+            key_path: key_path(file.language, &file.tree, &text, chunk.byte),
+            kind: chunk.kind,
+            distance: chunk.distance,
+            indexed_at: file.indexed.as_ref().and_then(|indexed| indexed.at),
+            stale,
+            blame: None,
+            permalink: None,
+            duplicates: chunk.duplicates,
+            explanation: None,
+            commit: self.commit.clone(),
+        })
+    }
+
+    /// The byte column `column` of byte `byte` in the configured units. Kept
+    /// in bytes if the file changed so the byte is no longer on a character.
+    fn encode_column(&self, text: &str, byte: usize, column: usize) -> usize {
+        byte.checked_sub(column)
+            .and_then(|line_start| text.get(line_start..byte))
+            .map_or(column, |line_prefix| {
+                self.column_encoding.column(line_prefix)
             })
+    }
+
+    /// The chunks of the file around the one containing byte `at`, in file
+    /// order: up to `before` chunks ahead of it, the chunk itself and up to
+    /// `after` following it. Chunks are cut from the file as last read.
+    pub fn neighbors(
+        &self,
+        file_path: &Path,
+        at: usize,
+        before: usize,
+        after: usize,
+    ) -> Vec<ResponseChunk> {
+        let Some(text) = self.text(file_path) else {
+            return Vec::new();
+        };
+        let mut chunks = self.file_chunks(file_path, &text);
+        chunks.sort_by_key(|chunk| (chunk.chunk.range.start, chunk.chunk.range.end));
+        let Some(index) = chunks
+            .iter()
+            .position(|chunk| chunk.chunk.range.contains(&at))
+        else {
+            return Vec::new();
+        };
+        let neighbors =
+            &chunks[index.saturating_sub(before)..(index + after + 1).min(chunks.len())];
+        neighbors
+            .iter()
+            .map(|chunk| OutputChunk {
+                path: file_path.to_path_buf(),
+                row: chunk.chunk.start.row..chunk.chunk.end.row,
+                column: chunk.chunk.start.column..chunk.chunk.end.column,
+                byte: chunk.chunk.range.clone(),
+                kind: chunk.kind,
+                content_hash: String::new(),
+                distance: 0.0,
+                duplicates: Vec::new(),
+            })
+            .filter_map(|chunk| self.chunk_to_response(chunk))
             .collect()
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct ResponseChunk {
     pub path: PathBuf,
     pub row: Range<usize>,
     pub column: Range<usize>,
-    pub content: String,
+    /// Points into the file contents held in memory rather than copying them.
+    pub content: TextSlice,
+    /// For YAML and JSON chunks, the keys leading to the chunk, e.g. `services.api.env`.
+    pub key_path: Option<String>,
+    pub kind: ChunkKind,
+    /// Distance to the query, only comparable between chunks of the same search.
+    pub distance: f32,
+    /// When the file was indexed, if known.
+    pub indexed_at: Option<SystemTime>,
+    /// The file changed on disk since it was indexed, so `content` and the
+    /// position may be out of date and the file should be re-read.
+    pub stale: bool,
+    /// Set when requested with `SearchOptions::blame`.
+    pub blame: Option<Blame>,
+    /// Link to the lines on the git remote, with `permalinks.enabled`.
+    pub permalink: Option<String>,
+    /// Other files with an identical copy of the chunk, e.g. vendored code.
+    pub duplicates: Vec<PathBuf>,
+    /// Set when requested with `SearchOptions::explain`.
+    pub explanation: Option<Explanation>,
+    /// The commit `content` is from, for projects registered at a revision.
+    /// The file may differ or not exist in the working tree.
+    pub commit: Option<String>,
+    /// Identifies the chunk for `chunk_neighbors`.
+    pub id: String,
+    /// How much of a prompt `content` takes.
+    pub size: ChunkSize,
+}
+
+/// The size of a result's contents, to budget how many results fit in a
+/// prompt without tokenizing them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkSize {
+    pub lines: usize,
+    /// Estimated from the length, see `estimate_tokens`.
+    pub approx_tokens: usize,
+}
+
+impl ChunkSize {
+    pub fn of(content: &str) -> Self {
+        Self {
+            lines: content.lines().count(),
+            approx_tokens: estimate_tokens(content),
+        }
+    }
+}
+
+impl ResponseChunk {
+    /// The rows the chunk covers, a chunk ending at the start of a row
+    /// doesn't include it.
+    pub fn rows(&self) -> RangeInclusive<usize> {
+        if self.column.end == 0 && self.row.end > self.row.start {
+            self.row.start..=self.row.end - 1
+        } else {
+            self.row.start..=self.row.end
+        }
+    }
+
+    /// Changes the distance, recording why if the result is explained.
+    pub fn adjust(&mut self, reason: impl Into<String>, delta: f32) {
+        self.distance += delta;
+        if let Some(explanation) = &mut self.explanation {
+            explanation.adjustments.push(Adjustment {
+                reason: reason.into(),
+                delta,
+            });
+        }
+    }
+}
+
+/// `path:start-end` with the byte range of the chunk.
+pub fn chunk_id(path: &Path, byte: &Range<usize>) -> String {
+    format!("{}:{}-{}", path.to_string_lossy(), byte.start, byte.end)
+}
+
+/// The path and byte range in a chunk id.
+pub fn parse_chunk_id(id: &str) -> Option<(PathBuf, Range<usize>)> {
+    let (path, range) = id.rsplit_once(':')?;
+    let (start, end) = range.split_once('-')?;
+    Some((path.into(), start.parse().ok()?..end.parse().ok()?))
 }
 
 struct ProjectFile {
     parser: tree_sitter::Parser,
+    language: &'static str,
     path: String,
-    text: String,
     hash: Vec<u8>,
     tree: Tree,
+    indexed: Option<IndexedVersion>,
+    todos: Vec<Todo>,
+}
+
+/// The version of a file the index holds.
+struct IndexedVersion {
+    at: Option<SystemTime>,
+    hash: Vec<u8>,
 }
 
 impl ProjectFile {
-    pub fn new(path: PathBuf) -> Result<Self> {
+    /// Reads and parses the file, returning its contents along with it.
+    pub fn new(path: PathBuf) -> Result<(Self, Arc<str>)> {
+        let text = std::fs::read_to_string(&path)?;
+        let file = Self::parse(path, &text)?;
+        Ok((file, text.into()))
+    }
+
+    fn parse(path: PathBuf, text: &str) -> Result<Self> {
         let mut parser = tree_sitter::Parser::new();
-        let Some(language) =
-            ext_to_language(&path.extension().unwrap_or_default().to_string_lossy())
-        else {
+        let Some((language_name, language)) = language_for(&path) else {
             return Err(anyhow::anyhow!("Unsupported file extension {:?}", path));
         };
         parser.set_language(&language)?;
 
-        let text = std::fs::read_to_string(&path)?;
-        let Some(tree) = parser.parse(&text, None) else {
+        let Some(tree) = parser.parse(text, None) else {
             return Err(anyhow::anyhow!("Failed to parse {:?}", path));
         };
-        let hash = hash_file(&text);
+        let hash = hash_file(text);
+        let todos = extract_todos(&path, &tree, text);
         Ok(Self {
             parser,
+            language: language_name,
             path: path.to_string_lossy().to_string(),
-            text,
             hash,
             tree,
+            indexed: None,
+            todos,
         })
     }
 
-    pub fn update(&mut self) -> Result<()> {
+    /// Reads and parses the file again, returning its new contents.
+    pub fn update(&mut self) -> Result<Arc<str>> {
         let file_contents = std::fs::read_to_string(&self.path)?;
-        let Some(new_tree) = self.parser.parse(&file_contents, Some(&self.tree)) else {
+        // The old tree can't be reused without the edits that produced the new
+        // contents, so reparse from scratch.
+        let Some(new_tree) = self.parser.parse(&file_contents, None) else {
             return Err(anyhow::anyhow!("Failed to parse {:?}", self.path));
         };
         self.hash = hash_file(&file_contents);
+        self.todos = extract_todos(Path::new(&self.path), &new_tree, &file_contents);
         self.tree = new_tree;
-        Ok(())
+        Ok(file_contents.into())
+    }
+
+    /// `text` must be the contents the file was parsed from.
+    pub fn chunks<'a>(
+        &'a self,
+        text: &'a str,
+        chunking: &ChunkingConfig,
+        preprocess: &PreprocessConfig,
+    ) -> Vec<EmbeddingChunk<'a>> {
+        let chunks = splitter_for(chunking, self.language).split(&self.tree, text);
+        let (preamble, mut chunks) = match preamble_end(&self.tree, text) {
+            Some(end) => split_off_preamble(chunks, text, end, chunking.max_chunk_size),
+            None => (Vec::new(), chunks),
+        };
+        let preamble_ranges: Vec<Range<usize>> =
+            preamble.iter().map(|chunk| chunk.range.clone()).collect();
+        chunks.splice(0..0, preamble);
+        let mut chunks = embedding_chunks(preprocess, &self.tree, text, chunks);
+        for chunk in &mut chunks {
+            if chunk.kind == ChunkKind::Code && preamble_ranges.contains(&chunk.chunk.range) {
+                chunk.kind = ChunkKind::Preamble;
+            } else if chunk.kind == ChunkKind::Code {
+                chunk.kind = classify(
+                    self.language,
+                    Path::new(&self.path),
+                    &self.tree,
+                    &chunk.chunk,
+                );
+            }
+            // Config values rarely say what they configure, their keys do.
+            if let Some(key_path) =
+                key_path(self.language, &self.tree, text, chunk.chunk.range.clone())
+            {
+                chunk.text = format!("{key_path}\n{}", chunk.text).into();
+            }
+        }
+        chunks
+    }
+}
+
+/// Files under `root` in a supported language, skipping ignored ones and
+/// ones past the project's boundaries. Files reachable through several
+/// links are listed once.
+pub fn supported_files(
+    root: &Path,
+    follow_symlinks: bool,
+    boundaries: &Boundaries,
+) -> impl Iterator<Item = Result<PathBuf>> {
+    let seen = SeenFiles::default();
+    walker(root, follow_symlinks, boundaries)
+        .build()
+        .filter_map(move |result| match result {
+            Ok(entry) => (is_supported(&entry) && seen.first_sight(entry.path()))
+                .then(|| Ok(entry.into_path())),
+            Err(e) if is_loop(&e) => {
+                tracing::warn!("skipping symlink loop: {}", e);
+                None
+            }
+            Err(e) => Some(Err(e.into())),
+        })
+}
+
+/// Every file under `root` the walk for its files finds, supported or not.
+pub fn walked_files(root: &Path, follow_symlinks: bool, boundaries: &Boundaries) -> Vec<PathBuf> {
+    walker(root, follow_symlinks, boundaries)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        })
+        .map(DirEntry::into_path)
+        .collect()
+}
+
+/// The directories under `root` the walk for its files enters, e.g. to watch
+/// one by one, leaving out ignored ones and ones past the boundaries.
+pub fn project_directories(
+    root: &Path,
+    follow_symlinks: bool,
+    boundaries: &Boundaries,
+) -> Vec<PathBuf> {
+    walker(root, follow_symlinks, boundaries)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir())
+        })
+        .map(DirEntry::into_path)
+        .collect()
+}
+
+/// Like `supported_files`, calling `f` from `threads` threads as files are
+/// found, or as many as fit the machine if 0. Stops early once `f` returns
+/// false.
+fn walk_supported_files(
+    mut walk: WalkBuilder,
+    threads: usize,
+    f: impl Fn(Result<PathBuf>) -> bool + Sync,
+) {
+    let seen = SeenFiles::default();
+    walk.threads(threads).build_parallel().run(|| {
+        let (f, seen) = (&f, &seen);
+        Box::new(move |result| {
+            let keep_going = match result {
+                Ok(entry) if is_supported(&entry) && seen.first_sight(entry.path()) => {
+                    f(Ok(entry.into_path()))
+                }
+                Ok(_) => true,
+                Err(e) if is_loop(&e) => {
+                    tracing::warn!("skipping symlink loop: {}", e);
+                    true
+                }
+                Err(e) => f(Err(e.into())),
+            };
+            if keep_going {
+                WalkState::Continue
+            } else {
+                WalkState::Quit
+            }
+        })
+    });
+}
+
+/// Where a project ends below its root: directories that are projects of
+/// their own.
+#[derive(Debug, Clone, Default)]
+pub struct Boundaries {
+    pub stop_markers: Vec<String>,
+    /// Git submodules are left out too.
+    pub submodules: bool,
+}
+
+impl Boundaries {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            stop_markers: config.stop_markers.clone(),
+            submodules: config.submodules != SubmodulePolicy::Index,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.stop_markers.is_empty() && !self.submodules
+    }
+
+    fn bound(&self, dir: &Path) -> bool {
+        self.stop_markers
+            .iter()
+            .any(|marker| dir.join(marker).exists())
+            || (self.submodules && is_submodule(dir))
+    }
+
+    /// Whether the file is in a directory below `root` that is a project of
+    /// its own.
+    pub fn excludes(&self, root: &Path, path: &Path) -> bool {
+        !self.is_empty()
+            && path
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| *dir != root && dir.starts_with(root))
+                .any(|dir| self.bound(dir))
+    }
+}
+
+/// Symlinks are skipped unless followed, the walker stops at ones leading
+/// back to a directory it's in and at the project's boundaries.
+fn walker(root: &Path, follow_symlinks: bool, boundaries: &Boundaries) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(root);
+    builder.follow_links(follow_symlinks);
+    if !boundaries.is_empty() {
+        let boundaries = boundaries.clone();
+        builder.filter_entry(move |entry| {
+            entry.depth() == 0
+                || !entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_dir())
+                || !boundaries.bound(entry.path())
+        });
     }
+    builder
+}
+
+fn is_supported(entry: &DirEntry) -> bool {
+    // Symlinks that aren't followed have the symlink file type.
+    entry
+        .file_type()
+        .is_some_and(|file_type| file_type.is_file())
+        && has_supported_extension(entry.path())
+}
+
+/// Whether files like this one are indexed.
+pub(super) fn has_supported_extension(path: &Path) -> bool {
+    let supported_extensions = ["rs", "ts", "tsx", "py", "java", "kt", "json", "yaml", "yml"];
+    let ext = extension_of(path).unwrap_or_default();
+    let ext = ext.rsplit('.').next().unwrap_or_default();
+    supported_extensions.contains(&ext) && ext_to_language(ext).is_some()
+}
+
+fn is_loop(error: &ignore::Error) -> bool {
+    match error {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        ignore::Error::Partial(errors) => errors.iter().all(is_loop),
+        _ => false,
+    }
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    let io_error = error.downcast_ref::<std::io::Error>().or_else(|| {
+        error
+            .downcast_ref::<ignore::Error>()
+            .and_then(ignore::Error::io_error)
+    });
+    io_error.is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Files already walked by device and inode, so hard links and symlinks to
+/// the same file aren't indexed twice.
+#[derive(Default)]
+struct SeenFiles(Mutex<HashSet<(u64, u64)>>);
+
+impl SeenFiles {
+    fn first_sight(&self, path: &Path) -> bool {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return true;
+        };
+        self.0
+            .lock()
+            .unwrap()
+            .insert((metadata.dev(), metadata.ino()))
+    }
+}
+
+/// Extensions of several parts that tell files apart from others of their
+/// language.
+struct CompoundExtension {
+    extension: &'static str,
+    /// Added to the distance of the file's chunks in searches.
+    penalty: f32,
+}
+
+const COMPOUND_EXTENSIONS: [CompoundExtension; 1] = [
+    // TypeScript declarations repeat the signatures of the implementation.
+    CompoundExtension {
+        extension: "d.ts",
+        penalty: 0.05,
+    },
+];
 
-    pub fn chunks(&self) -> Vec<Chunk> {
-        let splitter = CodeSplitter::new(&self.tree, &self.text, 1000);
-        splitter.chunks()
+/// The lowercase extension of the file, with the compound ones in
+/// `COMPOUND_EXTENSIONS` kept whole, e.g. "d.ts".
+pub(super) fn extension_of(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    let compound = COMPOUND_EXTENSIONS.iter().find(|compound| {
+        name.strip_suffix(compound.extension)
+            .and_then(|stem| stem.strip_suffix('.'))
+            .is_some_and(|stem| !stem.is_empty())
+    });
+    match compound {
+        Some(compound) => Some(compound.extension.to_string()),
+        None => Some(Path::new(&name).extension()?.to_str()?.to_string()),
     }
 }
 
-fn ext_to_language(ext: &str) -> Option<Language> {
+/// Ranks chunks of files with a compound extension like "d.ts" below those
+/// of the regular files of their language.
+pub(super) fn ranking_penalty(path: &Path) -> f32 {
+    let extension = extension_of(path).unwrap_or_default();
+    COMPOUND_EXTENSIONS
+        .iter()
+        .find(|compound| compound.extension == extension)
+        .map_or(0.0, |compound| compound.penalty)
+}
+
+/// Name of the file's language, e.g. "rust", if it's supported.
+pub(super) fn language_of(path: &Path) -> Option<&'static str> {
+    language_for(path).map(|(name, _)| name)
+}
+
+fn language_for(path: &Path) -> Option<(&'static str, Language)> {
+    ext_to_language(extension_of(path)?.rsplit('.').next()?)
+}
+
+/// Only languages whose `lang-*` feature is enabled are recognized.
+fn ext_to_language(ext: &str) -> Option<(&'static str, Language)> {
     match ext {
-        "c" => Some(tree_sitter_c::LANGUAGE.into()),
-        "cpp" => Some(tree_sitter_cpp::LANGUAGE.into()),
-        "cs" => Some(tree_sitter_c_sharp::LANGUAGE.into()),
-        "go" => Some(tree_sitter_go::LANGUAGE.into()),
-        "java" => Some(tree_sitter_java::LANGUAGE.into()),
-        "json" => Some(tree_sitter_json::LANGUAGE.into()),
-        "kt" => Some(tree_sitter_kotlin_ng::LANGUAGE.into()),
-        "py" => Some(tree_sitter_python::LANGUAGE.into()),
-        "rs" => Some(tree_sitter_rust::LANGUAGE.into()),
-        "scala" => Some(tree_sitter_scala::LANGUAGE.into()),
-        "ts" => Some(tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into()),
-        "tsx" => Some(tree_sitter_typescript::LANGUAGE_TSX.into()),
-        "yaml" | "yml" => Some(tree_sitter_yaml::LANGUAGE.into()),
+        #[cfg(feature = "lang-c")]
+        "c" => Some(("c", tree_sitter_c::LANGUAGE.into())),
+        #[cfg(feature = "lang-cpp")]
+        "cpp" => Some(("cpp", tree_sitter_cpp::LANGUAGE.into())),
+        #[cfg(feature = "lang-c-sharp")]
+        "cs" => Some(("c-sharp", tree_sitter_c_sharp::LANGUAGE.into())),
+        #[cfg(feature = "lang-go")]
+        "go" => Some(("go", tree_sitter_go::LANGUAGE.into())),
+        #[cfg(feature = "lang-java")]
+        "java" => Some(("java", tree_sitter_java::LANGUAGE.into())),
+        #[cfg(feature = "lang-json")]
+        "json" => Some(("json", tree_sitter_json::LANGUAGE.into())),
+        #[cfg(feature = "lang-kotlin")]
+        "kt" => Some(("kotlin", tree_sitter_kotlin_ng::LANGUAGE.into())),
+        #[cfg(feature = "lang-python")]
+        "py" => Some(("python", tree_sitter_python::LANGUAGE.into())),
+        #[cfg(feature = "lang-rust")]
+        "rs" => Some(("rust", tree_sitter_rust::LANGUAGE.into())),
+        #[cfg(feature = "lang-scala")]
+        "scala" => Some(("scala", tree_sitter_scala::LANGUAGE.into())),
+        #[cfg(feature = "lang-typescript")]
+        "ts" => Some((
+            "typescript",
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+        )),
+        #[cfg(feature = "lang-typescript")]
+        "tsx" => Some(("tsx", tree_sitter_typescript::LANGUAGE_TSX.into())),
+        #[cfg(feature = "lang-yaml")]
+        "yaml" | "yml" => Some(("yaml", tree_sitter_yaml::LANGUAGE.into())),
         _ => None,
     }
 }
@@ -163,3 +893,136 @@ fn hash_file(content: &str) -> Vec<u8> {
     hasher.update(content.as_bytes());
     hasher.finalize().to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_each_linked_file_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::hard_link(root.join("a.rs"), root.join("b.rs")).unwrap();
+        std::os::unix::fs::symlink(root.join("a.rs"), root.join("c.rs")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("loop")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let files: Vec<PathBuf> =
+                supported_files(root, follow_symlinks, &Boundaries::default())
+                    .collect::<Result<_>>()
+                    .unwrap();
+            assert_eq!(files.len(), 1, "{files:?}");
+        }
+    }
+
+    #[test]
+    fn stops_at_nested_project_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let boundaries = Boundaries {
+            stop_markers: vec![".code-search-root".to_string()],
+            submodules: false,
+        };
+        std::fs::create_dir_all(root.join("vendor/lib")).unwrap();
+        std::fs::write(root.join(".code-search-root"), "").unwrap();
+        std::fs::write(root.join("vendor/lib/.code-search-root"), "").unwrap();
+        std::fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(root.join("vendor/lib/lib.rs"), "fn lib() {}\n").unwrap();
+
+        let files: Vec<PathBuf> = supported_files(root, false, &boundaries)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(files, [root.join("main.rs")]);
+        assert!(boundaries.excludes(root, &root.join("vendor/lib/lib.rs")));
+        assert!(!boundaries.excludes(root, &root.join("main.rs")));
+    }
+
+    #[test]
+    fn notices_emptied_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        std::fs::write(&path, "fn main() {}\n").unwrap();
+        let mut files = ProjectFiles::new(
+            dir.path().to_path_buf(),
+            ChunkingConfig::default(),
+            PreprocessConfig::default(),
+            1,
+            false,
+            &Boundaries::default(),
+            usize::MAX,
+        )
+        .unwrap();
+        assert!(!files.was_emptied(&path));
+
+        std::fs::write(&path, "").unwrap();
+        assert!(files.was_emptied(&path));
+        files.create_or_update(&path).unwrap();
+        assert!(!files.was_emptied(&path));
+    }
+
+    #[test]
+    fn skips_chunks_of_files_that_shrank_since_indexing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        std::fs::write(&path, "fn main() {\n    println!(\"hello\");\n}\n").unwrap();
+        let mut files = ProjectFiles::new(
+            dir.path().to_path_buf(),
+            ChunkingConfig::default(),
+            PreprocessConfig::default(),
+            1,
+            false,
+            &Boundaries::default(),
+            usize::MAX,
+        )
+        .unwrap();
+        files.mark_indexed(&path, Some(SystemTime::now()));
+        let chunk = |byte: Range<usize>| OutputChunk {
+            path: path.clone(),
+            row: 0..3,
+            column: 0..1,
+            byte,
+            kind: ChunkKind::Function,
+            content_hash: String::new(),
+            distance: 0.0,
+            duplicates: Vec::new(),
+        };
+        assert!(files.chunk_to_response(chunk(0..35)).is_some());
+
+        // Still queued, the index has the chunks of the longer version.
+        std::fs::write(&path, "fn ä() {}\n").unwrap();
+        files.create_or_update(&path).unwrap();
+        assert!(files.chunk_to_response(chunk(0..35)).is_none());
+        assert!(files.chunk_to_response(chunk(0..4)).is_none());
+        let response = files.chunk_to_response(chunk(0..5)).unwrap();
+        assert_eq!(&*response.content, "fn ä");
+        assert!(!files.is_indexed(&path));
+    }
+
+    #[test]
+    fn chunk_ids_round_trip() {
+        let path = Path::new("/src/a:b/main.rs");
+        let id = chunk_id(path, &(120..480));
+        assert_eq!(id, "/src/a:b/main.rs:120-480");
+        assert_eq!(parse_chunk_id(&id), Some((path.to_path_buf(), 120..480)));
+        assert_eq!(parse_chunk_id("main.rs"), None);
+    }
+
+    #[test]
+    fn normalizes_extensions() {
+        let extension = |path: &str| extension_of(Path::new(path));
+        assert_eq!(extension("src/Main.RS").as_deref(), Some("rs"));
+        assert_eq!(extension("ui/Button.Test.TSX").as_deref(), Some("tsx"));
+        assert_eq!(extension("types/index.D.ts").as_deref(), Some("d.ts"));
+        assert_eq!(extension("d.ts").as_deref(), Some("ts"));
+        assert_eq!(language_of(Path::new("index.d.ts")), Some("typescript"));
+        assert!(ranking_penalty(Path::new("index.d.ts")) > ranking_penalty(Path::new("a.ts")));
+    }
+
+    #[test]
+    fn tells_files_that_disappeared_from_other_errors() {
+        let missing = ProjectFile::new("/nonexistent/a.rs".into()).context("Failed to read");
+        assert!(is_not_found(&missing.err().unwrap()));
+        assert!(!is_not_found(&anyhow::anyhow!("Failed to parse")));
+    }
+}
@@ -1,142 +1,482 @@
-use std::{ops::Range, path::PathBuf};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::{BufRead, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::Result;
-use fastembed::TextEmbedding;
-use rusqlite::{Connection, OptionalExtension, params};
-use zerocopy::IntoBytes;
+use anyhow::{Context, Result};
+use blake2::{Blake2s256, Digest};
+use serde::{Deserialize, Serialize};
 
-use super::code_splitter::Chunk;
+use crate::config::{Config, PreprocessConfig, StorageBackend, StorageConfig};
+
+use super::{
+    embedding_export::{ExportHeader, export_embeddings, import_embeddings},
+    memory_store::MemoryStore,
+    model::SharedModel,
+    preprocess::EmbeddingChunk,
+    project_files::{extension_of, ranking_penalty},
+    sqlite_store::SqliteStore,
+    vector_store::{ChunkFilter, ChunkKind, ChunkRecord, Corpus, VectorStore},
+};
+
+/// How many times the requested number of chunks a search fetches, to make
+/// up for collapsed copies of a chunk.
+const DUPLICATE_OVERFETCH: usize = 2;
+
+/// Appended to the file name of a removed project's index, followed by the
+/// time of removal in seconds since the Unix epoch.
+const REMOVED_SUFFIX: &str = ".removed-";
 
 pub struct ProjectRepository {
-    conn: Connection,
-    model: TextEmbedding,
+    store: Box<dyn VectorStore>,
+    model: SharedModel,
+    index_path: Option<PathBuf>,
+    index_key: Option<String>,
+    /// Index of another checkout of the same repository, embeddings of
+    /// chunks it already has are copied instead of computed.
+    seed: Option<Box<dyn VectorStore>>,
+    /// Embeddings imported from an export by content hash, used instead of
+    /// computed ones.
+    imported: HashMap<String, Vec<f32>>,
 }
 
 impl ProjectRepository {
-    pub fn new() -> Result<Self> {
-        let conn = Connection::open_in_memory()?;
-        let model = TextEmbedding::try_new(Default::default())?;
-        conn.execute(
-            "
-            CREATE TABLE IF NOT EXISTS files (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                path TEXT NOT NULL,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            );",
-            [],
-        )?;
-        conn.execute(
-            "
-            CREATE VIRTUAL TABLE IF NOT EXISTS chunks using vec0(
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                file_id INTEGER NOT NULL,
-                start_row INTEGER NOT NULL,
-                start_column INTEGER NOT NULL,
-                end_row INTEGER NOT NULL,
-                end_column INTEGER NOT NULL,
-                start_byte INTEGER NOT NULL,
-                end_byte INTEGER NOT NULL,
-                embeddings float[384]
-            )",
-            [],
-        )?;
-        Ok(Self { conn, model })
-    }
-
-    pub fn insert_file(&self, path: &str, chunks: Vec<Chunk>) -> Result<i64> {
-        let file_id = match self
-            .conn
-            .query_row(
-                "SELECT id FROM files WHERE path = ? LIMIT 1",
-                [path],
-                |row| row.get::<_, i64>(0),
-            )
-            .optional()?
-        {
-            Some(prev_file_id) => {
-                self.conn
-                    .execute("DELETE FROM chunks WHERE file_id = ?", [prev_file_id])?;
-                self.conn.execute(
-                    "UPDATE files SET updated_at = CURRENT_TIMESTAMP WHERE id = ?",
-                    [prev_file_id],
-                )?;
-                prev_file_id
+    pub fn new(config: &Config, project_root: &Path) -> Result<Self> {
+        Self::with_model(
+            SharedModel::from_config(config)?,
+            &config.storage,
+            project_root,
+        )
+    }
+
+    pub fn with_model(
+        model: SharedModel,
+        storage: &StorageConfig,
+        project_root: &Path,
+    ) -> Result<Self> {
+        let index_key = match (&storage.encryption, &storage.index_dir) {
+            (Some(key), Some(_)) => Some(key.resolve()?),
+            _ => None,
+        };
+        let key = index_key.as_deref();
+        let (store, index_path): (Box<dyn VectorStore>, _) = match storage.backend {
+            StorageBackend::Sqlite => {
+                let path = match &storage.index_dir {
+                    Some(index_dir) => {
+                        std::fs::create_dir_all(index_dir)?;
+                        Some(index_dir.join(index_file_name(
+                            project_root,
+                            &model,
+                            index_key.is_some(),
+                        )))
+                    }
+                    None => None,
+                };
+                let store = match &path {
+                    Some(path) if storage.read_only => SqliteStore::open_read_only(
+                        model.model_code(),
+                        model.dimensions(),
+                        project_root,
+                        path,
+                        key,
+                    )?,
+                    None if storage.read_only => {
+                        anyhow::bail!("Read-only storage needs an index_dir to read from")
+                    }
+                    _ => SqliteStore::new(
+                        model.model_code(),
+                        model.dimensions(),
+                        project_root,
+                        path.as_deref(),
+                        key,
+                    )?,
+                };
+                (Box::new(store), path)
+            }
+            StorageBackend::Memory if storage.read_only => {
+                anyhow::bail!("Read-only storage needs the sqlite backend")
+            }
+            StorageBackend::Memory => (Box::new(MemoryStore::default()), None),
+        };
+        Ok(Self {
+            store,
+            model,
+            index_path,
+            index_key,
+            seed: None,
+            imported: HashMap::new(),
+        })
+    }
+
+    /// Copies embeddings from the index of `sibling_root`, another worktree
+    /// or clone, while this one is built. Only on-disk indexes of the same
+    /// model can be shared.
+    pub fn seed_from(&mut self, config: &Config, sibling_root: &Path) -> Result<bool> {
+        let Some(path) = project_index_path(config, sibling_root)? else {
+            return Ok(false);
+        };
+        if self.index_path.is_none() || !path.exists() {
+            return Ok(false);
+        }
+        self.seed = Some(Box::new(SqliteStore::open_read_only(
+            self.model.model_code(),
+            self.model.dimensions(),
+            sibling_root,
+            &path,
+            self.index_key(),
+        )?));
+        Ok(true)
+    }
+
+    /// Closes the sibling's index and frees imported embeddings once the
+    /// initial indexing is done.
+    pub fn drop_seed(&mut self) {
+        self.seed = None;
+        self.imported = HashMap::new();
+    }
+
+    /// Reads embeddings exported for the same model and preprocessing, used
+    /// for the chunks they match until the queued files are indexed. Returns
+    /// how many were read.
+    pub fn import(&mut self, preprocess: &PreprocessConfig, input: impl BufRead) -> Result<usize> {
+        let imported = import_embeddings(input, &self.export_header(preprocess))?;
+        let count = imported.len();
+        self.imported.extend(imported);
+        Ok(count)
+    }
+
+    pub fn model(&self) -> &SharedModel {
+        &self.model
+    }
+
+    /// The database file of the index, if it's kept on disk.
+    pub fn index_path(&self) -> Option<&Path> {
+        self.index_path.as_deref()
+    }
+
+    /// Key the index database is encrypted with.
+    pub fn index_key(&self) -> Option<&str> {
+        self.index_key.as_deref()
+    }
+
+    /// Replaces the chunks of a file, embedding only chunks whose embedded
+    /// text isn't already stored for it.
+    #[tracing::instrument(skip(self, chunks))]
+    pub fn upsert_file(&mut self, path: &str, chunks: Vec<EmbeddingChunk>) -> Result<()> {
+        let records: Vec<ChunkRecord> = chunks
+            .iter()
+            .map(|chunk| ChunkRecord {
+                start: chunk.chunk.start,
+                end: chunk.chunk.end,
+                byte: chunk.chunk.range.clone(),
+                content_hash: hash_chunk(&chunk.text),
+                kind: chunk.kind,
+            })
+            .collect();
+
+        let mut stored = self.store.content_hashes(path)?;
+        let mut new_chunks: HashMap<&str, &str> = HashMap::new();
+        for (chunk, record) in chunks.iter().zip(&records) {
+            match stored.get_mut(&record.content_hash) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => {
+                    new_chunks.insert(&record.content_hash, &chunk.text);
+                }
+            }
+        }
+        // Copies of chunks stored for other files reuse their embedding.
+        let mut copied = HashMap::new();
+        for hash in new_chunks.keys() {
+            let mut embedding = self.store.embedding(hash)?;
+            if embedding.is_none()
+                && let Some(seed) = &self.seed
+            {
+                embedding = seed.embedding(hash)?;
             }
-            None => self.conn.query_row(
-                "INSERT INTO files (path) VALUES (?) RETURNING id",
-                [path],
-                |row| row.get(0),
-            )?,
+            if embedding.is_none() {
+                embedding = self.imported.get(*hash).cloned();
+            }
+            if let Some(embedding) = embedding {
+                copied.insert(hash.to_string(), embedding);
+            }
+        }
+        new_chunks.retain(|hash, _| !copied.contains_key(*hash));
+        let (hashes, content): (Vec<&str>, Vec<&str>) = new_chunks.into_iter().unzip();
+        let embeddings = if content.is_empty() {
+            Vec::new()
+        } else {
+            let _span = tracing::info_span!("embed", chunks = content.len()).entered();
+            self.model.embed(&content)?
         };
+        let mut embeddings: HashMap<String, Vec<f32>> = hashes
+            .into_iter()
+            .map(String::from)
+            .zip(embeddings)
+            .collect();
+        embeddings.extend(copied);
 
-        let mut stmt = self.conn.prepare(
-            "INSERT INTO chunks (
-                file_id,
-                start_row,
-                start_column,
-                end_row,
-                end_column,
-                start_byte,
-                end_byte,
-                embeddings
-            )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-        )?;
-        let content: Vec<&str> = chunks.iter().map(|chunk| chunk.text).collect();
-        let embeddings = self.model.embed(content, None)?;
-        for (chunk, embedding) in chunks.iter().zip(embeddings) {
-            stmt.execute(params![
-                file_id,
-                chunk.start.row,
-                chunk.start.column,
-                chunk.end.row,
-                chunk.end.column,
-                chunk.range.start,
-                chunk.range.end,
-                embedding.as_bytes(),
-            ])?;
+        let _span = tracing::info_span!("store", chunks = records.len()).entered();
+        self.store.upsert_file(path, &records, &embeddings)
+    }
+
+    /// Removes files for which `keep` returns false along with their chunks
+    /// and reclaims the space they used.
+    pub fn compact(&mut self, keep: impl Fn(&Path) -> bool) -> Result<CompactionStats> {
+        self.store.compact(&keep)
+    }
+
+    pub fn storage_bytes(&self) -> Result<u64> {
+        self.store.storage_bytes()
+    }
+
+    /// Writes every chunk with its embedding as JSON lines, paths relative to
+    /// `project_root`. Returns how many chunks were written.
+    pub fn export(
+        &self,
+        project_root: &Path,
+        preprocess: &PreprocessConfig,
+        out: impl Write,
+    ) -> Result<usize> {
+        let header = self.export_header(preprocess);
+        export_embeddings(&*self.store, &header, project_root, out)
+    }
+
+    /// What the embeddings of this index are computed with.
+    fn export_header(&self, preprocess: &PreprocessConfig) -> ExportHeader {
+        ExportHeader {
+            model: self.model.model_code().to_string(),
+            dimensions: self.model.dimensions(),
+            passage_prefix: self.model.passage_prefix(),
+            preprocess: preprocess.clone(),
         }
-        Ok(file_id)
-    }
-
-    pub fn search(&self, query: &str) -> Result<Vec<OutputChunk>> {
-        let query_embedding = self.model.embed(vec![query], None)?;
-        let mut search_stmt = self.conn.prepare(
-            "SELECT
-                file_id,
-                start_row,
-                end_row,
-                start_column,
-                end_column,
-                start_byte,
-                end_byte,
-                distance
-            FROM chunks
-            WHERE embeddings MATCH ?
-            ORDER BY distance
-            LIMIT 5",
-        )?;
-
-        let mut rows = search_stmt.query(params![query_embedding[0].as_bytes()])?;
+    }
+
+    /// Finds the chunks closest to the query among those matching the
+    /// filter. Identical chunks in files of the same language are returned
+    /// once, with the other files in `duplicates`.
+    pub fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        self.search_all(&[query], limit, filter)
+    }
+
+    /// Like `search` for several phrasings of the same query, e.g. expanded
+    /// ones. They are embedded and looked up together and each chunk is
+    /// scored by the phrasing closest to it.
+    pub fn search_all(
+        &self,
+        queries: &[&str],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        let query_embeddings = self.embed_queries(queries)?;
+        self.search_embedded(&query_embeddings, limit, filter)
+    }
+
+    /// Like `search_all`, drawing up to its limit of chunks from each corpus
+    /// instead of the closest overall. The results are closest first.
+    pub fn search_corpora(
+        &self,
+        queries: &[&str],
+        limits: &[(Corpus, usize)],
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        let query_embeddings = self.embed_queries(queries)?;
         let mut chunks = Vec::new();
-        while let Some(row) = rows.next()? {
-            let file_id: i64 = row.get(0)?;
-            let path =
-                self.conn
-                    .query_row("SELECT path FROM files WHERE id = ?", [file_id], |row| {
-                        row.get::<_, String>(0)
-                    })?;
-
-            chunks.push(OutputChunk {
-                path: PathBuf::from(path),
-                row: row.get(1)?..row.get(2)?,
-                column: row.get(3)?..row.get(4)?,
-                byte: row.get(5)?..row.get(6)?,
-            });
+        for (corpus, limit) in limits {
+            let kinds: Vec<ChunkKind> = corpus
+                .kinds()
+                .into_iter()
+                .filter(|kind| filter.kinds.is_empty() || filter.kinds.contains(kind))
+                .collect();
+            if kinds.is_empty() {
+                continue;
+            }
+            let filter = ChunkFilter {
+                kinds,
+                ..filter.clone()
+            };
+            chunks.extend(self.search_embedded(&query_embeddings, *limit, &filter)?);
         }
+        chunks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
         Ok(chunks)
     }
+
+    fn embed_queries(&self, queries: &[&str]) -> Result<Vec<Vec<f32>>> {
+        tracing::info_span!("embed", queries = queries.len())
+            .in_scope(|| self.model.embed_queries(queries))
+    }
+
+    fn search_embedded(
+        &self,
+        query_embeddings: &[Vec<f32>],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        let fetched = limit * DUPLICATE_OVERFETCH;
+        let mut chunks = tracing::info_span!("knn", limit = fetched)
+            .in_scope(|| self.store.search(query_embeddings, fetched, filter))?;
+
+        for chunk in &mut chunks {
+            chunk.distance += ranking_penalty(&chunk.path);
+        }
+        chunks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+        let mut collapsed: Vec<OutputChunk> = Vec::new();
+        for chunk in chunks {
+            if !collapsed.iter().any(|kept| is_copy(kept, &chunk)) {
+                collapsed.push(chunk);
+            }
+        }
+        collapsed.truncate(limit);
+        for chunk in &mut collapsed {
+            chunk.duplicates = self
+                .store
+                .paths_with(&chunk.content_hash)?
+                .into_iter()
+                .map(PathBuf::from)
+                .filter(|path| {
+                    *path != chunk.path && extension_of(path) == extension_of(&chunk.path)
+                })
+                .collect();
+        }
+        Ok(collapsed)
+    }
+}
+
+/// Where the on-disk index of the project is, whether or not it exists yet.
+pub fn project_index_path(config: &Config, project_root: &Path) -> Result<Option<PathBuf>> {
+    let Some(index_dir) = &config.storage.index_dir else {
+        return Ok(None);
+    };
+    if config.storage.backend != StorageBackend::Sqlite {
+        return Ok(None);
+    }
+    let model = SharedModel::from_config(config)?;
+    Ok(Some(index_dir.join(index_file_name(
+        project_root,
+        &model,
+        config.storage.encryption.is_some(),
+    ))))
+}
+
+/// Moves the index aside instead of deleting it, so an accidentally removed
+/// project can be restored until `purge_removed_indexes` deletes it.
+pub fn remove_index(index_path: &Path) -> Result<()> {
+    let removed_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut removed = index_path.as_os_str().to_owned();
+    removed.push(format!("{REMOVED_SUFFIX}{removed_at}"));
+    std::fs::rename(index_path, &removed)
+        .with_context(|| format!("Failed to move index {:?} aside", index_path))
+}
+
+/// Moves the most recently removed copy of the index back in place. Returns
+/// false if there is none.
+pub fn restore_index(index_path: &Path) -> Result<bool> {
+    if index_path.exists() {
+        anyhow::bail!(
+            "{:?} already has an index, remove the project first",
+            index_path
+        );
+    }
+    let (Some(index_dir), Some(file_name)) = (index_path.parent(), index_path.file_name()) else {
+        return Ok(false);
+    };
+    let latest = removed_indexes(index_dir)?
+        .into_iter()
+        .filter(|(_, original, _)| original == file_name)
+        .max_by_key(|(_, _, removed_at)| *removed_at);
+    let Some((removed, _, _)) = latest else {
+        return Ok(false);
+    };
+    std::fs::rename(&removed, index_path)
+        .with_context(|| format!("Failed to restore index {:?}", removed))?;
+    Ok(true)
+}
+
+/// Deletes the indexes of projects removed longer than `retention` ago.
+/// Returns how many were deleted.
+pub fn purge_removed_indexes(index_dir: &Path, retention: Duration) -> Result<usize> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut purged = 0;
+    for (path, _, removed_at) in removed_indexes(index_dir)? {
+        if now.saturating_sub(removed_at) >= retention.as_secs() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete removed index {:?}", path))?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Removed indexes in the directory with their original file name and time
+/// of removal.
+fn removed_indexes(index_dir: &Path) -> Result<Vec<(PathBuf, OsString, u64)>> {
+    let entries = match std::fs::read_dir(index_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut removed = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if let Some((original, removed_at)) = file_name.rsplit_once(REMOVED_SUFFIX)
+            && let Ok(removed_at) = removed_at.parse()
+        {
+            let original = OsString::from(original);
+            removed.push((path, original, removed_at));
+        }
+    }
+    Ok(removed)
+}
+
+/// Indexes of different models can't share a database, their embeddings
+/// differ in dimensions. Neither can those of different passage prefixes.
+fn index_file_name(project_root: &Path, model: &SharedModel, encrypted: bool) -> String {
+    let mut hasher = Blake2s256::new();
+    hasher.update(project_root.as_os_str().as_encoded_bytes());
+    hasher.update([0]);
+    hasher.update(model.model_code().as_bytes());
+    // Models without a prefix keep the indexes from before prefixes.
+    let passage_prefix = model.passage_prefix();
+    if !passage_prefix.is_empty() {
+        hasher.update(b"\0passage:");
+        hasher.update(passage_prefix.as_bytes());
+    }
+    // Enabling encryption starts a new index rather than failing to open the
+    // plain text one.
+    if encrypted {
+        hasher.update(b"\0encrypted");
+    }
+    format!("{:x}.db", hasher.finalize())
+}
+
+/// Chunks with the same embedded text are copies if their files are of the
+/// same language, e.g. a vendored library or generated twins.
+fn is_copy(a: &OutputChunk, b: &OutputChunk) -> bool {
+    a.content_hash == b.content_hash && extension_of(&a.path) == extension_of(&b.path)
+}
+
+pub(super) fn hash_chunk(text: &str) -> String {
+    format!("{:x}", Blake2s256::digest(text.as_bytes()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionStats {
+    pub removed_files: usize,
+    pub removed_chunks: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
 }
 
 pub struct OutputChunk {
@@ -144,4 +484,37 @@ pub struct OutputChunk {
     pub row: Range<usize>,
     pub column: Range<usize>,
     pub byte: Range<usize>,
+    pub kind: ChunkKind,
+    pub content_hash: String,
+    /// Distance to the query embedding, smaller is closer.
+    pub distance: f32,
+    /// Other files holding the same chunk, filled in by searches.
+    pub duplicates: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_indexes_are_restored_until_purged() {
+        let dir = tempfile::tempdir().unwrap();
+        let index_path = dir.path().join("project.db");
+        std::fs::write(&index_path, "index").unwrap();
+
+        remove_index(&index_path).unwrap();
+        assert!(!index_path.exists());
+        assert!(restore_index(&index_path).unwrap());
+        assert_eq!(std::fs::read_to_string(&index_path).unwrap(), "index");
+        assert!(restore_index(&index_path).is_err());
+
+        remove_index(&index_path).unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(purge_removed_indexes(dir.path(), day).unwrap(), 0);
+        assert_eq!(
+            purge_removed_indexes(dir.path(), Duration::ZERO).unwrap(),
+            1
+        );
+        assert!(!restore_index(&index_path).unwrap());
+    }
 }
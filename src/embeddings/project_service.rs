@@ -1,61 +1,1165 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use blake2::{Blake2s256, Digest};
 use futures::{StreamExt, executor::block_on};
+use serde::{Deserialize, Serialize};
 use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 use tarpc::{
     client, context,
     server::{self, Channel},
 };
+use tracing::Instrument;
+use tree_sitter::Point;
 
-use crate::rpc::RpcError;
+use crate::{
+    config::Config,
+    notifications::{Event, notify},
+    rpc::RpcError,
+};
 
 use super::{
-    project_files::{ProjectFiles, ResponseChunk},
-    project_repository::ProjectRepository,
+    blame,
+    chunk_hits::{ChunkHits, ColdChunk},
+    commit_history::{COMMIT_BATCH, CommitHistory, CommitMatch},
+    coverage::{Coverage, coverage},
+    definitions::{Definition, file_definitions, find_definitions},
+    exclusion::{penalize_excluded, split_exclusions},
+    expansion,
+    explain::Explanation,
+    git_revision::{changed_files, resolve_commit},
+    identifiers::{IdentifierIndex, boost_identifier_matches, fuse_identifier_hits, query_words},
+    index_queue::IndexQueue,
+    journal::{IndexJournal, JournalState},
+    permalink::RemoteRepository,
+    project_files::{
+        Boundaries, ProjectFiles, ResponseChunk, language_of, supported_files, walked_files,
+    },
+    project_repository::{CompactionStats, ProjectRepository, remove_index},
+    sqlite_store::open_database,
+    symbols::SymbolTable,
+    test_discovery::{Subject, TestMatch, find_tests},
+    todos::Todo,
+    vector_store::{ChunkFilter, ChunkKind, Corpus, draw_per_corpus},
 };
 
+/// Number of upcoming files listed by `queue_status`.
+const QUEUE_STATUS_NEXT: usize = 10;
+
+/// How much of a file is embedded to find tests similar to it.
+const FIND_TESTS_QUERY_CHARS: usize = 1000;
+
+/// Searches repeated at most with `require_fresh` while results come from
+/// files that changed since they were indexed.
+const FRESH_SEARCH_ATTEMPTS: usize = 3;
+
+/// Extra results fetched when searching with exclusions, so there's enough
+/// left after penalized ones drop to the end.
+const EXCLUSION_OVERFETCH: usize = 3;
+
 pub struct ProjectService {
+    root: PathBuf,
+    config: Arc<Config>,
     files: ProjectFiles,
     repository: ProjectRepository,
+    /// The index of the model before the configured one changed, searched
+    /// until every file is embedded with the new model. Files changed in the
+    /// meantime only are in the new index.
+    previous: Option<PreviousIndex>,
+    queue: IndexQueue,
+    /// Only kept for on-disk indexes, in-memory ones start over anyway.
+    journal: Option<IndexJournal>,
+    /// A verification pass is running.
+    verifying: bool,
+    /// Only kept with `history.enabled`.
+    history: Option<CommitHistory>,
+    /// Only looked up with `permalinks.enabled`.
+    remote: Option<RemoteRepository>,
+    /// The git revision the files are read from instead of the working tree,
+    /// e.g. "origin/main".
+    revision: Option<String>,
+    /// Files found emptied on disk and when, whose chunks are kept until
+    /// they stay empty for `empty_file_settle_ms`.
+    emptied: HashMap<PathBuf, Instant>,
+    /// When searches last returned each chunk.
+    hits: ChunkHits,
+    /// The definitions of the indexed files.
+    symbols: SymbolTable,
+    /// The identifiers of the indexed chunks, for lexical matches.
+    identifiers: IdentifierIndex,
+}
+
+/// The index of the previous model with the tables written along with its
+/// chunks, which searches read in its place until the switch is done.
+struct PreviousIndex {
+    repository: ProjectRepository,
+    hits: ChunkHits,
+    symbols: SymbolTable,
+    identifiers: IdentifierIndex,
+}
+
+impl PreviousIndex {
+    /// Deletes the on-disk index once it's been replaced, unless one of the
+    /// `kept` indexes shares the file.
+    fn delete(self, kept: &[&ProjectRepository]) {
+        let Some(path) = self.repository.index_path().map(Path::to_path_buf) else {
+            return;
+        };
+        // Closes every connection to the file first.
+        drop(self);
+        if kept
+            .iter()
+            .any(|repository| repository.index_path() == Some(&path))
+        {
+            return;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => tracing::info!("deleted the superseded index {:?}", path),
+            Err(e) => tracing::warn!("failed to delete the superseded index {:?}: {:?}", path, e),
+        }
+    }
 }
 
 impl ProjectService {
-    pub fn start(path: PathBuf) -> ProjectRpcClient {
+    /// `sibling` is another checkout of the same repository whose index the
+    /// new one copies embeddings from. With a `revision` the files are read
+    /// from the git object store as of that revision and indexed in memory.
+    /// If the project's files or index can't be read `on_failure` is called
+    /// with why and the client's requests fail.
+    pub fn start(
+        path: PathBuf,
+        config: Arc<Config>,
+        sibling: Option<PathBuf>,
+        revision: Option<String>,
+        defer_indexing: bool,
+        on_failure: impl FnOnce(anyhow::Error) + Send + 'static,
+    ) -> ProjectRpcClient {
         let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
         let server = server::BaseChannel::with_defaults(server_transport);
         tokio::task::spawn_blocking(move || {
-            let project_service = Arc::new(Mutex::new(ProjectService::new(path).unwrap()));
+            let project_service = match ProjectService::new(path, config, sibling, revision) {
+                Ok(project_service) => Arc::new(Mutex::new(project_service)),
+                Err(e) => return on_failure(e),
+            };
+            if !defer_indexing {
+                spawn_indexer(&project_service);
+            }
+            spawn_history_indexer(&project_service);
             block_on(
                 server
                     .execute(project_service.serve())
-                    // Handle all requests sequentially.
-                    .for_each(|response| response),
+                    // Only searches wait on anything, every other request
+                    // completes in its first poll and so in order. Running
+                    // them concurrently keeps the channel read while a search
+                    // runs, so its cancellation arrives.
+                    .for_each_concurrent(None, |response| response),
             )
         });
         ProjectRpcClient::new(client::Config::default(), client_transport).spawn()
     }
 
-    fn new(path: PathBuf) -> Result<Self> {
-        let files = ProjectFiles::new(path)?;
-        let repository = ProjectRepository::new()?;
+    fn new(
+        path: PathBuf,
+        config: Arc<Config>,
+        sibling: Option<PathBuf>,
+        revision: Option<String>,
+    ) -> Result<Self> {
+        let config = Arc::new(project_config(&config, &path, revision.is_some())?);
+        let mut files = match &revision {
+            Some(revision) => ProjectFiles::at_commit(
+                &path,
+                resolve_commit(&path, revision)?,
+                config.chunking.clone(),
+                config.preprocess.clone(),
+                config.text_cache_bytes,
+            )?,
+            None => ProjectFiles::new(
+                path.clone(),
+                config.chunking.clone(),
+                config.preprocess.clone(),
+                config.walk_threads,
+                config.follow_symlinks,
+                &Boundaries::new(&config),
+                config.text_cache_bytes,
+            )?,
+        };
+        files.set_column_encoding(config.search.column_encoding);
+        let mut repository = ProjectRepository::new(&config, &path)?;
+        if let Some(sibling) = sibling
+            && !config.storage.read_only
+        {
+            match repository.seed_from(&config, &sibling) {
+                Ok(true) => tracing::info!("seeding index of {:?} from {:?}", path, sibling),
+                Ok(false) => {}
+                Err(e) => tracing::warn!("not seeding index from {:?}: {:?}", sibling, e),
+            }
+        }
+        let journal = open_journal(&config, &repository)?;
+        let hits = open_hits(&config, &repository)?;
+        files.mark_hit(hits.hit_files()?);
+        let symbols = open_table(
+            &config,
+            &repository,
+            SymbolTable::persisted,
+            SymbolTable::new,
+        )?;
+        let identifiers = open_table(
+            &config,
+            &repository,
+            IdentifierIndex::persisted,
+            IdentifierIndex::new,
+        )?;
+        let history = open_history(&config, &repository);
+        let remote = discover_remote(&config, &path);
+
+        let mut service = Self {
+            root: path,
+            config,
+            files,
+            repository,
+            previous: None,
+            queue: IndexQueue::default(),
+            journal,
+            verifying: false,
+            history,
+            remote,
+            revision,
+            emptied: HashMap::new(),
+            hits,
+            symbols,
+            identifiers,
+        };
+        if !service.config.storage.read_only {
+            if service.symbols.created() || service.identifiers.created() {
+                // Unchanged files only get their symbols and identifiers by
+                // indexing them again, which embeds nothing new.
+                service.queue_all();
+            } else {
+                service.queue_changed();
+            }
+        }
+        Ok(service)
+    }
+
+    /// The index searches read, the previous one while the files are
+    /// embedded with a new model.
+    fn searched(&self) -> &ProjectRepository {
+        self.previous
+            .as_ref()
+            .map_or(&self.repository, |previous| &previous.repository)
+    }
+
+    /// The hits of the searched index.
+    fn searched_hits(&self) -> &ChunkHits {
+        self.previous
+            .as_ref()
+            .map_or(&self.hits, |previous| &previous.hits)
+    }
+
+    /// The symbols of the searched index.
+    fn searched_symbols(&self) -> &SymbolTable {
+        self.previous
+            .as_ref()
+            .map_or(&self.symbols, |previous| &previous.symbols)
+    }
+
+    /// The identifiers of the searched index.
+    fn searched_identifiers(&self) -> &IdentifierIndex {
+        self.previous
+            .as_ref()
+            .map_or(&self.identifiers, |previous| &previous.identifiers)
+    }
+
+    fn queue_all(&mut self) {
+        let paths = self.files.paths().map(Path::to_path_buf).collect();
+        self.queue_files(paths);
+    }
+
+    /// Queues the files the journal doesn't have as indexed in their current
+    /// version, which after a restart are the ones pending or changed since.
+    fn queue_changed(&mut self) {
+        let indexed = match self.journal.as_ref().map(IndexJournal::indexed) {
+            Some(Ok(indexed)) => indexed,
+            Some(Err(e)) => {
+                tracing::warn!("failed to read the index journal, reindexing: {:?}", e);
+                HashMap::new()
+            }
+            None => HashMap::new(),
+        };
+        let mut paths = Vec::new();
+        let mut current = Vec::new();
+        for (path, hash) in self.journal_hashes(self.files.paths()) {
+            match indexed.get(path) {
+                Some((indexed_hash, at)) if *indexed_hash == hash => {
+                    current.push((path.to_path_buf(), *at))
+                }
+                _ => paths.push(path.to_path_buf()),
+            }
+        }
+        for (path, at) in current {
+            self.files.mark_indexed(&path, at);
+        }
+        self.queue_files(paths);
+    }
+
+    fn queue_files(&mut self, paths: Vec<PathBuf>) {
+        for path in &paths {
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            self.queue.push(path.clone(), modified);
+        }
+        self.record(JournalState::Pending, &paths);
+    }
+
+    /// The file hashes combined with the settings that decide how files are
+    /// chunked, so changing them while the daemon is down still reindexes.
+    fn journal_hashes<'p>(
+        &self,
+        paths: impl IntoIterator<Item = &'p Path>,
+    ) -> Vec<(&'p Path, String)> {
+        let settings = toml::Table::try_from(&*self.config).unwrap_or_default();
+        let settings = format!(
+            "{:?}{:?}",
+            settings.get("chunking"),
+            settings.get("preprocess")
+        );
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                let mut hasher = Blake2s256::new();
+                hasher.update(self.files.file_hash(path)?);
+                hasher.update(&settings);
+                Some((path, format!("{:x}", hasher.finalize())))
+            })
+            .collect()
+    }
+
+    fn record(&self, state: JournalState, paths: &[PathBuf]) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let files = self.journal_hashes(paths.iter().map(PathBuf::as_path));
+        let files = files.iter().map(|(path, hash)| (*path, hash.as_str()));
+        if let Err(e) = journal.record(state, files) {
+            tracing::warn!("failed to update the index journal: {:?}", e);
+        }
+    }
+
+    /// Forgets files that no longer exist on disk and returns their paths.
+    fn remove_missing(&mut self) -> Vec<PathBuf> {
+        let missing = self.files.remove_missing();
+        for path in &missing {
+            self.queue.remove(path);
+            if let Some(journal) = &self.journal
+                && let Err(e) = journal.remove(path)
+            {
+                tracing::warn!("failed to update the index journal: {:?}", e);
+            }
+            let tables =
+                [(&self.hits, &self.symbols, &self.identifiers)]
+                    .into_iter()
+                    .chain(self.previous.iter().map(|previous| {
+                        (&previous.hits, &previous.symbols, &previous.identifiers)
+                    }));
+            for (hits, symbols, identifiers) in tables {
+                if let Err(e) = hits.remove(path) {
+                    tracing::warn!("failed to forget the search hits of {:?}: {:?}", path, e);
+                }
+                if let Err(e) = symbols.remove(path) {
+                    tracing::warn!("failed to forget the symbols of {:?}: {:?}", path, e);
+                }
+                if let Err(e) = identifiers.remove(path) {
+                    tracing::warn!("failed to forget the identifiers of {:?}: {:?}", path, e);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Rereads the supported files among `paths`, relative to the root, and
+    /// queues the ones that changed. Ones that are gone are forgotten.
+    /// Returns how many were queued.
+    fn reread(&mut self, paths: Vec<PathBuf>) -> Result<usize> {
+        let mut changed = Vec::new();
+        for path in paths {
+            let path = self.root.join(path);
+            if language_of(&path).is_none()
+                || !path.is_file()
+                || Boundaries::new(&self.config).excludes(&self.root, &path)
+            {
+                continue;
+            }
+            if !self.files.is_current(&path).unwrap_or(false) {
+                self.files.create_or_update(&path)?;
+                changed.push(path);
+            }
+        }
+        self.remove_missing();
+        let queued = changed.len();
+        self.queue_files(changed);
+        Ok(queued)
+    }
+
+    /// Rereads the files if the revision moved, e.g. a branch that got new
+    /// commits, and queues them. Unchanged chunks keep their embeddings.
+    fn refresh_revision(&mut self) -> Result<()> {
+        let Some(revision) = &self.revision else {
+            return Ok(());
+        };
+        let commit = resolve_commit(&self.root, revision)?;
+        if self.files.commit() == Some(commit.as_str()) {
+            return Ok(());
+        }
+        tracing::info!("{:?} at {} moved to {}", self.root, revision, commit);
+        self.files = ProjectFiles::at_commit(
+            &self.root,
+            commit,
+            self.config.chunking.clone(),
+            self.config.preprocess.clone(),
+            self.config.text_cache_bytes,
+        )?;
+        self.files
+            .set_column_encoding(self.config.search.column_encoding);
+        self.queue = IndexQueue::default();
+        let files = &self.files;
+        self.repository.compact(|path| files.contains(path))?;
+        self.queue_all();
+        Ok(())
+    }
+
+    /// Reindexes the files whose index doesn't hold what's on disk now,
+    /// pending, changed or emptied ones, and forgets deleted ones. Returns
+    /// whether there were any.
+    fn refresh_files(&mut self, paths: Vec<PathBuf>) -> Result<bool> {
+        let mut refreshed = false;
+        let mut missing = false;
+        for path in paths {
+            if !self.files.contains(&path) {
+                continue;
+            }
+            if !path.exists() {
+                missing = true;
+                continue;
+            }
+            let current = self.files.is_current(&path).unwrap_or(false);
+            if !current || !self.files.is_indexed(&path) || self.emptied.contains_key(&path) {
+                self.update_file(&path)?;
+                refreshed = true;
+            }
+        }
+        if missing {
+            refreshed |= !self.remove_missing().is_empty();
+        }
+        Ok(refreshed)
+    }
+
+    /// Rereads the file and indexes it, or queues it while the model loads.
+    fn update_file(&mut self, file_path: &Path) -> Result<()> {
+        self.emptied.remove(file_path);
+        self.files.create_or_update(file_path)?;
+        if self.repository.model().is_loading() {
+            // The indexer picks it up once the model has loaded.
+            self.queue_files(vec![file_path.to_path_buf()]);
+        } else {
+            // Modified files jump the queue by being indexed right away.
+            self.index_file(file_path)?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn index_file(&mut self, file_path: &Path) -> Result<()> {
+        self.queue.remove(file_path);
+        // Changed since it was read, the update queues it again.
+        let Some(text) = self.files.peek_text(file_path) else {
+            return Ok(());
+        };
+        let chunks = self.files.file_chunks(file_path, &text);
+        self.identifiers.replace(file_path, &chunks)?;
+        self.repository
+            .upsert_file(&file_path.to_string_lossy(), chunks)?;
+        if let Some((text, tree)) = self.files.syntax(file_path) {
+            self.symbols
+                .replace(file_path, &file_definitions(file_path, &text, tree))?;
+        }
+        self.files.mark_indexed(file_path, Some(SystemTime::now()));
+        self.record(JournalState::Indexed, &[file_path.to_path_buf()]);
+        Ok(())
+    }
+}
+
+/// The settings of the project, for revisions indexed in memory without
+/// watching or commit history.
+fn project_config(config: &Config, root: &Path, at_revision: bool) -> Result<Config> {
+    let mut config = config.for_project(root)?;
+    if at_revision {
+        config.watch = false;
+        config.storage.index_dir = None;
+        config.storage.read_only = false;
+        config.history.enabled = false;
+    }
+    Ok(config)
+}
+
+/// Only kept for on-disk indexes that are written to.
+fn open_journal(config: &Config, repository: &ProjectRepository) -> Result<Option<IndexJournal>> {
+    if config.storage.read_only {
+        return Ok(None);
+    }
+    repository
+        .index_path()
+        .map(|path| IndexJournal::new(open_database(path, repository.index_key(), false)?))
+        .transpose()
+}
+
+/// Hits are kept in on-disk indexes that are written to, in memory otherwise.
+fn open_hits(config: &Config, repository: &ProjectRepository) -> Result<ChunkHits> {
+    let conn = match repository.index_path() {
+        Some(path) if !config.storage.read_only => {
+            open_database(path, repository.index_key(), false)?
+        }
+        _ => rusqlite::Connection::open_in_memory()?,
+    };
+    ChunkHits::new(conn)
+}
+
+/// Tables written along with the chunks, kept in on-disk indexes. Read-only
+/// ones have those of the daemon that wrote them, if any.
+fn open_table<T>(
+    config: &Config,
+    repository: &ProjectRepository,
+    persisted: impl FnOnce(rusqlite::Connection) -> Option<T>,
+    new: impl FnOnce(rusqlite::Connection) -> Result<T>,
+) -> Result<T> {
+    let conn = match repository.index_path() {
+        Some(path) if config.storage.read_only => {
+            let conn = open_database(path, repository.index_key(), true)?;
+            if let Some(table) = persisted(conn) {
+                return Ok(table);
+            }
+            rusqlite::Connection::open_in_memory()?
+        }
+        Some(path) => open_database(path, repository.index_key(), false)?,
+        None => rusqlite::Connection::open_in_memory()?,
+    };
+    new(conn)
+}
+
+/// The commit history is kept next to the chunks, in memory if they are.
+fn open_history(config: &Config, repository: &ProjectRepository) -> Option<CommitHistory> {
+    if !config.history.enabled {
+        return None;
+    }
+    let conn = match repository.index_path() {
+        Some(path) => open_database(path, repository.index_key(), config.storage.read_only),
+        None => rusqlite::Connection::open_in_memory().map_err(Into::into),
+    };
+    match conn.and_then(|conn| {
+        CommitHistory::new(conn, repository.model().clone(), config.history.clone())
+    }) {
+        Ok(history) => Some(history),
+        Err(e) => {
+            tracing::warn!("commit history isn't searchable: {:?}", e);
+            None
+        }
+    }
+}
+
+fn discover_remote(config: &Config, root: &Path) -> Option<RemoteRepository> {
+    if !config.permalinks.enabled {
+        return None;
+    }
+    let remote = RemoteRepository::discover(root, &config.permalinks.remote);
+    if remote.is_none() {
+        tracing::warn!(
+            "no permalinks for {:?}, it has no git remote {:?} with a web view",
+            root,
+            config.permalinks.remote
+        );
+    }
+    remote
+}
+
+fn spawn_history_indexer(project_service: &Arc<Mutex<ProjectService>>) {
+    std::thread::spawn({
+        let project_service = project_service.clone();
+        move || index_history(&project_service)
+    });
+}
 
-        for (path, chunks) in files.all_chunks() {
-            repository.insert_file(&path.to_string_lossy(), chunks)?;
+/// Embeds the commits that aren't indexed yet a batch at a time.
+fn index_history(project_service: &Mutex<ProjectService>) {
+    let model = project_service.lock().unwrap().repository.model().clone();
+    if model.wait().is_err() {
+        return;
+    }
+    let commits = {
+        let service = project_service.lock().unwrap();
+        if service.config.storage.read_only {
+            return;
+        }
+        let Some(history) = &service.history else {
+            return;
+        };
+        match history.unindexed(&service.root) {
+            Ok(commits) => commits,
+            Err(e) => {
+                tracing::warn!("not indexing commit history: {:?}", e);
+                return;
+            }
         }
+    };
+    for batch in commits.chunks(COMMIT_BATCH) {
+        let mut service = project_service.lock().unwrap();
+        let Some(history) = &mut service.history else {
+            return;
+        };
+        if let Err(e) = history.store(batch) {
+            tracing::error!("error indexing commits: {:?}", e);
+            return;
+        }
+    }
+}
+
+fn spawn_indexer(project_service: &Arc<Mutex<ProjectService>>) {
+    std::thread::spawn({
+        let project_service = project_service.clone();
+        move || index_pending(&project_service)
+    });
+}
 
-        Ok(Self { files, repository })
+/// Embeds the queued files one at a time, releasing the lock in between so
+/// searches can be served from the partial index during warm-up.
+fn index_pending(project_service: &Mutex<ProjectService>) {
+    let model = project_service.lock().unwrap().repository.model().clone();
+    if let Err(e) = model.wait() {
+        tracing::error!("not indexing: {:?}", e);
+        return;
+    }
+    let mut indexed = 0;
+    loop {
+        let mut service = project_service.lock().unwrap();
+        let Some(path) = service.queue.pop() else {
+            service.repository.drop_seed();
+            if let Some(previous) = service.previous.take() {
+                previous.delete(&[&service.repository]);
+                let model = service.repository.model().model_code().to_string();
+                tracing::info!("{:?} switched to {}", service.root, model);
+                notify(Event::ModelSwitched {
+                    project: service.root.clone(),
+                    model,
+                });
+            }
+            if indexed > 0 {
+                notify(Event::IndexingFinished {
+                    project: service.root.clone(),
+                    files: indexed,
+                });
+            }
+            return;
+        };
+        match service.index_file(&path) {
+            Ok(()) => indexed += 1,
+            Err(e) => {
+                tracing::error!("error indexing {:?}: {:?}", path, e);
+                notify(Event::FileFailed {
+                    path,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+        drop(service);
+        std::thread::yield_now();
     }
 }
 
-type SearchCodeResponse = Vec<ResponseChunk>;
+/// Indexes the emptied file once it has stayed empty for `settle`, unless
+/// it got contents again in the meantime.
+fn spawn_emptied_check(
+    project_service: &Arc<Mutex<ProjectService>>,
+    file_path: PathBuf,
+    settle: Duration,
+) {
+    std::thread::spawn({
+        let project_service = project_service.clone();
+        move || {
+            std::thread::sleep(settle);
+            let mut service = project_service.lock().unwrap();
+            if service
+                .emptied
+                .get(&file_path)
+                .is_none_or(|at| at.elapsed() < settle)
+            {
+                return;
+            }
+            service.emptied.remove(&file_path);
+            if !service.files.was_emptied(&file_path) {
+                return;
+            }
+            tracing::debug!("{:?} stayed empty, indexing it", file_path);
+            if let Err(e) = service.update_file(&file_path) {
+                tracing::error!("error indexing {:?}: {:?}", file_path, e);
+            }
+        }
+    });
+}
+
+/// Verifies at full speed without `files_per_second`.
+fn spawn_verifier(project_service: &Arc<Mutex<ProjectService>>, files_per_second: Option<u32>) {
+    std::thread::spawn({
+        let project_service = project_service.clone();
+        move || {
+            verify_files(&project_service, files_per_second);
+            project_service.lock().unwrap().verifying = false;
+        }
+    });
+}
+
+/// A search as received, after expanding the query.
+struct SearchRequest {
+    query: String,
+    snippet: Option<String>,
+    exclude: Vec<String>,
+    options: SearchOptions,
+    deadline: Instant,
+}
+
+/// Sets its flag when dropped.
+#[derive(Default)]
+struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    fn flag(&self) -> Arc<AtomicBool> {
+        self.0.clone()
+    }
+}
+
+impl Drop for Cancellation {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Searches and hydrates the results, giving up with `RpcError::Cancelled`
+/// between steps once `cancelled` is set.
+fn search_blocking(
+    project_service: &Mutex<ProjectService>,
+    request: SearchRequest,
+    cancelled: &AtomicBool,
+) -> Result<SearchCodeResponse, RpcError> {
+    let SearchRequest {
+        query,
+        snippet,
+        exclude,
+        options,
+        deadline,
+    } = request;
+    let mut service = project_service.lock().unwrap();
+    if service.searched().model().is_loading() {
+        return Err(RpcError::ModelLoading);
+    }
+    let filter = ChunkFilter {
+        kinds: options.kinds.clone(),
+        languages: options.languages.clone(),
+        path_prefix: options
+            .path_prefix
+            .as_ref()
+            .map(|prefix| service.root.join(prefix)),
+    };
+    let mut queries = vec![query.as_str()];
+    queries.extend(snippet.as_deref());
+    let top_k = options.limit.unwrap_or(service.config.search.top_k);
+    let overfetch = if exclude.is_empty() {
+        1
+    } else {
+        EXCLUSION_OVERFETCH
+    };
+    let corpus_limits: Vec<(Corpus, usize)> = options
+        .corpus_limits
+        .iter()
+        .map(|(corpus, limit)| (*corpus, limit * overfetch))
+        .collect();
+    let search = |repository: &ProjectRepository| {
+        if corpus_limits.is_empty() {
+            repository.search_all(&queries, top_k * overfetch, &filter)
+        } else {
+            repository.search_corpora(&queries, &corpus_limits, &filter)
+        }
+    };
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(RpcError::Cancelled);
+    }
+    let mut chunks = search(service.searched())?;
+    if options.require_fresh && service.revision.is_none() {
+        for _ in 0..FRESH_SEARCH_ATTEMPTS {
+            let paths = chunks.iter().map(|chunk| chunk.path.clone()).collect();
+            if !service.refresh_files(paths)? {
+                break;
+            }
+            chunks = search(service.searched())?;
+        }
+    }
+    let identifier_weight = service.config.search.identifier_weight;
+    let identifier_scores = if identifier_weight > 0.0 {
+        let words = query_words(&query);
+        let hits = service
+            .searched_identifiers()
+            .search(&words, top_k * overfetch, &filter)?;
+        fuse_identifier_hits(&mut chunks, hits)
+    } else {
+        HashMap::new()
+    };
+
+    let mut response = SearchCodeResponse {
+        model: Some(service.searched().model().model_code().to_string()),
+        ..Default::default()
+    };
+    let _span = tracing::info_span!("hydrate", chunks = chunks.len()).entered();
+    let mut content_hashes = HashMap::new();
+    for chunk in chunks {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(RpcError::Cancelled);
+        }
+        if Instant::now() >= deadline {
+            response.partial = true;
+            break;
+        }
+        let content_hash = chunk.content_hash.clone();
+        let Some(mut chunk) = service.files.chunk_to_response(chunk) else {
+            continue;
+        };
+        content_hashes.insert(chunk.id.clone(), content_hash);
+        if options.explain {
+            chunk.explanation = Some(Explanation::of(&chunk, &options));
+        }
+        response.chunks.push(chunk);
+    }
+    if !identifier_scores.is_empty() {
+        boost_identifier_matches(&mut response.chunks, &identifier_scores, identifier_weight);
+    }
+    if !exclude.is_empty() {
+        penalize_excluded(
+            &mut response.chunks,
+            &exclude,
+            service.config.search.exclusion_penalty,
+        );
+    }
+    if !identifier_scores.is_empty() || !exclude.is_empty() {
+        options.limit_results(&mut response.chunks, top_k);
+    }
+    let hits = response
+        .chunks
+        .iter()
+        .map(|chunk| (chunk.path.as_path(), content_hashes[&chunk.id].as_str()));
+    // Into both indexes, the new one keeps them after the switch.
+    let recorded = service.hits.record(hits.clone()).and_then(|()| {
+        service
+            .previous
+            .as_ref()
+            .map_or(Ok(()), |previous| previous.hits.record(hits))
+    });
+    if let Err(e) = recorded {
+        tracing::warn!("failed to record search hits: {:?}", e);
+    }
+    service
+        .files
+        .mark_hit(response.chunks.iter().map(|chunk| chunk.path.clone()));
+    service.queue.prioritize_directories(
+        response
+            .chunks
+            .iter()
+            .filter_map(|chunk| chunk.path.parent()),
+    );
+    let remote = service.remote.clone();
+    let revision_commit = service.files.commit().map(str::to_string);
+    drop(service);
+
+    if let Some(remote) = remote
+        && let Some(commit) = revision_commit.clone().or_else(|| remote.head())
+    {
+        for chunk in &mut response.chunks {
+            chunk.permalink = remote.permalink(&commit, &chunk.path, chunk.rows());
+        }
+    }
+    if options.blame {
+        let _span = tracing::info_span!("blame").entered();
+        for chunk in &mut response.chunks {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(RpcError::Cancelled);
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            chunk.blame = blame::last_change(&chunk.path, chunk.rows(), revision_commit.as_deref());
+        }
+    }
+    Ok(response)
+}
+
+/// Rehashes every file of the project, reindexing the ones whose contents
+/// changed and adding the ones that appeared without the watcher noticing.
+/// Takes the lock one file at a time so searches aren't held up.
+fn verify_files(project_service: &Mutex<ProjectService>, files_per_second: Option<u32>) {
+    let (root, config) = {
+        let service = project_service.lock().unwrap();
+        (service.root.clone(), service.config.clone())
+    };
+    let delay = files_per_second.map_or(Duration::ZERO, |files| {
+        Duration::from_secs(1) / files.max(1)
+    });
+    let (mut checked, mut changed) = (0, Vec::new());
+    let boundaries = Boundaries::new(&config);
+    for path in supported_files(&root, config.follow_symlinks, &boundaries) {
+        let path = match path {
+            Ok(path) => path,
+            Err(e) => {
+                tracing::warn!("error walking {:?}: {:?}", root, e);
+                continue;
+            }
+        };
+        let mut service = project_service.lock().unwrap();
+        checked += 1;
+        if service.emptied.contains_key(&path) {
+            // Indexed once it settles.
+            continue;
+        }
+        match service.files.is_current(&path) {
+            Ok(true) => {}
+            Ok(false) => match service.files.create_or_update(&path) {
+                Ok(()) => changed.push(path),
+                Err(e) => tracing::warn!("error reading {:?}: {:?}", path, e),
+            },
+            Err(e) => tracing::warn!("error reading {:?}: {:?}", path, e),
+        }
+        drop(service);
+        std::thread::sleep(delay);
+    }
+
+    // An unmounted root would look like all of its files were deleted.
+    if !root.is_dir() {
+        tracing::warn!("not verifying {:?}, its directory is gone", root);
+        return;
+    }
+    let mut service = project_service.lock().unwrap();
+    let removed = service.remove_missing().len();
+    tracing::info!(
+        "verified {} files of {:?}: {} changed, {} removed",
+        checked,
+        root,
+        changed.len(),
+        removed
+    );
+    if !changed.is_empty() {
+        service.queue_files(changed);
+        drop(service);
+        index_pending(project_service);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchOptions {
+    /// Only return chunks of these kinds, any kind if empty.
+    pub kinds: Vec<ChunkKind>,
+    /// Only return chunks of files in these languages, e.g. "rust", any
+    /// language if empty.
+    pub languages: Vec<String>,
+    /// Only return chunks of files under this path, relative to the project
+    /// root.
+    pub path_prefix: Option<PathBuf>,
+    /// Rank chunks containing any of these terms after the rest, like
+    /// `-term` words in the query.
+    pub exclude: Vec<String>,
+    /// Number of chunks to return, `search.top_k` if unset.
+    pub limit: Option<usize>,
+    /// Named session opened with `open_session`, whose earlier results are
+    /// skipped and whose files are favored.
+    pub session: Option<String>,
+    /// Look up the last commit that changed each result, one git call per
+    /// result. Results left when the budget runs out go without.
+    pub blame: bool,
+    /// Explain the distance of each result, for tuning ranking.
+    pub explain: bool,
+    /// Reindex the files of results that changed on disk since they were
+    /// indexed and search again, so results match the working tree even
+    /// before the watcher catches up.
+    pub require_fresh: bool,
+    /// How many chunks to return of each corpus instead of `limit` closest
+    /// overall, e.g. 4 of code, 1 of docs and 1 of config. Corpora left out
+    /// aren't searched.
+    pub corpus_limits: Vec<(Corpus, usize)>,
+}
+
+impl SearchOptions {
+    /// Keeps the closest `top_k` chunks, or the closest of each corpus with
+    /// `corpus_limits`.
+    pub fn limit_results(&self, chunks: &mut Vec<ResponseChunk>, top_k: usize) {
+        if self.corpus_limits.is_empty() {
+            chunks.truncate(top_k);
+        } else {
+            *chunks = draw_per_corpus(chunks.drain(..), &self.corpus_limits, |chunk| chunk.kind);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchCodeResponse {
+    /// Closest chunks first.
+    pub chunks: Vec<ResponseChunk>,
+    /// The time budget ran out before all results were ready, `chunks` holds
+    /// the ones found so far.
+    pub partial: bool,
+    /// The embedding model the distances are from. Unset when the results
+    /// of projects using different models were merged by rank, as their
+    /// distances can't be compared.
+    pub model: Option<String>,
+    /// Identifies the search in the daemon's logs and traces, as their
+    /// `rpc.trace_id`.
+    pub request_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub files: usize,
+    pub text_bytes: usize,
+    /// Estimated, tree-sitter doesn't report exact tree sizes.
+    pub tree_bytes: usize,
+    pub index_bytes: u64,
+    /// Approximated by the size of the model weights.
+    pub model_bytes: u64,
+}
+
+/// How many files of the project still have to be embedded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueStatus {
+    pub pending: usize,
+    /// Nothing is embedded until the model has loaded.
+    pub model_loading: bool,
+    /// The files embedded next, in order.
+    pub next: Vec<PathBuf>,
+}
+
+/// A registered project and how up to date its index is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectInfo {
+    pub root: PathBuf,
+    pub files: usize,
+    /// Files waiting to be embedded, the index is current when there are none.
+    pub pending: usize,
+    pub model_loading: bool,
+    pub last_indexed: Option<SystemTime>,
+    pub read_only: bool,
+    /// The git revision the project was registered at, if not the working
+    /// tree.
+    pub revision: Option<String>,
+    /// The embedding model of the project's index.
+    pub model: String,
+    /// The model of the index searched while the files are embedded with
+    /// `model`, after the configured model changed.
+    pub previous_model: Option<String>,
+    /// False while the project's directory is gone, e.g. deleted or
+    /// unmounted.
+    pub available: bool,
+}
 
 #[tarpc::service]
 pub trait ProjectRpc {
-    async fn search_code(query: String) -> Result<SearchCodeResponse, RpcError>;
+    /// Stops hydrating results once `budget` has passed.
+    async fn search_code(
+        query: String,
+        options: SearchOptions,
+        budget: Duration,
+    ) -> Result<SearchCodeResponse, RpcError>;
 
     async fn file_updated(path: PathBuf) -> Result<(), RpcError>;
+
+    async fn resource_usage() -> Result<ResourceUsage, RpcError>;
+
+    async fn compact() -> Result<CompactionStats, RpcError>;
+
+    /// Writes the chunks and their embeddings to `output` as JSON lines.
+    async fn export_embeddings(output: PathBuf) -> Result<usize, RpcError>;
+
+    /// Uses the embeddings of an export of the same model for the files
+    /// still to index, then indexes them. Projects started with
+    /// `defer_indexing` start indexing here.
+    async fn import_embeddings(input: PathBuf) -> Result<usize, RpcError>;
+
+    async fn reload_config(config: Config) -> Result<(), RpcError>;
+
+    async fn queue_status() -> Result<QueueStatus, RpcError>;
+
+    async fn project_info() -> Result<ProjectInfo, RpcError>;
+
+    /// Chunks of the file around the one containing byte `at`.
+    async fn chunk_neighbors(
+        path: PathBuf,
+        at: usize,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<ResponseChunk>, RpcError>;
+
+    async fn prioritize(paths: Vec<PathBuf>) -> Result<usize, RpcError>;
+
+    /// Starts a verification pass in the background unless one is running.
+    /// Projects that aren't watched are left alone.
+    async fn verify(files_per_second: u32) -> Result<(), RpcError>;
+
+    /// Starts an unthrottled verification pass in the background unless one
+    /// is running, picking up changes to the files whether or not they're
+    /// watched.
+    async fn reindex() -> Result<(), RpcError>;
+
+    /// Reindexes the files that differ between two git revisions, e.g. after
+    /// switching branches. Returns how many changed on disk.
+    async fn reindex_changes(from: String, to: String) -> Result<usize, RpcError>;
+
+    /// Finds the test files most likely to cover `subject`, a file path
+    /// relative to the project root or a symbol name.
+    async fn find_tests_for(subject: String, limit: usize) -> Result<Vec<TestMatch>, RpcError>;
+
+    /// Definitions of the identifier at the zero-based row and byte column of
+    /// `file`, given relative to the project root.
+    async fn goto_definition(
+        file: String,
+        row: usize,
+        column: usize,
+        limit: usize,
+    ) -> Result<Vec<Definition>, RpcError>;
+
+    /// Definitions whose name contains `query`, see `SymbolTable::search`.
+    async fn search_symbols(query: String, limit: usize) -> Result<Vec<Definition>, RpcError>;
+
+    /// Definitions of `file`, given relative to the project root, in file
+    /// order.
+    async fn outline(file: String) -> Result<Vec<Definition>, RpcError>;
+
+    /// Finds the commits whose message is closest to the query.
+    async fn search_history(query: String, limit: usize) -> Result<Vec<CommitMatch>, RpcError>;
+
+    /// TODO, FIXME, HACK and XXX comments whose tag or text matches `filter`.
+    async fn list_todos(filter: String) -> Result<Vec<Todo>, RpcError>;
+
+    /// The files by language, which of them are indexed and how much of
+    /// their contents, and the files that are skipped.
+    async fn coverage() -> Result<Coverage, RpcError>;
+
+    /// Up to `limit` chunks no search has returned, in path order.
+    async fn never_matched(limit: usize) -> Result<Vec<ColdChunk>, RpcError>;
+
+    /// Stops indexing and moves the on-disk index aside so it can be restored.
+    async fn remove() -> Result<(), RpcError>;
+
+    /// Stops indexing before the daemon restarts, once the file being
+    /// indexed is written. Returns how many files were still queued, the
+    /// journal has them as pending for the next start.
+    async fn flush() -> Result<usize, RpcError>;
 }
 
 impl ProjectRpc for Arc<Mutex<ProjectService>> {
@@ -63,10 +1167,56 @@ impl ProjectRpc for Arc<Mutex<ProjectService>> {
         self,
         _ctx: context::Context,
         query: String,
+        options: SearchOptions,
+        budget: Duration,
     ) -> Result<SearchCodeResponse, RpcError> {
-        let service = self.lock().unwrap();
-        let chunks = service.repository.search(&query).unwrap();
-        Ok(service.files.chunks_to_response(chunks))
+        let deadline = Instant::now() + budget;
+        let (query, mut exclude) = split_exclusions(&query);
+        exclude.extend(options.exclude.iter().cloned());
+        let expansion = self.lock().unwrap().config.query_expansion.clone();
+        let snippet = if expansion.enabled {
+            let timeout = budget.min(Duration::from_millis(expansion.timeout_ms));
+            let query = query.clone();
+            let expanded = tokio::task::spawn_blocking(move || {
+                expansion::hypothetical_snippet(&expansion, &query, timeout)
+            })
+            .instrument(tracing::info_span!("expand_query"))
+            .await
+            .map_err(anyhow::Error::from)
+            .flatten();
+            match expanded {
+                Ok(snippet) => Some(snippet),
+                Err(e) => {
+                    tracing::warn!("searching without query expansion: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Dropped along with the request when the client cancels it or goes
+        // away, which stops the search at its next check.
+        let cancellation = Cancellation::default();
+        let cancelled = cancellation.flag();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                search_blocking(
+                    &self,
+                    SearchRequest {
+                        query,
+                        snippet,
+                        exclude,
+                        options,
+                        deadline,
+                    },
+                    &cancelled,
+                )
+            })
+        })
+        .await
+        .map_err(anyhow::Error::from)?
     }
 
     async fn file_updated(
@@ -75,11 +1225,479 @@ impl ProjectRpc for Arc<Mutex<ProjectService>> {
         file_path: PathBuf,
     ) -> Result<(), RpcError> {
         let mut service = self.lock().unwrap();
-        service.files.create_or_update(&file_path)?;
-        let chunks = service.files.file_chunks(&file_path);
-        service
+        if service.config.storage.read_only {
+            return Err(RpcError::ReadOnly);
+        }
+        // Files of a nested project that isn't registered are routed here.
+        if Boundaries::new(&service.config).excludes(&service.root, &file_path) {
+            return Ok(());
+        }
+        if service.files.was_emptied(&file_path) {
+            if !service.emptied.contains_key(&file_path) {
+                service.emptied.insert(file_path.clone(), Instant::now());
+                let settle = Duration::from_millis(service.config.empty_file_settle_ms);
+                drop(service);
+                spawn_emptied_check(&self, file_path, settle);
+            }
+            return Ok(());
+        }
+        service.update_file(&file_path)?;
+        Ok(())
+    }
+
+    async fn resource_usage(self, _ctx: context::Context) -> Result<ResourceUsage, RpcError> {
+        let service = self.lock().unwrap();
+        let (text_bytes, tree_bytes) = service.files.memory_usage();
+        Ok(ResourceUsage {
+            files: service.files.paths().count(),
+            text_bytes,
+            tree_bytes,
+            index_bytes: service.repository.storage_bytes()?,
+            model_bytes: service.repository.model().file_size(),
+        })
+    }
+
+    async fn export_embeddings(
+        self,
+        _ctx: context::Context,
+        output: PathBuf,
+    ) -> Result<usize, RpcError> {
+        let file = std::fs::File::create(&output)
+            .with_context(|| format!("Failed to create {:?}", output))?;
+        let service = self.lock().unwrap();
+        Ok(service.repository.export(
+            &service.root,
+            &service.config.preprocess,
+            std::io::BufWriter::new(file),
+        )?)
+    }
+
+    async fn import_embeddings(
+        self,
+        _ctx: context::Context,
+        input: PathBuf,
+    ) -> Result<usize, RpcError> {
+        let imported = (|| {
+            let file = std::fs::File::open(&input)
+                .with_context(|| format!("Failed to open {:?}", input))?;
+            let mut service = self.lock().unwrap();
+            if service.config.storage.read_only {
+                return Err(RpcError::ReadOnly);
+            }
+            let preprocess = service.config.preprocess.clone();
+            Ok(service
+                .repository
+                .import(&preprocess, std::io::BufReader::new(file))?)
+        })();
+        // Also when the import failed, the files are still to index. The
+        // indexer frees the imported embeddings once the queue is empty.
+        spawn_indexer(&self);
+        imported
+    }
+
+    async fn compact(self, _ctx: context::Context) -> Result<CompactionStats, RpcError> {
+        let mut service = self.lock().unwrap();
+        if service.config.storage.read_only {
+            return Err(RpcError::ReadOnly);
+        }
+        let service = &mut *service;
+        service.remove_missing();
+        let stats = service
             .repository
-            .insert_file(&file_path.to_string_lossy(), chunks)?;
+            .compact(|path| service.files.contains(path))?;
+        Ok(stats)
+    }
+
+    /// Files are rechunked with the new settings, which only re-embeds chunks
+    /// whose text changed. A different model or storage backend starts the
+    /// index over.
+    async fn reload_config(self, _ctx: context::Context, config: Config) -> Result<(), RpcError> {
+        let mut service = self.lock().unwrap();
+        let config = project_config(&config, &service.root, service.revision.is_some())?;
+        if service.config.requires_reindex(&config) {
+            tracing::warn!(
+                "embedding model or storage backend of {:?} changed, reindexing it from scratch in the background",
+                service.root
+            );
+            let repository = ProjectRepository::new(&config, &service.root)?;
+            let hits = open_hits(&config, &repository)?;
+            let symbols = open_table(
+                &config,
+                &repository,
+                SymbolTable::persisted,
+                SymbolTable::new,
+            )?;
+            let identifiers = open_table(
+                &config,
+                &repository,
+                IdentifierIndex::persisted,
+                IdentifierIndex::new,
+            )?;
+            service.journal = open_journal(&config, &repository)?;
+            let replaced = PreviousIndex {
+                repository: std::mem::replace(&mut service.repository, repository),
+                hits: std::mem::replace(&mut service.hits, hits),
+                symbols: std::mem::replace(&mut service.symbols, symbols),
+                identifiers: std::mem::replace(&mut service.identifiers, identifiers),
+            };
+            if config.storage.read_only {
+                // Indexes that are only read are left as they are.
+            } else if let Some(previous) = &service.previous {
+                // Switching again before the new index was done keeps
+                // searching the complete one.
+                replaced.delete(&[&previous.repository, &service.repository]);
+            } else {
+                service.previous = Some(replaced);
+            }
+        }
+        let history_changed =
+            service.config.requires_reindex(&config) || service.config.history != config.history;
+        if history_changed {
+            service.history = open_history(&config, &service.repository);
+        }
+        if service.config.permalinks != config.permalinks {
+            service.remote = discover_remote(&config, &service.root);
+        }
+        service.files.set_text_budget(config.text_cache_bytes);
+        service
+            .files
+            .set_column_encoding(config.search.column_encoding);
+        if config.storage.read_only {
+            service.queue = IndexQueue::default();
+        } else if service.config.requires_reindex(&config)
+            || service.config.chunking != config.chunking
+            || service.config.preprocess != config.preprocess
+        {
+            service
+                .files
+                .set_chunking(config.chunking.clone(), config.preprocess.clone());
+            service.queue_all();
+            spawn_indexer(&self);
+        }
+        service.config = Arc::new(config);
+        if history_changed {
+            spawn_history_indexer(&self);
+        }
         Ok(())
     }
+
+    async fn queue_status(self, _ctx: context::Context) -> Result<QueueStatus, RpcError> {
+        let service = self.lock().unwrap();
+        Ok(QueueStatus {
+            pending: service.queue.len(),
+            model_loading: service.repository.model().is_loading(),
+            next: service.queue.peek(QUEUE_STATUS_NEXT),
+        })
+    }
+
+    async fn reindex_changes(
+        self,
+        _ctx: context::Context,
+        from: String,
+        to: String,
+    ) -> Result<usize, RpcError> {
+        let mut service = self.lock().unwrap();
+        if service.config.storage.read_only {
+            return Err(RpcError::ReadOnly);
+        }
+        if service.revision.is_some() {
+            return Err(anyhow::anyhow!(
+                "Projects registered at a revision are refreshed with reindex"
+            )
+            .into());
+        }
+        let paths = changed_files(&service.root, &from, &to)?;
+        let queued = service.reread(paths)?;
+        drop(service);
+        spawn_indexer(&self);
+        Ok(queued)
+    }
+
+    async fn chunk_neighbors(
+        self,
+        _ctx: context::Context,
+        path: PathBuf,
+        at: usize,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<ResponseChunk>, RpcError> {
+        let service = self.lock().unwrap();
+        Ok(service.files.neighbors(&path, at, before, after))
+    }
+
+    async fn project_info(self, _ctx: context::Context) -> Result<ProjectInfo, RpcError> {
+        let service = self.lock().unwrap();
+        Ok(ProjectInfo {
+            root: service.root.clone(),
+            files: service.files.paths().count(),
+            pending: service.queue.len(),
+            model_loading: service.repository.model().is_loading(),
+            last_indexed: service.files.last_indexed(),
+            read_only: service.config.storage.read_only,
+            revision: service.revision.clone(),
+            model: service.repository.model().model_code().to_string(),
+            previous_model: service
+                .previous
+                .as_ref()
+                .map(|previous| previous.repository.model().model_code().to_string()),
+            available: true,
+        })
+    }
+
+    async fn prioritize(
+        self,
+        _ctx: context::Context,
+        paths: Vec<PathBuf>,
+    ) -> Result<usize, RpcError> {
+        let mut service = self.lock().unwrap();
+        Ok(service.queue.prioritize(paths.iter().map(PathBuf::as_path)))
+    }
+
+    async fn verify(self, _ctx: context::Context, files_per_second: u32) -> Result<(), RpcError> {
+        let mut service = self.lock().unwrap();
+        if !service.verifying && !service.config.storage.read_only && service.config.watch {
+            service.verifying = true;
+            spawn_verifier(&self, Some(files_per_second));
+        }
+        Ok(())
+    }
+
+    async fn reindex(self, _ctx: context::Context) -> Result<(), RpcError> {
+        let mut service = self.lock().unwrap();
+        if service.config.storage.read_only {
+            return Err(RpcError::ReadOnly);
+        }
+        if service.revision.is_some() {
+            service.refresh_revision()?;
+            drop(service);
+            spawn_indexer(&self);
+        } else if !service.verifying {
+            service.verifying = true;
+            spawn_verifier(&self, None);
+        }
+        Ok(())
+    }
+
+    /// Commits made since the history was indexed are added first, the most
+    /// recent batch of them if there are many.
+    async fn search_history(
+        self,
+        _ctx: context::Context,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<CommitMatch>, RpcError> {
+        let mut service = self.lock().unwrap();
+        if service.repository.model().is_loading() {
+            return Err(RpcError::ModelLoading);
+        }
+        let service = &mut *service;
+        let Some(history) = &mut service.history else {
+            return Err(anyhow::anyhow!(
+                "Commit history isn't indexed, it's enabled with history.enabled"
+            )
+            .into());
+        };
+        if !service.config.storage.read_only {
+            let commits = history.unindexed(&service.root)?;
+            history.store(&commits[..commits.len().min(COMMIT_BATCH)])?;
+        }
+        Ok(history.search(&query, limit)?)
+    }
+
+    /// Without the model only the naming and mention heuristics are used.
+    async fn find_tests_for(
+        self,
+        _ctx: context::Context,
+        subject: String,
+        limit: usize,
+    ) -> Result<Vec<TestMatch>, RpcError> {
+        let service = self.lock().unwrap();
+        let path = std::fs::canonicalize(service.root.join(&subject))
+            .ok()
+            .filter(|path| service.files.contains(path));
+        let (subject, query) = match &path {
+            Some(path) => (
+                Subject::File(path),
+                service
+                    .files
+                    .text(path)
+                    .as_deref()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(FIND_TESTS_QUERY_CHARS)
+                    .collect(),
+            ),
+            None => (Subject::Symbol(&subject), subject.clone()),
+        };
+        let similar = if service.searched().model().is_loading() {
+            Vec::new()
+        } else {
+            service
+                .searched()
+                .search(
+                    &query,
+                    limit,
+                    &ChunkFilter {
+                        kinds: vec![ChunkKind::Test],
+                        ..Default::default()
+                    },
+                )?
+                .into_iter()
+                .map(|chunk| (chunk.path, chunk.row))
+                .collect()
+        };
+        Ok(find_tests(&service.files, &subject, similar, limit))
+    }
+
+    async fn goto_definition(
+        self,
+        _ctx: context::Context,
+        file: String,
+        row: usize,
+        column: usize,
+        limit: usize,
+    ) -> Result<Vec<Definition>, RpcError> {
+        let service = self.lock().unwrap();
+        // Files read from a revision aren't on disk to canonicalize.
+        let mut path = service.root.join(&file);
+        if !service.files.contains(&path) {
+            path = std::fs::canonicalize(&path).unwrap_or(path);
+        }
+        Ok(find_definitions(
+            &service.files,
+            &path,
+            Point::new(row, column),
+            limit,
+        )?)
+    }
+
+    async fn search_symbols(
+        self,
+        _ctx: context::Context,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<Definition>, RpcError> {
+        let service = self.lock().unwrap();
+        Ok(service.searched_symbols().search(&query, limit)?)
+    }
+
+    async fn outline(
+        self,
+        _ctx: context::Context,
+        file: String,
+    ) -> Result<Vec<Definition>, RpcError> {
+        let service = self.lock().unwrap();
+        let path = service.root.join(&file);
+        Ok(service.searched_symbols().outline(&path)?)
+    }
+
+    async fn list_todos(
+        self,
+        _ctx: context::Context,
+        filter: String,
+    ) -> Result<Vec<Todo>, RpcError> {
+        Ok(self.lock().unwrap().files.todos(&filter))
+    }
+
+    async fn coverage(self, _ctx: context::Context) -> Result<Coverage, RpcError> {
+        let (root, config, at_commit) = {
+            let service = self.lock().unwrap();
+            let at_commit = service.files.commit().is_some();
+            (service.root.clone(), service.config.clone(), at_commit)
+        };
+        // Walked without the lock, revisions come from the object store.
+        let walked = if at_commit {
+            Vec::new()
+        } else {
+            walked_files(&root, config.follow_symlinks, &Boundaries::new(&config))
+        };
+        Ok(coverage(&self.lock().unwrap().files, &walked))
+    }
+
+    async fn never_matched(
+        self,
+        _ctx: context::Context,
+        limit: usize,
+    ) -> Result<Vec<ColdChunk>, RpcError> {
+        let service = self.lock().unwrap();
+        Ok(service
+            .searched_hits()
+            .never_matched(&service.files, limit)?)
+    }
+
+    /// Indexes mounted read-only are left where they are.
+    async fn remove(self, _ctx: context::Context) -> Result<(), RpcError> {
+        let mut service = self.lock().unwrap();
+        service.queue = IndexQueue::default();
+        if service.config.storage.read_only {
+            return Ok(());
+        }
+        if let Some(index_path) = service.repository.index_path() {
+            remove_index(index_path)?;
+        }
+        Ok(())
+    }
+
+    /// Files are indexed under the lock, so taking it waits for the file
+    /// being indexed and the indexer finds the queue empty.
+    async fn flush(self, _ctx: context::Context) -> Result<usize, RpcError> {
+        let mut service = self.lock().unwrap();
+        Ok(std::mem::take(&mut service.queue).len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A service for a project of one file, indexed in memory with the
+    /// default model once it has loaded.
+    fn service(root: &Path) -> Mutex<ProjectService> {
+        std::fs::write(
+            root.join("auth.rs"),
+            "pub fn parse_jwt_header(token: &str) -> Option<&str> {\n    token.split('.').next()\n}\n",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.storage.index_dir = None;
+        let service =
+            ProjectService::new(root.to_path_buf(), Arc::new(config), None, None).unwrap();
+        service.repository.model().wait().unwrap();
+        Mutex::new(service)
+    }
+
+    fn request(query: &str) -> SearchRequest {
+        SearchRequest {
+            query: query.to_string(),
+            snippet: None,
+            exclude: Vec::new(),
+            options: SearchOptions::default(),
+            deadline: Instant::now() + Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn dropping_the_cancellation_sets_its_flag() {
+        let cancellation = Cancellation::default();
+        let cancelled = cancellation.flag();
+        assert!(!cancelled.load(Ordering::Relaxed));
+        drop(cancellation);
+        assert!(cancelled.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn cancelled_searches_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = service(&dir.path().canonicalize().unwrap());
+
+        let cancelled = AtomicBool::new(true);
+        let result = search_blocking(&service, request("parse a jwt header"), &cancelled);
+        assert!(matches!(result, Err(RpcError::Cancelled)), "{result:?}");
+        let result = search_blocking(
+            &service,
+            request("parse a jwt header"),
+            &AtomicBool::new(false),
+        );
+        assert!(result.is_ok(), "{result:?}");
+    }
 }
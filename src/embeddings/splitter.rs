@@ -0,0 +1,134 @@
+use tree_sitter::Tree;
+
+use crate::config::{ChunkingConfig, SplitterKind};
+
+use super::code_splitter::{Chunk, CodeSplitter, TextPosition};
+
+pub trait Splitter {
+    /// Splits the source into chunks that together cover every byte of it.
+    fn split<'a>(&self, tree: &'a Tree, source: &'a str) -> Vec<Chunk<'a>>;
+}
+
+pub fn splitter_for(config: &ChunkingConfig, language: &str) -> Box<dyn Splitter> {
+    match config.strategy_for(language) {
+        SplitterKind::Ast => Box::new(AstSplitter {
+            max_chunk_size: config.max_chunk_size,
+        }),
+        SplitterKind::LineWindow => Box::new(LineWindowSplitter {
+            max_lines: config.window_lines,
+        }),
+        SplitterKind::TokenWindow => Box::new(TokenWindowSplitter {
+            max_tokens: config.window_tokens,
+        }),
+    }
+}
+
+/// Groups sibling syntax nodes into chunks, descending into nodes that don't fit.
+pub struct AstSplitter {
+    pub max_chunk_size: usize,
+}
+
+impl Splitter for AstSplitter {
+    fn split<'a>(&self, tree: &'a Tree, source: &'a str) -> Vec<Chunk<'a>> {
+        CodeSplitter::new(tree, source, self.max_chunk_size).chunks()
+    }
+}
+
+/// Fixed windows of `max_lines` lines, ignoring the syntax tree.
+pub struct LineWindowSplitter {
+    pub max_lines: usize,
+}
+
+impl Splitter for LineWindowSplitter {
+    fn split<'a>(&self, _tree: &'a Tree, source: &'a str) -> Vec<Chunk<'a>> {
+        let max_lines = self.max_lines.max(1);
+        let ends = source
+            .match_indices('\n')
+            .map(|(index, _)| index + 1)
+            .skip(max_lines - 1)
+            .step_by(max_lines);
+        window_chunks(source, ends)
+    }
+}
+
+/// Fixed windows of `max_tokens` whitespace separated tokens, ignoring the syntax tree.
+pub struct TokenWindowSplitter {
+    pub max_tokens: usize,
+}
+
+impl Splitter for TokenWindowSplitter {
+    fn split<'a>(&self, _tree: &'a Tree, source: &'a str) -> Vec<Chunk<'a>> {
+        let max_tokens = self.max_tokens.max(1);
+        // A window ends where the token following its last token starts.
+        let ends = token_starts(source).skip(max_tokens).step_by(max_tokens);
+        window_chunks(source, ends)
+    }
+}
+
+fn token_starts(source: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut previous_is_whitespace = true;
+    source.char_indices().filter_map(move |(index, c)| {
+        let is_token_start = previous_is_whitespace && !c.is_whitespace();
+        previous_is_whitespace = c.is_whitespace();
+        is_token_start.then_some(index)
+    })
+}
+
+/// Cuts the source at the given ascending byte offsets, the last window runs to the end.
+fn window_chunks(source: &str, ends: impl Iterator<Item = usize>) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut position = TextPosition::default();
+    for end in ends.chain([source.len()]) {
+        if end <= start {
+            continue;
+        }
+        let text = &source[start..end];
+        let end_position = position.advance(text);
+        chunks.push(Chunk {
+            text,
+            range: start..end,
+            start: position,
+            end: end_position,
+        });
+        start = end;
+        position = end_position;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Parser;
+
+    use super::*;
+
+    fn parse(source: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    #[test]
+    fn line_windows_cover_the_source() {
+        let source = "fn a() {}\nfn b() {}\nfn c() {}\n";
+        let tree = parse(source);
+        let chunks = LineWindowSplitter { max_lines: 2 }.split(&tree, source);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text).collect();
+        assert_eq!(texts, ["fn a() {}\nfn b() {}\n", "fn c() {}\n"]);
+        assert_eq!(chunks[1].start, TextPosition { row: 2, column: 0 });
+        assert_eq!(chunks[1].end, TextPosition { row: 3, column: 0 });
+    }
+
+    #[test]
+    fn token_windows_cover_the_source() {
+        let source = "  let x = 1;\nlet y = 2;";
+        let tree = parse(source);
+        let chunks = TokenWindowSplitter { max_tokens: 4 }.split(&tree, source);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text).collect();
+        assert_eq!(texts, ["  let x = 1;\n", "let y = 2;"]);
+        assert_eq!(chunks[1].start, TextPosition { row: 1, column: 0 });
+    }
+}
@@ -0,0 +1,1418 @@
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_int},
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+use anyhow::{Context, Result};
+use rusqlite::{
+    Connection, OpenFlags, OptionalExtension,
+    ffi::{sqlite3, sqlite3_api_routines, sqlite3_auto_extension},
+    params, params_from_iter,
+    types::Value,
+};
+use sqlite_vec::sqlite3_vec_init;
+use zerocopy::IntoBytes;
+
+use super::{
+    project_files::language_of,
+    project_repository::{CompactionStats, OutputChunk},
+    vector_store::{
+        ChunkFilter, ChunkKind, ChunkRecord, VectorStore, merge_nearest, min_distance, nearest,
+        top_directory,
+    },
+};
+
+/// Version of the layout of indexes, recorded in them. Bumped when older
+/// versions can't read the indexes this one writes.
+const SCHEMA_VERSION: u32 = 2;
+
+/// How vec0 tables are told to measure cosine distances like the rest.
+const COSINE_METRIC: &str = "distance_metric=cosine";
+
+/// How many times the requested number of chunks a search under a path
+/// fetches from the path's partition before filtering by path.
+const PATH_FILTER_OVERFETCH: usize = 10;
+
+/// Columns of the chunks table other than the id and vector id.
+const CHUNK_COLUMNS: &str = "file_id, start_row, start_column, end_row, end_column, start_byte, end_byte, content_hash, kind";
+
+/// Columns selecting the position of a chunk found in a search along with
+/// its file, read by `found_chunk`.
+const FOUND_COLUMNS: &str = "files.path,
+    chunks.start_row,
+    chunks.end_row,
+    chunks.start_column,
+    chunks.end_column,
+    chunks.start_byte,
+    chunks.end_byte,
+    chunks.kind,
+    chunks.content_hash";
+
+/// Embeddings are stored once per content in the `vectors` table and
+/// referenced by the chunks with that content, so files copied many times,
+/// e.g. fixtures, don't store the same embedding over and over.
+pub struct SqliteStore {
+    conn: Connection,
+    /// Vectors are partitioned by their chunks' directory under the project
+    /// root.
+    root: PathBuf,
+    vector_search: VectorSearch,
+}
+
+/// How nearest chunks are found. Without the sqlite-vec extension embeddings
+/// are kept in a plain table and compared in Rust, which is slower but works.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VectorSearch {
+    SqliteVec,
+    BruteForce,
+}
+
+/// What chunks sharing a vector have in common. Kind, language and
+/// directory are filtered by inside the KNN, so chunks differing in them
+/// need vectors of their own.
+struct VectorKey<'a> {
+    content_hash: &'a str,
+    kind: ChunkKind,
+    language: &'a str,
+    directory: &'a str,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, or an in-memory one if no path is given,
+    /// for embeddings of the model with the code `model_code`.
+    pub fn new(
+        model_code: &str,
+        dimensions: usize,
+        root: &Path,
+        path: Option<&Path>,
+        key: Option<&str>,
+    ) -> Result<Self> {
+        register_sqlite_vec();
+        let conn = match path {
+            Some(path) => open_database(path, key, false)?,
+            None => Connection::open_in_memory()?,
+        };
+        check_schema_version(&conn)?;
+        let files_table = "
+            CREATE TABLE IF NOT EXISTS files (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );";
+        conn.execute(files_table, [])?;
+        let vector_search = match conn
+            .query_row("SELECT vec_version()", [], |row| row.get::<_, String>(0))
+        {
+            Ok(_) => VectorSearch::SqliteVec,
+            Err(e) => {
+                tracing::warn!("sqlite-vec unavailable, falling back to brute force search: {e}");
+                VectorSearch::BruteForce
+            }
+        };
+        if table_exists(&conn, "chunks")?
+            && conn.prepare("SELECT kind FROM chunks LIMIT 0").is_err()
+        {
+            tracing::warn!("index predates chunk kinds, rebuilding it");
+            conn.execute_batch("DROP TABLE chunks; DROP TABLE files;")?;
+            conn.execute(files_table, [])?;
+        }
+        let legacy = table_exists(&conn, "chunks")?
+            && conn
+                .prepare("SELECT embeddings FROM chunks LIMIT 0")
+                .is_ok();
+        if legacy {
+            check_dimensions(&conn, "chunks", "embeddings", dimensions)?;
+        }
+
+        // Kind and language are vec0 metadata columns and the directory its
+        // partition key, so searches filter by them inside the KNN.
+        let create_vectors = match vector_search {
+            VectorSearch::SqliteVec => format!(
+                "
+            CREATE VIRTUAL TABLE IF NOT EXISTS vectors using vec0(
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                language TEXT NOT NULL,
+                directory TEXT partition key,
+                embeddings float[{dimensions}] {COSINE_METRIC}
+            )"
+            ),
+            VectorSearch::BruteForce => "
+            CREATE TABLE IF NOT EXISTS vectors (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                language TEXT NOT NULL,
+                directory TEXT NOT NULL,
+                embeddings BLOB NOT NULL
+            )"
+            .to_string(),
+        };
+        let tx = conn.unchecked_transaction()?;
+        if legacy {
+            tx.execute_batch(&format!(
+                "CREATE TABLE chunks_migration AS
+                    SELECT chunks.id, {CHUNK_COLUMNS}, embeddings, path
+                    FROM chunks
+                    JOIN files ON files.id = chunks.file_id;
+                DROP TABLE chunks;
+                DROP TABLE IF EXISTS chunk_hashes;"
+            ))?;
+        }
+        // vec0 tables measured L2 distances before, while the brute force
+        // search and the other stores measure cosine distances.
+        let l2_vectors = vector_search == VectorSearch::SqliteVec
+            && vectors_schema(&tx)?.is_some_and(|schema| !schema.contains(COSINE_METRIC));
+        if l2_vectors {
+            tx.execute_batch(
+                "CREATE TABLE vectors_migration AS
+                    SELECT id, kind, language, directory, embeddings FROM vectors;
+                DROP TABLE vectors;",
+            )?;
+        }
+        tx.execute(&create_vectors, [])?;
+        if l2_vectors {
+            tx.execute_batch(
+                "INSERT INTO vectors (id, kind, language, directory, embeddings)
+                    SELECT id, kind, language, directory, embeddings FROM vectors_migration;
+                DROP TABLE vectors_migration;",
+            )?;
+        }
+        // vec0 tables can't be indexed, so vectors are looked up by what
+        // their chunks share through a table of their own.
+        tx.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS vector_keys (
+                vector_id INTEGER PRIMARY KEY,
+                content_hash TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                language TEXT NOT NULL,
+                directory TEXT NOT NULL
+            );
+            CREATE UNIQUE INDEX IF NOT EXISTS vector_keys_key
+                ON vector_keys (content_hash, kind, language, directory);
+            CREATE TABLE IF NOT EXISTS chunks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                file_id INTEGER NOT NULL,
+                start_row INTEGER NOT NULL,
+                start_column INTEGER NOT NULL,
+                end_row INTEGER NOT NULL,
+                end_column INTEGER NOT NULL,
+                start_byte INTEGER NOT NULL,
+                end_byte INTEGER NOT NULL,
+                content_hash TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                vector_id INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS chunks_file_id ON chunks (file_id);
+            CREATE INDEX IF NOT EXISTS chunks_content_hash ON chunks (content_hash);
+            CREATE INDEX IF NOT EXISTS chunks_vector_id ON chunks (vector_id);",
+        )?;
+        if legacy {
+            migrate_chunks(&tx, root)?;
+        }
+        tx.commit()?;
+        check_dimensions(&conn, "vectors", "embeddings", dimensions)?;
+        check_model(&conn, model_code, dimensions, false)?;
+        record_schema_version(&conn)?;
+        Ok(Self {
+            conn,
+            root: root.to_path_buf(),
+            vector_search,
+        })
+    }
+
+    /// Opens an existing index without writing to it, e.g. one built in CI
+    /// and mounted read-only.
+    pub fn open_read_only(
+        model_code: &str,
+        dimensions: usize,
+        root: &Path,
+        path: &Path,
+        key: Option<&str>,
+    ) -> Result<Self> {
+        register_sqlite_vec();
+        let conn = open_database(path, key, true)?;
+        if !table_exists(&conn, "chunks")? {
+            anyhow::bail!("{:?} is not an index", path);
+        }
+        check_schema_version(&conn)?;
+        if conn.prepare("SELECT kind FROM chunks LIMIT 0").is_err() {
+            anyhow::bail!(
+                "Index {:?} predates chunk kinds and has to be rebuilt",
+                path
+            );
+        }
+        let Some(schema) = vectors_schema(&conn)? else {
+            anyhow::bail!(
+                "Index {:?} predates shared embeddings, open it writable once to migrate it",
+                path
+            );
+        };
+        let vector_search = if schema.contains("vec0") {
+            VectorSearch::SqliteVec
+        } else {
+            VectorSearch::BruteForce
+        };
+        if vector_search == VectorSearch::SqliteVec && !schema.contains(COSINE_METRIC) {
+            anyhow::bail!(
+                "Index {:?} predates cosine distances, open it writable once to migrate it",
+                path
+            );
+        }
+        check_dimensions(&conn, "vectors", "embeddings", dimensions)?;
+        check_model(&conn, model_code, dimensions, true)?;
+        Ok(Self {
+            conn,
+            root: root.to_path_buf(),
+            vector_search,
+        })
+    }
+
+    /// Returns the page count and page size of the database.
+    fn storage_usage(&self) -> Result<(u64, u64)> {
+        let page_count = self
+            .conn
+            .query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size = self
+            .conn
+            .query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok((page_count, page_size))
+    }
+
+    fn file_id(&self, path: &str) -> Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id FROM files WHERE path = ? LIMIT 1",
+                [path],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?)
+    }
+
+    /// The directory partition holding every file under `prefix`, if the
+    /// prefix is within one, and whether the prefix is narrower than it.
+    fn partition(&self, prefix: &Path) -> Option<(String, bool)> {
+        let relative = prefix.strip_prefix(&self.root).ok()?;
+        let mut components = relative.components();
+        let directory = components.next()?;
+        let narrower = components.next().is_some();
+        if !narrower && prefix.is_file() {
+            // A file directly in the root is in the root's partition.
+            return Some((String::new(), true));
+        }
+        Some((directory.as_os_str().to_string_lossy().into(), narrower))
+    }
+
+    /// Runs one KNN per query embedding with the same prepared statement in
+    /// a single transaction, so all of them see the same index. Every chunk
+    /// sharing a found vector is returned.
+    fn knn_search(
+        &self,
+        query_embeddings: &[Vec<f32>],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        let mut sql = "SELECT id, distance
+            FROM vectors
+            WHERE embeddings MATCH ? AND k = ?"
+            .to_string();
+        let partition = filter
+            .path_prefix
+            .as_deref()
+            .and_then(|prefix| self.partition(prefix));
+        // Only the top directory is filtered inside the KNN, deeper prefixes
+        // are applied to the results.
+        let fetched = match partition {
+            Some((_, true)) => limit * PATH_FILTER_OVERFETCH,
+            _ => limit,
+        };
+        let mut values = vec![Value::Null, Value::Integer(fetched as i64)];
+        if let Some((directory, _)) = partition {
+            sql.push_str(" AND directory = ?");
+            values.push(Value::Text(directory));
+        }
+        let kinds = filter.kinds.iter().map(|kind| kind.as_str().to_string());
+        for (column, allowed) in [
+            ("kind", kinds.collect::<Vec<_>>()),
+            ("language", filter.languages.clone()),
+        ] {
+            if allowed.is_empty() {
+                continue;
+            }
+            let placeholders = vec!["?"; allowed.len()].join(", ");
+            sql.push_str(&format!(" AND {column} IN ({placeholders})"));
+            values.extend(allowed.into_iter().map(Value::Text));
+        }
+        sql.push_str(" ORDER BY distance");
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut search_stmt = tx.prepare(&sql)?;
+        let mut chunks_stmt = tx.prepare(&format!(
+            "SELECT {FOUND_COLUMNS}
+            FROM chunks
+            JOIN files ON files.id = chunks.file_id
+            WHERE chunks.vector_id = ?"
+        ))?;
+        let mut chunks = Vec::new();
+        for query_embedding in query_embeddings {
+            values[0] = Value::Blob(query_embedding.as_bytes().to_vec());
+            let mut rows = search_stmt.query(params_from_iter(&values))?;
+            let mut found = 0;
+            while let Some(row) = rows.next()?
+                && found < limit
+            {
+                let vector_id: i64 = row.get(0)?;
+                let distance: f32 = row.get(1)?;
+                let mut sharing = chunks_stmt.query([vector_id])?;
+                while let Some(chunk) = sharing.next()? {
+                    let chunk = found_chunk(chunk, distance)?;
+                    if filter
+                        .path_prefix
+                        .as_ref()
+                        .is_none_or(|prefix| chunk.path.starts_with(prefix))
+                    {
+                        found += 1;
+                        chunks.push(chunk);
+                    }
+                }
+            }
+        }
+        Ok(merge_nearest(chunks, limit))
+    }
+
+    /// Scores every chunk by cosine distance to the query, used when sqlite-vec
+    /// can't be loaded. Each vector is scored once.
+    fn brute_force_search(
+        &self,
+        query_embeddings: &[Vec<f32>],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        let mut search_stmt = self.conn.prepare(&format!(
+            "SELECT {FOUND_COLUMNS}, chunks.vector_id, vectors.embeddings
+            FROM chunks
+            JOIN files ON files.id = chunks.file_id
+            JOIN vectors ON vectors.id = chunks.vector_id"
+        ))?;
+
+        let mut rows = search_stmt.query([])?;
+        let mut distances: HashMap<i64, f32> = HashMap::new();
+        let mut scored = Vec::new();
+        while let Some(row) = rows.next()? {
+            let chunk = found_chunk(row, 0.0)?;
+            if !filter.matches(&chunk.path, chunk.kind) {
+                continue;
+            }
+            let vector_id: i64 = row.get(9)?;
+            let distance = match distances.get(&vector_id) {
+                Some(distance) => *distance,
+                None => {
+                    let embedding: Vec<u8> = row.get(10)?;
+                    let distance =
+                        min_distance(query_embeddings, &embedding_from_bytes(&embedding));
+                    distances.insert(vector_id, distance);
+                    distance
+                }
+            };
+            scored.push(OutputChunk { distance, ..chunk });
+        }
+        Ok(nearest(scored, limit))
+    }
+}
+
+/// The id of the vector the chunks with the key share, stored with the
+/// embedding from `embedding` if there is none yet.
+fn vector_id(
+    conn: &Connection,
+    key: &VectorKey,
+    embedding: impl FnOnce() -> Result<Vec<u8>>,
+) -> Result<i64> {
+    let existing = conn
+        .query_row(
+            "SELECT vector_id FROM vector_keys
+            WHERE content_hash = ? AND kind = ? AND language = ? AND directory = ?",
+            params![
+                key.content_hash,
+                key.kind.as_str(),
+                key.language,
+                key.directory
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(vector_id) = existing {
+        return Ok(vector_id);
+    }
+    conn.execute(
+        "INSERT INTO vectors (kind, language, directory, embeddings) VALUES (?, ?, ?, ?)",
+        params![key.kind.as_str(), key.language, key.directory, embedding()?],
+    )?;
+    let vector_id = conn.last_insert_rowid();
+    conn.execute(
+        "INSERT INTO vector_keys (vector_id, content_hash, kind, language, directory)
+        VALUES (?, ?, ?, ?, ?)",
+        params![
+            vector_id,
+            key.content_hash,
+            key.kind.as_str(),
+            key.language,
+            key.directory
+        ],
+    )?;
+    Ok(vector_id)
+}
+
+fn stored_embedding(conn: &Connection, vector_id: i64) -> Result<Vec<u8>> {
+    Ok(conn.query_row(
+        "SELECT embeddings FROM vectors WHERE id = ?",
+        [vector_id],
+        |row| row.get(0),
+    )?)
+}
+
+/// Deletes the vectors no chunk refers to anymore, of the given ones or of
+/// all if unset.
+fn delete_unused_vectors(conn: &Connection, candidates: Option<&[i64]>) -> Result<usize> {
+    let unused: Vec<i64> = match candidates {
+        Some(candidates) => {
+            let mut stmt = conn.prepare("SELECT 1 FROM chunks WHERE vector_id = ? LIMIT 1")?;
+            let mut unused = Vec::new();
+            for vector_id in candidates {
+                if !stmt.exists([vector_id])? && !unused.contains(vector_id) {
+                    unused.push(*vector_id);
+                }
+            }
+            unused
+        }
+        None => conn
+            .prepare(
+                "SELECT vector_id FROM vector_keys
+                WHERE vector_id NOT IN (SELECT vector_id FROM chunks)",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?,
+    };
+    for vector_id in &unused {
+        // vec0 tables are only deleted from by id.
+        conn.execute("DELETE FROM vectors WHERE id = ?", [vector_id])?;
+        conn.execute("DELETE FROM vector_keys WHERE vector_id = ?", [vector_id])?;
+    }
+    Ok(unused.len())
+}
+
+/// A chunk from a row starting with `FOUND_COLUMNS`.
+fn found_chunk(row: &rusqlite::Row, distance: f32) -> Result<OutputChunk> {
+    Ok(OutputChunk {
+        path: PathBuf::from(row.get::<_, String>(0)?),
+        row: row.get(1)?..row.get(2)?,
+        column: row.get(3)?..row.get(4)?,
+        byte: row.get(5)?..row.get(6)?,
+        kind: kind_from_column(row.get(7)?),
+        content_hash: row.get(8)?,
+        distance,
+        duplicates: Vec::new(),
+    })
+}
+
+impl VectorStore for SqliteStore {
+    fn content_hashes(&self, path: &str) -> Result<HashMap<String, usize>> {
+        let mut hashes = HashMap::new();
+        let Some(file_id) = self.file_id(path)? else {
+            return Ok(hashes);
+        };
+        let mut stmt = self
+            .conn
+            .prepare("SELECT content_hash FROM chunks WHERE file_id = ?")?;
+        let mut rows = stmt.query([file_id])?;
+        while let Some(row) = rows.next()? {
+            *hashes.entry(row.get(0)?).or_default() += 1;
+        }
+        Ok(hashes)
+    }
+
+    /// Runs in a single transaction. Chunks whose text is unchanged keep their
+    /// id and embedding and only get their position updated.
+    fn upsert_file(
+        &mut self,
+        path: &str,
+        chunks: &[ChunkRecord],
+        embeddings: &HashMap<String, Vec<f32>>,
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let file_id = match self.file_id(path)? {
+            Some(prev_file_id) => {
+                tx.execute(
+                    "UPDATE files SET updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                    [prev_file_id],
+                )?;
+                prev_file_id
+            }
+            None => tx.query_row(
+                "INSERT INTO files (path) VALUES (?) RETURNING id",
+                [path],
+                |row| row.get(0),
+            )?,
+        };
+
+        let mut existing: HashMap<String, Vec<(i64, i64)>> = HashMap::new();
+        // Vectors chunks of the file stopped referring to.
+        let mut left = Vec::new();
+        {
+            let mut stmt =
+                tx.prepare("SELECT id, content_hash, vector_id FROM chunks WHERE file_id = ?")?;
+            let mut rows = stmt.query([file_id])?;
+            while let Some(row) = rows.next()? {
+                existing
+                    .entry(row.get(1)?)
+                    .or_default()
+                    .push((row.get(0)?, row.get(2)?));
+            }
+        }
+
+        let language = language_of(Path::new(path)).unwrap_or_default();
+        let directory = top_directory(&self.root, Path::new(path));
+        let mut update_stmt = tx.prepare(
+            "UPDATE chunks SET
+                start_row = ?,
+                start_column = ?,
+                end_row = ?,
+                end_column = ?,
+                start_byte = ?,
+                end_byte = ?,
+                kind = ?,
+                vector_id = ?
+            WHERE id = ?",
+        )?;
+        let mut insert_stmt = tx.prepare(&format!(
+            "INSERT INTO chunks ({CHUNK_COLUMNS}, vector_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ))?;
+        for chunk in chunks {
+            let key = VectorKey {
+                content_hash: &chunk.content_hash,
+                kind: chunk.kind,
+                language,
+                directory: &directory,
+            };
+            match existing.get_mut(&chunk.content_hash).and_then(Vec::pop) {
+                Some((chunk_id, prev_vector_id)) => {
+                    // A changed kind moves the chunk to another vector.
+                    let vector_id = vector_id(&tx, &key, || stored_embedding(&tx, prev_vector_id))?;
+                    if vector_id != prev_vector_id {
+                        left.push(prev_vector_id);
+                    }
+                    update_stmt.execute(params![
+                        chunk.start.row,
+                        chunk.start.column,
+                        chunk.end.row,
+                        chunk.end.column,
+                        chunk.byte.start,
+                        chunk.byte.end,
+                        chunk.kind.as_str(),
+                        vector_id,
+                        chunk_id,
+                    ])?;
+                }
+                None => {
+                    let vector_id = vector_id(&tx, &key, || {
+                        let embedding = embeddings
+                            .get(&chunk.content_hash)
+                            .context("Missing embedding for new chunk")?;
+                        Ok(embedding.as_bytes().to_vec())
+                    })?;
+                    insert_stmt.execute(params![
+                        file_id,
+                        chunk.start.row,
+                        chunk.start.column,
+                        chunk.end.row,
+                        chunk.end.column,
+                        chunk.byte.start,
+                        chunk.byte.end,
+                        chunk.content_hash,
+                        chunk.kind.as_str(),
+                        vector_id,
+                    ])?;
+                }
+            }
+        }
+        for (chunk_id, vector_id) in existing.into_values().flatten() {
+            tx.execute("DELETE FROM chunks WHERE id = ?", [chunk_id])?;
+            left.push(vector_id);
+        }
+        delete_unused_vectors(&tx, Some(&left))?;
+
+        drop((update_stmt, insert_stmt));
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Also drops chunks whose file row is gone and vacuums the database.
+    fn compact(&mut self, keep: &dyn Fn(&Path) -> bool) -> Result<CompactionStats> {
+        let bytes_before = self.storage_bytes()?;
+
+        let tx = self.conn.transaction()?;
+        let files: Vec<(i64, String)> = tx
+            .prepare("SELECT id, path FROM files")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        let removed: Vec<i64> = files
+            .into_iter()
+            .filter(|(_, path)| !keep(Path::new(path)))
+            .map(|(id, _)| id)
+            .collect();
+
+        let mut removed_chunks = 0;
+        for file_id in &removed {
+            removed_chunks += tx.execute("DELETE FROM chunks WHERE file_id = ?", [file_id])?;
+            tx.execute("DELETE FROM files WHERE id = ?", [file_id])?;
+        }
+        removed_chunks += tx.execute(
+            "DELETE FROM chunks WHERE file_id NOT IN (SELECT id FROM files)",
+            [],
+        )?;
+        delete_unused_vectors(&tx, None)?;
+        tx.commit()?;
+        self.conn.execute("VACUUM", [])?;
+
+        Ok(CompactionStats {
+            removed_files: removed.len(),
+            removed_chunks,
+            bytes_before,
+            bytes_after: self.storage_bytes()?,
+        })
+    }
+
+    fn storage_bytes(&self) -> Result<u64> {
+        let (page_count, page_size) = self.storage_usage()?;
+        Ok(page_count * page_size)
+    }
+
+    fn embedding(&self, content_hash: &str) -> Result<Option<Vec<f32>>> {
+        let vector_id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT vector_id FROM vector_keys WHERE content_hash = ? LIMIT 1",
+                [content_hash],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(vector_id) = vector_id else {
+            return Ok(None);
+        };
+        Ok(Some(embedding_from_bytes(&stored_embedding(
+            &self.conn, vector_id,
+        )?)))
+    }
+
+    fn paths_with(&self, content_hash: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT files.path
+            FROM chunks
+            JOIN files ON files.id = chunks.file_id
+            WHERE chunks.content_hash = ?",
+        )?;
+        let paths = stmt
+            .query_map([content_hash], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(paths)
+    }
+
+    fn for_each_embedding(
+        &self,
+        visit: &mut dyn FnMut(OutputChunk, Vec<f32>) -> Result<()>,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {FOUND_COLUMNS}, vectors.embeddings
+            FROM chunks
+            JOIN files ON files.id = chunks.file_id
+            JOIN vectors ON vectors.id = chunks.vector_id
+            ORDER BY files.path, chunks.start_byte"
+        ))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let embedding: Vec<u8> = row.get(9)?;
+            visit(found_chunk(row, 0.0)?, embedding_from_bytes(&embedding))?;
+        }
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        embeddings: &[Vec<f32>],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>> {
+        match self.vector_search {
+            VectorSearch::SqliteVec => self.knn_search(embeddings, limit, filter),
+            VectorSearch::BruteForce => self.brute_force_search(embeddings, limit, filter),
+        }
+    }
+}
+
+/// Moves the chunks of an index from before embeddings were shared, copied
+/// to `chunks_migration` along with their paths, to the vectors and chunks
+/// tables, keeping their ids. Older ones lack languages and directories,
+/// which are taken from the paths.
+fn migrate_chunks(conn: &Connection, root: &Path) -> Result<()> {
+    tracing::info!("sharing the embeddings of identical chunks in the index");
+    {
+        let mut select = conn.prepare(&format!(
+            "SELECT id, {CHUNK_COLUMNS}, embeddings, path FROM chunks_migration"
+        ))?;
+        let mut insert = conn.prepare(&format!(
+            "INSERT INTO chunks (id, {CHUNK_COLUMNS}, vector_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ))?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(11)?;
+            let path = Path::new(&path);
+            let content_hash: String = row.get(8)?;
+            let key = VectorKey {
+                content_hash: &content_hash,
+                kind: kind_from_column(row.get(9)?),
+                language: language_of(path).unwrap_or_default(),
+                directory: &top_directory(root, path),
+            };
+            let vector_id = vector_id(conn, &key, || Ok(row.get(10)?))?;
+            let mut values = (0..10)
+                .map(|column| row.get::<_, Value>(column))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            values.push(Value::Integer(vector_id));
+            insert.execute(params_from_iter(values))?;
+        }
+    }
+    conn.execute("DROP TABLE chunks_migration", [])?;
+    Ok(())
+}
+
+/// Fails if `table` holds embeddings of other than `dimensions`, e.g. ones of
+/// a model that was replaced by a differently sized version.
+pub(super) fn check_dimensions(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    dimensions: usize,
+) -> Result<()> {
+    let schema: String = conn.query_row(
+        "SELECT sql FROM sqlite_master WHERE name = ?",
+        [table],
+        |row| row.get(0),
+    )?;
+    let stored = match schema.split_once("float[") {
+        Some((_, declared)) => declared
+            .split_once(']')
+            .and_then(|(dimensions, _)| dimensions.trim().parse().ok()),
+        // Plain tables only tell by the embeddings stored in them.
+        None => conn
+            .query_row(
+                &format!("SELECT length({column}) FROM {table} LIMIT 1"),
+                [],
+                |row| row.get::<_, usize>(0),
+            )
+            .optional()?
+            .map(|bytes| bytes / size_of::<f32>()),
+    };
+    if let Some(stored) = stored
+        && stored != dimensions
+    {
+        let path = conn.path().unwrap_or("memory");
+        anyhow::bail!(
+            "Index {} holds {}-dimensional embeddings but the model makes {}-dimensional ones. \
+            It was built with another model, delete it to have it rebuilt.",
+            path,
+            stored,
+            dimensions
+        );
+    }
+    Ok(())
+}
+
+/// Fails if the index's embeddings are from another model than the one
+/// with `model_code`, recording the model in new indexes. Indexes from
+/// before the record are only checked by `check_dimensions`.
+fn check_model(
+    conn: &Connection,
+    model_code: &str,
+    dimensions: usize,
+    read_only: bool,
+) -> Result<()> {
+    if !read_only {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS model (code TEXT NOT NULL, dimensions INTEGER NOT NULL)",
+            [],
+        )?;
+    } else if !table_exists(conn, "model")? {
+        return Ok(());
+    }
+    let stored = conn
+        .query_row("SELECT code, dimensions FROM model", [], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?))
+        })
+        .optional()?;
+    match stored {
+        Some((code, stored_dimensions))
+            if code != model_code || stored_dimensions != dimensions =>
+        {
+            anyhow::bail!(
+                "Index {} holds embeddings of {} but the project uses {}. \
+                Delete it to have it rebuilt.",
+                conn.path().unwrap_or("memory"),
+                code,
+                model_code
+            )
+        }
+        Some(_) => {}
+        None if read_only => {}
+        None => {
+            conn.execute(
+                "INSERT INTO model (code, dimensions) VALUES (?, ?)",
+                params![model_code, dimensions],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Fails if the index was written by a newer version in a layout this one
+/// doesn't know, rather than migrating it to an older one.
+fn check_schema_version(conn: &Connection) -> Result<()> {
+    if !table_exists(conn, "schema_version")? {
+        return Ok(());
+    }
+    let stored: u32 = conn
+        .query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+        .optional()?
+        .unwrap_or(0);
+    if stored > SCHEMA_VERSION {
+        anyhow::bail!(
+            "Index {} has schema version {} but this version reads up to {}. \
+            Upgrade, or delete the index to have it rebuilt.",
+            conn.path().unwrap_or("memory"),
+            stored,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Records that the index was migrated to `SCHEMA_VERSION`.
+fn record_schema_version(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);
+        DELETE FROM schema_version;",
+    )?;
+    conn.execute(
+        "INSERT INTO schema_version (version) VALUES (?)",
+        [SCHEMA_VERSION],
+    )?;
+    Ok(())
+}
+
+/// The statement the vectors table was created with, if there is one.
+fn vectors_schema(conn: &Connection) -> Result<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE name = 'vectors'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn table_exists(conn: &Connection, name: &str) -> Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+            [name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+fn kind_from_column(kind: String) -> ChunkKind {
+    ChunkKind::parse(&kind).unwrap_or_default()
+}
+
+fn embedding_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(size_of::<f32>())
+        .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Opens an on-disk index, unlocking it with `key` if it's encrypted.
+pub fn open_database(path: &Path, key: Option<&str>, read_only: bool) -> Result<Connection> {
+    let flags = if read_only {
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX
+    } else {
+        OpenFlags::default()
+    };
+    let conn = Connection::open_with_flags(path, flags)
+        .with_context(|| format!("Failed to open index {:?}", path))?;
+    if let Some(key) = key {
+        // Plain SQLite ignores the key, which would leave the index unencrypted.
+        if !cfg!(feature = "encryption") {
+            anyhow::bail!("Index encryption needs the `encryption` feature");
+        }
+        conn.pragma_update(None, "key", key)?;
+        conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+            .with_context(|| format!("Wrong key for index {:?}", path))?;
+    }
+    Ok(conn)
+}
+
+fn register_sqlite_vec() {
+    type EntryPoint =
+        unsafe extern "C" fn(*mut sqlite3, *mut *mut c_char, *const sqlite3_api_routines) -> c_int;
+
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| unsafe {
+        sqlite3_auto_extension(Some(std::mem::transmute::<*const (), EntryPoint>(
+            sqlite3_vec_init as *const (),
+        )));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embeddings::code_splitter::TextPosition;
+
+    use super::*;
+
+    fn record(row: usize, content_hash: &str) -> ChunkRecord {
+        kind_record(row, content_hash, ChunkKind::Code)
+    }
+
+    fn kind_record(row: usize, content_hash: &str, kind: ChunkKind) -> ChunkRecord {
+        ChunkRecord {
+            start: TextPosition { row, column: 0 },
+            end: TextPosition {
+                row: row + 1,
+                column: 0,
+            },
+            byte: 0..1,
+            content_hash: content_hash.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn looks_up_chunks_by_content_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None).unwrap();
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0]),
+        ]);
+        store
+            .upsert_file("f", &[record(0, "a"), record(1, "b")], &embeddings)
+            .unwrap();
+        store
+            .upsert_file("vendor/f", &[record(0, "a")], &embeddings)
+            .unwrap();
+        store
+            .upsert_file("f", &[record(0, "b")], &embeddings)
+            .unwrap();
+        drop(store);
+
+        let store = SqliteStore::open_read_only("test", 2, Path::new("/p"), &path, None).unwrap();
+        assert_eq!(store.embedding("a").unwrap(), Some(vec![1.0, 0.0]));
+        assert_eq!(store.embedding("c").unwrap(), None);
+        assert_eq!(store.paths_with("a").unwrap(), ["vendor/f"]);
+        assert_eq!(store.paths_with("b").unwrap(), ["f"]);
+        let mut exported = Vec::new();
+        store
+            .for_each_embedding(&mut |chunk, embedding| {
+                exported.push((chunk.path, embedding));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            exported,
+            [
+                (PathBuf::from("f"), vec![0.0, 1.0]),
+                (PathBuf::from("vendor/f"), vec![1.0, 0.0])
+            ]
+        );
+        drop(store);
+
+        let error = SqliteStore::new("test", 3, Path::new("/p"), Some(&path), None)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("2-dimensional"), "{error}");
+        assert!(SqliteStore::open_read_only("test", 3, Path::new("/p"), &path, None).is_err());
+        let error = SqliteStore::new("other", 2, Path::new("/p"), Some(&path), None)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("embeddings of test"), "{error}");
+    }
+
+    #[test]
+    fn refuses_indexes_of_newer_schema_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        drop(SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None).unwrap());
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(
+            "UPDATE schema_version SET version = ?",
+            [SCHEMA_VERSION + 1],
+        )
+        .unwrap();
+        drop(conn);
+        let error = SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("schema version"), "{error}");
+        assert!(SqliteStore::open_read_only("test", 2, Path::new("/p"), &path, None).is_err());
+    }
+
+    #[test]
+    fn shares_embeddings_of_copied_chunks() {
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), None, None).unwrap();
+        let count = |store: &SqliteStore, table: &str| -> usize {
+            store
+                .conn
+                .query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0]),
+        ]);
+        let copies = ["/p/fixtures/0.rs", "/p/fixtures/1.rs", "/p/fixtures/2.rs"];
+        for copy in copies {
+            store
+                .upsert_file(copy, &[record(0, "a")], &embeddings)
+                .unwrap();
+        }
+        assert_eq!(count(&store, "vectors"), 1);
+        assert_eq!(count(&store, "chunks"), 3);
+        let found = store
+            .search(&[vec![1.0, 0.0]], 10, &ChunkFilter::default())
+            .unwrap();
+        assert_eq!(found.len(), 3);
+
+        // Kinds are filtered inside the KNN, so they take a vector of their own.
+        store
+            .upsert_file(
+                copies[0],
+                &[kind_record(0, "a", ChunkKind::Test)],
+                &embeddings,
+            )
+            .unwrap();
+        assert_eq!(count(&store, "vectors"), 2);
+        for copy in &copies[1..] {
+            store
+                .upsert_file(copy, &[record(0, "b")], &embeddings)
+                .unwrap();
+        }
+        assert_eq!(count(&store, "vectors"), 2);
+        assert_eq!(store.embedding("a").unwrap(), Some(vec![1.0, 0.0]));
+
+        store.compact(&|path| path != Path::new(copies[0])).unwrap();
+        assert_eq!(count(&store, "vectors"), 1);
+        assert_eq!(store.embedding("a").unwrap(), None);
+    }
+
+    #[test]
+    fn compacts_away_files_no_longer_in_the_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None).unwrap();
+        let count = |store: &SqliteStore, table: &str| -> usize {
+            store
+                .conn
+                .query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0]),
+            ("c".to_string(), vec![1.0, 1.0]),
+        ]);
+        store
+            .upsert_file("/p/kept.rs", &[record(0, "a"), record(1, "b")], &embeddings)
+            .unwrap();
+        store
+            .upsert_file("/p/gone.rs", &[record(0, "c"), record(1, "a")], &embeddings)
+            .unwrap();
+
+        let stats = store
+            .compact(&|path| path != Path::new("/p/gone.rs"))
+            .unwrap();
+        assert_eq!((stats.removed_files, stats.removed_chunks), (1, 2));
+        assert_eq!(count(&store, "files"), 1);
+        assert_eq!(count(&store, "chunks"), 2);
+        assert_eq!(count(&store, "vectors"), 2);
+        assert_eq!(store.embedding("c").unwrap(), None);
+        assert_eq!(store.embedding("a").unwrap(), Some(vec![1.0, 0.0]));
+    }
+
+    #[test]
+    fn keeps_unchanged_chunks_when_their_file_changes() {
+        let mut store = SqliteStore::new("test", 3, Path::new("/p"), None, None).unwrap();
+        let rows = |store: &SqliteStore| -> HashMap<String, (i64, i64)> {
+            let mut stmt = store
+                .conn
+                .prepare("SELECT content_hash, id, vector_id FROM chunks")
+                .unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0, 0.0]),
+            ("c".to_string(), vec![0.0, 0.0, 1.0]),
+        ]);
+        store
+            .upsert_file(
+                "/p/lib.rs",
+                &[record(0, "a"), record(1, "b"), record(2, "c")],
+                &embeddings,
+            )
+            .unwrap();
+        let before = rows(&store);
+
+        // Only the changed chunk needs an embedding.
+        let changed = HashMap::from([("d".to_string(), vec![1.0, 1.0, 0.0])]);
+        store
+            .upsert_file(
+                "/p/lib.rs",
+                &[record(0, "a"), record(1, "d"), record(3, "c")],
+                &changed,
+            )
+            .unwrap();
+        let after = rows(&store);
+        assert_eq!(after.len(), 3);
+        assert_eq!(after["a"], before["a"]);
+        assert_eq!(after["c"], before["c"]);
+        assert!(!after.contains_key("b"));
+        assert_eq!(store.embedding("c").unwrap(), Some(vec![0.0, 0.0, 1.0]));
+        assert_eq!(store.embedding("b").unwrap(), None);
+
+        for (query, content_hash, row) in [
+            (vec![1.0, 0.0, 0.0], "a", 0..1),
+            (vec![1.0, 1.0, 0.0], "d", 1..2),
+            (vec![0.0, 0.0, 1.0], "c", 3..4),
+        ] {
+            let found = store.search(&[query], 1, &ChunkFilter::default()).unwrap();
+            assert_eq!(found[0].content_hash, content_hash);
+            assert_eq!(found[0].row, row);
+            assert!(found[0].distance < 1e-6);
+        }
+    }
+
+    #[test]
+    fn filters_inside_the_search() {
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), None, None).unwrap();
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.9, 0.1]),
+            ("c".to_string(), vec![0.8, 0.2]),
+        ]);
+        store
+            .upsert_file(
+                "/p/src/lib.rs",
+                &[record(0, "a"), kind_record(1, "b", ChunkKind::Test)],
+                &embeddings,
+            )
+            .unwrap();
+        store
+            .upsert_file("/p/src/net/api.py", &[record(0, "c")], &embeddings)
+            .unwrap();
+        store
+            .upsert_file("/p/build.rs", &[record(0, "b")], &embeddings)
+            .unwrap();
+
+        let search = |filter: ChunkFilter| -> Vec<(PathBuf, usize)> {
+            store
+                .search(&[vec![1.0, 0.0]], 10, &filter)
+                .unwrap()
+                .into_iter()
+                .map(|chunk| (chunk.path, chunk.row.start))
+                .collect()
+        };
+        assert_eq!(search(ChunkFilter::default()).len(), 4);
+        assert_eq!(
+            search(ChunkFilter {
+                kinds: vec![ChunkKind::Test],
+                ..Default::default()
+            }),
+            [("/p/src/lib.rs".into(), 1)]
+        );
+        assert_eq!(
+            search(ChunkFilter {
+                languages: vec!["python".to_string()],
+                ..Default::default()
+            }),
+            [("/p/src/net/api.py".into(), 0)]
+        );
+        assert_eq!(
+            search(ChunkFilter {
+                path_prefix: Some("/p/src/net".into()),
+                ..Default::default()
+            }),
+            [("/p/src/net/api.py".into(), 0)]
+        );
+    }
+
+    #[test]
+    fn migrates_chunks_without_languages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        register_sqlite_vec();
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "
+            CREATE TABLE files (id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL);
+            CREATE VIRTUAL TABLE chunks using vec0(
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                file_id INTEGER NOT NULL,
+                start_row INTEGER NOT NULL,
+                start_column INTEGER NOT NULL,
+                end_row INTEGER NOT NULL,
+                end_column INTEGER NOT NULL,
+                start_byte INTEGER NOT NULL,
+                end_byte INTEGER NOT NULL,
+                content_hash TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                embeddings float[2]
+            );
+            INSERT INTO files (path) VALUES ('/p/src/lib.rs');",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO chunks (file_id, start_row, start_column, end_row, end_column, start_byte, end_byte, content_hash, kind, embeddings)
+            VALUES (1, 3, 0, 4, 0, 10, 20, 'a', 'function', ?)",
+            [[1.0f32, 0.0].as_bytes()],
+        )
+        .unwrap();
+        drop(conn);
+
+        let store = SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None).unwrap();
+        let found = store
+            .search(
+                &[vec![1.0, 0.0]],
+                10,
+                &ChunkFilter {
+                    languages: vec!["rust".to_string()],
+                    path_prefix: Some("/p/src".into()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].row, 3..4);
+        assert_eq!(found[0].kind, ChunkKind::Function);
+        assert_eq!(store.embedding("a").unwrap(), Some(vec![1.0, 0.0]));
+    }
+
+    #[test]
+    fn sqlite_vec_and_brute_force_measure_the_same_distances() {
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), None, None).unwrap();
+        assert_eq!(store.vector_search, VectorSearch::SqliteVec);
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![3.0, 4.0]),
+        ]);
+        store
+            .upsert_file("/p/lib.rs", &[record(0, "a"), record(1, "b")], &embeddings)
+            .unwrap();
+
+        let queries = [vec![2.0, 0.5]];
+        let filter = ChunkFilter::default();
+        let distances = |chunks: Vec<OutputChunk>| {
+            chunks
+                .into_iter()
+                .map(|chunk| (chunk.content_hash, chunk.distance))
+                .collect::<Vec<_>>()
+        };
+        let knn = distances(store.knn_search(&queries, 2, &filter).unwrap());
+        let brute_force = distances(store.brute_force_search(&queries, 2, &filter).unwrap());
+        assert_eq!(knn.len(), 2);
+        for ((knn_hash, knn), (hash, brute_force)) in knn.iter().zip(&brute_force) {
+            assert_eq!(knn_hash, hash);
+            assert!((knn - brute_force).abs() < 1e-6, "{knn} != {brute_force}");
+        }
+    }
+
+    #[test]
+    fn migrates_l2_vectors_to_cosine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None).unwrap();
+        let embeddings = HashMap::from([("a".to_string(), vec![3.0, 4.0])]);
+        store
+            .upsert_file("/p/lib.rs", &[record(0, "a")], &embeddings)
+            .unwrap();
+        // Back to the vectors table of schema version 1.
+        store
+            .conn
+            .execute_batch(
+                "CREATE TABLE old_vectors AS SELECT * FROM vectors;
+                DROP TABLE vectors;
+                CREATE VIRTUAL TABLE vectors using vec0(
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    kind TEXT NOT NULL,
+                    language TEXT NOT NULL,
+                    directory TEXT partition key,
+                    embeddings float[2]
+                );
+                INSERT INTO vectors SELECT * FROM old_vectors;
+                DROP TABLE old_vectors;
+                UPDATE schema_version SET version = 1;",
+            )
+            .unwrap();
+        drop(store);
+        assert!(
+            SqliteStore::open_read_only("test", 2, Path::new("/p"), &path, None)
+                .is_err_and(|e| e.to_string().contains("cosine"))
+        );
+
+        let store = SqliteStore::new("test", 2, Path::new("/p"), Some(&path), None).unwrap();
+        let found = store
+            .search(&[vec![6.0, 8.0]], 1, &ChunkFilter::default())
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].distance < 1e-6);
+        assert_eq!(store.embedding("a").unwrap(), Some(vec![3.0, 4.0]));
+    }
+
+    #[test]
+    fn merges_the_results_of_several_queries() {
+        let mut store = SqliteStore::new("test", 2, Path::new("/p"), None, None).unwrap();
+        let embeddings = HashMap::from([
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0]),
+        ]);
+        store
+            .upsert_file("/p/lib.rs", &[record(0, "a"), record(1, "b")], &embeddings)
+            .unwrap();
+
+        let found = store
+            .search(
+                &[vec![1.0, 0.0], vec![0.1, 1.0]],
+                2,
+                &ChunkFilter::default(),
+            )
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].row, 0..1);
+        assert!(found[0].distance < 1e-6);
+        assert!(found[1].distance < 0.2);
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+
+/// Mode of submodule entries in the git index.
+const GITLINK_MODE: &str = "160000";
+
+/// Whether the directory is a checked out submodule, which has a `.git` file
+/// pointing into the superproject's repository instead of a directory.
+pub fn is_submodule(dir: &Path) -> bool {
+    dir.join(".git").is_file()
+}
+
+/// The checked out submodules of the repository at `root`, not ones nested
+/// in them.
+pub fn submodules(root: &Path) -> Result<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["ls-files", "--stage", "-z"])
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to list the files of {:?}: {}",
+            root,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_gitlinks(&output.stdout)
+        .into_iter()
+        .map(|path| root.join(path))
+        .filter(|path| is_submodule(path))
+        .collect())
+}
+
+/// Paths of the submodule entries in `git ls-files --stage -z` output.
+fn parse_gitlinks(output: &[u8]) -> Vec<PathBuf> {
+    output
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let (info, path) = std::str::from_utf8(entry).ok()?.split_once('\t')?;
+            (info.split(' ').next()? == GITLINK_MODE).then(|| PathBuf::from(path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_gitlinks() {
+        let output = b"100644 aaa 0\tsrc/main.rs\x00160000 bbb 0\tvendor/lib\x00100644 ccc 0\tvendor/README.md\x00";
+        assert_eq!(parse_gitlinks(output), [PathBuf::from("vendor/lib")]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use rusqlite::{Connection, params};
+
+use crate::config::Config;
+
+use super::{
+    definitions::Definition, project_repository::project_index_path, sqlite_store::open_database,
+    vector_store::ChunkKind,
+};
+
+const SYMBOL_COLUMNS: &str = "path, name, kind, start_row, end_row, signature";
+
+/// The definitions of the indexed files, written along with their chunks so
+/// symbols and outlines are there as soon as the daemon starts, before the
+/// files are parsed again. Lives in the index database for on-disk indexes.
+pub struct SymbolTable {
+    conn: Connection,
+    created: bool,
+}
+
+impl SymbolTable {
+    /// Takes its own connection to the index database, or an in-memory one.
+    pub fn new(conn: Connection) -> Result<Self> {
+        let created = !has_symbols(&conn);
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS symbols (
+                path TEXT NOT NULL,
+                name TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                start_row INTEGER NOT NULL,
+                end_row INTEGER NOT NULL,
+                signature TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS symbols_path ON symbols (path);
+            CREATE INDEX IF NOT EXISTS symbols_name ON symbols (name COLLATE NOCASE);",
+        )?;
+        Ok(Self { conn, created })
+    }
+
+    /// The symbols persisted in the on-disk index of the project, read-only.
+    /// None for projects indexed in memory or without symbols written yet.
+    pub fn open_persisted(config: &Config, project_root: &Path) -> Result<Option<Self>> {
+        let Some(path) = project_index_path(config, project_root)? else {
+            return Ok(None);
+        };
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let key = match &config.storage.encryption {
+            Some(key) => Some(key.resolve()?),
+            None => None,
+        };
+        Ok(Self::persisted(open_database(&path, key.as_deref(), true)?))
+    }
+
+    /// The symbols already in the database, None if it has none.
+    pub fn persisted(conn: Connection) -> Option<Self> {
+        has_symbols(&conn).then_some(Self {
+            conn,
+            created: false,
+        })
+    }
+
+    /// Whether `new` created the table, so the files indexed before have no
+    /// symbols yet.
+    pub fn created(&self) -> bool {
+        self.created
+    }
+
+    /// Replaces the symbols of the file.
+    pub fn replace(&self, path: &Path, definitions: &[Definition]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM symbols WHERE path = ?",
+            [path.to_string_lossy()],
+        )?;
+        {
+            let mut stmt = tx.prepare(&format!(
+                "INSERT INTO symbols ({SYMBOL_COLUMNS}) VALUES (?, ?, ?, ?, ?, ?)"
+            ))?;
+            for definition in definitions {
+                stmt.execute(params![
+                    path.to_string_lossy(),
+                    definition.name,
+                    definition.kind.as_str(),
+                    definition.row.start,
+                    definition.row.end,
+                    definition.signature,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM symbols WHERE path = ?",
+            [path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Symbols whose name contains `query` regardless of case, exact matches
+    /// first, then ones starting with it, then shorter names.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Definition>> {
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        self.query(
+            &format!(
+                "SELECT {SYMBOL_COLUMNS} FROM symbols
+                WHERE name LIKE ?1 ESCAPE '\\'
+                ORDER BY name = ?2 COLLATE NOCASE DESC, name LIKE ?3 ESCAPE '\\' DESC,
+                    length(name), path, start_row
+                LIMIT ?4"
+            ),
+            params![
+                format!("%{escaped}%"),
+                query,
+                format!("{escaped}%"),
+                limit as i64
+            ],
+        )
+    }
+
+    /// The symbols of the file in file order.
+    pub fn outline(&self, path: &Path) -> Result<Vec<Definition>> {
+        self.query(
+            &format!(
+                "SELECT {SYMBOL_COLUMNS} FROM symbols WHERE path = ? ORDER BY start_row, end_row"
+            ),
+            params![path.to_string_lossy()],
+        )
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Definition>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let definitions = stmt
+            .query_map(params, |row| {
+                let kind: String = row.get(2)?;
+                Ok(Definition {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    name: row.get(1)?,
+                    kind: ChunkKind::parse(&kind).unwrap_or(ChunkKind::Code),
+                    row: row.get(3)?..row.get(4)?,
+                    signature: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(definitions)
+    }
+}
+
+fn has_symbols(conn: &Connection) -> bool {
+    conn.prepare("SELECT path FROM symbols LIMIT 0").is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(path: &str, name: &str, row: usize) -> Definition {
+        Definition {
+            path: path.into(),
+            name: name.to_string(),
+            kind: ChunkKind::Function,
+            row: row..row + 3,
+            signature: format!("fn {name}() {{"),
+        }
+    }
+
+    #[test]
+    fn finds_symbols_by_name_and_file() {
+        let symbols = SymbolTable::new(Connection::open_in_memory().unwrap()).unwrap();
+        let (a, b) = (Path::new("/p/a.rs"), Path::new("/p/b.rs"));
+        symbols
+            .replace(
+                a,
+                &[
+                    definition("/p/a.rs", "parse_config", 10),
+                    definition("/p/a.rs", "stale", 0),
+                ],
+            )
+            .unwrap();
+        symbols
+            .replace(
+                a,
+                &[
+                    definition("/p/a.rs", "parse_config", 10),
+                    definition("/p/a.rs", "Config", 2),
+                ],
+            )
+            .unwrap();
+        symbols
+            .replace(b, &[definition("/p/b.rs", "config_path", 4)])
+            .unwrap();
+
+        let names = |found: Vec<Definition>| -> Vec<String> {
+            found
+                .into_iter()
+                .map(|definition| definition.name)
+                .collect()
+        };
+        assert_eq!(
+            names(symbols.search("config", 10).unwrap()),
+            ["Config", "config_path", "parse_config"]
+        );
+        assert_eq!(
+            names(symbols.search("_", 10).unwrap()),
+            ["config_path", "parse_config"]
+        );
+        assert_eq!(
+            names(symbols.outline(a).unwrap()),
+            ["Config", "parse_config"]
+        );
+        symbols.remove(a).unwrap();
+        assert!(symbols.outline(a).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{classify::is_test_file, project_files::ProjectFiles};
+
+/// Weights of the heuristics, a test named after the subject is the
+/// strongest hint.
+const NAME_SCORE: u32 = 4;
+const MENTION_SCORE: u32 = 2;
+const SIMILARITY_SCORE: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestMatch {
+    pub path: PathBuf,
+    /// The most similar test chunk of the file, if embeddings found one.
+    pub rows: Option<Range<usize>>,
+    /// Why the file is thought to test the subject, e.g. "named after session".
+    pub reasons: Vec<String>,
+    #[serde(skip)]
+    score: u32,
+}
+
+/// What tests are looked for, a file of the project or a symbol name.
+pub enum Subject<'a> {
+    File(&'a Path),
+    Symbol(&'a str),
+}
+
+/// Ranks the test files of the project by how likely they test `subject`.
+/// `similar` holds test chunks found by embedding search, closest first.
+pub fn find_tests(
+    files: &ProjectFiles,
+    subject: &Subject,
+    similar: Vec<(PathBuf, Range<usize>)>,
+    limit: usize,
+) -> Vec<TestMatch> {
+    let name = match subject {
+        Subject::File(path) => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default(),
+        Subject::Symbol(symbol) => symbol,
+    };
+    let mut matches: HashMap<PathBuf, TestMatch> = HashMap::new();
+
+    for path in files.paths() {
+        let Some(text) = files.peek_text(path) else {
+            continue;
+        };
+        if !is_test_file(path, &text) {
+            continue;
+        }
+        if let Subject::File(subject) = subject
+            && *subject == path
+        {
+            add(
+                &mut matches,
+                path,
+                NAME_SCORE,
+                "has tests of its own".to_string(),
+            );
+            continue;
+        }
+        if named_after(path, name) {
+            add(
+                &mut matches,
+                path,
+                NAME_SCORE,
+                format!("named after {name}"),
+            );
+        }
+        if mentions(&text, name) {
+            add(
+                &mut matches,
+                path,
+                MENTION_SCORE,
+                format!("mentions {name}"),
+            );
+        }
+    }
+    let mut seen = Vec::new();
+    for (path, rows) in similar {
+        // Only the closest chunk of each file counts.
+        if seen.contains(&path) {
+            continue;
+        }
+        add(
+            &mut matches,
+            &path,
+            SIMILARITY_SCORE,
+            "similar test code".to_string(),
+        )
+        .rows = Some(rows);
+        seen.push(path);
+    }
+
+    let mut matches: Vec<TestMatch> = matches.into_values().collect();
+    matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+    matches.truncate(limit);
+    matches
+}
+
+fn add<'m>(
+    matches: &'m mut HashMap<PathBuf, TestMatch>,
+    path: &Path,
+    score: u32,
+    reason: String,
+) -> &'m mut TestMatch {
+    let found = matches
+        .entry(path.to_path_buf())
+        .or_insert_with(|| TestMatch {
+            path: path.to_path_buf(),
+            rows: None,
+            reasons: Vec::new(),
+            score: 0,
+        });
+    found.score += score;
+    found.reasons.push(reason);
+    found
+}
+
+/// Whether the test file's name is the subject's with a test prefix or
+/// suffix, e.g. `session_test.go` or `test_session.py` for `session`.
+fn named_after(test_path: &Path, name: &str) -> bool {
+    let Some(stem) = test_path.file_stem().and_then(|stem| stem.to_str()) else {
+        return false;
+    };
+    let normalized = |name: &str| name.replace(['_', '-', '.'], "").to_lowercase();
+    let (stem, name) = (normalized(stem), normalized(name));
+    !name.is_empty()
+        && (stem == name
+            || ["test", "tests", "spec"]
+                .iter()
+                .any(|affix| stem == format!("{name}{affix}") || stem == format!("{affix}{name}")))
+}
+
+/// Whether `word` occurs in the text on its own, not as part of a longer
+/// identifier.
+fn mentions(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(is_identifier) && !after.is_some_and(is_identifier)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_test_names() {
+        assert!(named_after(Path::new("/p/session_test.go"), "session"));
+        assert!(named_after(Path::new("/p/test_session.py"), "session"));
+        assert!(named_after(Path::new("/p/SessionTest.kt"), "Session"));
+        assert!(named_after(Path::new("/p/session.spec.ts"), "session"));
+        assert!(named_after(
+            Path::new("/p/tests/user_session.rs"),
+            "user_session"
+        ));
+        assert!(!named_after(
+            Path::new("/p/session_store_test.go"),
+            "session"
+        ));
+    }
+
+    #[test]
+    fn mentions_whole_identifiers_only() {
+        let text = "use crate::session::open_session;\nfn reopen_session() {}";
+        assert!(mentions(text, "open_session"));
+        assert!(mentions(text, "session"));
+        assert!(!mentions(text, "open"));
+        assert!(!mentions("sessions", "session"));
+    }
+}
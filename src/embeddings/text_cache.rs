@@ -0,0 +1,157 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// File contents by path within a byte budget, dropping the least recently
+/// used ones to make room. Files searches never returned go first.
+pub struct TextCache {
+    budget: usize,
+    bytes: usize,
+    /// Incremented on every use, orders the texts by recency.
+    clock: u64,
+    texts: HashMap<PathBuf, (Arc<str>, u64)>,
+    by_use: BTreeMap<u64, PathBuf>,
+    /// Files searches returned, in this run or, by their recorded hits,
+    /// earlier ones.
+    hit: HashSet<PathBuf>,
+}
+
+impl TextCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            bytes: 0,
+            clock: 0,
+            texts: HashMap::new(),
+            by_use: BTreeMap::new(),
+            hit: HashSet::new(),
+        }
+    }
+
+    pub fn get(&mut self, path: &Path) -> Option<Arc<str>> {
+        self.clock += 1;
+        let (text, used) = self.texts.get_mut(path)?;
+        self.by_use.remove(used);
+        *used = self.clock;
+        self.by_use.insert(self.clock, path.to_path_buf());
+        Some(text.clone())
+    }
+
+    /// The text without counting as a use, so passes over every file don't
+    /// push out the ones searches keep coming back to.
+    pub fn peek(&self, path: &Path) -> Option<Arc<str>> {
+        self.texts.get(path).map(|(text, _)| text.clone())
+    }
+
+    /// Keeps the text even if it alone is over the budget, so it's there
+    /// for its first use.
+    pub fn insert(&mut self, path: PathBuf, text: Arc<str>) {
+        self.remove(&path);
+        self.clock += 1;
+        self.bytes += text.len();
+        self.by_use.insert(self.clock, path.clone());
+        self.texts.insert(path, (text, self.clock));
+        self.evict();
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        if let Some((text, used)) = self.texts.remove(path) {
+            self.bytes -= text.len();
+            self.by_use.remove(&used);
+        }
+    }
+
+    /// Keeps the texts of the files over those of files never hit when
+    /// making room.
+    pub fn mark_hit(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.hit.extend(paths);
+    }
+
+    /// Removes the text and that the file was hit, for files that are gone.
+    pub fn forget(&mut self, path: &Path) {
+        self.remove(path);
+        self.hit.remove(path);
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Bytes of text held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.budget && self.by_use.len() > 1 {
+            let used = self
+                .by_use
+                .iter()
+                .find(|(_, path)| !self.hit.contains(*path))
+                .or_else(|| self.by_use.first_key_value())
+                .map(|(&used, _)| used);
+            let Some(path) = used.and_then(|used| self.by_use.remove(&used)) else {
+                break;
+            };
+            if let Some((text, _)) = self.texts.remove(&path) {
+                self.bytes -= text.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_least_recently_used_over_budget() {
+        let mut cache = TextCache::new(10);
+        cache.insert("a.rs".into(), "aaaa".into());
+        cache.insert("b.rs".into(), "bbbb".into());
+        assert!(cache.get(Path::new("a.rs")).is_some());
+        cache.insert("c.rs".into(), "cccc".into());
+
+        assert!(cache.get(Path::new("b.rs")).is_none());
+        assert!(cache.get(Path::new("a.rs")).is_some());
+        assert!(cache.get(Path::new("c.rs")).is_some());
+        assert_eq!(cache.bytes(), 8);
+
+        cache.insert("large.rs".into(), "x".repeat(20).into());
+        assert_eq!(cache.bytes(), 20);
+        assert!(cache.get(Path::new("large.rs")).is_some());
+    }
+
+    #[test]
+    fn drops_files_never_hit_first() {
+        let mut cache = TextCache::new(8);
+        cache.mark_hit(["a.rs".into()]);
+        cache.insert("a.rs".into(), "aaaa".into());
+        cache.insert("b.rs".into(), "bbbb".into());
+        cache.insert("c.rs".into(), "cccc".into());
+
+        assert!(cache.peek(Path::new("a.rs")).is_some());
+        assert!(cache.peek(Path::new("b.rs")).is_none());
+        assert!(cache.peek(Path::new("c.rs")).is_some());
+
+        cache.forget(Path::new("a.rs"));
+        cache.insert("a.rs".into(), "aaaa".into());
+        cache.insert("d.rs".into(), "dddd".into());
+        assert!(cache.peek(Path::new("c.rs")).is_none());
+    }
+
+    #[test]
+    fn peeking_is_no_use() {
+        let mut cache = TextCache::new(8);
+        cache.insert("a.rs".into(), "aaaa".into());
+        cache.insert("b.rs".into(), "bbbb".into());
+        assert!(cache.peek(Path::new("a.rs")).is_some());
+        cache.insert("c.rs".into(), "cccc".into());
+
+        assert!(cache.peek(Path::new("a.rs")).is_none());
+        assert!(cache.peek(Path::new("b.rs")).is_some());
+    }
+}
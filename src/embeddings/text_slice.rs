@@ -0,0 +1,107 @@
+use std::{fmt, ops::Deref, ops::Range, sync::Arc};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A range of a shared text, so results can point into file contents held
+/// in memory without copying them. Serialized as a plain string.
+#[derive(Clone, Default)]
+pub struct TextSlice {
+    text: Arc<str>,
+    range: Range<usize>,
+}
+
+impl TextSlice {
+    /// Panics if `range` isn't on character boundaries of `text`, like
+    /// slicing a `str`.
+    pub fn new(text: Arc<str>, range: Range<usize>) -> Self {
+        let _ = &text[range.clone()];
+        Self { text, range }
+    }
+
+    /// None if `range` isn't on character boundaries of `text`, e.g. the
+    /// range of a chunk of an older version of the file.
+    pub fn get(text: Arc<str>, range: Range<usize>) -> Option<Self> {
+        text.get(range.clone())?;
+        Some(Self { text, range })
+    }
+}
+
+impl Deref for TextSlice {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text[self.range.clone()]
+    }
+}
+
+impl From<&str> for TextSlice {
+    fn from(text: &str) -> Self {
+        Self::from(Arc::<str>::from(text))
+    }
+}
+
+impl From<String> for TextSlice {
+    fn from(text: String) -> Self {
+        Self::from(Arc::<str>::from(text))
+    }
+}
+
+impl From<Arc<str>> for TextSlice {
+    fn from(text: Arc<str>) -> Self {
+        let range = 0..text.len();
+        Self { text, range }
+    }
+}
+
+impl PartialEq for TextSlice {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl fmt::Debug for TextSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for TextSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl Serialize for TextSlice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TextSlice {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_as_the_slice() {
+        let text: Arc<str> = "fn main() {}\n".into();
+        let slice = TextSlice::new(text.clone(), 3..7);
+        assert_eq!(&*slice, "main");
+        let json = serde_json::to_string(&slice).unwrap();
+        assert_eq!(json, "\"main\"");
+        assert_eq!(serde_json::from_str::<TextSlice>(&json).unwrap(), slice);
+    }
+
+    #[test]
+    fn gets_only_ranges_of_the_text() {
+        let text: Arc<str> = "fn ä() {}\n".into();
+        assert_eq!(TextSlice::get(text.clone(), 3..5).as_deref(), Some("ä"));
+        assert!(TextSlice::get(text.clone(), 3..4).is_none());
+        assert!(TextSlice::get(text, 3..40).is_none());
+    }
+}
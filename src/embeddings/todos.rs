@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::Tree;
+
+use super::preprocess::comments;
+
+const TAGS: [&str; 4] = ["TODO", "FIXME", "HACK", "XXX"];
+
+/// A TODO-style note left in a comment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Todo {
+    pub path: PathBuf,
+    /// Zero-based position of the tag.
+    pub row: usize,
+    pub column: usize,
+    /// One of "TODO", "FIXME", "HACK" or "XXX".
+    pub tag: String,
+    /// The rest of the line after the tag, e.g. "(katis): handle retries".
+    pub text: String,
+}
+
+impl Todo {
+    /// Whether the tag or text contains `filter`, ignoring case. An empty
+    /// filter matches every note.
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        self.tag.to_lowercase() == filter || self.text.to_lowercase().contains(&filter)
+    }
+}
+
+/// Notes tagged with one of `TAGS` as a whole word in the comments of the file.
+pub fn extract_todos(path: &Path, tree: &Tree, source: &str) -> Vec<Todo> {
+    let mut todos = Vec::new();
+    for comment in comments(tree, source) {
+        for (i, line) in comment.text.lines().enumerate() {
+            let Some((offset, tag)) = find_tag(line) else {
+                continue;
+            };
+            let text = line[offset + tag.len()..]
+                .trim()
+                .trim_end_matches("*/")
+                .trim_end_matches("-->")
+                .trim_start_matches(':')
+                .trim();
+            let (row, column) = if i == 0 {
+                (comment.start.row, comment.start.column + offset)
+            } else {
+                (comment.start.row + i, offset)
+            };
+            todos.push(Todo {
+                path: path.to_path_buf(),
+                row,
+                column,
+                tag: tag.to_string(),
+                text: text.to_string(),
+            });
+        }
+    }
+    todos
+}
+
+fn find_tag(line: &str) -> Option<(usize, &'static str)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    TAGS.iter()
+        .flat_map(|tag| {
+            line.match_indices(tag)
+                .map(move |(offset, _)| (offset, *tag))
+        })
+        .filter(|(offset, tag)| {
+            let before = line[..*offset].chars().next_back();
+            let after = line[offset + tag.len()..].chars().next();
+            !before.is_some_and(is_word) && !after.is_some_and(is_word)
+        })
+        .min_by_key(|(offset, _)| *offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_tagged_comments_only() {
+        let source = "// TODO: handle errors\nfn main() {\n    let s = \"TODO not a note\";\n    /* keep going\n       FIXME(katis) leaks */\n    // TODOS aren't tags\n}\n";
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_rust::LANGUAGE.into())
+            .unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let todos = extract_todos(Path::new("/p/main.rs"), &tree, source);
+
+        let found: Vec<_> = todos
+            .iter()
+            .map(|todo| (todo.row, todo.column, todo.tag.as_str(), todo.text.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0, 3, "TODO", "handle errors"),
+                (4, 7, "FIXME", "(katis) leaks")
+            ]
+        );
+        assert!(todos[1].matches("fixme"));
+        assert!(todos[1].matches("LEAKS"));
+        assert!(!todos[0].matches("hack"));
+    }
+}
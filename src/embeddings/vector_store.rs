@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    code_splitter::TextPosition,
+    project_files::language_of,
+    project_repository::{CompactionStats, OutputChunk},
+};
+
+/// Storage and nearest neighbour search of chunk embeddings.
+pub trait VectorStore: Send {
+    /// Number of stored chunks of the file per content hash.
+    fn content_hashes(&self, path: &str) -> Result<HashMap<String, usize>>;
+
+    /// Replaces the chunks of a file. Chunks whose content hash is already
+    /// stored for the file reuse the stored embedding, `embeddings` has one for
+    /// every other content hash.
+    fn upsert_file(
+        &mut self,
+        path: &str,
+        chunks: &[ChunkRecord],
+        embeddings: &HashMap<String, Vec<f32>>,
+    ) -> Result<()>;
+
+    /// Removes files for which `keep` returns false along with their chunks
+    /// and reclaims the space they used.
+    fn compact(&mut self, keep: &dyn Fn(&Path) -> bool) -> Result<CompactionStats>;
+
+    fn storage_bytes(&self) -> Result<u64>;
+
+    /// An embedding stored for the content hash in any file, so copies of a
+    /// chunk, e.g. in vendored code, are only embedded once.
+    fn embedding(&self, content_hash: &str) -> Result<Option<Vec<f32>>>;
+
+    /// Paths of the files holding a chunk with the content hash.
+    fn paths_with(&self, content_hash: &str) -> Result<Vec<String>>;
+
+    /// Calls `visit` with every stored chunk and its embedding, in path order.
+    fn for_each_embedding(
+        &self,
+        visit: &mut dyn FnMut(OutputChunk, Vec<f32>) -> Result<()>,
+    ) -> Result<()>;
+
+    /// The `limit` chunks closest to any of the embeddings among those
+    /// matching the filter, each scored by the embedding it's closest to.
+    fn search(
+        &self,
+        embeddings: &[Vec<f32>],
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<OutputChunk>>;
+}
+
+/// Narrows a search down to some of the chunks before the nearest are picked.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkFilter {
+    /// Any kind if empty.
+    pub kinds: Vec<ChunkKind>,
+    /// Any language if empty, e.g. "rust" or "typescript".
+    pub languages: Vec<String>,
+    /// Only chunks of files under this path.
+    pub path_prefix: Option<PathBuf>,
+}
+
+impl ChunkFilter {
+    pub fn matches(&self, path: &Path, kind: ChunkKind) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&kind))
+            && (self.languages.is_empty()
+                || language_of(path)
+                    .is_some_and(|language| self.languages.iter().any(|l| l == language)))
+            && self
+                .path_prefix
+                .as_ref()
+                .is_none_or(|prefix| path.starts_with(prefix))
+    }
+}
+
+/// The directory directly under `root` the file is in, empty for files in
+/// `root` itself. Chunks are partitioned by it.
+pub fn top_directory(root: &Path, path: &Path) -> String {
+    let mut components = path.strip_prefix(root).unwrap_or(path).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(directory)), Some(_)) => directory.to_string_lossy().into(),
+        _ => String::new(),
+    }
+}
+
+/// What a chunk holds, so searches can be narrowed to e.g. type definitions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkKind {
+    /// Code that isn't mostly one function or type.
+    #[default]
+    Code,
+    Function,
+    Type,
+    Test,
+    /// YAML and JSON.
+    Config,
+    /// Comments indexed apart from the code they belong to.
+    Doc,
+    /// The imports, package declaration and module docs a file starts with.
+    Preamble,
+}
+
+impl ChunkKind {
+    pub const ALL: [ChunkKind; 7] = [
+        ChunkKind::Code,
+        ChunkKind::Function,
+        ChunkKind::Type,
+        ChunkKind::Test,
+        ChunkKind::Config,
+        ChunkKind::Doc,
+        ChunkKind::Preamble,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChunkKind::Code => "code",
+            ChunkKind::Function => "function",
+            ChunkKind::Type => "type",
+            ChunkKind::Test => "test",
+            ChunkKind::Config => "config",
+            ChunkKind::Doc => "doc",
+            ChunkKind::Preamble => "preamble",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == kind)
+    }
+
+    pub fn corpus(self) -> Corpus {
+        match self {
+            ChunkKind::Config => Corpus::Config,
+            ChunkKind::Doc => Corpus::Docs,
+            _ => Corpus::Code,
+        }
+    }
+}
+
+/// The chunk kinds searches can draw a set number of results from each of,
+/// so e.g. a few docs aren't crowded out by the far more numerous code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Corpus {
+    Code,
+    Docs,
+    Config,
+}
+
+impl Corpus {
+    pub const ALL: [Corpus; 3] = [Corpus::Code, Corpus::Docs, Corpus::Config];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Corpus::Code => "code",
+            Corpus::Docs => "docs",
+            Corpus::Config => "config",
+        }
+    }
+
+    pub fn parse(corpus: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == corpus)
+    }
+
+    /// The kinds of chunks in the corpus.
+    pub fn kinds(self) -> Vec<ChunkKind> {
+        ChunkKind::ALL
+            .into_iter()
+            .filter(|kind| kind.corpus() == self)
+            .collect()
+    }
+}
+
+/// Keeps the first chunks of each corpus up to its limit, in order, and
+/// drops the chunks of corpora without one.
+pub fn draw_per_corpus<T>(
+    chunks: impl IntoIterator<Item = T>,
+    limits: &[(Corpus, usize)],
+    kind_of: impl Fn(&T) -> ChunkKind,
+) -> Vec<T> {
+    let mut remaining: HashMap<Corpus, usize> = HashMap::new();
+    for (corpus, limit) in limits {
+        *remaining.entry(*corpus).or_default() += limit;
+    }
+    chunks
+        .into_iter()
+        .filter(|chunk| match remaining.get_mut(&kind_of(chunk).corpus()) {
+            Some(left) if *left > 0 => {
+                *left -= 1;
+                true
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkRecord {
+    pub start: TextPosition,
+    pub end: TextPosition,
+    pub byte: Range<usize>,
+    pub content_hash: String,
+    pub kind: ChunkKind,
+}
+
+impl ChunkRecord {
+    pub fn output(&self, path: &str, distance: f32) -> OutputChunk {
+        OutputChunk {
+            path: path.into(),
+            row: self.start.row..self.end.row,
+            column: self.start.column..self.end.column,
+            byte: self.byte.clone(),
+            kind: self.kind,
+            content_hash: self.content_hash.clone(),
+            distance,
+            duplicates: Vec::new(),
+        }
+    }
+}
+
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 1.0;
+    }
+    1.0 - dot / (norm_a * norm_b)
+}
+
+/// Distance to the closest of the query embeddings.
+pub fn min_distance(queries: &[Vec<f32>], embedding: &[f32]) -> f32 {
+    queries
+        .iter()
+        .map(|query| cosine_distance(query, embedding))
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Like `nearest` for chunks found by several queries, a chunk found more
+/// than once keeps its smallest distance.
+pub fn merge_nearest(chunks: Vec<OutputChunk>, limit: usize) -> Vec<OutputChunk> {
+    let mut merged: HashMap<_, OutputChunk> = HashMap::new();
+    for chunk in chunks {
+        let key = (
+            chunk.path.clone(),
+            chunk.byte.clone(),
+            chunk.kind,
+            chunk.content_hash.clone(),
+        );
+        match merged.get(&key) {
+            Some(found) if found.distance <= chunk.distance => {}
+            _ => {
+                merged.insert(key, chunk);
+            }
+        }
+    }
+    nearest(merged.into_values().collect(), limit)
+}
+
+/// Keeps the `limit` chunks with the smallest distance, closest first.
+pub fn nearest(mut chunks: Vec<OutputChunk>, limit: usize) -> Vec<OutputChunk> {
+    chunks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    chunks.truncate(limit);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_up_to_the_limit_of_each_corpus() {
+        let chunks = [
+            ChunkKind::Function,
+            ChunkKind::Type,
+            ChunkKind::Test,
+            ChunkKind::Doc,
+            ChunkKind::Config,
+            ChunkKind::Doc,
+        ];
+        let limits = [(Corpus::Code, 2), (Corpus::Docs, 1)];
+
+        assert_eq!(
+            draw_per_corpus(chunks, &limits, |kind| *kind),
+            [ChunkKind::Function, ChunkKind::Type, ChunkKind::Doc]
+        );
+        assert_eq!(Corpus::Docs.kinds(), [ChunkKind::Doc]);
+    }
+}
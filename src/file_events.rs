@@ -0,0 +1,297 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use watchexec_events::{
+    Event, FileType, Tag,
+    filekind::{FileEventKind, ModifyKind, RenameMode},
+};
+
+/// File name prefixes of editor temporaries: emacs locks, GTK's atomic
+/// saves and the `tempfile` crate's files.
+const TEMPORARY_PREFIXES: &[&str] = &[".#", ".goutputstream-", ".tmp"];
+
+/// File name suffixes of editor temporaries: backups, vim swap files and
+/// the temporaries of atomic saves.
+const TEMPORARY_SUFFIXES: &[&str] = &[
+    "~",
+    ".swp",
+    ".swo",
+    ".swx",
+    ".tmp",
+    "___jb_tmp___",
+    "___jb_old___",
+];
+
+/// The files created or written in a batch of events, once each in the order
+/// they were first seen. Editors often save by writing a temporary and
+/// renaming it over the file: the temporaries are left out, along with files
+/// renamed away later in the batch, and the file renamed to is in.
+pub fn changed_files(events: &[Event]) -> Vec<PathBuf> {
+    let mut order = Vec::new();
+    let mut queued = HashSet::new();
+    let mut written: HashMap<&Path, bool> = HashMap::new();
+    for event in events {
+        let kind = event.tags.iter().find_map(|tag| match tag {
+            Tag::FileEventKind(kind) => Some(kind),
+            _ => None,
+        });
+        let moved_away = match kind {
+            Some(FileEventKind::Modify(ModifyKind::Name(RenameMode::From))) => usize::MAX,
+            // The paths are the source and then the destination.
+            Some(FileEventKind::Modify(ModifyKind::Name(RenameMode::Both))) => 1,
+            Some(FileEventKind::Create(_) | FileEventKind::Modify(_)) => 0,
+            _ => continue,
+        };
+        for (i, (path, file_type)) in event.paths().enumerate() {
+            if i < moved_away {
+                written.insert(path, false);
+            } else if file_type == Some(&FileType::File) && !is_temporary(path) {
+                // Also files renamed away before, e.g. by vim's backup.
+                if queued.insert(path) {
+                    order.push(path);
+                }
+                written.insert(path, true);
+            }
+        }
+    }
+    order
+        .into_iter()
+        .filter(|path| written[path])
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Whether the file looks like an editor's temporary or backup file.
+fn is_temporary(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    // vim checks whether it can write to a directory with this file.
+    name == "4913"
+        || (name.starts_with('#') && name.ends_with('#'))
+        || TEMPORARY_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        || TEMPORARY_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+/// The innermost of the project `roots` containing `path`, with the path
+/// under that root. Paths that don't start with a root as given are matched
+/// normalized, e.g. ones through a symlink like macOS' /tmp to /private/tmp,
+/// and then regardless of case for roots on case-insensitive filesystems.
+pub fn project_root_of(roots: &[PathBuf], path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let innermost = |path: &Path, ignore_case: bool| {
+        roots
+            .iter()
+            .filter_map(|root| {
+                let ignore_case = ignore_case && is_case_insensitive(root);
+                Some((root, relative_to(path, root, ignore_case)?))
+            })
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(root, relative)| (root.clone(), root.join(relative)))
+    };
+    innermost(path, false).or_else(|| {
+        let path = normalize_path(path);
+        innermost(&path, false).or_else(|| innermost(&path, true))
+    })
+}
+
+/// The path with symlinks resolved like `fs::canonicalize`, also for paths
+/// that are gone, e.g. deleted files, by resolving their parent. Falls back
+/// to dropping `.` and `..` components.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    if let (Some(parent), Some(name)) = (path.parent(), path.file_name())
+        && let Ok(parent) = fs::canonicalize(parent)
+    {
+        return parent.join(name);
+    }
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` relative to `root`, comparing their components regardless of
+/// case with `ignore_case`.
+fn relative_to(path: &Path, root: &Path, ignore_case: bool) -> Option<PathBuf> {
+    if !ignore_case {
+        return path.strip_prefix(root).ok().map(Path::to_path_buf);
+    }
+    let mut components = path.components();
+    for root_component in root.components() {
+        let component = components.next()?;
+        let same = component.as_os_str().to_string_lossy().to_lowercase()
+            == root_component.as_os_str().to_string_lossy().to_lowercase();
+        if !same {
+            return None;
+        }
+    }
+    Some(components.collect())
+}
+
+/// Whether the filesystem of `dir` matches names regardless of case, told
+/// by looking `dir` up with the case of its name flipped.
+fn is_case_insensitive(dir: &Path) -> bool {
+    let Some(name) = dir.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let flipped: String = name
+        .chars()
+        .map(|c| {
+            if c.is_uppercase() {
+                c.to_ascii_lowercase()
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect();
+    if flipped == name {
+        return false;
+    }
+    match (fs::metadata(dir), fs::metadata(dir.with_file_name(flipped))) {
+        (Ok(dir), Ok(flipped)) => dir.dev() == flipped.dev() && dir.ino() == flipped.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use watchexec_events::filekind::{CreateKind, DataChange};
+
+    use super::*;
+
+    fn event(kind: FileEventKind, paths: &[(&str, Option<FileType>)]) -> Event {
+        let mut tags = vec![Tag::FileEventKind(kind)];
+        tags.extend(paths.iter().map(|(path, file_type)| Tag::Path {
+            path: path.into(),
+            file_type: *file_type,
+        }));
+        Event {
+            tags,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn atomic_saves_change_the_renamed_to_file_once() {
+        let file = Some(FileType::File);
+        let events = [
+            event(
+                FileEventKind::Create(CreateKind::File),
+                &[("/p/.main.rs.swp", file), ("/p/main.rs.tmp", file)],
+            ),
+            event(
+                FileEventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                &[("/p/main.rs", file), ("/p/lib.rs", file)],
+            ),
+            event(
+                FileEventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &[("/p/main.rs.tmp", None), ("/p/main.rs", file)],
+            ),
+            event(
+                FileEventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &[("/p/lib.rs", None), ("/p/lib.rs~", file)],
+            ),
+            // vim's backup by rename, of a file not written before.
+            event(
+                FileEventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &[("/p/util.rs", None), ("/p/util.rs~", file)],
+            ),
+            event(
+                FileEventKind::Create(CreateKind::File),
+                &[("/p/.#main.rs", file), ("/p/4913", file)],
+            ),
+            event(
+                FileEventKind::Create(CreateKind::File),
+                &[("/p/util.rs", file), ("/p/lib.rs", file)],
+            ),
+        ];
+
+        assert_eq!(
+            changed_files(&events),
+            [
+                PathBuf::from("/p/main.rs"),
+                PathBuf::from("/p/lib.rs"),
+                PathBuf::from("/p/util.rs")
+            ]
+        );
+    }
+
+    #[test]
+    fn files_renamed_away_are_left_out() {
+        let file = Some(FileType::File);
+        let events = [
+            event(
+                FileEventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                &[("/p/lib.rs", file)],
+            ),
+            event(
+                FileEventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &[("/p/lib.rs", None), ("/p/lib.rs.bak", file)],
+            ),
+        ];
+
+        assert_eq!(changed_files(&events), [PathBuf::from("/p/lib.rs.bak")]);
+    }
+
+    #[test]
+    fn matches_paths_through_symlinks_to_canonical_roots() {
+        // Like /tmp linking to /private/tmp on macOS.
+        let dir = tempfile::tempdir().unwrap();
+        let private = dir.path().join("private/tmp/project");
+        fs::create_dir_all(private.join("src")).unwrap();
+        fs::write(private.join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("private/tmp"), dir.path().join("tmp")).unwrap();
+        let root = fs::canonicalize(&private).unwrap();
+        let roots = [root.clone(), root.join("src/nested")];
+
+        let linked = dir.path().join("tmp/project");
+        assert_eq!(
+            project_root_of(&roots, &linked.join("src/main.rs")),
+            Some((root.clone(), root.join("src/main.rs")))
+        );
+        // Deleted files can't be canonicalized themselves.
+        assert_eq!(
+            project_root_of(&roots, &linked.join("src/deleted.rs")),
+            Some((root.clone(), root.join("src/deleted.rs")))
+        );
+        assert_eq!(
+            project_root_of(&roots, &root.join("src/nested/lib.rs")),
+            Some((root.join("src/nested"), root.join("src/nested/lib.rs")))
+        );
+        assert_eq!(project_root_of(&roots, &dir.path().join("other.rs")), None);
+    }
+
+    #[test]
+    fn matches_roots_regardless_of_case_if_told_to() {
+        let root = Path::new("/Users/Me/Code");
+        let path = Path::new("/users/me/code/Src/Main.rs");
+        assert_eq!(relative_to(path, root, false), None);
+        assert_eq!(relative_to(path, root, true), Some("Src/Main.rs".into()));
+        assert_eq!(relative_to(Path::new("/users/me"), root, true), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("Project");
+        fs::create_dir(&project).unwrap();
+        assert_eq!(
+            is_case_insensitive(&project),
+            dir.path().join("pROJECT").exists()
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+
+const VERBS: [&str; 16] = [
+    "load", "parse", "retry", "merge", "validate", "render", "encode", "decode", "schedule",
+    "resolve", "fetch", "store", "flush", "split", "rank", "refresh",
+];
+
+const NOUNS: [&str; 16] = [
+    "config", "session", "token", "batch", "request", "invoice", "user", "order", "cache",
+    "header", "payload", "queue", "report", "policy", "account", "event",
+];
+
+/// Names of the generated directories, numbered once they run out.
+const MODULES: [&str; 8] = [
+    "auth", "billing", "storage", "api", "jobs", "search", "metrics", "admin",
+];
+
+/// Languages the generated files are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureLanguage {
+    Rust,
+    Python,
+    Go,
+    TypeScript,
+    Java,
+    Yaml,
+    Json,
+}
+
+impl FixtureLanguage {
+    pub const ALL: [FixtureLanguage; 7] = [
+        FixtureLanguage::Rust,
+        FixtureLanguage::Python,
+        FixtureLanguage::Go,
+        FixtureLanguage::TypeScript,
+        FixtureLanguage::Java,
+        FixtureLanguage::Yaml,
+        FixtureLanguage::Json,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            FixtureLanguage::Rust => "rs",
+            FixtureLanguage::Python => "py",
+            FixtureLanguage::Go => "go",
+            FixtureLanguage::TypeScript => "ts",
+            FixtureLanguage::Java => "java",
+            FixtureLanguage::Yaml => "yaml",
+            FixtureLanguage::Json => "json",
+        }
+    }
+
+    /// The language of a file extension, e.g. "rs".
+    pub fn parse(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.extension() == extension)
+    }
+}
+
+/// The shape of a synthetic repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureSpec {
+    /// Files generated, copies included.
+    pub files: usize,
+    /// Directory levels the files are nested in below the root.
+    pub depth: usize,
+    /// Subdirectories of each directory.
+    pub fan_out: usize,
+    /// Share of the files, from 0 to 1, that copy an earlier file of the same
+    /// language, as vendored or generated code does.
+    pub duplication: f64,
+    /// Functions, or sections of config files, per file.
+    pub functions: usize,
+    /// Languages the files are spread over evenly.
+    pub languages: Vec<FixtureLanguage>,
+    /// The same spec and seed always generate the same repository.
+    pub seed: u64,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            files: 100,
+            depth: 2,
+            fan_out: 3,
+            duplication: 0.1,
+            functions: 5,
+            languages: FixtureLanguage::ALL.to_vec(),
+            seed: 0,
+        }
+    }
+}
+
+/// What `generate_fixture` wrote.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FixtureStats {
+    pub files: usize,
+    /// Files copying an earlier one.
+    pub duplicates: usize,
+    pub bytes: u64,
+}
+
+/// Writes a repository of the spec's shape under `root`, for benchmarks and
+/// integration tests of indexing at scale. Files are made of small functions
+/// or config sections with names drawn from a fixed vocabulary.
+pub fn generate_fixture(spec: &FixtureSpec, root: &Path) -> Result<FixtureStats> {
+    if spec.languages.is_empty() {
+        bail!("A fixture needs at least one language");
+    }
+    if !(0.0..=1.0).contains(&spec.duplication) {
+        bail!("The duplication rate must be between 0 and 1");
+    }
+    let mut rng = Rng(spec.seed);
+    let mut stats = FixtureStats::default();
+    // Contents written so far, per language, for duplicates to copy.
+    let mut written: Vec<Vec<String>> = vec![Vec::new(); spec.languages.len()];
+    for index in 0..spec.files {
+        let language_index = index % spec.languages.len();
+        let language = spec.languages[language_index];
+        let earlier = &written[language_index];
+        let contents = if !earlier.is_empty() && rng.chance(spec.duplication) {
+            stats.duplicates += 1;
+            earlier[rng.below(earlier.len())].clone()
+        } else {
+            let contents = file_contents(language, index, spec.functions, &mut rng);
+            written[language_index].push(contents.clone());
+            contents
+        };
+        let dir = root.join(directory(spec, &mut rng));
+        std::fs::create_dir_all(&dir)?;
+        let name = format!(
+            "{}_{}.{}",
+            NOUNS[rng.below(NOUNS.len())],
+            index,
+            language.extension()
+        );
+        std::fs::write(dir.join(name), &contents)?;
+        stats.files += 1;
+        stats.bytes += contents.len() as u64;
+    }
+    Ok(stats)
+}
+
+/// A directory `depth` levels down, relative to the root.
+fn directory(spec: &FixtureSpec, rng: &mut Rng) -> PathBuf {
+    (0..spec.depth)
+        .map(|_| {
+            let index = rng.below(spec.fan_out.max(1));
+            match MODULES.get(index) {
+                Some(module) => module.to_string(),
+                None => format!("module{index}"),
+            }
+        })
+        .collect()
+}
+
+fn file_contents(
+    language: FixtureLanguage,
+    index: usize,
+    functions: usize,
+    rng: &mut Rng,
+) -> String {
+    let mut names = HashSet::new();
+    let sections: Vec<String> = (0..functions)
+        .map(|i| {
+            let (verb, noun) = (VERBS[rng.below(VERBS.len())], NOUNS[rng.below(NOUNS.len())]);
+            let mut name = Name {
+                verb,
+                noun: noun.to_string(),
+                other: NOUNS[rng.below(NOUNS.len())],
+            };
+            if !names.insert((verb, noun)) {
+                name.noun = format!("{noun}{i}");
+            }
+            section(language, &name, 2 + rng.below(7), 2 + rng.below(97))
+        })
+        .collect();
+    match language {
+        FixtureLanguage::Rust => {
+            format!("//! Generated file {index}.\n\n{}", sections.join("\n"))
+        }
+        FixtureLanguage::Python => format!(
+            "\"\"\"Generated file {index}.\"\"\"\n\n\n{}",
+            sections.join("\n\n")
+        ),
+        FixtureLanguage::Go => {
+            format!(
+                "// Generated file {index}.\npackage fixture\n\n{}",
+                sections.join("\n")
+            )
+        }
+        FixtureLanguage::TypeScript => {
+            format!("// Generated file {index}.\n\n{}", sections.join("\n"))
+        }
+        FixtureLanguage::Java => format!(
+            "// Generated file {index}.\npublic class Generated{index} {{\n{}}}\n",
+            sections.join("\n")
+        ),
+        FixtureLanguage::Yaml => format!("# Generated file {index}.\n{}", sections.concat()),
+        FixtureLanguage::Json => {
+            format!("{{\n  \"file\": {index},\n{}\n}}\n", sections.join(",\n"))
+        }
+    }
+}
+
+struct Name {
+    verb: &'static str,
+    noun: String,
+    /// Another noun for the doc comment.
+    other: &'static str,
+}
+
+impl Name {
+    fn camel(&self) -> String {
+        format!("{}{}", self.verb, capitalize(&self.noun))
+    }
+
+    fn pascal(&self) -> String {
+        capitalize(&self.camel())
+    }
+}
+
+/// A function, or a config section, keeping every `divisor`th item times
+/// `factor`.
+fn section(language: FixtureLanguage, name: &Name, divisor: usize, factor: usize) -> String {
+    let Name { verb, noun, other } = name;
+    match language {
+        FixtureLanguage::Rust => format!(
+            "/// {} the {noun} of a {other}.
+pub fn {verb}_{noun}(input: &[u32], limit: usize) -> Vec<u32> {{
+    let mut out = Vec::with_capacity(limit);
+    for value in input.iter().take(limit) {{
+        if value % {divisor} == 0 {{
+            out.push(value * {factor});
+        }}
+    }}
+    out
+}}
+",
+            capitalize(verb)
+        ),
+        FixtureLanguage::Python => format!(
+            "def {verb}_{noun}(items, limit):
+    \"\"\"{} the {noun} of a {other}.\"\"\"
+    result = []
+    for item in items[:limit]:
+        if item % {divisor} == 0:
+            result.append(item * {factor})
+    return result
+",
+            capitalize(verb)
+        ),
+        FixtureLanguage::Go => format!(
+            "// {pascal} handles the {noun} of a {other}.
+func {pascal}(items []int, limit int) []int {{
+\tresult := make([]int, 0, limit)
+\tfor i, item := range items {{
+\t\tif i >= limit {{
+\t\t\tbreak
+\t\t}}
+\t\tif item%{divisor} == 0 {{
+\t\t\tresult = append(result, item*{factor})
+\t\t}}
+\t}}
+\treturn result
+}}
+",
+            pascal = name.pascal()
+        ),
+        FixtureLanguage::TypeScript => format!(
+            "/** {} the {noun} of a {other}. */
+export function {camel}(items: number[], limit: number): number[] {{
+  return items
+    .slice(0, limit)
+    .filter((item) => item % {divisor} === 0)
+    .map((item) => item * {factor});
+}}
+",
+            capitalize(verb),
+            camel = name.camel()
+        ),
+        FixtureLanguage::Java => format!(
+            "    /** {} the {noun} of a {other}. */
+    public static java.util.List<Integer> {camel}(java.util.List<Integer> items, int limit) {{
+        java.util.List<Integer> result = new java.util.ArrayList<>();
+        for (int item : items.subList(0, Math.min(limit, items.size()))) {{
+            if (item % {divisor} == 0) {{
+                result.add(item * {factor});
+            }}
+        }}
+        return result;
+    }}
+",
+            capitalize(verb),
+            camel = name.camel()
+        ),
+        FixtureLanguage::Yaml => format!(
+            "{verb}_{noun}:
+  {other}: {noun}-{divisor}
+  limit: {divisor}
+  timeout_ms: {factor}
+"
+        ),
+        FixtureLanguage::Json => format!(
+            "  \"{verb}_{noun}\": {{ \"{other}\": \"{noun}-{divisor}\", \"limit\": {divisor}, \"timeout_ms\": {factor} }}"
+        ),
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// SplitMix64, reproducible across platforms and good enough for fixtures.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64) < probability * (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_tree(dir: &Path, files: &mut Vec<(PathBuf, String)>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                read_tree(&path, files);
+            } else {
+                files.push((path.clone(), std::fs::read_to_string(path).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn generates_the_same_repository_from_a_seed() {
+        let spec = FixtureSpec {
+            files: 40,
+            duplication: 0.5,
+            ..Default::default()
+        };
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let stats = generate_fixture(&spec, a.path()).unwrap();
+        assert_eq!(generate_fixture(&spec, b.path()).unwrap(), stats);
+
+        let (mut files_a, mut files_b) = (Vec::new(), Vec::new());
+        read_tree(a.path(), &mut files_a);
+        read_tree(b.path(), &mut files_b);
+        files_a.sort();
+        files_b.sort();
+        assert_eq!(files_a.len(), 40);
+        assert_eq!(stats.files, 40);
+        assert!(stats.duplicates > 0 && stats.duplicates < 40);
+        for ((path_a, text_a), (path_b, text_b)) in files_a.iter().zip(&files_b) {
+            assert_eq!(path_a.strip_prefix(a.path()), path_b.strip_prefix(b.path()));
+            assert_eq!(text_a, text_b);
+            assert_eq!(
+                path_a.strip_prefix(a.path()).unwrap().components().count(),
+                3
+            );
+        }
+        let distinct: HashSet<&String> = files_a.iter().map(|(_, text)| text).collect();
+        assert_eq!(distinct.len(), 40 - stats.duplicates);
+    }
+}
@@ -1,2 +1,14 @@
+pub mod audit;
+pub mod config;
+pub mod context;
+pub mod daemon;
 pub mod embeddings;
+pub mod file_events;
+pub mod fixtures;
+pub mod notifications;
+pub mod result_formats;
 pub mod rpc;
+pub mod session;
+pub mod socket_activation;
+pub mod telemetry;
+pub mod watch_limits;
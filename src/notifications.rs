@@ -0,0 +1,176 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Notifications kept for clients that fall behind.
+const KEPT_NOTIFICATIONS: usize = 1000;
+
+static NOTIFICATIONS: LazyLock<Notifications> = LazyLock::new(|| Notifications {
+    recent: Mutex::default(),
+    latest: watch::Sender::new(0),
+});
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Increases by one with every notification of the daemon.
+    pub id: u64,
+    pub at: SystemTime,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    /// The project's queue ran empty after indexing `files` files.
+    IndexingFinished {
+        project: PathBuf,
+        files: usize,
+    },
+    /// The file couldn't be read, parsed or indexed.
+    FileFailed {
+        path: PathBuf,
+        error: String,
+    },
+    ModelLoaded {
+        model: String,
+    },
+    ModelFailed {
+        model: String,
+        error: String,
+    },
+    /// The project's directory is gone, it isn't searched or watched until
+    /// it's back.
+    ProjectUnavailable {
+        project: PathBuf,
+    },
+    ProjectAvailable {
+        project: PathBuf,
+    },
+    /// The project's files or index couldn't be read, it was unregistered.
+    ProjectFailed {
+        project: PathBuf,
+        error: String,
+    },
+    /// Every file of the project was embedded with a newly configured model,
+    /// searches use it from now on instead of the index of the one before.
+    ModelSwitched {
+        project: PathBuf,
+        model: String,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::IndexingFinished { project, files } => write!(
+                f,
+                "finished indexing {} ({} files)",
+                project.to_string_lossy(),
+                files
+            ),
+            Event::FileFailed { path, error } => {
+                write!(f, "failed to index {}: {}", path.to_string_lossy(), error)
+            }
+            Event::ModelLoaded { model } => write!(f, "loaded embedding model {model}"),
+            Event::ModelFailed { model, error } => {
+                write!(f, "failed to load embedding model {model}: {error}")
+            }
+            Event::ProjectUnavailable { project } => write!(
+                f,
+                "the directory of {} is gone, it's unavailable",
+                project.to_string_lossy()
+            ),
+            Event::ProjectAvailable { project } => {
+                write!(f, "{} is available again", project.to_string_lossy())
+            }
+            Event::ProjectFailed { project, error } => {
+                write!(f, "failed to open {}: {}", project.to_string_lossy(), error)
+            }
+            Event::ModelSwitched { project, model } => write!(
+                f,
+                "{} is searched with embedding model {}",
+                project.to_string_lossy(),
+                model
+            ),
+        }
+    }
+}
+
+struct Notifications {
+    recent: Mutex<VecDeque<Notification>>,
+    /// Id of the latest notification, 0 before the first.
+    latest: watch::Sender<u64>,
+}
+
+/// Sends the event to every subscribed client.
+pub fn notify(event: Event) {
+    let mut recent = NOTIFICATIONS.recent.lock().unwrap();
+    let id = *NOTIFICATIONS.latest.borrow() + 1;
+    recent.push_back(Notification {
+        id,
+        at: SystemTime::now(),
+        event,
+    });
+    if recent.len() > KEPT_NOTIFICATIONS {
+        recent.pop_front();
+    }
+    NOTIFICATIONS.latest.send_replace(id);
+}
+
+/// The notifications after the id `after`, or the ones sent from now on if
+/// unset. Waits until there is one or until the deadline, returning none
+/// then.
+pub async fn notifications_after(after: Option<u64>, deadline: Instant) -> Vec<Notification> {
+    let mut latest = NOTIFICATIONS.latest.subscribe();
+    let after = after.unwrap_or_else(|| *latest.borrow_and_update());
+    loop {
+        let found: Vec<Notification> = NOTIFICATIONS
+            .recent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|notification| notification.id > after)
+            .cloned()
+            .collect();
+        if !found.is_empty() {
+            return found;
+        }
+        let changed = tokio::time::timeout_at(deadline.into(), latest.changed());
+        if !matches!(changed.await, Ok(Ok(()))) {
+            return Vec::new();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_the_next_notification() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let waiting = tokio::spawn(notifications_after(None, deadline));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        notify(Event::ModelLoaded {
+            model: "test".to_string(),
+        });
+        let received = waiting.await.unwrap();
+        assert_eq!(received.len(), 1);
+
+        let after = received[0].id;
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(notifications_after(Some(after), deadline).await.is_empty());
+        assert_eq!(
+            notifications_after(Some(after - 1), deadline).await,
+            received
+        );
+    }
+}
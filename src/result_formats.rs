@@ -0,0 +1,127 @@
+use serde_json::{Value, json};
+
+use crate::embeddings::ResponseChunk;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The rule every search result is reported under in SARIF.
+const SARIF_RULE: &str = "search-result";
+
+/// One `path:line:column: message` line per chunk, the format of vim's
+/// default `errorformat` and of emacs' grep and compilation modes. Lines
+/// and columns start at 1.
+pub fn quickfix(chunks: &[ResponseChunk]) -> String {
+    chunks
+        .iter()
+        .map(|chunk| {
+            format!(
+                "{}:{}:{}: {}\n",
+                chunk.path.to_string_lossy(),
+                chunk.row.start + 1,
+                chunk.column.start + 1,
+                summary(chunk)
+            )
+        })
+        .collect()
+}
+
+/// A SARIF 2.1.0 log with the chunks as results of one run, closest first,
+/// for code review tools and editors that read static analysis results.
+/// Columns count what `search.column_encoding` is set to, bytes by default.
+pub fn sarif(query: &str, chunks: &[ResponseChunk]) -> Value {
+    let results: Vec<Value> = chunks
+        .iter()
+        .enumerate()
+        .map(|(rank, chunk)| {
+            let rows = chunk.rows();
+            let mut region = json!({
+                "startLine": chunk.row.start + 1,
+                "startColumn": chunk.column.start + 1,
+                "endLine": rows.end() + 1,
+                "snippet": { "text": &*chunk.content },
+            });
+            // Without an end column the region runs to the end of its last line.
+            if *rows.end() == chunk.row.end {
+                region["endColumn"] = json!(chunk.column.end + 1);
+            }
+            json!({
+                "ruleId": SARIF_RULE,
+                "level": "note",
+                "rank": rank,
+                "message": { "text": summary(chunk) },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": {
+                            "uri": format!("file://{}", chunk.path.to_string_lossy()),
+                        },
+                        "region": region,
+                    },
+                }],
+                "properties": {
+                    "chunkId": chunk.id,
+                    "kind": chunk.kind,
+                    "distance": chunk.distance,
+                    "stale": chunk.stale,
+                },
+            })
+        })
+        .collect();
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "code_search",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [{
+                        "id": SARIF_RULE,
+                        "shortDescription": { "text": "Code matching a semantic search" },
+                    }],
+                },
+            },
+            "properties": { "query": query },
+            "results": results,
+        }],
+    })
+}
+
+/// The chunk's first non-blank line, with its distance to the query.
+fn summary(chunk: &ResponseChunk) -> String {
+    let line = chunk
+        .content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    format!("{} (distance {:.3})", line, chunk.distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_chunks_for_editors_and_sarif() {
+        let chunk = ResponseChunk {
+            path: "/p/src/main.rs".into(),
+            row: 3..6,
+            column: 0..0,
+            content: "\n  fn main() {\n    run();\n}\n".into(),
+            distance: 0.25,
+            id: "/p/src/main.rs:40-80".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            quickfix(std::slice::from_ref(&chunk)),
+            "/p/src/main.rs:4:1: fn main() { (distance 0.250)\n"
+        );
+        let log = sarif("entry point", &[chunk]);
+        let region = &log["runs"][0]["results"][0]["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startLine"], 4);
+        assert_eq!(region["endLine"], 6);
+        assert!(region.get("endColumn").is_none());
+        assert_eq!(log["runs"][0]["results"][0]["ruleId"], SARIF_RULE);
+    }
+}
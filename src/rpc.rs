@@ -1,20 +1,237 @@
-use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+    os::unix::process::CommandExt,
+    path::Path,
+    pin::Pin,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use bytes::{Bytes, BytesMut};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tarpc::{
+    client,
+    tokio_serde::{Deserializer, Serializer},
+};
 use thiserror::Error;
 
-use crate::embeddings::ResponseChunk;
+use crate::{
+    embeddings::{
+        ColdChunk, CommitMatch, CompactionStats, Coverage, Definition, IndexEstimate, ProjectInfo,
+        QueueStatus, ResourceUsage, ResponseChunk, SearchCodeResponse, SearchOptions, TestMatch,
+        Todo,
+    },
+    notifications::Notification,
+    watch_limits::WatchStatus,
+};
+
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/code_search.sock";
+
+/// Frames larger than this are deflated by clients.
+pub const DEFAULT_COMPRESSION_BYTES: usize = 64 * 1024;
+
+const DAEMON_BINARY: &str = "code_search_server";
+
+/// How long `start_daemon` waits for the daemon to listen.
+const DAEMON_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Marks deflated frames, JSON never starts with it.
+const COMPRESSED_FRAME: u8 = b'z';
 
 #[tarpc::service]
 pub trait CodeSearchRpc {
     async fn search_code(
         project_path: String,
         query: String,
+        options: SearchOptions,
+    ) -> Result<SearchCodeResponse, RpcError>;
+
+    /// Searches several projects and merges their results by distance.
+    async fn search_projects(
+        scope: ProjectScope,
+        query: String,
+        options: SearchOptions,
+    ) -> Result<SearchCodeResponse, RpcError>;
+
+    async fn resource_usage(project_path: String) -> Result<ResourceUsage, RpcError>;
+
+    async fn compact(project_path: String) -> Result<CompactionStats, RpcError>;
+
+    /// Writes every chunk of the project with its embedding to `output_path`
+    /// on the daemon's machine: a JSON line naming the model, then one per
+    /// chunk. Returns how many chunks were written.
+    async fn export_embeddings(
+        project_path: String,
+        output_path: String,
+    ) -> Result<usize, RpcError>;
+
+    /// Registers the project if needed and indexes its files with the
+    /// embeddings of an export at `input_path`, made for the same model e.g.
+    /// in CI, instead of computing them. Only chunks whose embedded text
+    /// matches are taken from it. Returns how many embeddings were read.
+    async fn import_embeddings(project_path: String, input_path: String)
+    -> Result<usize, RpcError>;
+
+    async fn queue_status(project_path: String) -> Result<QueueStatus, RpcError>;
+
+    /// The registered projects by root, with their file counts and how up to
+    /// date their indexes are.
+    async fn list_projects() -> Result<Vec<ProjectInfo>, RpcError>;
+
+    /// Registers a project without searching it. With a git `revision`, e.g.
+    /// "origin/main", its files are indexed as of that revision from the
+    /// object store, without checking it out. Returns the project path to
+    /// use for it in other calls, `root@revision` for revisions.
+    async fn register_project(
+        project_path: String,
+        revision: Option<String>,
+    ) -> Result<String, RpcError>;
+
+    /// Rereads the project's files and reindexes the ones that changed,
+    /// registering it first if needed. The only way projects with `watch`
+    /// off are refreshed, revisions are reread if they moved.
+    async fn reindex(project_path: String) -> Result<(), RpcError>;
+
+    /// Reindexes only the files that differ between two git revisions, read
+    /// from the working tree, e.g. after switching branches or pulling.
+    /// Returns how many of them changed.
+    async fn reindex_changes(
+        project_path: String,
+        from: String,
+        to: String,
+    ) -> Result<usize, RpcError>;
+
+    /// The chunks around a search result in its file, in file order, given
+    /// its chunk id: up to `before` chunks ahead of it, the chunk itself and
+    /// up to `after` following it.
+    async fn chunk_neighbors(
+        chunk_id: String,
+        before: usize,
+        after: usize,
     ) -> Result<Vec<ResponseChunk>, RpcError>;
+
+    /// Moves the given files to the front of their projects' indexing queues,
+    /// e.g. the files open in an editor. Returns how many were still queued.
+    async fn prioritize(paths: Vec<String>) -> Result<usize, RpcError>;
+
+    /// Finds the tests likely covering a file, given relative to the project
+    /// root, or a symbol.
+    async fn find_tests_for(
+        project_path: String,
+        path_or_symbol: String,
+    ) -> Result<Vec<TestMatch>, RpcError>;
+
+    /// Finds where the identifier at a zero-based row and byte column of a
+    /// file, given relative to the project root, is defined in the project.
+    /// Best effort by name and kind, without a language server.
+    async fn goto_definition(
+        project_path: String,
+        file: String,
+        row: usize,
+        column: usize,
+    ) -> Result<Vec<Definition>, RpcError>;
+
+    /// Up to `limit` functions, types and other definitions of the project
+    /// whose name contains `query`, regardless of case. Read from the on-disk
+    /// index right away after a restart, before the files are parsed again.
+    async fn search_symbols(
+        project_path: String,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<Definition>, RpcError>;
+
+    /// The definitions of a file, given relative to the project root, in file
+    /// order. Read from the on-disk index like `search_symbols`.
+    async fn outline(project_path: String, file: String) -> Result<Vec<Definition>, RpcError>;
+
+    /// Searches the commit messages of the project's git repository, needs
+    /// `history.enabled`.
+    async fn search_history(
+        project_path: String,
+        query: String,
+    ) -> Result<Vec<CommitMatch>, RpcError>;
+
+    /// Lists the TODO, FIXME, HACK and XXX comments of the project, an empty
+    /// filter lists all of them.
+    async fn list_todos(project_path: String, filter: String) -> Result<Vec<Todo>, RpcError>;
+
+    /// Per-language file counts of the project, how many of its files are
+    /// indexed and skipped, with why, and how much of their bytes chunks
+    /// cover, to find blind spots of searches.
+    async fn coverage(project_path: String) -> Result<Coverage, RpcError>;
+
+    /// Up to `limit` chunks of the project no search has returned since the
+    /// index was created, in path order: dead or hard to find code.
+    async fn never_matched(project_path: String, limit: usize) -> Result<Vec<ColdChunk>, RpcError>;
+
+    /// Stops watching and indexing the project. Its on-disk index is kept for
+    /// `storage.removed_retention_secs` in case the removal was a mistake.
+    async fn remove_project(project_path: String) -> Result<(), RpcError>;
+
+    /// Registers a removed project again with the index it had.
+    async fn restore_project(project_path: String) -> Result<(), RpcError>;
+
+    /// Projects the file count, size, indexing time and memory of a project
+    /// without registering it.
+    async fn estimate_index(project_path: String) -> Result<IndexEstimate, RpcError>;
+
+    /// Waits for notifications from the daemon, e.g. that a project finished
+    /// indexing: returns the ones after the id `after`, or the next ones if
+    /// unset, once there are any or empty when the request deadline nears.
+    /// Pass the id of the last one seen to get the rest.
+    async fn subscribe(after: Option<u64>) -> Result<Vec<Notification>, RpcError>;
+
+    /// How many inotify watches the daemon uses of the limit, and whether it
+    /// ran out of them.
+    async fn watch_status() -> Result<WatchStatus, RpcError>;
+
+    /// Starts a named search session, or starts it over. Searches in it skip
+    /// chunks returned before and favor the files they were in.
+    async fn open_session(name: String) -> Result<(), RpcError>;
+
+    async fn close_session(name: String) -> Result<(), RpcError>;
+
+    /// Stops indexing, saves which projects are registered and runs the
+    /// daemon binary again in its place, e.g. after upgrading it. The new
+    /// daemon registers the same projects and picks up their on-disk indexes
+    /// where they were left. Returns before the restart.
+    async fn restart() -> Result<(), RpcError>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ProjectScope {
+    /// Project roots, registered on first search like with `search_code`.
+    Paths(Vec<String>),
+    /// Every project the daemon has been asked about before.
+    AllRegistered,
 }
 
 #[derive(Debug, Serialize, Deserialize, Error)]
 pub enum RpcError {
     #[error("Tarpc error: {0}")]
     Tarpc(String),
+    #[error("Project {0} is not registered")]
+    UnknownProject(String),
+    #[error("Project path {0} doesn't exist")]
+    NotFound(String),
+    #[error("Project path {0} isn't a directory")]
+    NotADirectory(String),
+    #[error("No permission to read project path {0}")]
+    PermissionDenied(String),
+    #[error("The directory of project {0} is gone, it's searched again once it's back")]
+    ProjectUnavailable(String),
+    #[error("Already indexing the maximum of {0} projects")]
+    TooManyProjects(usize),
+    #[error("The embedding model is still loading, try again shortly")]
+    ModelLoading,
+    #[error("The index of this project is read-only")]
+    ReadOnly,
+    #[error("The request was cancelled")]
+    Cancelled,
+    #[error("No session named {0} is open")]
+    UnknownSession(String),
     #[error("Internal error: {0}")]
     Internal(String),
 }
@@ -30,3 +247,120 @@ impl From<tarpc::client::RpcError> for RpcError {
         RpcError::Tarpc(error.to_string())
     }
 }
+
+/// Connects to the daemon, deflating requests larger than `compression_bytes`
+/// like the configured `rpc_compression_bytes`.
+pub async fn connect(
+    socket_path: impl AsRef<Path>,
+    compression_bytes: Option<usize>,
+) -> io::Result<CodeSearchRpcClient> {
+    let mut transport = tarpc::serde_transport::unix::connect(socket_path, move || {
+        CompressedJson::new(compression_bytes)
+    });
+    transport.config_mut().max_frame_length(usize::MAX);
+    Ok(CodeSearchRpcClient::new(client::Config::default(), transport.await?).spawn())
+}
+
+/// Starts the daemon listening on `socket_path` and connects to it once it
+/// does. The daemon binary next to the running one is preferred over the one
+/// on the `PATH`, it runs detached from the caller's terminal and output.
+pub async fn start_daemon(
+    socket_path: &Path,
+    compression_bytes: Option<usize>,
+) -> io::Result<CodeSearchRpcClient> {
+    let program = std::env::current_exe()
+        .map(|exe| exe.with_file_name(DAEMON_BINARY))
+        .ok()
+        .filter(|program| program.is_file())
+        .unwrap_or_else(|| DAEMON_BINARY.into());
+    let mut daemon = Command::new(program)
+        .arg("--socket")
+        .arg(socket_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    // Reaped when it exits, e.g. after shutting down idle.
+    std::thread::spawn(move || daemon.wait());
+    let started = Instant::now();
+    loop {
+        match connect(socket_path, compression_bytes).await {
+            Ok(client) => return Ok(client),
+            Err(e) if started.elapsed() > DAEMON_START_TIMEOUT => return Err(e),
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+}
+
+/// JSON frames, deflated when larger than the threshold. Both plain and
+/// deflated frames are read, so each side decides on its own what it sends.
+pub struct CompressedJson<Item, SinkItem> {
+    threshold: Option<usize>,
+    ghost: PhantomData<(Item, SinkItem)>,
+}
+
+impl<Item, SinkItem> CompressedJson<Item, SinkItem> {
+    /// Frames are never compressed if `threshold` is unset.
+    pub fn new(threshold: Option<usize>) -> Self {
+        Self {
+            threshold,
+            ghost: PhantomData,
+        }
+    }
+}
+
+impl<Item, SinkItem: Serialize> Serializer<SinkItem> for CompressedJson<Item, SinkItem> {
+    type Error = io::Error;
+
+    fn serialize(self: Pin<&mut Self>, item: &SinkItem) -> Result<Bytes, Self::Error> {
+        let json = serde_json::to_vec(item)?;
+        if self
+            .threshold
+            .is_none_or(|threshold| json.len() <= threshold)
+        {
+            return Ok(json.into());
+        }
+        let mut encoder = DeflateEncoder::new(vec![COMPRESSED_FRAME], Compression::fast());
+        encoder.write_all(&json)?;
+        Ok(encoder.finish()?.into())
+    }
+}
+
+impl<Item: DeserializeOwned, SinkItem> Deserializer<Item> for CompressedJson<Item, SinkItem> {
+    type Error = io::Error;
+
+    fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> Result<Item, Self::Error> {
+        match src.strip_prefix(&[COMPRESSED_FRAME]) {
+            Some(compressed) => {
+                let mut json = Vec::new();
+                DeflateDecoder::new(compressed).read_to_end(&mut json)?;
+                Ok(serde_json::from_slice(&json)?)
+            }
+            None => Ok(serde_json::from_slice(src)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_frames_are_deflated() {
+        let mut codec = CompressedJson::<Vec<String>, Vec<String>>::new(Some(100));
+        let small = vec!["fn main() {}".to_string()];
+        let large = vec!["fn main() {}".repeat(100)];
+
+        let frame = Pin::new(&mut codec).serialize(&small).unwrap();
+        assert_eq!(frame[0], b'[');
+        let frame = Pin::new(&mut codec).serialize(&large).unwrap();
+        assert_eq!(frame[0], COMPRESSED_FRAME);
+        assert!(frame.len() < 100);
+
+        let decoded = Pin::new(&mut codec)
+            .deserialize(&BytesMut::from(&frame[..]))
+            .unwrap();
+        assert_eq!(decoded, large);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::embeddings::ResponseChunk;
+
+/// Subtracted from the distance of chunks in files returned earlier in the
+/// session.
+const SAME_FILE_BOOST: f32 = 0.05;
+/// Subtracted from the distance of chunks next to files returned earlier.
+const SAME_DIRECTORY_BOOST: f32 = 0.02;
+
+/// Sessions unused for this long are dropped when another one is opened.
+pub const SESSION_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// What a client has been shown over several searches, so following searches
+/// favor the files it's exploring and don't repeat chunks it has seen.
+#[derive(Debug)]
+pub struct SearchSession {
+    chunks: HashSet<(PathBuf, Range<usize>)>,
+    files: HashSet<PathBuf>,
+    last_used: Instant,
+}
+
+impl Default for SearchSession {
+    fn default() -> Self {
+        Self {
+            chunks: HashSet::new(),
+            files: HashSet::new(),
+            last_used: Instant::now(),
+        }
+    }
+}
+
+impl SearchSession {
+    /// Drops chunks returned before and boosts the ones related to earlier
+    /// results, keeping the closest first.
+    pub fn apply(&mut self, chunks: &mut Vec<ResponseChunk>) {
+        self.last_used = Instant::now();
+        chunks.retain(|chunk| {
+            !self
+                .chunks
+                .contains(&(chunk.path.clone(), chunk.row.clone()))
+        });
+        for chunk in chunks.iter_mut() {
+            if self.files.contains(&chunk.path) {
+                chunk.adjust("in a file of earlier session results", -SAME_FILE_BOOST);
+            } else if self.has_sibling_of(&chunk.path) {
+                chunk.adjust(
+                    "next to a file of earlier session results",
+                    -SAME_DIRECTORY_BOOST,
+                );
+            }
+        }
+        chunks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    }
+
+    /// Remembers the chunks returned to the client.
+    pub fn record(&mut self, chunks: &[ResponseChunk]) {
+        for chunk in chunks {
+            self.chunks.insert((chunk.path.clone(), chunk.row.clone()));
+            self.files.insert(chunk.path.clone());
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.last_used.elapsed() >= SESSION_IDLE
+    }
+
+    fn has_sibling_of(&self, path: &Path) -> bool {
+        path.parent()
+            .is_some_and(|dir| self.files.iter().any(|file| file.parent() == Some(dir)))
+    }
+}
+
+/// Named sessions of the daemon's clients.
+#[derive(Debug, Default)]
+pub struct Sessions(HashMap<String, SearchSession>);
+
+impl Sessions {
+    /// Starts the session over if it exists.
+    pub fn open(&mut self, name: String) {
+        self.0.retain(|_, session| !session.is_idle());
+        self.0.insert(name, SearchSession::default());
+    }
+
+    /// Whether the session existed.
+    pub fn close(&mut self, name: &str) -> bool {
+        self.0.remove(name).is_some()
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut SearchSession> {
+        self.0.get_mut(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(path: &str, row: Range<usize>, distance: f32) -> ResponseChunk {
+        ResponseChunk {
+            path: path.into(),
+            row,
+            column: 0..0,
+            content: Default::default(),
+            key_path: None,
+            kind: Default::default(),
+            distance,
+            indexed_at: None,
+            stale: false,
+            blame: None,
+            permalink: None,
+            duplicates: Vec::new(),
+            explanation: None,
+            commit: None,
+            id: String::new(),
+            size: Default::default(),
+        }
+    }
+
+    #[test]
+    fn skips_seen_chunks_and_boosts_related_files() {
+        let mut session = SearchSession::default();
+        session.record(&[chunk("/p/src/a.rs", 0..10, 0.1)]);
+
+        let mut chunks = vec![
+            chunk("/p/src/a.rs", 0..10, 0.1),
+            chunk("/p/lib/c.rs", 0..5, 0.305),
+            chunk("/p/src/b.rs", 0..5, 0.32),
+            chunk("/p/src/a.rs", 20..30, 0.34),
+        ];
+        session.apply(&mut chunks);
+        let found: Vec<_> = chunks
+            .iter()
+            .map(|chunk| (chunk.path.to_str().unwrap(), chunk.row.start))
+            .collect();
+        assert_eq!(
+            found,
+            [("/p/src/a.rs", 20), ("/p/src/b.rs", 0), ("/p/lib/c.rs", 0)]
+        );
+    }
+}
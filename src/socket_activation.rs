@@ -0,0 +1,105 @@
+use std::os::{
+    fd::{FromRawFd, RawFd},
+    unix::net::UnixListener,
+};
+
+use anyhow::Result;
+
+/// The first descriptor systemd passes, `SD_LISTEN_FDS_START`.
+const SYSTEMD_FIRST_FD: RawFd = 3;
+
+/// The key of the socket in the `Sockets` dictionary of the launchd plist.
+#[cfg(target_os = "macos")]
+const LAUNCHD_SOCKET_NAME: &str = "Listeners";
+
+/// The socket the service manager bound for the daemon and started it on
+/// the first connection to, from systemd's `LISTEN_FDS` or launchd. None
+/// when the daemon wasn't socket-activated and binds the socket itself.
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    let fds = systemd_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    if fds > 0 {
+        if fds > 1 {
+            tracing::warn!(
+                "listening on the first of {} sockets passed by systemd",
+                fds
+            );
+        }
+        // The variables are left set, the daemon gets the socket again when
+        // it restarts in place.
+        return Ok(Some(listener_from_fd(SYSTEMD_FIRST_FD)?));
+    }
+    launchd_listener()
+}
+
+/// How many sockets systemd passed, only counted if they're meant for this
+/// process and not one that inherited the variables.
+fn systemd_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds
+        .and_then(|listen_fds| listen_fds.parse().ok())
+        .unwrap_or(0)
+}
+
+fn listener_from_fd(fd: RawFd) -> Result<UnixListener> {
+    // SAFETY: the service manager passed the descriptor for the daemon to own.
+    let listener = unsafe { UnixListener::from_raw_fd(fd) };
+    // Tokio only takes non-blocking listeners.
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+#[cfg(target_os = "macos")]
+fn launchd_listener() -> Result<Option<UnixListener>> {
+    use std::ffi::{CString, c_char, c_int, c_void};
+
+    unsafe extern "C" {
+        fn launch_activate_socket(
+            name: *const c_char,
+            fds: *mut *mut c_int,
+            count: *mut usize,
+        ) -> c_int;
+        fn free(ptr: *mut c_void);
+    }
+
+    let name = CString::new(LAUNCHD_SOCKET_NAME)?;
+    let mut fds: *mut c_int = std::ptr::null_mut();
+    let mut count = 0;
+    // SAFETY: on success launchd allocates `count` descriptors for the
+    // caller to free.
+    let fds = unsafe {
+        // Fails with ESRCH when not started by launchd.
+        if launch_activate_socket(name.as_ptr(), &mut fds, &mut count) != 0 {
+            return Ok(None);
+        }
+        let passed = std::slice::from_raw_parts(fds, count).to_vec();
+        free(fds.cast());
+        passed
+    };
+    fds.first().map(|&fd| listener_from_fd(fd)).transpose()
+}
+
+#[cfg(not(target_os = "macos"))]
+fn launchd_listener() -> Result<Option<UnixListener>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_sockets_passed_to_this_process() {
+        assert_eq!(systemd_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(systemd_fds(Some("42"), Some("2"), 42), 2);
+        assert_eq!(systemd_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(systemd_fds(None, Some("1"), 42), 0);
+        assert_eq!(systemd_fds(Some("42"), None, 42), 0);
+        assert_eq!(systemd_fds(Some("42"), Some("x"), 42), 0);
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use opentelemetry::{KeyValue, trace::TracerProvider as _};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{Resource, runtime, trace::TracerProvider};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::config::Config;
+
+/// Flushes exported spans when dropped.
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            tracing::warn!("failed to flush spans: {e}");
+        }
+    }
+}
+
+/// Installs the global tracing subscriber. Logs go to stderr, stdout carries
+/// the MCP protocol in the client. With `telemetry.otlp_endpoint` set, spans
+/// are also exported over OTLP, and tarpc passes their trace context along
+/// with every request so client and daemon spans end up in the same trace.
+pub fn init(config: &Config, service_name: &'static str) -> Result<Telemetry> {
+    let filter = EnvFilter::try_new(&config.log_level)?;
+    let fmt = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let Some(endpoint) = &config.telemetry.otlp_endpoint else {
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt)
+            .try_init()?;
+        return Ok(Telemetry { provider: None });
+    };
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            opentelemetry_sdk::trace::Config::default()
+                .with_resource(Resource::new([KeyValue::new("service.name", service_name)])),
+        )
+        .install_batch(runtime::TokioCurrentThread)?;
+    let tracer = provider.tracer(service_name);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    Ok(Telemetry {
+        provider: Some(provider),
+    })
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::config::WatchFallback;
+
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+/// Percentage of the user's inotify watches the daemon takes at most before
+/// watching projects directory by directory, the rest is left to editors
+/// and other tools.
+const WATCH_SHARE_PERCENT: usize = 75;
+
+/// How the daemon's file watching fares against the inotify limits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchStatus {
+    /// inotify watches the daemon holds, unknown off Linux.
+    pub watches: Option<usize>,
+    /// Watches a user may hold across all their processes.
+    pub max_user_watches: Option<usize>,
+    /// Whether adding a watch failed for lack of watches since the daemon
+    /// started.
+    pub limit_reached: bool,
+    /// Projects watched directory by directory, with their directory counts.
+    pub by_directory: Vec<(PathBuf, usize)>,
+    /// Set once the native watcher failed and the daemon polls or stopped
+    /// watching.
+    pub fallback: Option<WatchFallback>,
+}
+
+pub fn max_user_watches() -> Option<usize> {
+    fs::read_to_string(MAX_USER_WATCHES)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Whether watching `directories` more would take the daemon past its share
+/// of the user's watches. Never on platforms without the limit.
+pub fn over_watch_share(directories: usize) -> bool {
+    let Some(max) = max_user_watches() else {
+        return false;
+    };
+    watches_in_use().unwrap_or(0) + directories > max * WATCH_SHARE_PERCENT / 100
+}
+
+/// Directories under `root`, ignored ones included, i.e. the watches a
+/// recursive watch of it takes.
+pub fn count_directories(root: &Path) -> usize {
+    WalkBuilder::new(root)
+        .standard_filters(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir())
+        })
+        .count()
+}
+
+/// inotify watches held by this process, counted from the fdinfo of its
+/// inotify file descriptors.
+pub fn watches_in_use() -> Option<usize> {
+    let mut watches = 0;
+    for fd in fs::read_dir("/proc/self/fd").ok()?.flatten() {
+        let is_inotify =
+            fs::read_link(fd.path()).is_ok_and(|target| target.as_os_str() == "anon_inode:inotify");
+        if !is_inotify {
+            continue;
+        }
+        let fdinfo = PathBuf::from("/proc/self/fdinfo").join(fd.file_name());
+        if let Ok(fdinfo) = fs::read_to_string(fdinfo) {
+            watches += count_watches(&fdinfo);
+        }
+    }
+    Some(watches)
+}
+
+fn count_watches(fdinfo: &str) -> usize {
+    fdinfo
+        .lines()
+        .filter(|line| line.starts_with("inotify wd:"))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_watches_in_fdinfo() {
+        let fdinfo = "pos:\t0\nflags:\t02004000\nmnt_id:\t15\nino:\t1057\n\
+            inotify wd:2 ino:6b3 sdev:800001 mask:fc6 ignored_mask:0 fhandle-bytes:8 fhandle-type:1 f_handle:b306000000000000\n\
+            inotify wd:1 ino:2 sdev:800001 mask:fc6 ignored_mask:0 fhandle-bytes:8 fhandle-type:1 f_handle:0200000000000000\n";
+        assert_eq!(count_watches(fdinfo), 2);
+        assert_eq!(count_watches("pos:\t0\n"), 0);
+    }
+}
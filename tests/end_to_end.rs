@@ -0,0 +1,386 @@
+mod support;
+
+use std::{collections::HashMap, time::Duration};
+
+use local_code_search::{embeddings::SearchOptions, rpc::RpcError};
+use support::TestDaemon;
+
+#[actix::test]
+async fn search_finds_indexed_files() {
+    let daemon = TestDaemon::start("golden").await.unwrap();
+
+    // Files are embedded in the background, so early searches may only see part of the project.
+    for (query, expected) in [
+        ("parse the header of a jwt", "src/auth.rs"),
+        (
+            "retry an operation with exponential backoff",
+            "src/retry.py",
+        ),
+        (
+            "where is the database url configured",
+            "config/database.yaml",
+        ),
+    ] {
+        let expected = daemon.path(expected);
+        daemon
+            .wait_for_search(query, Duration::from_secs(60), |chunks| {
+                chunks.first().is_some_and(|chunk| chunk.path == expected)
+            })
+            .await
+            .unwrap();
+    }
+}
+
+#[actix::test]
+async fn search_picks_up_watched_changes() {
+    let daemon = TestDaemon::start("golden").await.unwrap();
+    daemon.search("warm up").await.unwrap();
+
+    let path = daemon
+        .write_file(
+            "src/cache.rs",
+            "pub fn evict_least_recently_used(entries: &mut Vec<(u64, String)>) {\n    entries.sort_by_key(|(last_used, _)| *last_used);\n    entries.remove(0);\n}\n",
+        )
+        .unwrap();
+
+    daemon
+        .wait_for_search(
+            "evict the least recently used cache entry",
+            Duration::from_secs(30),
+            |chunks| chunks.first().is_some_and(|chunk| chunk.path == path),
+        )
+        .await
+        .unwrap();
+}
+
+#[actix::test]
+async fn search_all_registered_projects_merges_by_distance() {
+    let daemon = TestDaemon::start("golden").await.unwrap();
+    let expected = daemon.path("src/auth.rs");
+    daemon
+        .wait_for_search(
+            "parse the header of a jwt",
+            Duration::from_secs(60),
+            |chunks| chunks.first().is_some_and(|chunk| chunk.path == expected),
+        )
+        .await
+        .unwrap();
+
+    let chunks = daemon
+        .search_all("parse the header of a jwt")
+        .await
+        .unwrap();
+    assert_eq!(chunks.first().map(|chunk| &chunk.path), Some(&expected));
+    assert!(
+        chunks
+            .windows(2)
+            .all(|pair| pair[0].distance <= pair[1].distance)
+    );
+}
+
+#[actix::test]
+async fn search_all_honours_a_limit_above_top_k() {
+    let search = toml::Table::from_iter([("top_k".to_string(), 1.into())]);
+    let daemon = TestDaemon::start_with(
+        "golden",
+        toml::Table::from_iter([("search".to_string(), search.into())]),
+    )
+    .await
+    .unwrap();
+    // Wait until every file is indexed, so there are more chunks than the limit.
+    for (query, expected) in [
+        ("parse the header of a jwt", "src/auth.rs"),
+        (
+            "retry an operation with exponential backoff",
+            "src/retry.py",
+        ),
+        (
+            "where is the database url configured",
+            "config/database.yaml",
+        ),
+    ] {
+        let expected = daemon.path(expected);
+        daemon
+            .wait_for_search(query, Duration::from_secs(60), |chunks| {
+                chunks.first().is_some_and(|chunk| chunk.path == expected)
+            })
+            .await
+            .unwrap();
+    }
+
+    let chunks = daemon
+        .search_all_with(
+            "parse the header of a jwt",
+            SearchOptions {
+                limit: Some(3),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(chunks.len(), 3);
+}
+
+#[actix::test]
+async fn search_reads_the_previous_index_while_switching_models() {
+    let daemon = TestDaemon::start("golden").await.unwrap();
+    let expected = daemon.path("src/auth.rs");
+    daemon
+        .wait_for_search(
+            "parse the header of a jwt",
+            Duration::from_secs(60),
+            |chunks| chunks.first().is_some_and(|chunk| chunk.path == expected),
+        )
+        .await
+        .unwrap();
+
+    daemon
+        .write_config("[embedding]\nmodel = \"sentence-transformers/all-MiniLM-L6-v2\"\n")
+        .unwrap();
+    // Switching while the new model loads.
+    daemon
+        .wait_for_projects(Duration::from_secs(30), |projects| {
+            projects
+                .iter()
+                .any(|project| project.previous_model.is_some())
+        })
+        .await
+        .unwrap();
+    let chunks = daemon.search("parse the header of a jwt").await.unwrap();
+    assert_eq!(chunks.first().map(|chunk| &chunk.path), Some(&expected));
+    let symbols = daemon.search_symbols("parse_jwt_header").await.unwrap();
+    assert_eq!(symbols.first().map(|symbol| &symbol.path), Some(&expected));
+
+    daemon
+        .wait_for_projects(Duration::from_secs(300), |projects| {
+            projects
+                .iter()
+                .all(|project| project.previous_model.is_none())
+        })
+        .await
+        .unwrap();
+    // The index of the previous model is deleted once switched.
+    let indexes = std::fs::read_dir(&daemon.index_dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "db")
+        })
+        .count();
+    assert_eq!(indexes, 1);
+}
+
+#[actix::test]
+async fn projects_are_unavailable_while_their_directory_is_gone() {
+    let daemon = TestDaemon::start("golden").await.unwrap();
+    daemon.search("warm up").await.unwrap();
+
+    let moved = daemon.project.with_extension("moved");
+    std::fs::rename(&daemon.project, &moved).unwrap();
+    daemon
+        .wait_for_search_result(
+            "parse the header of a jwt",
+            Duration::from_secs(60),
+            |result| {
+                result.as_ref().is_err_and(|e| {
+                    matches!(e.downcast_ref(), Some(RpcError::ProjectUnavailable(_)))
+                })
+            },
+        )
+        .await
+        .unwrap();
+
+    std::fs::rename(&moved, &daemon.project).unwrap();
+    let expected = daemon.path("src/auth.rs");
+    daemon
+        .wait_for_search_result(
+            "parse the header of a jwt",
+            Duration::from_secs(60),
+            |result| {
+                result
+                    .as_ref()
+                    .is_ok_and(|chunks| chunks.first().is_some_and(|chunk| chunk.path == expected))
+            },
+        )
+        .await
+        .unwrap();
+}
+
+/// Reads the embeddings of an export by content hash.
+fn exported_embeddings(export: &str) -> HashMap<String, Vec<f32>> {
+    export
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let chunk: serde_json::Value = serde_json::from_str(line).unwrap();
+            let embedding = serde_json::from_value(chunk["embedding"].clone()).unwrap();
+            (
+                chunk["content_hash"].as_str().unwrap().to_string(),
+                embedding,
+            )
+        })
+        .collect()
+}
+
+#[actix::test]
+async fn imported_embeddings_are_used_instead_of_computed_ones() {
+    let exporting = TestDaemon::start("golden").await.unwrap();
+    exporting.search("warm up").await.unwrap();
+    exporting
+        .wait_until_indexed(Duration::from_secs(300))
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let export = dir.path().join("export.jsonl");
+    exporting.export_embeddings(&export).await.unwrap();
+    // Negated, so embeddings the importing daemon computes itself differ.
+    let mut lines = std::fs::read_to_string(&export)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    for line in &mut lines[1..] {
+        let mut chunk: serde_json::Value = serde_json::from_str(line).unwrap();
+        for value in chunk["embedding"].as_array_mut().unwrap() {
+            *value = (-value.as_f64().unwrap()).into();
+        }
+        *line = chunk.to_string();
+    }
+    let negated = lines.join("\n");
+    std::fs::write(&export, &negated).unwrap();
+
+    let importing = TestDaemon::start("golden").await.unwrap();
+    let imported = importing.import_embeddings(&export).await.unwrap();
+    assert!(imported > 0);
+    importing
+        .wait_until_indexed(Duration::from_secs(300))
+        .await
+        .unwrap();
+    let reexport = dir.path().join("reexport.jsonl");
+    importing.export_embeddings(&reexport).await.unwrap();
+
+    let expected = exported_embeddings(&negated);
+    let indexed = exported_embeddings(&std::fs::read_to_string(&reexport).unwrap());
+    assert!(!indexed.is_empty());
+    for (hash, embedding) in &indexed {
+        assert_eq!(Some(embedding), expected.get(hash), "{hash}");
+    }
+}
+
+#[actix::test]
+async fn idle_verification_repairs_changes_the_watcher_missed() {
+    let verify = toml::Table::from_iter([
+        ("interval_secs".to_string(), 0.into()),
+        ("idle_secs".to_string(), 0.into()),
+    ]);
+    let daemon = TestDaemon::start_with(
+        "golden",
+        toml::Table::from_iter([
+            ("watch".to_string(), false.into()),
+            ("verify".to_string(), verify.into()),
+        ]),
+    )
+    .await
+    .unwrap();
+    daemon.search("warm up").await.unwrap();
+    daemon
+        .wait_until_indexed(Duration::from_secs(300))
+        .await
+        .unwrap();
+
+    // Unwatched, only a verification pass finds the edit.
+    let edited = daemon
+        .write_file(
+            "src/retry.py",
+            "def evict_least_recently_used(entries):\n    entries.sort(key=lambda entry: entry[0])\n    entries.pop(0)\n",
+        )
+        .unwrap();
+    daemon
+        .wait_for_search(
+            "evict the least recently used cache entry",
+            Duration::from_secs(300),
+            |chunks| {
+                chunks.first().is_some_and(|chunk| {
+                    chunk.path == edited && chunk.content.contains("evict_least_recently_used")
+                })
+            },
+        )
+        .await
+        .unwrap();
+}
+
+#[actix::test]
+async fn reindexing_changes_between_commits_reads_only_the_changed_files() {
+    let daemon = TestDaemon::start_with(
+        "golden",
+        toml::Table::from_iter([("watch".to_string(), false.into())]),
+    )
+    .await
+    .unwrap();
+    daemon.git(&["init", "--quiet"]).unwrap();
+    daemon.git(&["add", "."]).unwrap();
+    daemon.git(&["commit", "--quiet", "-m", "golden"]).unwrap();
+    let from = daemon.git(&["rev-parse", "HEAD"]).unwrap();
+    daemon.search("warm up").await.unwrap();
+    daemon
+        .wait_until_indexed(Duration::from_secs(300))
+        .await
+        .unwrap();
+
+    let edited = daemon
+        .write_file(
+            "src/retry.py",
+            "def evict_least_recently_used(entries):\n    entries.sort(key=lambda entry: entry[0])\n    entries.pop(0)\n",
+        )
+        .unwrap();
+    daemon.git(&["commit", "--quiet", "-am", "evict"]).unwrap();
+    let changed = daemon.reindex_changes(&from, "HEAD").await.unwrap();
+    assert_eq!(changed, 1);
+    daemon
+        .wait_for_search(
+            "evict the least recently used cache entry",
+            Duration::from_secs(60),
+            |chunks| {
+                chunks.first().is_some_and(|chunk| {
+                    chunk.path == edited && chunk.content.contains("evict_least_recently_used")
+                })
+            },
+        )
+        .await
+        .unwrap();
+}
+
+#[actix::test]
+async fn reloading_top_k_keeps_the_index() {
+    let daemon = TestDaemon::start("golden").await.unwrap();
+    let chunks = daemon.search("parse the header of a jwt").await.unwrap();
+    assert!(chunks.len() > 1, "{chunks:?}");
+    daemon
+        .wait_until_indexed(Duration::from_secs(300))
+        .await
+        .unwrap();
+    let indexed = daemon
+        .wait_for_projects(Duration::from_secs(1), |_| true)
+        .await
+        .unwrap();
+
+    daemon.write_config("[search]\ntop_k = 1\n").unwrap();
+    daemon
+        .wait_for_search(
+            "parse the header of a jwt",
+            Duration::from_secs(30),
+            |chunks| chunks.len() == 1,
+        )
+        .await
+        .unwrap();
+    let reloaded = daemon
+        .wait_for_projects(Duration::from_secs(1), |_| true)
+        .await
+        .unwrap();
+    assert_eq!(reloaded[0].pending, 0);
+    assert_eq!(reloaded[0].last_indexed, indexed[0].last_indexed);
+}
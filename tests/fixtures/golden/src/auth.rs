@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+
+pub struct JwtHeader {
+    pub alg: String,
+    pub typ: String,
+}
+
+pub fn parse_jwt_header(token: &str) -> Option<JwtHeader> {
+    let header = token.split('.').next()?;
+    let fields: HashMap<&str, &str> = header
+        .split(',')
+        .filter_map(|field| field.split_once(':'))
+        .collect();
+    Some(JwtHeader {
+        alg: fields.get("alg")?.to_string(),
+        typ: fields.get("typ")?.to_string(),
+    })
+}
+
+pub fn is_token_expired(expires_at: u64, now: u64) -> bool {
+    expires_at <= now
+}
@@ -0,0 +1,14 @@
+import time
+
+
+def retry_with_backoff(operation, attempts=5, base_delay=0.5):
+    """Call operation until it succeeds, doubling the delay after every failure."""
+    delay = base_delay
+    for attempt in range(attempts):
+        try:
+            return operation()
+        except Exception:
+            if attempt == attempts - 1:
+                raise
+            time.sleep(delay)
+            delay *= 2
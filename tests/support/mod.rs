@@ -0,0 +1,323 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use local_code_search::{
+    config::ConfigSource,
+    daemon,
+    embeddings::{Definition, ProjectInfo, ResponseChunk, SearchCodeResponse, SearchOptions},
+    rpc::{self, CodeSearchRpcClient, ProjectScope, RpcError},
+};
+use tarpc::context;
+use tempfile::TempDir;
+
+/// Generous deadline, the first search has to wait for the model and index the fixture.
+const SEARCH_DEADLINE: Duration = Duration::from_secs(300);
+
+/// A daemon running in-process on a temporary socket, serving a temporary copy
+/// of a fixture repository from `tests/fixtures`.
+pub struct TestDaemon {
+    pub project: PathBuf,
+    pub index_dir: PathBuf,
+    /// Watched by the daemon, empty until written.
+    config_path: PathBuf,
+    client: CodeSearchRpcClient,
+    _dir: TempDir,
+}
+
+impl TestDaemon {
+    /// Must be called from inside an `#[actix::test]`, the daemon needs a local task set.
+    pub async fn start(fixture: &str) -> Result<Self> {
+        Self::start_with(fixture, toml::Table::new()).await
+    }
+
+    /// Like `start`, with `overrides` applied on top of the test configuration.
+    pub async fn start_with(fixture: &str, overrides: toml::Table) -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let fixture_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(fixture);
+        let project = dir.path().join(fixture);
+        copy_dir(&fixture_path, &project)?;
+        let project = std::fs::canonicalize(project)?;
+
+        let socket_path = dir.path().join("code_search.sock");
+        let index_dir = dir.path().join("index");
+        let storage = toml::Table::from_iter([(
+            "index_dir".to_string(),
+            index_dir.to_string_lossy().into_owned().into(),
+        )]);
+        // A directory of its own, the daemon watches the config's directory.
+        let config_dir = dir.path().join("config");
+        std::fs::create_dir(&config_dir)?;
+        let config_path = std::fs::canonicalize(config_dir)?.join("config.toml");
+        let config_source = ConfigSource {
+            path: Some(config_path.clone()),
+            overrides: toml::Table::from_iter([
+                (
+                    "socket_path".to_string(),
+                    socket_path.to_string_lossy().into_owned().into(),
+                ),
+                ("storage".to_string(), storage.into()),
+            ])
+            .into_iter()
+            .chain(overrides)
+            .collect(),
+        };
+        tokio::task::spawn_local(daemon::run(config_source));
+        let client = connect_with_retry(&socket_path).await?;
+
+        Ok(Self {
+            project,
+            index_dir,
+            config_path,
+            client,
+            _dir: dir,
+        })
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<ResponseChunk>> {
+        self.retry_while_model_loads(|ctx| {
+            self.client.search_code(
+                ctx,
+                self.project.to_string_lossy().into(),
+                query.into(),
+                SearchOptions::default(),
+            )
+        })
+        .await
+    }
+
+    /// Searches every project registered with the daemon.
+    pub async fn search_all(&self, query: &str) -> Result<Vec<ResponseChunk>> {
+        self.search_all_with(query, SearchOptions::default()).await
+    }
+
+    pub async fn search_all_with(
+        &self,
+        query: &str,
+        options: SearchOptions,
+    ) -> Result<Vec<ResponseChunk>> {
+        self.retry_while_model_loads(|ctx| {
+            self.client.search_projects(
+                ctx,
+                ProjectScope::AllRegistered,
+                query.into(),
+                options.clone(),
+            )
+        })
+        .await
+    }
+
+    async fn retry_while_model_loads<F>(
+        &self,
+        search: impl Fn(context::Context) -> F,
+    ) -> Result<Vec<ResponseChunk>>
+    where
+        F: Future<Output = Result<Result<SearchCodeResponse, RpcError>, tarpc::client::RpcError>>,
+    {
+        let started = Instant::now();
+        loop {
+            let mut ctx = context::current();
+            ctx.deadline = Instant::now() + SEARCH_DEADLINE;
+            match search(ctx).await? {
+                Err(RpcError::ModelLoading) if started.elapsed() < SEARCH_DEADLINE => {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                result => return Ok(result?.chunks),
+            }
+        }
+    }
+
+    /// Repeats the search until `predicate` accepts the results, for waiting on
+    /// watcher-driven reindexing.
+    pub async fn wait_for_search(
+        &self,
+        query: &str,
+        timeout: Duration,
+        predicate: impl Fn(&[ResponseChunk]) -> bool,
+    ) -> Result<Vec<ResponseChunk>> {
+        let started = Instant::now();
+        loop {
+            let chunks = self.search(query).await?;
+            if predicate(&chunks) {
+                return Ok(chunks);
+            }
+            if started.elapsed() > timeout {
+                bail!("search {:?} did not match within {:?}", query, timeout);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Like `wait_for_search`, for searches expected to start or stop
+    /// failing.
+    pub async fn wait_for_search_result(
+        &self,
+        query: &str,
+        timeout: Duration,
+        predicate: impl Fn(&Result<Vec<ResponseChunk>>) -> bool,
+    ) -> Result<()> {
+        let started = Instant::now();
+        loop {
+            if predicate(&self.search(query).await) {
+                return Ok(());
+            }
+            if started.elapsed() > timeout {
+                bail!("search {:?} did not match within {:?}", query, timeout);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Waits until the registered projects are as `predicate` wants them,
+    /// checking often to catch short-lived states.
+    pub async fn wait_for_projects(
+        &self,
+        timeout: Duration,
+        predicate: impl Fn(&[ProjectInfo]) -> bool,
+    ) -> Result<Vec<ProjectInfo>> {
+        let started = Instant::now();
+        loop {
+            let projects = self.client.list_projects(context::current()).await??;
+            if predicate(&projects) {
+                return Ok(projects);
+            }
+            if started.elapsed() > timeout {
+                bail!("projects did not match within {:?}", timeout);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    pub async fn search_symbols(&self, query: &str) -> Result<Vec<Definition>> {
+        Ok(self
+            .client
+            .search_symbols(
+                context::current(),
+                self.project.to_string_lossy().into(),
+                query.into(),
+                10,
+            )
+            .await??)
+    }
+
+    /// Registers the project if needed and indexes it with the embeddings
+    /// exported to `input`.
+    pub async fn import_embeddings(&self, input: &Path) -> Result<usize> {
+        Ok(self
+            .client
+            .import_embeddings(
+                context::current(),
+                self.project.to_string_lossy().into(),
+                input.to_string_lossy().into(),
+            )
+            .await??)
+    }
+
+    pub async fn export_embeddings(&self, output: &Path) -> Result<usize> {
+        Ok(self
+            .client
+            .export_embeddings(
+                context::current(),
+                self.project.to_string_lossy().into(),
+                output.to_string_lossy().into(),
+            )
+            .await??)
+    }
+
+    /// Waits until no project has files left to embed.
+    pub async fn wait_until_indexed(&self, timeout: Duration) -> Result<()> {
+        self.wait_for_projects(timeout, |projects| {
+            projects
+                .iter()
+                .all(|project| !project.model_loading && project.pending == 0)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Reindexes the files that differ between the revisions, returns how
+    /// many did.
+    pub async fn reindex_changes(&self, from: &str, to: &str) -> Result<usize> {
+        Ok(self
+            .client
+            .reindex_changes(
+                context::current(),
+                self.project.to_string_lossy().into(),
+                from.into(),
+                to.into(),
+            )
+            .await??)
+    }
+
+    /// Runs git in the project, returns its trimmed output.
+    pub fn git(&self, args: &[&str]) -> Result<String> {
+        let output = std::process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "commit.gpgsign=false",
+            ])
+            .args(args)
+            .current_dir(&self.project)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "git {:?} failed: {}",
+                args,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    }
+
+    /// Replaces the config file, which the daemon reloads.
+    pub fn write_config(&self, contents: &str) -> Result<()> {
+        std::fs::write(&self.config_path, contents)?;
+        Ok(())
+    }
+
+    pub fn write_file(&self, relative_path: &str, contents: &str) -> Result<PathBuf> {
+        let path = self.project.join(relative_path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    pub fn path(&self, relative_path: &str) -> PathBuf {
+        self.project.join(relative_path)
+    }
+}
+
+async fn connect_with_retry(socket_path: &Path) -> Result<CodeSearchRpcClient> {
+    let started = Instant::now();
+    loop {
+        match rpc::connect(socket_path, Some(rpc::DEFAULT_COMPRESSION_BYTES)).await {
+            Ok(client) => return Ok(client),
+            Err(e) if started.elapsed() > Duration::from_secs(10) => return Err(e.into()),
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}